@@ -0,0 +1,331 @@
+///MESや解析チーム向けの読み出し専用 REST/JSON API
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use subtle::ConstantTimeEq;
+use tauri::{AppHandle, Manager};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::data_handler::{is_valid_identifier, open_read_connection};
+use crate::db_query::{self, configured_plcs, resolve_table, table_for_plc};
+use crate::live_feed::LiveFilter;
+use crate::plc_commands::collect_plc_status;
+use crate::shutdown::ShutdownState;
+use crate::state::{ConnectionState, LiveFeedState};
+use crate::types::{ApiConfig, QualityConfig};
+
+/// APIハンドラーで共有する状態
+#[derive(Clone)]
+struct ApiState {
+    app: AppHandle,
+    token: String,
+    pass_bins: Vec<i64>,
+}
+
+/// APIのエラー応答
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+/// テーブル指定用のクエリパラメータ(plc_id か table のどちらか)
+#[derive(Deserialize)]
+struct TableQuery {
+    plc_id: Option<u32>,
+    table: Option<String>,
+}
+
+/// チップ検索用のクエリパラメータ
+#[derive(Deserialize)]
+struct ChipQuery {
+    lot: String,
+    serial: i64,
+    plc_id: Option<u32>,
+    table: Option<String>,
+}
+
+/// ライブ配信の絞り込み用クエリパラメータ(カンマ区切りで複数指定可)
+#[derive(Deserialize)]
+struct LiveQuery {
+    plc: Option<String>,
+    unit: Option<String>,
+    kind: Option<String>,
+}
+
+/// APIのルーティングを作成する
+fn router(app: AppHandle, config: &ApiConfig, quality: &QualityConfig) -> Router {
+    let state = ApiState {
+        app,
+        token: config.token.clone(),
+        pass_bins: quality.pass_bins.clone(),
+    };
+
+    Router::new()
+        .route("/api/schema/chip", get(get_chip_schema))
+        .route("/api/plcs", get(get_plcs))
+        .route("/api/plcs/{plc_id}/status", get(get_plc_status))
+        .route("/api/lots", get(get_lots))
+        .route("/api/lots/{lot_name}/yield", get(get_lot_yield))
+        .route("/api/chips", get(get_chip))
+        .route("/ws/live", get(live_feed))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+/// APIサーバーを起動する(終了処理が始まったら停止する)
+pub async fn serve(app: AppHandle, config: ApiConfig, quality: QualityConfig) {
+    if config.token.is_empty() {
+        log::error!("API token is not configured, HTTP API is not started");
+        return;
+    }
+
+    let addr = format!("{}:{}", config.bind_addr, config.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind HTTP API to {}: {}", addr, e);
+            return;
+        }
+    };
+    log::info!("HTTP API listening on http://{}/api", addr);
+
+    let mut shutdown_rx = app.state::<ShutdownState>().subscribe();
+    let router = router(app, &config, &quality);
+    let result = axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown_rx.changed().await;
+        })
+        .await;
+    if let Err(e) = result {
+        log::error!("HTTP API stopped with error: {}", e);
+    }
+}
+
+/// 認証用のクエリパラメータ
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Authorization: Bearer <token> を確認する
+/// ブラウザのWebSocketはヘッダーを付けられないため ?token= も受け付ける
+/// 一致するまでの時間からトークンを推測されないよう、比較は一定時間で行う
+async fn require_token(
+    State(state): State<ApiState>,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let header_token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let authorized = header_token
+        .or(query.token.as_deref())
+        .map(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())))
+        .unwrap_or(false);
+
+    if authorized {
+        next.run(request).await
+    } else {
+        ApiError(StatusCode::UNAUTHORIZED, "Invalid or missing token".to_string()).into_response()
+    }
+}
+
+/// クエリパラメータからテーブル名を決める
+fn table_from_query(plc_id: Option<u32>, table: Option<&str>) -> Result<String, ApiError> {
+    match (plc_id, table) {
+        (Some(id), _) => table_for_plc(id).map_err(|e| ApiError(StatusCode::NOT_FOUND, e)),
+        (None, Some(table)) => resolve_table(table).map_err(|e| ApiError(StatusCode::NOT_FOUND, e)),
+        (None, None) => Err(ApiError(StatusCode::BAD_REQUEST, "plc_id or table is required".to_string())),
+    }
+}
+
+/// DB読み出しを別スレッドで実行する
+async fn with_read_connection<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&rusqlite::Connection) -> Result<T, ApiError> + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection()?;
+        f(&conn)
+    })
+    .await
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+/// GET /api/schema/chip
+async fn get_chip_schema() -> ApiResult {
+    Ok(Json(db_query::chip_json_schema()))
+}
+
+/// GET /api/plcs
+async fn get_plcs(State(state): State<ApiState>) -> ApiResult {
+    let plcs = configured_plcs().map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let status = collect_plc_status(&state.app.state::<ConnectionState>());
+
+    let list: Vec<Value> = plcs
+        .into_iter()
+        .map(|plc| {
+            let plc_status = status.iter().find(|s| s.plc_id == plc.id);
+            serde_json::json!({
+                "id": plc.id,
+                "name": plc.name,
+                "table_name": plc.table_name,
+                "plc_ip": plc.plc_ip,
+                "plc_port": plc.plc_port,
+                "is_connected": plc_status.map(|s| s.is_connected).unwrap_or(false),
+                "status": plc_status,
+            })
+        })
+        .collect();
+    Ok(Json(Value::from(list)))
+}
+
+/// GET /api/plcs/{plc_id}/status
+async fn get_plc_status(State(state): State<ApiState>, Path(plc_id): Path<u32>) -> ApiResult {
+    table_for_plc(plc_id).map_err(|e| ApiError(StatusCode::NOT_FOUND, e))?;
+    let status = collect_plc_status(&state.app.state::<ConnectionState>())
+        .into_iter()
+        .find(|s| s.plc_id == plc_id);
+
+    match status {
+        Some(status) => Ok(Json(serde_json::to_value(status).unwrap_or(Value::Null))),
+        None => Ok(Json(serde_json::json!({ "plc_id": plc_id, "is_connected": false }))),
+    }
+}
+
+/// GET /api/lots?plc_id=1
+async fn get_lots(Query(query): Query<TableQuery>) -> ApiResult {
+    let table = table_from_query(query.plc_id, query.table.as_deref())?;
+    with_read_connection(move |conn| {
+        let lots = db_query::list_lots(conn, &table)?;
+        Ok(Json(serde_json::to_value(lots).unwrap_or(Value::Null)))
+    })
+    .await
+}
+
+/// GET /api/lots/{lot_name}/yield?plc_id=1
+async fn get_lot_yield(State(state): State<ApiState>, Path(lot_name): Path<String>, Query(query): Query<TableQuery>) -> ApiResult {
+    let table = table_from_query(query.plc_id, query.table.as_deref())?;
+    let pass_bins = state.pass_bins.clone();
+    with_read_connection(move |conn| {
+        let summary = db_query::lot_yield(conn, &table, &lot_name, &pass_bins)?;
+        if summary.chip_count == 0 {
+            return Err(ApiError(StatusCode::NOT_FOUND, format!("Lot {} not found", lot_name)));
+        }
+        Ok(Json(serde_json::to_value(summary).unwrap_or(Value::Null)))
+    })
+    .await
+}
+
+/// チップ検索の対象テーブル(plc_id/table を省略した場合は全テーブル)
+fn chip_tables(query: &ChipQuery) -> Result<Vec<String>, ApiError> {
+    if query.plc_id.is_some() || query.table.is_some() {
+        return Ok(vec![table_from_query(query.plc_id, query.table.as_deref())?]);
+    }
+    Ok(configured_plcs()
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .into_iter()
+        .map(|plc| plc.table_name)
+        .filter(|table| is_valid_identifier(table))
+        .collect())
+}
+
+/// GET /api/chips?lot=XXX&serial=123[&plc_id=1]
+/// plc_id/table を省略した場合は全テーブルから探す
+async fn get_chip(Query(query): Query<ChipQuery>) -> ApiResult {
+    let tables = chip_tables(&query)?;
+
+    with_read_connection(move |conn| {
+        match db_query::find_chip(conn, &tables, &query.lot, query.serial)? {
+            Some((table, row)) => Ok(Json(serde_json::json!({ "table_name": table, "chip": row }))),
+            None => Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("Chip {}/{} not found", query.lot, query.serial),
+            )),
+        }
+    })
+    .await
+}
+
+/// GET /ws/live?plc=1,2&unit=U2&kind=test_stage,status
+/// 接続後にJSON({"plc":[1],"unit":["U2"],"kind":[]})を送ると絞り込み条件を変更できる
+async fn live_feed(State(state): State<ApiState>, Query(query): Query<LiveQuery>, ws: WebSocketUpgrade) -> Response {
+    let filter = match LiveFilter::from_query(query.plc.as_deref(), query.unit.as_deref(), query.kind.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return ApiError(StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    ws.on_upgrade(move |socket| stream_live_feed(socket, state.app, filter))
+}
+
+/// 購読者1件分の配信ループ
+/// 送信が追いつかない場合は読み飛ばした件数を通知し、受信処理は待たせない
+async fn stream_live_feed(mut socket: WebSocket, app: AppHandle, mut filter: LiveFilter) {
+    let mut events = app.state::<LiveFeedState>().subscribe();
+    let mut shutdown_rx = app.state::<ShutdownState>().subscribe();
+    log::info!("Live feed subscriber connected ({} total)", app.state::<LiveFeedState>().receiver_count());
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.changed() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            event = events.recv() => {
+                let text = match event {
+                    Ok(event) if filter.matches(&event) => serde_json::to_string(&event).unwrap_or_default(),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Live feed subscriber lagged, {} events skipped", skipped);
+                        serde_json::json!({ "type": "lagged", "skipped": skipped }).to_string()
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<LiveFilter>(&text) {
+                        Ok(new_filter) => filter = new_filter,
+                        Err(e) => {
+                            let error = serde_json::json!({ "type": "error", "error": format!("Invalid filter: {}", e) });
+                            if socket.send(Message::Text(error.to_string().into())).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+
+    log::info!("Live feed subscriber disconnected");
+}
@@ -0,0 +1,387 @@
+///チップ情報の保存先(SQLite・PostgreSQL)の共通処理
+///受信データはユニットごとに (LOT_NAME, SERIAL) をキーとしたupsertと、アラームなどの履歴の追加に変換して保存する
+use crate::output_sink::{IngestFrame, OutputSink};
+use crate::regist_data_to_db::history_insert;
+
+/// upsertするカラムの値
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnValue {
+    Int(i64),
+    Text(String),
+    /// 受信データに無い項目(SQLにはプレースホルダーではなくNULLを直接書く)
+    Null,
+    /// 新規行は1、既存行は現在値+1(NULLの場合は1)にする
+    Increment,
+}
+
+/// (LOT_NAME, SERIAL) をキーとした1行分のupsert
+#[derive(Debug, Clone)]
+pub struct ChipUpsert {
+    pub machine_name: String,
+    pub type_name: String,
+    pub lot_name: String,
+    pub serial: i64,
+    pub columns: Vec<(String, ColumnValue)>,
+}
+
+impl ChipUpsert {
+    pub fn new(machine_name: &str, type_name: &str, lot_name: &str, serial: i64) -> Self {
+        ChipUpsert {
+            machine_name: machine_name.to_string(),
+            type_name: type_name.to_string(),
+            lot_name: lot_name.to_string(),
+            serial,
+            columns: Vec::new(),
+        }
+    }
+
+    /// 値が None の場合はNULLを登録する
+    pub fn int(mut self, column: impl Into<String>, value: impl Into<Option<i64>>) -> Self {
+        let value = value.into().map(ColumnValue::Int).unwrap_or(ColumnValue::Null);
+        self.columns.push((column.into(), value));
+        self
+    }
+
+    /// 値が None の場合はNULLを登録する
+    pub fn text<'a>(mut self, column: impl Into<String>, value: impl Into<Option<&'a str>>) -> Self {
+        let value = value
+            .into()
+            .map(|v| ColumnValue::Text(v.to_string()))
+            .unwrap_or(ColumnValue::Null);
+        self.columns.push((column.into(), value));
+        self
+    }
+
+    pub fn increment(mut self, column: impl Into<String>) -> Self {
+        self.columns.push((column.into(), ColumnValue::Increment));
+        self
+    }
+
+    /// SQLにバインドする値(upsert_sql のプレースホルダー順)
+    pub fn params(&self) -> Vec<ColumnValue> {
+        let mut params = vec![
+            ColumnValue::Text(self.machine_name.clone()),
+            ColumnValue::Text(self.type_name.clone()),
+            ColumnValue::Text(self.lot_name.clone()),
+            ColumnValue::Int(self.serial),
+        ];
+        params.extend(self.columns.iter().filter_map(|(_, value)| match value {
+            ColumnValue::Null => None,
+            ColumnValue::Increment => Some(ColumnValue::Int(1)),
+            value => Some(value.clone()),
+        }));
+        params
+    }
+}
+
+/// 履歴として追加する1行(アラーム履歴・設備イベント)
+/// 同じ受信データを再送した場合に重複しないよう、同じ内容の行が既にあれば追加しない
+#[derive(Debug, Clone)]
+pub struct HistoryInsert {
+    pub table_name: String,
+    pub columns: Vec<(String, ColumnValue)>,
+}
+
+impl HistoryInsert {
+    pub fn new(table_name: impl Into<String>) -> Self {
+        HistoryInsert {
+            table_name: table_name.into(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// 値が None の場合はNULLを登録する
+    pub fn int(mut self, column: impl Into<String>, value: impl Into<Option<i64>>) -> Self {
+        let value = value.into().map(ColumnValue::Int).unwrap_or(ColumnValue::Null);
+        self.columns.push((column.into(), value));
+        self
+    }
+
+    /// 値が None の場合はNULLを登録する
+    pub fn text<'a>(mut self, column: impl Into<String>, value: impl Into<Option<&'a str>>) -> Self {
+        let value = value
+            .into()
+            .map(|v| ColumnValue::Text(v.to_string()))
+            .unwrap_or(ColumnValue::Null);
+        self.columns.push((column.into(), value));
+        self
+    }
+
+    /// SQLにバインドする値(insert_sql のプレースホルダー順)
+    pub fn params(&self) -> Vec<ColumnValue> {
+        self.columns
+            .iter()
+            .filter(|(_, value)| *value != ColumnValue::Null)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+/// ユニット情報1件から行う登録
+#[derive(Debug, Clone)]
+pub enum ChipWrite {
+    /// チップの行へのupsert
+    Upsert(ChipUpsert),
+    /// 履歴テーブルへの追加
+    Insert(HistoryInsert),
+}
+
+/// 履歴を追加するSQL文を作成する(SQLite・PostgreSQL共通)
+pub fn insert_sql(row: &HistoryInsert, placeholder: fn(usize) -> String) -> String {
+    let columns: Vec<String> = row.columns.iter().map(|(c, _)| format!("\"{}\"", c)).collect();
+    let mut values = Vec::new();
+    let mut next = 1;
+    for (_, value) in &row.columns {
+        if *value == ColumnValue::Null {
+            values.push("NULL".to_string());
+        } else {
+            values.push(placeholder(next));
+            next += 1;
+        }
+    }
+    format!(
+        "INSERT INTO \"{}\" ({}) VALUES ({}) ON CONFLICT DO NOTHING",
+        row.table_name,
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+/// upsertのSQL文を作成する(SQLite・PostgreSQL共通)
+/// placeholder は n番目(1始まり)のプレースホルダー表記を返す
+pub fn upsert_sql(table_name: &str, row: &ChipUpsert, placeholder: fn(usize) -> String) -> String {
+    let mut columns = vec!["MACHINE_NAME", "TYPE_NAME", "LOT_NAME", "SERIAL"];
+    columns.extend(row.columns.iter().map(|(column, _)| column.as_str()));
+
+    let insert_columns: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+    let mut values: Vec<String> = (1..=4).map(placeholder).collect();
+    let mut next = 5;
+    for (_, value) in &row.columns {
+        if *value == ColumnValue::Null {
+            values.push("NULL".to_string());
+        } else {
+            values.push(placeholder(next));
+            next += 1;
+        }
+    }
+
+    let mut updates = vec![
+        "\"MACHINE_NAME\" = excluded.\"MACHINE_NAME\"".to_string(),
+        "\"TYPE_NAME\" = excluded.\"TYPE_NAME\"".to_string(),
+    ];
+    for (column, value) in &row.columns {
+        updates.push(match value {
+            ColumnValue::Increment => format!("\"{column}\" = COALESCE(\"{table_name}\".\"{column}\", 0) + 1"),
+            _ => format!("\"{column}\" = excluded.\"{column}\""),
+        });
+    }
+
+    format!(
+        "INSERT INTO \"{table_name}\" ({}) VALUES ({}) ON CONFLICT (\"LOT_NAME\", \"SERIAL\") DO UPDATE SET {}",
+        insert_columns.join(", "),
+        values.join(", "),
+        updates.join(", ")
+    )
+}
+
+/// 保存時のエラー
+#[derive(Debug)]
+pub enum StoreError {
+    /// その行だけの異常(値・制約など)。他の行の保存は続ける
+    Record(String),
+    /// 接続断など保存先自体の異常。受信データごと再送する
+    Backend(String),
+}
+
+/// チップ情報の保存先
+pub trait ChipStore: Send {
+    /// 出力先名(PLCの sinks で指定する名前)
+    fn name(&self) -> &'static str;
+
+    fn begin(&mut self) -> Result<(), String>;
+
+    /// テーブル(アラーム履歴・更新履歴のテーブルを含む)が無ければ作成する
+    fn ensure_table(&mut self, table_name: &str) -> Result<(), String>;
+
+    fn upsert(&mut self, table_name: &str, row: &ChipUpsert) -> Result<(), StoreError>;
+
+    fn insert(&mut self, row: &HistoryInsert) -> Result<(), StoreError>;
+
+    /// 受信データ1件分のセーブポイントを作る
+    fn begin_frame(&mut self) -> Result<(), String>;
+
+    /// 受信データ1件分のセーブポイントを解放する
+    fn end_frame(&mut self) -> Result<(), String>;
+
+    /// 保存先の異常で途中まで書いた受信データ1件分を取り消す
+    /// 受信データごと再送するので、Increment のカラムが二重に数えられないようにする
+    fn rollback_frame(&mut self);
+
+    fn commit(&mut self) -> Result<(), String>;
+}
+
+/// 保存先を出力先として使うためのアダプター
+pub struct StoreSink<S: ChipStore> {
+    store: S,
+    /// チップの行へのupsertを {TABLE}_history にも追加する
+    history: bool,
+}
+
+impl<S: ChipStore> StoreSink<S> {
+    pub fn new(store: S, history: bool) -> Self {
+        StoreSink { store, history }
+    }
+}
+
+impl<S: ChipStore> OutputSink for StoreSink<S> {
+    fn name(&self) -> &'static str {
+        self.store.name()
+    }
+
+    fn begin_batch(&mut self) -> Result<(), String> {
+        self.store.begin()
+    }
+
+    fn write(&mut self, input: &IngestFrame) -> Result<usize, String> {
+        self.store.ensure_table(&input.table_name)?;
+
+        self.store.begin_frame()?;
+        let result = self
+            .write_records(input)
+            .and_then(|failures| self.store.end_frame().map(|()| failures));
+        if result.is_err() {
+            self.store.rollback_frame();
+        }
+        result
+    }
+
+    fn end_batch(&mut self) -> Result<(), String> {
+        self.store.commit()
+    }
+}
+
+impl<S: ChipStore> StoreSink<S> {
+    /// 受信データ1件分のユニット情報を登録する(保存先の異常はErrで、途中までの分は呼び出し側で取り消す)
+    fn write_records(&mut self, input: &IngestFrame) -> Result<usize, String> {
+        //各ユニット情報の登録
+        let mut failures = 0;
+        for (record, writes) in input.unit_writes() {
+            let writes = match writes {
+                Ok(writes) => writes,
+                Err(e) => {
+                    log::error!("Failed to register {} data ({}): {}", record.kind.as_str(), record.key, e);
+                    failures += 1;
+                    continue;
+                }
+            };
+            // 1つのユニット情報で複数の登録がある場合(アラームなど)も失敗は1件として数える
+            let mut record_failed = false;
+            for write in writes {
+                let result = match write {
+                    ChipWrite::Upsert(row) => match self.store.upsert(&input.table_name, row) {
+                        Ok(()) if self.history => {
+                            self.store.insert(&history_insert(&input.table_name, record, row, &input.received_at))
+                        }
+                        result => result,
+                    },
+                    ChipWrite::Insert(row) => self.store.insert(row),
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(StoreError::Record(e)) => {
+                        log::error!("Failed to register {} data ({}): {}", record.kind.as_str(), record.key, e);
+                        record_failed = true;
+                    }
+                    Err(StoreError::Backend(e)) => return Err(e),
+                }
+            }
+            if record_failed {
+                failures += 1;
+            }
+        }
+        Ok(failures)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{params_from_iter, Connection};
+
+    fn placeholder(n: usize) -> String {
+        format!("?{}", n)
+    }
+
+    fn chip_table() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE \"PLC 1\" (\"MACHINE_NAME\" TEXT, \"TYPE_NAME\" TEXT, \"LOT_NAME\" TEXT, \"SERIAL\" INTEGER, \
+             \"BIN\" INTEGER, \"PROBE\" TEXT, \"COUNT\" INTEGER, UNIQUE(\"LOT_NAME\", \"SERIAL\"))",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn upsert_sql_quotes_columns_and_updates_on_conflict() {
+        let row = ChipUpsert::new("M1", "T1", "L1", 7).int("BIN", 3).text("PROBE", None).increment("COUNT");
+        assert_eq!(
+            upsert_sql("PLC 1", &row, placeholder),
+            "INSERT INTO \"PLC 1\" (\"MACHINE_NAME\", \"TYPE_NAME\", \"LOT_NAME\", \"SERIAL\", \"BIN\", \"PROBE\", \"COUNT\") \
+             VALUES (?1, ?2, ?3, ?4, ?5, NULL, ?6) \
+             ON CONFLICT (\"LOT_NAME\", \"SERIAL\") DO UPDATE SET \"MACHINE_NAME\" = excluded.\"MACHINE_NAME\", \
+             \"TYPE_NAME\" = excluded.\"TYPE_NAME\", \"BIN\" = excluded.\"BIN\", \"PROBE\" = excluded.\"PROBE\", \
+             \"COUNT\" = COALESCE(\"PLC 1\".\"COUNT\", 0) + 1"
+        );
+        // NULLはプレースホルダーを使わないので、値はその分詰める
+        assert_eq!(
+            row.params(),
+            vec![
+                ColumnValue::Text("M1".to_string()),
+                ColumnValue::Text("T1".to_string()),
+                ColumnValue::Text("L1".to_string()),
+                ColumnValue::Int(7),
+                ColumnValue::Int(3),
+                ColumnValue::Int(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn upsert_sql_runs_on_sqlite() {
+        let conn = chip_table();
+        let first = ChipUpsert::new("M1", "T1", "L1", 7).int("BIN", 3).text("PROBE", "P-1").increment("COUNT");
+        let second = ChipUpsert::new("M2", "T1", "L1", 7).int("BIN", 4).text("PROBE", None).increment("COUNT");
+        for row in [&first, &second] {
+            conn.execute(&upsert_sql("PLC 1", row, placeholder), params_from_iter(row.params())).unwrap();
+        }
+        let (machine, bin, probe, count): (String, i64, Option<String>, i64) = conn
+            .query_row("SELECT MACHINE_NAME, BIN, PROBE, COUNT FROM \"PLC 1\"", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+            })
+            .unwrap();
+        // 2回目で上書きされ、受信しなかった項目はNULLになり、回数は加算される
+        assert_eq!((machine.as_str(), bin, probe, count), ("M2", 4, None, 2));
+    }
+
+    #[test]
+    fn insert_sql_writes_null_literally_and_ignores_conflicts() {
+        let row = HistoryInsert::new("PLC 1_alarms").text("LOT_NAME", "L1").int("SERIAL", None).int("ALARM_NUM", 5);
+        let sql = insert_sql(&row, placeholder);
+        assert_eq!(
+            sql,
+            "INSERT INTO \"PLC 1_alarms\" (\"LOT_NAME\", \"SERIAL\", \"ALARM_NUM\") VALUES (?1, NULL, ?2) ON CONFLICT DO NOTHING"
+        );
+        assert_eq!(row.params(), vec![ColumnValue::Text("L1".to_string()), ColumnValue::Int(5)]);
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE \"PLC 1_alarms\" (\"LOT_NAME\" TEXT, \"SERIAL\" INTEGER, \"ALARM_NUM\" INTEGER, \
+             UNIQUE(\"LOT_NAME\", \"ALARM_NUM\"))",
+        )
+        .unwrap();
+        assert_eq!(conn.execute(&sql, params_from_iter(row.params())).unwrap(), 1);
+        assert_eq!(conn.execute(&sql, params_from_iter(row.params())).unwrap(), 0);
+    }
+}
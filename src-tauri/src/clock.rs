@@ -0,0 +1,345 @@
+///拠点のタイムゾーンでの時刻の扱い
+///受信時刻の記録・シフトの集計は拠点のタイムゾーンで行い、PLCの日時は設定された書式とタイムゾーンで読んで
+///ISO 8601(オフセット付き)とエポックミリ秒に変換する。PLCの日時と受信時刻の差は時計のずれとして監視する
+use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::chip_store::{ChipWrite, ColumnValue};
+use crate::output_sink::IngestFrame;
+use crate::types::{default_plc_date_formats, Config, TimeConfig};
+
+/// 受信時刻・記録時刻の書式(拠点のタイムゾーン)
+pub const LOCAL_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// 時計のずれのイベント種別(machine_events の EVENT_TYPE)
+pub const MACHINE_EVENT_CLOCK_SKEW: &str = "clock_skew";
+pub const MACHINE_EVENT_CLOCK_SKEW_CLEARED: &str = "clock_skew_cleared";
+
+struct SiteClock {
+    timezone: Tz,
+    plc_timezone: Tz,
+    plc_date_formats: Vec<String>,
+}
+
+lazy_static! {
+    static ref SITE_CLOCK: parking_lot::RwLock<SiteClock> = parking_lot::RwLock::new(SiteClock {
+        timezone: chrono_tz::Asia::Tokyo,
+        plc_timezone: chrono_tz::Asia::Tokyo,
+        plc_date_formats: default_plc_date_formats(),
+    });
+}
+
+/// 設定のタイムゾーンと日時の書式を反映する(タイムゾーン名が不正な場合はErrで、設定は変えない)
+pub fn init(config: &TimeConfig) -> Result<(), String> {
+    let timezone: Tz = config
+        .timezone
+        .parse()
+        .map_err(|e| format!("Invalid timezone {}: {}", config.timezone, e))?;
+    let plc_timezone: Tz = match &config.plc_timezone {
+        Some(name) => name.parse().map_err(|e| format!("Invalid PLC timezone {}: {}", name, e))?,
+        None => timezone,
+    };
+    let mut clock = SITE_CLOCK.write();
+    clock.timezone = timezone;
+    clock.plc_timezone = plc_timezone;
+    clock.plc_date_formats = config.plc_date_formats.clone();
+    log::info!("Site timezone: {} (PLC: {})", timezone, plc_timezone);
+    Ok(())
+}
+
+fn site_timezone() -> Tz {
+    SITE_CLOCK.read().timezone
+}
+
+/// 拠点のタイムゾーンでの現在時刻
+pub fn now() -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&site_timezone()).fixed_offset()
+}
+
+/// 拠点のタイムゾーンの "YYYY-MM-DD hh:mm:ss" にする
+pub fn format_local(time: &DateTime<FixedOffset>) -> String {
+    time.with_timezone(&site_timezone()).format(LOCAL_FORMAT).to_string()
+}
+
+/// 現在時刻を ISO 8601(ミリ秒・オフセット付き)で返す(DBに記録する時刻はこの書式にそろえる)
+pub fn now_string() -> String {
+    to_iso(&now())
+}
+
+/// ISO 8601(ミリ秒・オフセット付き)にする
+pub fn to_iso(time: &DateTime<FixedOffset>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+/// 拠点のタイムゾーンでの日時(オフセット無し)にする
+pub fn local_naive(time: &DateTime<FixedOffset>) -> NaiveDateTime {
+    time.with_timezone(&site_timezone()).naive_local()
+}
+
+/// エポックミリ秒を拠点のタイムゾーンの時刻にする
+pub fn from_epoch_ms(epoch_ms: i64) -> Option<DateTime<FixedOffset>> {
+    let utc = DateTime::<Utc>::from_timestamp_millis(epoch_ms)?;
+    Some(utc.with_timezone(&site_timezone()).fixed_offset())
+}
+
+/// 拠点のタイムゾーンの日時文字列(オフセット付きのRFC 3339も可)を読む
+pub fn parse_local(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time);
+    }
+    let timezone = site_timezone();
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y/%m/%d %H:%M:%S%.f", "%Y%m%d%H%M%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|naive| localize(timezone, &naive))
+}
+
+/// PLCの日時を設定の書式とタイムゾーンで読み、拠点のタイムゾーンの時刻にする
+pub fn parse_plc_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&site_timezone()).fixed_offset());
+    }
+    let clock = SITE_CLOCK.read();
+    let naive = clock
+        .plc_date_formats
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())?;
+    let time = localize(clock.plc_timezone, &naive)?;
+    Some(time.with_timezone(&clock.timezone).fixed_offset())
+}
+
+/// タイムゾーンでの日時にする(夏時間の切り替えで重複する時刻は早い方、存在しない時刻は無効)
+fn localize(timezone: Tz, naive: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    timezone.from_local_datetime(naive).earliest().map(|time| time.fixed_offset())
+}
+
+/// PLCの日時を変換した結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlcTime {
+    /// ISO 8601(拠点のタイムゾーンのオフセット付き)
+    pub iso: String,
+    pub epoch_ms: i64,
+    /// PLCの日時 - 受信時刻(ms)
+    pub skew_ms: i64,
+}
+
+/// PLCの日時を受信時刻と合わせて変換する(読めない場合は None)
+pub fn plc_time(value: Option<&str>, received_at: &DateTime<FixedOffset>) -> Option<PlcTime> {
+    let time = parse_plc_date(value?)?;
+    Some(PlcTime {
+        iso: to_iso(&time),
+        epoch_ms: time.timestamp_millis(),
+        skew_ms: time.timestamp_millis() - received_at.timestamp_millis(),
+    })
+}
+
+/// 時計のずれが許容範囲を出入りした通知
+#[derive(Serialize, Debug, Clone)]
+pub struct ClockSkewReport {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub timestamp: String,
+    /// 受信時刻(エポックミリ秒)
+    pub received_at_ms: i64,
+    /// PLCの日時 - 受信時刻(ms)
+    pub skew_ms: i64,
+    pub max_skew_ms: i64,
+    /// true: 許容範囲を超えた / false: 許容範囲に戻った
+    pub exceeded: bool,
+}
+
+/// PLCの時計のずれの監視
+/// 日時付きのユニット情報(LDピックアップ・ULD挿入)ごとに受信時刻との差を求める
+pub struct ClockSkewMonitor {
+    max_skew_ms: i64,
+    /// PLC ID -> 許容範囲を超えているか
+    exceeded: HashMap<u32, bool>,
+    pending: Vec<ClockSkewReport>,
+    reports: UnboundedSender<ClockSkewReport>,
+}
+
+impl ClockSkewMonitor {
+    pub fn from_config(config: &Config, reports: UnboundedSender<ClockSkewReport>) -> Self {
+        ClockSkewMonitor {
+            max_skew_ms: (config.time.max_clock_skew_secs * 1000.0) as i64,
+            exceeded: HashMap::new(),
+            pending: Vec::new(),
+            reports,
+        }
+    }
+
+    /// 受信データの時計のずれ(差が最も大きいもの)を返す
+    /// 許容範囲を出入りした場合は通知を溜めておく
+    pub fn check(&mut self, input: &IngestFrame) -> Option<i64> {
+        let skew_ms = input
+            .writes
+            .iter()
+            .flatten()
+            .flatten()
+            .filter_map(|write| match write {
+                ChipWrite::Upsert(row) => Some(row),
+                ChipWrite::Insert(_) => None,
+            })
+            .flat_map(|row| &row.columns)
+            .filter_map(|(column, value)| match value {
+                ColumnValue::Int(v) if column.ends_with("_CLOCK_SKEW_MS") => Some(*v),
+                _ => None,
+            })
+            .max_by_key(|v| v.abs())?;
+
+        let exceeded = skew_ms.abs() > self.max_skew_ms;
+        let previous = self.exceeded.insert(input.plc_id, exceeded).unwrap_or(false);
+        if exceeded != previous {
+            self.pending.push(ClockSkewReport {
+                plc_id: input.plc_id,
+                table_name: input.table_name.clone(),
+                timestamp: input.timestamp.clone(),
+                received_at_ms: input.received_at.timestamp_millis(),
+                skew_ms,
+                max_skew_ms: self.max_skew_ms,
+                exceeded,
+            });
+        }
+        Some(skew_ms)
+    }
+
+    /// 溜めておいた通知を取り出す
+    pub fn take_reports(&mut self) -> Vec<ClockSkewReport> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// 通知をフロントエンドに送る
+    pub fn notify(&self, report: ClockSkewReport) {
+        // 受信側が無い場合(起動前・終了後)は通知しない
+        let _ = self.reports.send(report);
+    }
+}
+
+/// 時計のずれをフロントエンドへ通知する(plc-clock-skew イベント)
+pub async fn forward_reports(app: AppHandle, mut rx: UnboundedReceiver<ClockSkewReport>) {
+    while let Some(report) = rx.recv().await {
+        if let Err(e) = app.emit("plc-clock-skew", &report) {
+            log::error!("Failed to emit clock skew report: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    lazy_static! {
+        // タイムゾーンの設定は全体で共有するので、設定を変えるテストは順に実行する
+        static ref TEST_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+    }
+
+    fn with_timezones<F: FnOnce()>(timezone: &str, plc_timezone: Option<&str>, f: F) {
+        let _guard = TEST_LOCK.lock();
+        init(&TimeConfig {
+            timezone: timezone.to_string(),
+            plc_timezone: plc_timezone.map(str::to_string),
+            plc_date_formats: default_plc_date_formats(),
+            max_clock_skew_secs: 60.0,
+        })
+        .unwrap();
+        f();
+        init(&TimeConfig {
+            timezone: "Asia/Tokyo".to_string(),
+            plc_timezone: None,
+            plc_date_formats: default_plc_date_formats(),
+            max_clock_skew_secs: 60.0,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn now_string_is_iso_in_site_timezone() {
+        with_timezones("America/New_York", None, || {
+            let value = now_string();
+            let time = DateTime::parse_from_rfc3339(&value).unwrap();
+            assert_eq!(value, to_iso(&time));
+            assert_eq!(time.offset(), now().offset());
+            assert_eq!(value.len(), "2026-01-15T08:30:00.000-05:00".len());
+        });
+    }
+
+    #[test]
+    fn parse_plc_date_in_site_timezone() {
+        with_timezones("Asia/Tokyo", None, || {
+            for value in ["2026-01-15 08:30:00", "2026/01/15 08:30:00", "2026-01-15T08:30:00", "20260115083000"] {
+                let time = parse_plc_date(value).unwrap();
+                assert_eq!(to_iso(&time), "2026-01-15T08:30:00.000+09:00", "{}", value);
+            }
+            let time = parse_plc_date(" 2026-01-15 08:30:00.250 ").unwrap();
+            assert_eq!(time.timestamp_millis() % 1000, 250);
+            assert_eq!(parse_plc_date(""), None);
+            assert_eq!(parse_plc_date("15-01-2026"), None);
+        });
+    }
+
+    #[test]
+    fn parse_plc_date_converts_plc_timezone() {
+        // PLCの時計はUTC、拠点は東京
+        with_timezones("Asia/Tokyo", Some("UTC"), || {
+            let time = parse_plc_date("2026-01-15 08:30:00").unwrap();
+            assert_eq!(to_iso(&time), "2026-01-15T17:30:00.000+09:00");
+            // オフセット付きはPLCのタイムゾーンに関係なくそのまま読む
+            let time = parse_plc_date("2026-01-15T08:30:00+02:00").unwrap();
+            assert_eq!(to_iso(&time), "2026-01-15T15:30:00.000+09:00");
+        });
+        assert!(init(&TimeConfig {
+            timezone: "Mars/Olympus".to_string(),
+            plc_timezone: None,
+            plc_date_formats: default_plc_date_formats(),
+            max_clock_skew_secs: 60.0,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn daylight_saving_transitions() {
+        with_timezones("America/New_York", None, || {
+            // 夏時間の開始で存在しない時刻
+            assert_eq!(parse_plc_date("2026-03-08 02:30:00"), None);
+            // 夏時間の終了で重複する時刻は早い方(夏時間)
+            let time = parse_plc_date("2026-11-01 01:30:00").unwrap();
+            assert_eq!(to_iso(&time), "2026-11-01T01:30:00.000-04:00");
+            let time = parse_local("2026-07-01 12:00:00").unwrap();
+            assert_eq!(format_local(&time), "2026-07-01 12:00:00");
+            assert_eq!(time.offset().local_minus_utc(), -4 * 3600);
+        });
+    }
+
+    #[test]
+    fn plc_time_skew_against_received_at() {
+        with_timezones("Asia/Tokyo", None, || {
+            let received_at = parse_local("2026-01-15 08:30:05").unwrap();
+            let time = plc_time(Some("2026-01-15 08:30:00"), &received_at).unwrap();
+            assert_eq!(time.skew_ms, -5000);
+            assert_eq!(time.iso, "2026-01-15T08:30:00.000+09:00");
+            assert_eq!(time.epoch_ms, received_at.timestamp_millis() - 5000);
+            assert_eq!(plc_time(None, &received_at), None);
+            assert_eq!(plc_time(Some("not a date"), &received_at), None);
+        });
+    }
+
+    #[test]
+    fn epoch_ms_round_trip() {
+        with_timezones("Asia/Tokyo", None, || {
+            let time = parse_local("2026-01-15T08:30:00.123+09:00").unwrap();
+            let restored = from_epoch_ms(time.timestamp_millis()).unwrap();
+            assert_eq!(restored, time);
+            assert_eq!(format_local(&restored), "2026-01-15 08:30:00");
+        });
+    }
+}
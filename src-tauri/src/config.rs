@@ -3,12 +3,19 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 use tauri::command;
-use crate::types::{Config, PlcConfig};
+use crate::types::{default_plc_sinks, Config, PlcConfig};
 
 /// config.jsonを読み込んでPLC設定情報をフロントエンドに渡す
 #[command]
 pub async fn init_socket() -> Result<Vec<PlcConfig>, String> {
     // 実行ファイルのディレクトリからconfig.jsonを読み込む
+    let config = load_config()?;
+
+    Ok(config.plcs)
+}
+
+/// config.jsonを読み込んで設定全体を返す
+pub fn load_config() -> Result<Config, String> {
     let config_path = get_config_path()?;
 
     // デバッグ用: パスを出力
@@ -22,7 +29,7 @@ pub async fn init_socket() -> Result<Vec<PlcConfig>, String> {
     let config: Config = serde_json::from_str(&config_content)
         .map_err(|e| format!("Failed to parse config JSON: {}", e))?;
 
-    Ok(config.plcs)
+    Ok(config)
 }
 
 /// 設定ファイルのパスを取得
@@ -96,6 +103,8 @@ pub fn add_plc(
             plc_ip:plc_ip,
             plc_port:plc_port,
             pc_ip:pc_ip,
+            sinks:default_plc_sinks(),
+            schema:None,
         });
 
     //jsonに書き込み
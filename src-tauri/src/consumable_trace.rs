@@ -0,0 +1,262 @@
+///消耗品からのチップの逆引き(不良のプローブカード・ステージ・コレットの影響範囲の調査用)
+///登録されている全テーブルを UNION ALL でまとめて検索し、該当チップの一覧をCSVに出力できる
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::data_handler::{is_valid_identifier, open_read_connection, table_columns};
+use crate::db_query::configured_plcs;
+
+/// 省略時の最大件数
+const DEFAULT_LIMIT: usize = 10000;
+
+/// 消耗品の種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsumableKind {
+    /// プローブカード(*_PROBE_SERIAL が一致)
+    ProbeCard,
+    /// ステージ(*_STAGE_SERIAL が一致)
+    Stage,
+    /// コレット(*_COLLET の使用回数が min_count 以上)
+    Collet,
+}
+
+/// 逆引きの条件
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConsumableQuery {
+    pub kind: ConsumableKind,
+    /// プローブカード・ステージのシリアル
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// コレットの使用回数の下限
+    #[serde(default)]
+    pub min_count: Option<i64>,
+    /// ユニットの絞り込み(DC1, AC1 など。省略時は全ユニット)
+    #[serde(default)]
+    pub units: Option<Vec<String>>,
+    /// 時刻の範囲(LD_PICKUP_DATE、無い場合は ULD_PUT_DATE と文字列で比較する。to は含まない)
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// 該当チップ1件(複数のユニットで該当した場合はユニットごとに1件)
+#[derive(Serialize, Debug, Clone)]
+pub struct AffectedChip {
+    pub table_name: String,
+    pub machine_name: Option<String>,
+    pub type_name: Option<String>,
+    pub lot_name: Option<String>,
+    pub serial: Option<i64>,
+    pub pickup_date: Option<String>,
+    pub put_date: Option<String>,
+    /// 該当したカラム
+    pub column: String,
+    /// 該当したカラムの値(シリアルまたはコレット使用回数)
+    pub value: Option<String>,
+    /// 使用時点の使用回数(プローブカード・ステージ・コレット)
+    pub use_count: Option<i64>,
+}
+
+/// 逆引きの結果
+#[derive(Serialize, Debug, Clone)]
+pub struct ConsumableLookup {
+    pub kind: ConsumableKind,
+    pub chips: Vec<AffectedChip>,
+    /// limit で打ち切ったか
+    pub truncated: bool,
+    /// 該当したロット数
+    pub lot_count: usize,
+}
+
+/// 検索するカラムと、使用回数のカラム
+fn target_columns(query: &ConsumableQuery) -> Vec<(String, String)> {
+    let suffix = match query.kind {
+        ConsumableKind::ProbeCard => "_PROBE_SERIAL",
+        ConsumableKind::Stage => "_STAGE_SERIAL",
+        ConsumableKind::Collet => "_COLLET",
+    };
+    table_columns()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.ends_with(suffix))
+        .filter(|name| {
+            query.units.as_ref().map_or(true, |units| {
+                units.iter().any(|unit| name.starts_with(&format!("{}_", unit.to_ascii_uppercase())))
+            })
+        })
+        .map(|name| {
+            let count_column = match query.kind {
+                ConsumableKind::ProbeCard => name.replace("_PROBE_SERIAL", "_PROBE_COUNT"),
+                ConsumableKind::Stage => name.replace("_STAGE_SERIAL", "_STAGE_COUNT"),
+                ConsumableKind::Collet => name.clone(),
+            };
+            (name, count_column)
+        })
+        .collect()
+}
+
+/// 作成済みのテーブル(登録されていても未作成のテーブルは除く)
+pub fn existing_tables(conn: &Connection) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")
+        .map_err(|e| e.to_string())?;
+    let mut tables = Vec::new();
+    for plc in configured_plcs()? {
+        if is_valid_identifier(&plc.table_name) && !tables.contains(&plc.table_name) && stmt.exists([&plc.table_name]).map_err(|e| e.to_string())? {
+            tables.push(plc.table_name);
+        }
+    }
+    Ok(tables)
+}
+
+/// 全テーブルから該当チップを検索する
+pub fn lookup(conn: &Connection, query: &ConsumableQuery) -> Result<ConsumableLookup, String> {
+    let condition = match query.kind {
+        ConsumableKind::ProbeCard | ConsumableKind::Stage => {
+            let serial = query.serial.as_deref().filter(|s| !s.is_empty()).ok_or("serial is required")?;
+            ("= ?1", SqlValue::Text(serial.to_string()))
+        }
+        ConsumableKind::Collet => {
+            let min_count = query.min_count.ok_or("min_count is required")?;
+            (">= ?1", SqlValue::Integer(min_count))
+        }
+    };
+    let columns = target_columns(query);
+    if columns.is_empty() {
+        return Err("No columns match the given units".to_string());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).max(1);
+
+    // カラムごとに SELECT を分けて、シリアルのインデックスを使わせる
+    let mut selects = Vec::new();
+    for table in existing_tables(conn)? {
+        for (column, count_column) in &columns {
+            selects.push(format!(
+                "SELECT '{table}' AS TABLE_NAME, ID, MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, LD_PICKUP_DATE, ULD_PUT_DATE,
+                    '{column}' AS MATCHED_COLUMN, CAST(\"{column}\" AS TEXT) AS MATCHED_VALUE, \"{count_column}\" AS USE_COUNT,
+                    COALESCE(LD_PICKUP_DATE, ULD_PUT_DATE) AS EVENT_DATE
+                FROM {table}
+                WHERE \"{column}\" {op}
+                    AND (?2 IS NULL OR COALESCE(LD_PICKUP_DATE, ULD_PUT_DATE) >= ?2)
+                    AND (?3 IS NULL OR COALESCE(LD_PICKUP_DATE, ULD_PUT_DATE) < ?3)",
+                op = condition.0,
+            ));
+        }
+    }
+    if selects.is_empty() {
+        return Ok(ConsumableLookup {
+            kind: query.kind,
+            chips: Vec::new(),
+            truncated: false,
+            lot_count: 0,
+        });
+    }
+    let sql = format!(
+        "{} ORDER BY EVENT_DATE, TABLE_NAME, ID LIMIT ?4",
+        selects.join(" UNION ALL ")
+    );
+
+    let params = [
+        condition.1,
+        query.from.clone().map_or(SqlValue::Null, SqlValue::Text),
+        query.to.clone().map_or(SqlValue::Null, SqlValue::Text),
+        // 打ち切りを判定するため1件多く読む
+        SqlValue::Integer(limit as i64 + 1),
+    ];
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(AffectedChip {
+                table_name: row.get(0)?,
+                machine_name: row.get(2)?,
+                type_name: row.get(3)?,
+                lot_name: row.get(4)?,
+                serial: row.get(5)?,
+                pickup_date: row.get(6)?,
+                put_date: row.get(7)?,
+                column: row.get(8)?,
+                value: row.get(9)?,
+                use_count: row.get(10)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    let mut chips = rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())?;
+
+    let truncated = chips.len() > limit;
+    chips.truncate(limit);
+    let mut lots: Vec<(&str, Option<&str>)> = chips.iter().map(|c| (c.table_name.as_str(), c.lot_name.as_deref())).collect();
+    lots.sort();
+    lots.dedup();
+    let lot_count = lots.len();
+
+    Ok(ConsumableLookup {
+        kind: query.kind,
+        chips,
+        truncated,
+        lot_count,
+    })
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn csv_opt<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| csv_field(&v.to_string())).unwrap_or_default()
+}
+
+/// 該当チップの一覧をCSVにする(Excelで開けるようBOM付き)
+pub fn to_csv(result: &ConsumableLookup) -> String {
+    let mut csv = String::from("\u{feff}TABLE_NAME,MACHINE_NAME,TYPE_NAME,LOT_NAME,SERIAL,LD_PICKUP_DATE,ULD_PUT_DATE,COLUMN,VALUE,USE_COUNT\r\n");
+    for chip in &result.chips {
+        let fields = [
+            csv_field(&chip.table_name),
+            csv_opt(&chip.machine_name),
+            csv_opt(&chip.type_name),
+            csv_opt(&chip.lot_name),
+            csv_opt(&chip.serial),
+            csv_opt(&chip.pickup_date),
+            csv_opt(&chip.put_date),
+            csv_field(&chip.column),
+            csv_opt(&chip.value),
+            csv_opt(&chip.use_count),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+async fn run_lookup(query: ConsumableQuery) -> Result<ConsumableLookup, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        lookup(&conn, &query)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// 消耗品から該当チップを検索してフロントエンドに返す
+#[command]
+pub async fn find_chips_by_consumable(query: ConsumableQuery) -> Result<ConsumableLookup, String> {
+    run_lookup(query).await
+}
+
+/// 消耗品から該当チップを検索してCSVファイルに出力する(出力した件数を返す)
+#[command]
+pub async fn export_chips_by_consumable(query: ConsumableQuery, path: String) -> Result<usize, String> {
+    let result = run_lookup(query).await?;
+    std::fs::write(&path, to_csv(&result)).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    log::info!("Exported {} chips to {}", result.chips.len(), path);
+    Ok(result.chips.len())
+}
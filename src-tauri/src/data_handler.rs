@@ -1,36 +1,221 @@
 ///PLCから受け取ったデータのハンドラー
-use rusqlite::{Connection, Result};
+use chrono::{DateTime, FixedOffset};
+use rusqlite::types::ToSqlOutput;
+use rusqlite::{params_from_iter, Connection, OpenFlags, Result, ToSql};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use lazy_static::lazy_static;
-use tokio::sync::mpsc;
-use std::collections::HashMap;
-use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
 use std::env;
+use tauri::command;
 
-use crate::regist_data_to_db::*;
+use crate::chip_store::{insert_sql, upsert_sql, ChipStore, ChipUpsert, ColumnValue, HistoryInsert, StoreError};
+use crate::clock::{self, ClockSkewMonitor};
+use crate::consumable_trace::existing_tables;
+use crate::frame::parse_frame;
+use crate::output_sink::{IngestFrame, SinkSet};
+use crate::db_queue::{DbQueue, PushOutcome};
+use crate::replication;
+use crate::retention;
+use crate::regist_data_to_db::{alarm_table, build_writes};
+use crate::validation::{FrameValidator, QuarantineReport};
+use crate::limits::{LimitChecker, LimitViolation};
+use crate::lot_tracker::{self, LotChange, LotTracker};
+use crate::state::ConnectionState;
+use crate::types::{DbQueueConfig, DbWriterConfig};
 
 lazy_static! {
     static ref DB_CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+    static ref WRITER_METRICS: parking_lot::Mutex<WriterMetrics> = parking_lot::Mutex::new(WriterMetrics::new());
+    static ref WRITER_STOPPED: Mutex<Option<std::sync::mpsc::Receiver<()>>> = Mutex::new(None);
 }
 
 //テーブルを作成するためのsql文を読み込み
 static CREATE_TABLE_SQL:&str = include_str!("sql/create_table.sql");
+static CREATE_QUARANTINE_SQL:&str = include_str!("sql/create_quarantine.sql");
+static CREATE_LIMIT_VIOLATIONS_SQL:&str = include_str!("sql/create_limit_violations.sql");
+static CREATE_CONSUMABLE_INDEXES_SQL:&str = include_str!("sql/create_consumable_indexes.sql");
+static CREATE_ALARM_TABLE_SQL:&str = include_str!("sql/create_alarm_table.sql");
+static CREATE_HISTORY_TABLE_SQL:&str = include_str!("sql/create_history_table.sql");
+static CREATE_MACHINE_EVENTS_SQL:&str = include_str!("sql/create_machine_events.sql");
+static CREATE_LOTS_SQL:&str = include_str!("sql/create_lots.sql");
+
+//スループットを計算する集計期間
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+
+//メトリクスをログに出力する間隔
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+//SQLiteの再送を待つ間に終了処理の開始を確認する間隔
+const BACKOFF_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+//レイテンシヒストグラムのバケット上限(ms)
+pub const LATENCY_BUCKETS_MS: [f64; 12] = [1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+//読み出し用接続でDBロック解除を待つ最大時間
+const READ_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// DB書き込みリクエストの構造体
-#[derive(Debug, Clone)]
+/// Spill時はJSONとしてファイルに退避する
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DbWriteRequest {
     pub plc_id: u32,
     pub table_name: String,
     pub timestamp: String,
+    /// 受信時刻(エポックミリ秒、以前のバージョンで退避したものは0)
+    #[serde(default)]
+    pub received_at_ms: i64,
     pub message: String,
+    /// 書き込む出力先を限定する場合の出力先名(SQLiteに書けなかった分を退避したものなど)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sinks: Option<Vec<String>>,
+    /// 受信データではなく設備イベント(接続・切断)の記録の場合
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<MachineEvent>,
+    #[serde(skip, default = "Instant::now")]
+    pub enqueued_at: Instant,
+}
+
+/// 受信データ以外の設備イベント(machine_events の EVENT_TYPE・CODE)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MachineEvent {
+    pub event_type: String,
+    pub code: Option<i64>,
+}
+
+impl DbWriteRequest {
+    /// 受信時刻(以前のバージョンで退避したリクエストは受信時刻の文字列から求める)
+    fn received_at(&self) -> DateTime<FixedOffset> {
+        Some(self.received_at_ms)
+            .filter(|ms| *ms > 0)
+            .and_then(clock::from_epoch_ms)
+            .or_else(|| clock::parse_local(&self.timestamp))
+            .unwrap_or_else(clock::now)
+    }
+
+    /// SQLiteに書けなかった受信データを、SQLiteにだけ書き戻すリクエストにする
+    fn sqlite_retry(input: &IngestFrame) -> Self {
+        DbWriteRequest {
+            plc_id: input.plc_id,
+            table_name: input.table_name.clone(),
+            timestamp: input.timestamp.clone(),
+            received_at_ms: input.received_at.timestamp_millis(),
+            message: input.message.clone(),
+            sinks: Some(vec![SQLITE_SINK.to_string()]),
+            event: None,
+            enqueued_at: Instant::now(),
+        }
+    }
+}
+
+/// DB書き込みスレッドのメトリクス(フロントエンド返却用)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DbWriterMetrics {
+    pub total_requests: u64,
+    pub failed_requests: u64,
+    pub total_batches: u64,
+    pub failed_commits: u64,
+    pub last_batch_size: usize,
+    pub max_batch_size: usize,
+    pub avg_batch_size: f64,
+    pub throughput_per_sec: f64,
+    pub last_commit_ms: f64,
+    pub avg_commit_ms: f64,
+    pub max_commit_ms: f64,
+    pub avg_queue_latency_ms: f64,
+    pub max_queue_latency_ms: f64,
+}
+
+/// レイテンシのヒストグラム(バケットは LATENCY_BUCKETS_MS、countsは各バケット単独の件数)
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    pub counts: [u64; LATENCY_BUCKETS_MS.len()],
+    pub overflow: u64,
+    pub sum_ms: f64,
+    pub count: u64,
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, value_ms: f64) {
+        match LATENCY_BUCKETS_MS.iter().position(|bound| value_ms <= *bound) {
+            Some(i) => self.counts[i] += 1,
+            None => self.overflow += 1,
+        }
+        self.sum_ms += value_ms;
+        self.count += 1;
+    }
+}
+
+/// メトリクスの内部集計用
+struct WriterMetrics {
+    snapshot: DbWriterMetrics,
+    commit_histogram: LatencyHistogram,
+    queue_latency_histogram: LatencyHistogram,
+    total_commit_ms: f64,
+    total_queue_latency_ms: f64,
+    window_start: Instant,
+    window_count: u64,
+}
+
+impl WriterMetrics {
+    fn new() -> Self {
+        WriterMetrics {
+            snapshot: DbWriterMetrics::default(),
+            commit_histogram: LatencyHistogram::default(),
+            queue_latency_histogram: LatencyHistogram::default(),
+            total_commit_ms: 0.0,
+            total_queue_latency_ms: 0.0,
+            window_start: Instant::now(),
+            window_count: 0,
+        }
+    }
+
+    /// 1バッチ分の結果を集計する
+    fn record_batch(&mut self, batch_size: usize, failed: u64, commit_ok: bool, commit_ms: f64, queue_latencies_ms: &[f64]) {
+        let m = &mut self.snapshot;
+        m.total_requests += batch_size as u64;
+        m.failed_requests += failed;
+        m.total_batches += 1;
+        if !commit_ok {
+            m.failed_commits += 1;
+        }
+        m.last_batch_size = batch_size;
+        m.max_batch_size = m.max_batch_size.max(batch_size);
+        m.avg_batch_size = m.total_requests as f64 / m.total_batches as f64;
+
+        m.last_commit_ms = commit_ms;
+        m.max_commit_ms = m.max_commit_ms.max(commit_ms);
+        self.total_commit_ms += commit_ms;
+        m.avg_commit_ms = self.total_commit_ms / m.total_batches as f64;
+        self.commit_histogram.observe(commit_ms);
+
+        for latency in queue_latencies_ms {
+            self.total_queue_latency_ms += latency;
+            m.max_queue_latency_ms = m.max_queue_latency_ms.max(*latency);
+            self.queue_latency_histogram.observe(*latency);
+        }
+        if m.total_requests > 0 {
+            m.avg_queue_latency_ms = self.total_queue_latency_ms / m.total_requests as f64;
+        }
+
+        //直近の集計期間でスループットを計算
+        self.window_count += batch_size as u64;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= THROUGHPUT_WINDOW {
+            m.throughput_per_sec = self.window_count as f64 / elapsed.as_secs_f64();
+            self.window_start = Instant::now();
+            self.window_count = 0;
+        }
+    }
 }
 
 /// データベースを初期化し、DB書き込み専用スレッドを起動する
-/// チャネルの送信側を返すので、各スレッドで clone して使用する
-pub fn init_database() -> Result<mpsc::UnboundedSender<DbWriteRequest>> {
-    let path:String=env::var("DB_PATH").unwrap_or("C:\\Users\\takahashi\\Desktop\\chiptest.db".to_string());
-    let db_path=PathBuf::from(path);
+/// 書き込みキューを返すので、各スレッドで clone して使用する
+#[allow(clippy::too_many_arguments)]
+pub fn init_database(writer_config: DbWriterConfig, queue_config: DbQueueConfig, sinks: SinkSet, validator: FrameValidator, limits: LimitChecker, mut lots: LotTracker, clock_skew: ClockSkewMonitor, connection_state: ConnectionState, replication_enabled: bool) -> Result<Arc<DbQueue>> {
+    let db_path=get_db_path();
 
     // ディレクトリが存在しない場合は作成
     if let Some(parent) = db_path.parent() {
@@ -39,196 +224,736 @@ pub fn init_database() -> Result<mpsc::UnboundedSender<DbWriteRequest>> {
 
     let conn = Connection::open(&db_path)?;
 
+    // WALモードにして書き込み中でも読み出しをブロックしないようにする
+    let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.set_prepared_statement_cache_capacity(writer_config.statement_cache_capacity);
+
+    // 検証に失敗した受信データの退避先
+    conn.execute_batch(CREATE_QUARANTINE_SQL)?;
+    // 規格外の測定値の記録先
+    conn.execute_batch(CREATE_LIMIT_VIOLATIONS_SQL)?;
+    // チップに紐付かない設備のアラームの記録先
+    create_event_table(&conn, "machine_events", CREATE_MACHINE_EVENTS_SQL)?;
+    // ロットの履歴(前回の起動時に実行中だったロットは引き継ぐ)
+    conn.execute_batch(CREATE_LOTS_SQL)?;
+    lots.restore(&conn)?;
+    // 以前のバージョンで作成したPLCごとのテーブルに、後から追加したカラムを足す(接続前の読み出しにも備える)
+    match existing_tables(&conn) {
+        Ok(tables) => {
+            for table_name in tables {
+                add_missing_columns(&conn, &table_name)?;
+                create_alarm_table(&conn, &table_name)?;
+            }
+        }
+        Err(e) => log::warn!("Failed to list tables to migrate: {}", e),
+    }
+
+    // 中央サーバーへの複製用の変更ログを準備
+    replication::init_schema(&conn, replication_enabled)?;
+
     // データベース接続をグローバルに保存
     let mut db = DB_CONNECTION.lock().unwrap();
     *db = Some(conn);
 
-    log::info!("Database initialized at: {:?}", db_path);
+    log::info!("Database initialized at: {:?} (journal_mode={})", db_path, journal_mode);
 
-    // DB書き込み専用スレッドを起動し、チャネルの送信側を返す
-    let tx = start_db_writer_thread();
+    // DB書き込み専用スレッドを起動し、書き込みキューを返す
+    let queue = Arc::new(DbQueue::new(&queue_config));
+    start_db_writer_thread(writer_config, Arc::clone(&queue), sinks, validator, limits, lots, clock_skew, connection_state);
+
+    Ok(queue)
+}
+
+/// DBファイルのパスを取得する(環境変数 DB_PATH で上書き可能)
+pub fn get_db_path() -> PathBuf {
+    let path:String=env::var("DB_PATH").unwrap_or("C:\\Users\\takahashi\\Desktop\\chiptest.db".to_string());
+    PathBuf::from(path)
+}
+
+/// 読み出し専用の接続を開く
+/// WALモードなので書き込みスレッドと並行して読み出せる
+/// 保管用DBをATTACHしている場合は保管分も合わせて読める
+pub fn open_read_connection() -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        get_db_path(),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.busy_timeout(READ_BUSY_TIMEOUT)?;
+    retention::attach_to(&conn)?;
+    Ok(conn)
+}
+
+/// テーブル名・カラム名としてSQLに埋め込んでよい文字列か(英数字とアンダースコアのみ)
+pub fn is_valid_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// create_table.sql からカラム名と型の一覧を取り出す
+pub fn table_columns() -> Vec<(String, String)> {
+    parse_columns(CREATE_TABLE_SQL)
+}
+
+/// CREATE TABLE 文からカラム名と型の一覧を取り出す(1行1カラムで書かれていること)
+pub fn parse_columns(create_sql: &str) -> Vec<(String, String)> {
+    create_sql
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if !line.starts_with('"') {
+                return None;
+            }
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.trim_matches('"').to_string();
+            let sql_type = parts.next()?.trim_end_matches(',').to_string();
+            Some((name, sql_type))
+        })
+        .collect()
+}
+
+/// 以前のバージョンで作成したテーブルに、create_table.sql で追加されたカラムを足す
+/// テーブルが無い場合は何もしない
+fn add_missing_columns(conn: &Connection, table_name: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{}\")", table_name))?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<HashSet<String>>>()?;
+    if existing.is_empty() {
+        return Ok(());
+    }
+    for (name, sql_type) in table_columns() {
+        if !existing.contains(&name) {
+            conn.execute_batch(&format!("ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}", table_name, name, sql_type))?;
+            log::info!("Added column {} to {}", name, table_name);
+        }
+    }
+    Ok(())
+}
+
+/// UNIQUE制約の受信時刻を秒からエポックミリ秒(RECEIVED_EPOCH_MS)に変えたテーブルを、以前のバージョンで作成したものから作り直す
+/// SQLiteでは制約を変更できないので、名前を変えて作成し直してから行を移す(インデックスは移した後に作り直す)
+/// テーブルが無い場合・作り直し済みの場合は何もしない
+fn rebuild_with_epoch_key(conn: &Connection, table_name: &str, create_sql: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{}\")", table_name))?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>>>()?;
+    if existing.is_empty() || existing.iter().any(|name| name == "RECEIVED_EPOCH_MS") {
+        return Ok(());
+    }
+    let names: Vec<String> = existing.iter().map(|name| format!("\"{}\"", name)).collect();
+    let names = names.join(", ");
+    conn.execute_batch(&format!(
+        "SAVEPOINT rebuild;
+        ALTER TABLE \"{table_name}\" RENAME TO \"{table_name}_old\";
+        {create_sql}
+        INSERT INTO \"{table_name}\" ({names}) SELECT {names} FROM \"{table_name}_old\";
+        DROP TABLE \"{table_name}_old\";
+        {create_sql}
+        RELEASE rebuild;"
+    ))?;
+    log::info!("Rebuilt {} with millisecond unique key", table_name);
+    Ok(())
+}
 
-    tx
+/// CREATE TABLE 文の後にある CREATE UNIQUE INDEX の名前とキーの式を取り出す(1行で書かれていること)
+fn parse_unique_index(create_sql: &str) -> Option<(String, String)> {
+    let line = create_sql.lines().map(str::trim).find(|line| line.starts_with("CREATE UNIQUE INDEX"))?;
+    let name = line.split('"').nth(1)?.to_string();
+    let keys = line.get(line.find('(')? + 1..line.rfind(')')?)?.to_string();
+    Some((name, keys))
+}
+
+/// UNIQUEインデックスを作る前に、それまでに登録された重複行を削除する(最も古い行を残す)
+/// 以前はNULLを含むUNIQUE制約だったため、UNIT・CODE・SERIAL が無い行は ON CONFLICT DO NOTHING でも重複していた
+/// 受信時刻(エポックミリ秒)の無い以前のバージョンの行は対象外
+/// テーブルが無い場合・インデックス作成済みの場合は何もしない
+fn remove_duplicate_keys(conn: &Connection, table_name: &str, create_sql: &str) -> Result<()> {
+    let Some((index_name, keys)) = parse_unique_index(create_sql) else {
+        return Ok(());
+    };
+    let exists = |kind: &str, name: &str| -> Result<bool> {
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = ?1 AND name = ?2)",
+            [kind, name],
+            |row| row.get(0),
+        )
+    };
+    if !exists("table", table_name)? || exists("index", &index_name)? {
+        return Ok(());
+    }
+    let deleted = conn.execute(
+        &format!(
+            "DELETE FROM \"{table_name}\" WHERE \"RECEIVED_EPOCH_MS\" IS NOT NULL AND \"ID\" NOT IN (
+                SELECT MIN(\"ID\") FROM \"{table_name}\" WHERE \"RECEIVED_EPOCH_MS\" IS NOT NULL GROUP BY {keys}
+            )"
+        ),
+        [],
+    )?;
+    if deleted > 0 {
+        log::warn!("Removed {} duplicate rows from {}", deleted, table_name);
+    }
+    Ok(())
+}
+
+/// 受信時刻をキーに含むイベントのテーブル(アラーム履歴・設備イベント)を作成する
+/// 以前のバージョンで作成したものは作り直し、重複行を削除してからUNIQUEインデックスを作る
+fn create_event_table(conn: &Connection, table_name: &str, create_sql: &str) -> Result<()> {
+    rebuild_with_epoch_key(conn, table_name, create_sql)?;
+    remove_duplicate_keys(conn, table_name, create_sql)?;
+    conn.execute_batch(create_sql)
+}
+
+/// PLCのアラーム履歴のテーブルを作成する(create_event_table)
+fn create_alarm_table(conn: &Connection, table_name: &str) -> Result<()> {
+    let alarm_sql = CREATE_ALARM_TABLE_SQL.replace("{TABLE_NAME}", table_name);
+    create_event_table(conn, &alarm_table(table_name), &alarm_sql)
 }
 
 /// DB書き込み専用スレッドを起動する
-/// チャネルの送信側を返すので、呼び出し側で clone して使用する
-fn start_db_writer_thread() -> Result<mpsc::UnboundedSender<DbWriteRequest>,rusqlite::Error> {
-    let (tx, mut rx) = mpsc::unbounded_channel::<DbWriteRequest>();
+/// キューからリクエストを取り出して検証し、PLCごとに設定された出力先に書き込む
+/// 書き込んだ測定値は規格と照合し、LOT・TYPE の切り替わりでロットの開始・完了を記録する
+/// PLCの日時と受信時刻の差が許容範囲を出入りしたら machine_events に記録する
+/// パース失敗・検証失敗・規格違反・DB書き込み失敗はPLCごとの統計に加算する
+#[allow(clippy::too_many_arguments)]
+fn start_db_writer_thread(writer_config: DbWriterConfig, queue: Arc<DbQueue>, mut sinks: SinkSet, mut validator: FrameValidator, mut limits: LimitChecker, mut lots: LotTracker, mut clock_skew: ClockSkewMonitor, connection_state: ConnectionState) {
+    // スレッドの停止を終了処理に知らせるためのチャネル
+    let (stopped_tx, stopped_rx) = std::sync::mpsc::channel::<()>();
+    *WRITER_STOPPED.lock().unwrap() = Some(stopped_rx);
 
     // DB書き込み専用スレッドを起動
     std::thread::spawn(move || {
-        log::info!("DB writer thread started");
-
-        while let Some(request) = rx.blocking_recv() {
-            // 受信データをログ出力
-            log::info!(
-                "Received PLC data - ID: {}, Size: {} bytes",
-                request.plc_id,
-                request.message.len()
-            );
-            log::debug!("PLC data content: {}", request.message);
+        log::info!(
+            "DB writer thread started (batch_size={}, max_latency_ms={})",
+            writer_config.batch_size,
+            writer_config.max_latency_ms
+        );
 
-            let table_name = request.table_name;
+        let max_latency = Duration::from_millis(writer_config.max_latency_ms);
+        let mut last_metrics_log = Instant::now();
+
+        // 最初の1件が届くまで待機し、以降はキューに溜まっている分をまとめて1バッチ(SQLiteは1トランザクション)で書き込む
+        loop {
+            // SQLiteの再送待ちがある間は次に試すまで新しいリクエストを取り出さない
+            // (その間にキューが満杯になったら設定されたポリシーに従う)
+            while let Some(until) = sinks.sqlite_backoff() {
+                if queue.is_closed() {
+                    break;
+                }
+                std::thread::sleep(until.saturating_duration_since(Instant::now()).min(BACKOFF_POLL_INTERVAL));
+            }
+            let Some(first) = queue.pop_blocking() else {
+                break;
+            };
+            sinks.begin_batch();
 
-            let db = DB_CONNECTION.lock().unwrap();
-            if let Some(conn) = db.as_ref() {
-                if let Err(e) = conn.execute("BEGIN TRANSACTION",[]) {
-                    log::error!("Failed to begin transaction: {}", e);
+            let batch_start = Instant::now();
+            let mut batch_size = 0;
+            let mut failed = 0;
+            let mut queue_latencies_ms = Vec::new();
+            // PLC ID -> (リクエスト数, パース失敗数)
+            let mut plc_requests: HashMap<u32, (u64, u64)> = HashMap::new();
+            // 検証に失敗した受信データ(コミット後に退避する)
+            let mut quarantined = Vec::new();
+            // 規格外の測定値(コミット後に記録する)
+            let mut violations = Vec::new();
+            // PLC ID -> 直近の時計のずれ(ms)
+            let mut clock_skews: HashMap<u32, i64> = HashMap::new();
+            // 接続・切断などの設備イベント(コミット後に記録する)
+            let mut events = Vec::new();
+            let mut next = Some(first);
+
+            while let Some(mut request) = next.take() {
+                batch_size += 1;
+                if let Some(event) = request.event.take() {
+                    queue_latencies_ms.push(request.enqueued_at.elapsed().as_secs_f64() * 1000.0);
+                    events.push((request.received_at(), request.table_name, event));
+                    if batch_size >= writer_config.batch_size || batch_start.elapsed() >= max_latency {
+                        break;
+                    }
+                    next = queue.try_pop();
                     continue;
                 }
-                //PLCから受信したjson形式データをhashmapに変換する
-                let recv_data: HashMap<String, Value> = serde_json::from_str(&request.message).unwrap();
-                
-                //ロット番号のとりだし
-                let lot_name = recv_data
-                    .get("LOT")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("unknown");
-
-                //機種名のとりだし
-                let type_name = recv_data
-                    .get("TYPE")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("unknown");
-
-                //装置名のとりだし
-                let machine_name = recv_data
-                    .get("MACHINE")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("unknown");
-                
-                //各ユニット情報の取り出し
-                for (key,value) in &recv_data{
-                    if key.contains("U1_TR"){ //LD TRAYデータを登録
-                        match regist_u1_tr_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register tray data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        };
-                    }else if key.contains("_A1_"){ //上流アームコレットの使用回数データを登録
-                        //アームのユニット名を取得
-                        let unit_name = match key.split('_').next() {
-                            Some(v) => v,
-                            None => continue,  // ここで continue が使える！
-                        };
-                        match regist_arm1_info(&conn,&table_name,&machine_name,&lot_name,&type_name,&unit_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register arm1 data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("_A2_"){ //下流アームコレットの使用回数データを登録
-                        //アームのユニット名を取得
-                        let unit_name = match key.split('_').next() {
-                            Some(v) => v,
-                            None => continue,  // ここで continue が使える！
-                        };
-                        match regist_arm2_info(&conn,&table_name,&machine_name,&lot_name,&type_name,&unit_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register arm2 data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("_PH_"){ //DC1,ULD予熱テーブルのデータを登録
-                        //アームのユニット名を取得
-                        let unit_name = match key.split('_').next() {
-                            Some(v) => v,
-                            None => continue,  // ここで continue が使える！
-                        };
-                        match regist_ph_info(&conn,&table_name,&machine_name,&lot_name,&type_name,&unit_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register preheat data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("_TS_") && !key.contains("U6"){ //DC1~DC2検査テーブルのデータを登録
-                        //ユニット名を取得
-                        let unit_name = match key.split('_').next() {
-                            Some(v) => v,
-                            None => continue,  // ここで continue が使える！
-                        };
-                        match regist_ts_info(&conn,&table_name,&machine_name,&lot_name,&type_name,&unit_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register teststage data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("_TS_") && key.contains("U6"){ //IP検査テーブルのデータを登録
-                        match regist_ip_ts_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register IP teststage data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("U6_T1_"){ //IP表面検査のBINデータを登録
-                        match regist_ip_surf_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register IP surface data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("U6_T2_"){ //IP裏面検検のBINデータを登録
-                        match regist_ip_back_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register IP back data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("U7_PI_"){ //ULDポケット認識時のデータを登録
-                        match regist_uld_pocket_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register ULD pocket data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("U7_CI_"){ //ULDポケット挿入時のデータを登録
-                        match regist_uld_chip_info(&conn,&table_name,&machine_name,&lot_name,&type_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register ULD chip data: {}", e);
-                                continue;
-                            },
-                            _=>{}
-                        }
-                    }else if key.contains("_AL_"){ //アラーム情報の登録
-                        //ユニット名を取得
-                        let unit_name = match key.split('_').next() {
-                            Some(v) => v,
-                            None => continue,  // ここで continue が使える！
-                        };
-                        match regist_alarm_info(&conn,&table_name,&machine_name,&lot_name,&type_name,&unit_name,value){
-                            Err(e)=>{
-                                log::error!("Failed to register alarm data: {}", e);
-                                continue;
-                            },
-                            _=>{}
+                let counts = plc_requests.entry(request.plc_id).or_default();
+                counts.0 += 1;
+                match parse_request(&request) {
+                    Ok(input) => match validator.validate(&input, &request.message) {
+                        Ok(()) => {
+                            violations.extend(limits.check(&input));
+                            if let Some(skew_ms) = clock_skew.check(&input) {
+                                clock_skews.insert(input.plc_id, skew_ms);
+                            }
+                            lots.observe(&input);
+                            sinks.write(input);
                         }
+                        Err(report) => quarantined.push((report, request.message.clone())),
+                    },
+                    Err(e) => {
+                        log::error!("Failed to register data for PLC ID {}: {}", request.plc_id, e);
+                        counts.1 += 1;
+                        failed += 1;
+                        // 分解できなかった受信データも後から確認できるよう退避する
+                        let report = QuarantineReport::parse_failure(request.plc_id, &request.table_name, &clock::to_iso(&request.received_at()), e);
+                        quarantined.push((Box::new(report), request.message.clone()));
                     }
                 }
-                if let Err(e) = conn.execute("COMMIT",[]) {
-                    log::error!("Failed to commit transaction: {}", e);
+                queue_latencies_ms.push(request.enqueued_at.elapsed().as_secs_f64() * 1000.0);
+
+                // バッチサイズか待ち時間の上限に達したらコミットする
+                if batch_size >= writer_config.batch_size || batch_start.elapsed() >= max_latency {
+                    break;
+                }
+                next = queue.try_pop();
+            }
+
+            let commit_start = Instant::now();
+            let report = sinks.end_batch();
+            // 書き戻した退避データはSQLiteの再送待ちに移っていても書き込み済みとして扱う(終了時に退避し直す)
+            queue.commit_replayed();
+            let commit_ms = commit_start.elapsed().as_secs_f64() * 1000.0;
+            let commit_ok = !report.failed_sinks.contains(&SQLITE_SINK);
+            if commit_ok {
+                log::debug!("DB write completed: {} requests in batch", batch_size);
+            }
+            // SQLiteの再送待ちが保持件数を超えた分は退避ファイルに書き出す
+            if !report.overflow.is_empty() {
+                log::warn!("SQLite retry buffer full, {} requests spilled", report.overflow.len());
+                spill_sqlite_retry(&queue, &report.overflow);
+            }
+
+            // 検証に失敗した受信データを退避してフロントエンドに通知する
+            for (report, message) in quarantined {
+                match report.issues.first() {
+                    Some(first) => log::warn!(
+                        "Frame from PLC ID {} quarantined ({} issues, first: {}: {})",
+                        report.plc_id,
+                        report.issues.len() + report.omitted,
+                        first.path,
+                        first.message
+                    ),
+                    None => log::warn!("Frame from PLC ID {} quarantined", report.plc_id),
+                }
+                if let Err(e) = save_quarantine(&report, &message) {
+                    log::error!("Failed to save quarantined frame from PLC ID {}: {}", report.plc_id, e);
+                }
+                if let Some(conn) = connection_state.lock().get_mut(&report.plc_id) {
+                    conn.stats.quarantined += 1;
+                }
+                validator.notify(*report);
+            }
+
+            // 規格外の測定値を記録してフロントエンドに通知する
+            if !violations.is_empty() {
+                if let Err(e) = save_limit_violations(&violations) {
+                    log::error!("Failed to save {} limit violations: {}", violations.len(), e);
+                }
+                let mut connections = connection_state.lock();
+                for violation in violations {
+                    log::warn!(
+                        "Limit violation on {} (lot {}, serial {}): {} {}",
+                        violation.table_name,
+                        violation.lot,
+                        violation.serial,
+                        violation.column,
+                        violation.detail
+                    );
+                    if let Some(conn) = connections.get_mut(&violation.plc_id) {
+                        conn.stats.limit_violations += 1;
+                    }
+                    limits.notify(violation);
+                }
+            }
+
+            // ロットの開始・完了を記録してフロントエンドに通知する
+            let mut lot_changes = lots.take_changes();
+            if !lot_changes.is_empty() {
+                if let Err(e) = save_lot_changes(&mut lot_changes) {
+                    log::error!("Failed to save {} lot changes: {}", lot_changes.len(), e);
+                }
+                for change in lot_changes {
+                    lots.notify(change);
+                }
+            }
+
+            // 接続・切断を記録する(稼働率の集計用)
+            for (received_at, table_name, event) in events {
+                if let Err(e) = record_machine_event(&table_name, &received_at, &event.event_type, event.code) {
+                    log::error!("Failed to record {} event for {}: {}", event.event_type, table_name, e);
+                }
+            }
+
+            // 時計のずれが許容範囲を出入りしたら記録してフロントエンドに通知する
+            for report in clock_skew.take_reports() {
+                let event_type = if report.exceeded {
+                    log::warn!(
+                        "Clock of PLC ID {} is off by {} ms (limit {} ms)",
+                        report.plc_id,
+                        report.skew_ms,
+                        report.max_skew_ms
+                    );
+                    clock::MACHINE_EVENT_CLOCK_SKEW
                 } else {
-                    log::info!("DB write completed for PLC ID: {}", request.plc_id);
+                    log::info!("Clock of PLC ID {} is back within limit ({} ms)", report.plc_id, report.skew_ms);
+                    clock::MACHINE_EVENT_CLOCK_SKEW_CLEARED
+                };
+                let received_at = clock::from_epoch_ms(report.received_at_ms).unwrap_or_else(clock::now);
+                if let Err(e) = record_machine_event(&report.table_name, &received_at, event_type, Some(report.skew_ms)) {
+                    log::error!("Failed to record clock skew of PLC ID {}: {}", report.plc_id, e);
                 }
-            } else {
-                log::error!("DB connection not available for PLC ID: {}", request.plc_id);
+                clock_skew.notify(report);
+            }
+
+            // SQLiteに登録できなかった件数をPLCごとのDB書き込み失敗として数える
+            // (コミットに失敗した分は再送されるので、再送を諦めた時点で数える)
+            {
+                let mut connections = connection_state.lock();
+                for (plc_id, (_, parse_failures)) in &plc_requests {
+                    if let Some(conn) = connections.get_mut(plc_id) {
+                        conn.stats.parse_failures += parse_failures;
+                    }
+                }
+                for (plc_id, skew_ms) in &clock_skews {
+                    if let Some(conn) = connections.get_mut(plc_id) {
+                        conn.stats.clock_skew_ms = Some(*skew_ms);
+                    }
+                }
+                for ((sink, plc_id), count) in &report.failures {
+                    if *sink != SQLITE_SINK {
+                        continue;
+                    }
+                    failed += 1;
+                    if let Some(conn) = connections.get_mut(plc_id) {
+                        conn.stats.db_failures += count;
+                    }
+                }
+            }
+
+            WRITER_METRICS.lock().record_batch(batch_size, failed, commit_ok, commit_ms, &queue_latencies_ms);
+
+            if last_metrics_log.elapsed() >= METRICS_LOG_INTERVAL {
+                let m = get_writer_metrics_snapshot();
+                log::info!(
+                    "DB writer metrics - total: {}, failed: {}, batches: {}, avg batch: {:.1}, throughput: {:.1}/s, avg commit: {:.2}ms, avg latency: {:.2}ms",
+                    m.total_requests,
+                    m.failed_requests,
+                    m.total_batches,
+                    m.avg_batch_size,
+                    m.throughput_per_sec,
+                    m.avg_commit_ms,
+                    m.avg_queue_latency_ms
+                );
+                last_metrics_log = Instant::now();
             }
         }//<-threadの終端
 
+        // SQLiteに書けずに残っている再送待ちは次回起動時に書き戻す
+        let retry = sinks.take_sqlite_retry();
+        if !retry.is_empty() {
+            log::warn!("{} requests not yet written to SQLite, spilled for next start", retry.len());
+            spill_sqlite_retry(&queue, &retry);
+        }
+
         log::warn!("DB writer thread stopped");
+        stopped_tx.send(()).ok();
     });
+}
+
+/// SQLiteに書けなかった受信データを、SQLiteにだけ書き戻すよう退避ファイルに書き出す
+fn spill_sqlite_retry(queue: &DbQueue, inputs: &[Arc<IngestFrame>]) {
+    for input in inputs {
+        if let Err(e) = queue.spill(&DbWriteRequest::sqlite_retry(input)) {
+            log::error!("Failed to spill SQLite retry for PLC ID {}: {}", input.plc_id, e);
+        }
+    }
+}
+
+/// DB書き込みスレッドの停止を待つ
+/// timeout以内に停止した場合は true を返す
+pub fn wait_writer_stopped(timeout: Duration) -> bool {
+    let stopped = WRITER_STOPPED.lock().unwrap();
+    match stopped.as_ref() {
+        Some(rx) => !matches!(rx.recv_timeout(timeout), Err(std::sync::mpsc::RecvTimeoutError::Timeout)),
+        None => true,
+    }
+}
+
+/// 受信データ1件をパースし、出力先に渡す形にする(JSONとして読めない場合はErr)
+fn parse_request(request: &DbWriteRequest) -> Result<IngestFrame, String> {
+    // 受信データをログ出力
+    log::debug!(
+        "Received PLC data - ID: {}, Size: {} bytes",
+        request.plc_id,
+        request.message.len()
+    );
+    log::debug!("PLC data content: {}", request.message);
+
+    //PLCから受信したjson形式データをユニットごとに分解する
+    let frame = parse_frame(&request.message)?;
+    let received_at = request.received_at();
+    let mut input = IngestFrame {
+        plc_id: request.plc_id,
+        table_name: request.table_name.clone(),
+        // 以前のバージョンで退避したリクエストも ISO 8601 にそろえる
+        timestamp: clock::to_iso(&received_at),
+        received_at,
+        frame,
+        message: request.message.clone(),
+        sinks: request.sinks.clone(),
+        writes: Vec::new(),
+    };
+    //ユニット情報ごとの登録内容への変換は1回だけ行う
+    let writes = input.frame.records.iter().map(|record| build_writes(&input, record)).collect();
+    input.writes = writes;
+    Ok(input)
+}
+
+/// 検証に失敗した受信データを quarantine テーブルに退避する
+fn save_quarantine(report: &QuarantineReport, message: &str) -> Result<(), String> {
+    let errors = serde_json::to_string(&report.issues).map_err(|e| e.to_string())?;
+    let db = DB_CONNECTION.lock().unwrap();
+    let conn = db.as_ref().ok_or("DB connection not available")?;
+    conn.prepare_cached(
+        "INSERT INTO quarantine (\"PLC_ID\", \"TABLE_NAME\", \"RECEIVED_AT\", \"QUARANTINED_AT\", \"SCHEMA_NAME\", \"MACHINE_NAME\", \"TYPE_NAME\", \"LOT_NAME\", \"ERRORS\", \"MESSAGE\")
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )
+    .and_then(|mut stmt| {
+        stmt.execute(rusqlite::params![
+            report.plc_id,
+            report.table_name,
+            report.timestamp,
+            clock::now_string(),
+            report.schema,
+            report.machine,
+            report.type_name,
+            report.lot,
+            errors,
+            message,
+        ])
+    })
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// 規格外の測定値を limit_violations テーブルに記録する
+fn save_limit_violations(violations: &[LimitViolation]) -> Result<(), String> {
+    let detected_at = clock::now_string();
+    let db = DB_CONNECTION.lock().unwrap();
+    let conn = db.as_ref().ok_or("DB connection not available")?;
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT INTO limit_violations (\"DETECTED_AT\", \"RECEIVED_AT\", \"PLC_ID\", \"TABLE_NAME\", \"MACHINE_NAME\", \"TYPE_NAME\", \"LOT_NAME\", \"SERIAL\", \"COLUMN_NAME\", \"VALUE\", \"RULE\", \"LOWER_LIMIT\", \"UPPER_LIMIT\", \"DETAIL\")
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        )
+        .map_err(|e| e.to_string())?;
+    for v in violations {
+        stmt.execute(rusqlite::params![
+            detected_at,
+            v.timestamp,
+            v.plc_id,
+            v.table_name,
+            v.machine,
+            v.type_name,
+            v.lot,
+            v.serial,
+            v.column,
+            v.value,
+            v.rule.as_str(),
+            v.lower,
+            v.upper,
+            v.detail,
+        ])
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// ロットの開始・更新・完了を lots テーブルに記録する
+fn save_lot_changes(changes: &mut [LotChange]) -> Result<(), String> {
+    let db = DB_CONNECTION.lock().unwrap();
+    let conn = db.as_ref().ok_or("DB connection not available")?;
+    lot_tracker::save_changes(conn, changes).map_err(|e| e.to_string())
+}
+
+/// PLCとの接続・切断のイベント種別(machine_events の EVENT_TYPE)
+pub const MACHINE_EVENT_CONNECTED: &str = "connected";
+pub const MACHINE_EVENT_DISCONNECTED: &str = "disconnected";
+
+/// 受信データ以外の設備イベント(接続・切断・時計のずれ)を machine_events テーブルに記録する
+/// DB書き込みスレッドからバッチのコミット後に呼ぶ
+fn record_machine_event(table_name: &str, received_at: &DateTime<FixedOffset>, event_type: &str, code: Option<i64>) -> Result<(), String> {
+    let db = DB_CONNECTION.lock().unwrap();
+    let conn = db.as_ref().ok_or("DB connection not available")?;
+    insert_machine_event(conn, table_name, &clock::to_iso(received_at), received_at.timestamp_millis(), event_type, code)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// machine_events に1行登録する(同じイベントが登録済みなら何もせず0を返す)
+fn insert_machine_event(conn: &Connection, table_name: &str, received_at: &str, received_at_ms: i64, event_type: &str, code: Option<i64>) -> Result<usize> {
+    conn.prepare_cached("INSERT INTO machine_events (\"RECEIVED_AT\", \"RECEIVED_EPOCH_MS\", \"TABLE_NAME\", \"EVENT_TYPE\", \"CODE\") VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT DO NOTHING")
+        .and_then(|mut stmt| stmt.execute(rusqlite::params![received_at, received_at_ms, table_name, event_type, code]))
+}
+
+/// SQLiteの出力先名
+pub const SQLITE_SINK: &str = "sqlite";
+
+impl ToSql for ColumnValue {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            ColumnValue::Int(v) => ToSqlOutput::from(*v),
+            ColumnValue::Text(v) => ToSqlOutput::from(v.as_str()),
+            ColumnValue::Null => ToSqlOutput::from(rusqlite::types::Null),
+            ColumnValue::Increment => ToSqlOutput::from(1),
+        })
+    }
+}
+
+/// SQLiteのエラーを行単位の異常とDB自体の異常に分ける
+/// ロック待ち・ディスクの異常などは受信データごと再送する
+fn store_error(e: rusqlite::Error) -> StoreError {
+    use rusqlite::ErrorCode;
+    match e.sqlite_error_code() {
+        Some(
+            ErrorCode::DatabaseBusy
+            | ErrorCode::DatabaseLocked
+            | ErrorCode::DiskFull
+            | ErrorCode::SystemIoFailure
+            | ErrorCode::CannotOpen
+            | ErrorCode::ReadOnly
+            | ErrorCode::OutOfMemory,
+        ) => StoreError::Backend(e.to_string()),
+        _ => StoreError::Record(e.to_string()),
+    }
+}
+
+/// PLCごとのテーブルに登録するSQLiteの保存先
+/// バッチ単位で1トランザクションにまとめる
+pub struct SqliteStore {
+    created_tables: HashSet<String>,
+    /// 中央サーバーへ複製する場合はテーブルに変更ログのトリガーを作成する
+    replicate: bool,
+}
+
+impl SqliteStore {
+    pub fn new(replicate: bool) -> Self {
+        SqliteStore {
+            created_tables: HashSet::new(),
+            replicate,
+        }
+    }
+
+    fn with_connection<T>(f: impl FnOnce(&Connection) -> Result<T>) -> Result<T, String> {
+        let db = DB_CONNECTION.lock().unwrap();
+        let conn = db.as_ref().ok_or("DB connection not available")?;
+        f(conn).map_err(|e| e.to_string())
+    }
+}
+
+impl ChipStore for SqliteStore {
+    fn name(&self) -> &'static str {
+        SQLITE_SINK
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        Self::with_connection(|conn| conn.execute_batch("BEGIN"))
+            .map_err(|e| format!("Failed to begin transaction: {}", e))
+    }
+
+    fn ensure_table(&mut self, table_name: &str) -> Result<(), String> {
+        if self.created_tables.contains(table_name) {
+            return Ok(());
+        }
+        if !is_valid_identifier(table_name) {
+            return Err(format!("Invalid table name: {}", table_name));
+        }
+        let sql = CREATE_TABLE_SQL.replace("{TABLE_NAME}", table_name);
+        let index_sql = CREATE_CONSUMABLE_INDEXES_SQL.replace("{TABLE_NAME}", table_name);
+        let history_sql = CREATE_HISTORY_TABLE_SQL.replace("{TABLE_NAME}", table_name);
+        let replicate = self.replicate;
+        Self::with_connection(|conn| {
+            conn.execute_batch(&sql)?;
+            add_missing_columns(conn, table_name)?;
+            // 消耗品(プローブカード・ステージ)・トレイIDからの逆引き用
+            conn.execute_batch(&index_sql)?;
+            create_alarm_table(conn, table_name)?;
+            conn.execute_batch(&history_sql)?;
+            if replicate {
+                replication::ensure_triggers(conn, table_name)?;
+            }
+            Ok(())
+        })?;
+        self.created_tables.insert(table_name.to_string());
+        Ok(())
+    }
+
+    fn upsert(&mut self, table_name: &str, row: &ChipUpsert) -> Result<(), StoreError> {
+        let db = DB_CONNECTION.lock().unwrap();
+        let conn = db
+            .as_ref()
+            .ok_or_else(|| StoreError::Backend("DB connection not available".to_string()))?;
+        let sql = upsert_sql(table_name, row, |n| format!("?{}", n));
+        conn.prepare_cached(&sql)
+            .and_then(|mut stmt| stmt.execute(params_from_iter(row.params())))
+            .map(|_| ())
+            .map_err(store_error)
+    }
 
-    Ok(tx)
+    fn insert(&mut self, row: &HistoryInsert) -> Result<(), StoreError> {
+        let db = DB_CONNECTION.lock().unwrap();
+        let conn = db
+            .as_ref()
+            .ok_or_else(|| StoreError::Backend("DB connection not available".to_string()))?;
+        let sql = insert_sql(row, |n| format!("?{}", n));
+        conn.prepare_cached(&sql)
+            .and_then(|mut stmt| stmt.execute(params_from_iter(row.params())))
+            .map(|_| ())
+            .map_err(store_error)
+    }
+
+    fn begin_frame(&mut self) -> Result<(), String> {
+        Self::with_connection(|conn| conn.execute_batch("SAVEPOINT frame"))
+            .map_err(|e| format!("Failed to create savepoint: {}", e))
+    }
+
+    fn end_frame(&mut self) -> Result<(), String> {
+        Self::with_connection(|conn| conn.execute_batch("RELEASE SAVEPOINT frame"))
+            .map_err(|e| format!("Failed to release savepoint: {}", e))
+    }
+
+    fn rollback_frame(&mut self) {
+        if let Err(e) = Self::with_connection(|conn| conn.execute_batch("ROLLBACK TO SAVEPOINT frame; RELEASE SAVEPOINT frame")) {
+            log::error!("Failed to roll back frame: {}", e);
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        Self::with_connection(|conn| {
+            let result = conn.execute_batch("COMMIT");
+            // コミットに失敗したら次のバッチを始められるようにロールバックしておく
+            if result.is_err() && !conn.is_autocommit() {
+                conn.execute_batch("ROLLBACK").ok();
+            }
+            result
+        })
+        .map_err(|e| format!("Failed to commit transaction: {}", e))
+    }
+}
+
+/// 現在のDB書き込みメトリクスを取得する
+pub fn get_writer_metrics_snapshot() -> DbWriterMetrics {
+    WRITER_METRICS.lock().snapshot.clone()
+}
+
+/// コミット時間とキュー待ち時間のヒストグラムを取得する
+pub fn get_writer_histograms() -> (LatencyHistogram, LatencyHistogram) {
+    let metrics = WRITER_METRICS.lock();
+    (metrics.commit_histogram.clone(), metrics.queue_latency_histogram.clone())
+}
+
+/// DB書き込みスレッドのスループット・レイテンシをフロントエンドに返す
+#[command]
+pub fn get_db_writer_metrics() -> DbWriterMetrics {
+    get_writer_metrics_snapshot()
 }
 
 
@@ -236,40 +961,244 @@ fn start_db_writer_thread() -> Result<mpsc::UnboundedSender<DbWriteRequest>,rusq
 /// テーブル名: plc_data_{plc_id}
 pub fn create_table_for_plc(table_name: &str) -> Result<()> {
     let sql=CREATE_TABLE_SQL.replace("{TABLE_NAME}",table_name);
+    let index_sql=CREATE_CONSUMABLE_INDEXES_SQL.replace("{TABLE_NAME}",table_name);
+    let history_sql=CREATE_HISTORY_TABLE_SQL.replace("{TABLE_NAME}",table_name);
 
     let db = DB_CONNECTION.lock().unwrap();
     if let Some(conn) = db.as_ref() {
         conn.execute(&sql, [])?;
+        add_missing_columns(conn, table_name)?;
+        conn.execute_batch(&index_sql)?;
+        create_alarm_table(conn, table_name)?;
+        conn.execute_batch(&history_sql)?;
         log::info!("Table '{}' created or already exists", table_name);
     }
 
     Ok(())
 }
 
-/// PLCから受信したデータをDB書き込みスレッドに送信する
-/// 各受信タスクは独自の tx クローンを持っているので、ロック不要
-pub fn save_plc_data(
-    tx: &mpsc::UnboundedSender<DbWriteRequest>,
+/// PLCから受信したデータをDB書き込みキューに追加する
+/// キューが満杯の場合は設定されたポリシーに従って待機・退避・破棄する
+pub async fn save_plc_data(
+    queue: &DbQueue,
     plc_id: u32,
     table_name:&str,
-    timestamp: &str,
+    received_at: &DateTime<FixedOffset>,
     message: &str,
-) -> Result<(), String> {
+) -> Result<PushOutcome, String> {
     let request = DbWriteRequest {
         plc_id:plc_id,
         table_name:table_name.to_string(),
-        timestamp: timestamp.to_string(),
+        timestamp: clock::to_iso(received_at),
+        received_at_ms: received_at.timestamp_millis(),
         message: message.to_string(),
+        sinks: None,
+        event: None,
+        enqueued_at: Instant::now(),
     };
 
-    tx.send(request)
-        .map_err(|e| format!("Failed to send to DB writer thread: {}", e))?;
-    Ok(())
+    queue.push(request).await
+}
+
+/// 接続・切断などの設備イベントをDB書き込みキューに追加する
+/// 記録はDB書き込みスレッドが行うので、非同期タスクや他のロックを持ったままDBを待たない
+pub async fn save_machine_event(
+    queue: &DbQueue,
+    plc_id: u32,
+    table_name: &str,
+    received_at: &DateTime<FixedOffset>,
+    event_type: &str,
+) -> Result<PushOutcome, String> {
+    let request = DbWriteRequest {
+        plc_id,
+        table_name: table_name.to_string(),
+        timestamp: clock::to_iso(received_at),
+        received_at_ms: received_at.timestamp_millis(),
+        message: String::new(),
+        sinks: None,
+        event: Some(MachineEvent {
+            event_type: event_type.to_string(),
+            code: None,
+        }),
+        enqueued_at: Instant::now(),
+    };
+
+    queue.push(request).await
 }
 
 /// データベース接続をクローズする(アプリケーション終了時)
+/// WALの内容をDB本体に書き戻してからクローズする
 pub fn close_database() {
     let mut db = DB_CONNECTION.lock().unwrap();
+    if let Some(conn) = db.as_ref() {
+        checkpoint_wal(conn, "TRUNCATE");
+    }
     *db = None;
     log::info!("Database connection closed");
 }
+
+/// 書き込みスレッドが止まっていなくても、書き込み済みの分をWALからDBファイルに反映する(接続は閉じない)
+/// 書き込み中のトランザクションがある場合、その分は次回起動時に反映される
+pub fn checkpoint_database() {
+    let db = DB_CONNECTION.lock().unwrap();
+    if let Some(conn) = db.as_ref() {
+        checkpoint_wal(conn, "PASSIVE");
+    }
+}
+
+/// WALのチェックポイントを実行し、結果をログに出す
+fn checkpoint_wal(conn: &Connection, mode: &str) {
+    match conn.query_row(&format!("PRAGMA wal_checkpoint({})", mode), [], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+    }) {
+        Ok((busy, log_frames, checkpointed)) => log::info!(
+            "WAL checkpoint completed (busy={}, log={}, checkpointed={})",
+            busy,
+            log_frames,
+            checkpointed
+        ),
+        Err(e) => log::error!("Failed to checkpoint WAL: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_store::ChipWrite;
+
+    fn count(conn: &Connection, table_name: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table_name), [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn same_machine_event_is_inserted_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_event_table(&conn, "machine_events", CREATE_MACHINE_EVENTS_SQL).unwrap();
+
+        // UNIT・CODE が NULL でも同じイベントは1行だけ
+        let received_at = "2024-01-01T00:00:00.123+09:00";
+        let ms = 1_704_034_800_123;
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms, MACHINE_EVENT_CONNECTED, None).unwrap(), 1);
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms, MACHINE_EVENT_CONNECTED, None).unwrap(), 0);
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms, "clock_skew", Some(1500)).unwrap(), 1);
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms, "clock_skew", Some(1500)).unwrap(), 0);
+
+        // キーが1つでも違えば別のイベント
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms, "clock_skew", Some(0)).unwrap(), 1);
+        assert_eq!(insert_machine_event(&conn, "PLC_2", received_at, ms, MACHINE_EVENT_CONNECTED, None).unwrap(), 1);
+        assert_eq!(insert_machine_event(&conn, "PLC_1", received_at, ms + 1, MACHINE_EVENT_CONNECTED, None).unwrap(), 1);
+        assert_eq!(count(&conn, "machine_events"), 5);
+    }
+
+    #[test]
+    fn same_alarm_without_serial_is_inserted_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_alarm_table(&conn, "PLC_1").unwrap();
+
+        let insert = "INSERT INTO PLC_1_alarms (\"RECEIVED_AT\", \"LOT_NAME\", \"SERIAL\", \"UNIT\", \"ALARM_NUM\", \"RECEIVED_EPOCH_MS\")
+            VALUES (?1, ?2, ?3, 'U2', ?4, 1704034800123) ON CONFLICT DO NOTHING";
+        let received_at = "2024-01-01T00:00:00.123+09:00";
+        for _ in 0..2 {
+            conn.execute(insert, rusqlite::params![received_at, "L1", None::<i64>, 12]).unwrap();
+            conn.execute(insert, rusqlite::params![received_at, None::<String>, None::<i64>, None::<i64>]).unwrap();
+            conn.execute(insert, rusqlite::params![received_at, "L1", 1001, 12]).unwrap();
+        }
+        assert_eq!(count(&conn, "PLC_1_alarms"), 3);
+    }
+
+    #[test]
+    fn duplicates_are_removed_before_creating_unique_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        // NULLを含むUNIQUE制約だった以前のテーブル
+        conn.execute_batch(
+            "CREATE TABLE machine_events (
+                \"ID\" INTEGER NOT NULL,
+                \"RECEIVED_AT\" VARCHAR NOT NULL,
+                \"TABLE_NAME\" VARCHAR NOT NULL,
+                \"MACHINE_NAME\" VARCHAR,
+                \"TYPE_NAME\" VARCHAR,
+                \"LOT_NAME\" VARCHAR,
+                \"UNIT\" VARCHAR,
+                \"EVENT_TYPE\" VARCHAR NOT NULL,
+                \"CODE\" INTEGER,
+                \"RECEIVED_EPOCH_MS\" INTEGER,
+                PRIMARY KEY(\"ID\"),
+                CONSTRAINT \"uix_machine_event\" UNIQUE(\"TABLE_NAME\",\"RECEIVED_EPOCH_MS\",\"UNIT\",\"EVENT_TYPE\",\"CODE\")
+            );
+            INSERT INTO machine_events (\"RECEIVED_AT\", \"TABLE_NAME\", \"EVENT_TYPE\", \"RECEIVED_EPOCH_MS\") VALUES
+                ('2024-01-01T00:00:00.000+09:00', 'PLC_1', 'connected', 1704034800000),
+                ('2024-01-01T00:00:00.000+09:00', 'PLC_1', 'connected', 1704034800000),
+                ('2024-01-01T00:00:01.000+09:00', 'PLC_1', 'connected', 1704034801000),
+                ('2024-01-01 00:00:02', 'PLC_1', 'connected', NULL),
+                ('2024-01-01 00:00:03', 'PLC_1', 'connected', NULL);",
+        )
+        .unwrap();
+
+        create_event_table(&conn, "machine_events", CREATE_MACHINE_EVENTS_SQL).unwrap();
+
+        // 最も古い行を残し、エポックミリ秒の無い以前の行は残す
+        let ids: Vec<i64> = conn
+            .prepare("SELECT \"ID\" FROM machine_events ORDER BY \"ID\"")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(ids, vec![1, 3, 4, 5]);
+        assert_eq!(insert_machine_event(&conn, "PLC_1", "2024-01-01T00:00:00.000+09:00", 1_704_034_800_000, "connected", None).unwrap(), 0);
+    }
+
+    // ISO 8601(ミリ秒・オフセット付き)で、エポックミリ秒と同じ時刻か
+    fn assert_iso(value: &str, epoch_ms: i64) {
+        let time = DateTime::parse_from_rfc3339(value).unwrap_or_else(|e| panic!("{}: {}", value, e));
+        assert_eq!(time.timestamp_millis(), epoch_ms, "{}", value);
+        assert_eq!(value.len(), "2024-01-01T00:00:00.123+09:00".len(), "{}", value);
+    }
+
+    #[test]
+    fn received_at_is_stored_as_iso_with_millis() {
+        let ms = 1_704_034_800_123;
+        // 以前のバージョンで退避した "YYYY-MM-DD hh:mm:ss" のリクエスト
+        let request = DbWriteRequest {
+            plc_id: 1,
+            table_name: "PLC_1".to_string(),
+            timestamp: "2024-01-01 00:00:00".to_string(),
+            received_at_ms: ms,
+            message: r#"{"MACHINE": "M1", "LOT": "L1", "TYPE": "T1", "U2_AL_1": {"serial": [0, 1001], "alarm_num": 12}, "U3_AL_1": {"alarm_num": 7}}"#.to_string(),
+            sinks: None,
+            event: None,
+            enqueued_at: Instant::now(),
+        };
+        let input = parse_request(&request).unwrap();
+        assert_iso(&input.timestamp, ms);
+
+        // アラーム履歴・設備イベントの RECEIVED_AT も同じ書式
+        let inserts: Vec<&HistoryInsert> = input
+            .writes
+            .iter()
+            .flatten()
+            .flatten()
+            .filter_map(|write| match write {
+                ChipWrite::Insert(row) => Some(row),
+                ChipWrite::Upsert(_) => None,
+            })
+            .collect();
+        let tables: Vec<&str> = inserts.iter().map(|row| row.table_name.as_str()).collect();
+        assert_eq!(tables, vec!["PLC_1_alarms", "machine_events"]);
+        for row in inserts {
+            let received_at = row.columns.iter().find(|(column, _)| column == "RECEIVED_AT").map(|(_, value)| value);
+            match received_at {
+                Some(ColumnValue::Text(value)) => assert_iso(value, ms),
+                other => panic!("RECEIVED_AT of {}: {:?}", row.table_name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn unique_index_is_parsed_from_create_sql() {
+        let (name, keys) = parse_unique_index(CREATE_MACHINE_EVENTS_SQL).unwrap();
+        assert_eq!(name, "uix_machine_event_key");
+        assert_eq!(keys, "\"TABLE_NAME\", \"RECEIVED_EPOCH_MS\", COALESCE(\"UNIT\", ''), \"EVENT_TYPE\", COALESCE(\"CODE\", -1)");
+        assert_eq!(parse_unique_index(CREATE_QUARANTINE_SQL), None);
+    }
+}
@@ -0,0 +1,275 @@
+///PLCごとのテーブルに対する読み出し専用クエリ
+use std::collections::BTreeMap;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::command;
+
+use crate::clock;
+use crate::config::load_config;
+use crate::data_handler::{is_valid_identifier, open_read_connection, table_columns};
+use crate::types::PlcConfig;
+
+/// BINを記録しているカラム(ユニット名, カラム名)
+pub const BIN_COLUMNS: [(&str, &str); 6] = [
+    ("DC1", "DC1_TEST_BIN"),
+    ("AC1", "AC1_TEST_BIN"),
+    ("AC2", "AC2_TEST_BIN"),
+    ("DC2", "DC2_TEST_BIN"),
+    ("IP_SURF", "IP_SURF_BIN"),
+    ("IP_BACK", "IP_BACK_BIN"),
+];
+
+/// ロットの一覧情報
+#[derive(Serialize, Debug, Clone)]
+pub struct LotInfo {
+    pub table_name: String,
+    pub lot_name: String,
+    pub type_name: Option<String>,
+    pub machine_name: Option<String>,
+    pub chip_count: i64,
+    pub first_pickup_date: Option<String>,
+    pub last_put_date: Option<String>,
+}
+
+/// ユニットごとのBIN集計
+#[derive(Serialize, Debug, Clone)]
+pub struct UnitYield {
+    pub unit: String,
+    pub tested: u64,
+    pub passed: u64,
+    pub bins: BTreeMap<i64, u64>,
+}
+
+/// ロットの歩留まり集計
+#[derive(Serialize, Debug, Clone)]
+pub struct YieldSummary {
+    pub table_name: String,
+    pub lot_name: String,
+    pub chip_count: u64,
+    /// 1つ以上のBINが記録されているチップ数
+    pub tested: u64,
+    /// 記録されたBINが全て良品BINのチップ数
+    pub passed: u64,
+    pub yield_percent: f64,
+    pub units: Vec<UnitYield>,
+}
+
+/// チップに紐付かない設備のイベント(シリアルの無いアラームなど)
+#[derive(Serialize, Debug, Clone)]
+pub struct MachineEventInfo {
+    pub received_at: String,
+    pub table_name: String,
+    pub machine_name: Option<String>,
+    pub type_name: Option<String>,
+    pub lot_name: Option<String>,
+    pub unit: Option<String>,
+    pub event_type: String,
+    pub code: Option<i64>,
+}
+
+/// config.json に登録されているPLC(テーブル)の一覧
+pub fn configured_plcs() -> Result<Vec<PlcConfig>, String> {
+    Ok(load_config()?.plcs)
+}
+
+/// テーブル名が config.json に登録されているものか確認する
+pub fn resolve_table(table_name: &str) -> Result<String, String> {
+    let plcs = configured_plcs()?;
+    if is_valid_identifier(table_name) && plcs.iter().any(|plc| plc.table_name == table_name) {
+        Ok(table_name.to_string())
+    } else {
+        Err(format!("Unknown table: {}", table_name))
+    }
+}
+
+/// PLC IDからテーブル名を取得する
+pub fn table_for_plc(plc_id: u32) -> Result<String, String> {
+    configured_plcs()?
+        .into_iter()
+        .find(|plc| plc.id == plc_id)
+        .map(|plc| plc.table_name)
+        .filter(|table| is_valid_identifier(table))
+        .ok_or_else(|| format!("PLC with ID {} not found", plc_id))
+}
+
+/// 行をカラム名をキーとするJSONオブジェクトに変換する
+pub fn row_to_json(row: &Row, column_names: &[String]) -> rusqlite::Result<Map<String, Value>> {
+    let mut map = Map::new();
+    for (i, name) in column_names.iter().enumerate() {
+        let value = match row.get_ref(i)? {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(v) => Value::from(v),
+            ValueRef::Real(v) => Value::from(v),
+            ValueRef::Text(v) => Value::from(String::from_utf8_lossy(v).into_owned()),
+            ValueRef::Blob(v) => Value::from(v.to_vec()),
+        };
+        map.insert(name.clone(), value);
+    }
+    Ok(map)
+}
+
+/// テーブル内のロット一覧を取得する
+pub fn list_lots(conn: &Connection, table_name: &str) -> rusqlite::Result<Vec<LotInfo>> {
+    let sql = format!(
+        "SELECT LOT_NAME, MAX(TYPE_NAME), MAX(MACHINE_NAME), COUNT(*), MIN(LD_PICKUP_DATE), MAX(ULD_PUT_DATE), MIN(ID) AS FIRST_ID
+        FROM {table_name}
+        GROUP BY LOT_NAME
+        ORDER BY FIRST_ID DESC"
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| {
+        Ok(LotInfo {
+            table_name: table_name.to_string(),
+            lot_name: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            type_name: row.get(1)?,
+            machine_name: row.get(2)?,
+            chip_count: row.get(3)?,
+            first_pickup_date: row.get(4)?,
+            last_put_date: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// (LOT_NAME, SERIAL) でチップの行を取得する
+pub fn get_chip(conn: &Connection, table_name: &str, lot_name: &str, serial: i64) -> rusqlite::Result<Option<Map<String, Value>>> {
+    let sql = format!("SELECT * FROM {table_name} WHERE LOT_NAME = ?1 AND SERIAL = ?2");
+    let mut stmt = conn.prepare(&sql)?;
+    let column_names: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let mut rows = stmt.query(params![lot_name, serial])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_json(row, &column_names)?)),
+        None => Ok(None),
+    }
+}
+
+/// 登録されている全テーブルから (LOT_NAME, SERIAL) のチップを探す
+pub fn find_chip(conn: &Connection, tables: &[String], lot_name: &str, serial: i64) -> rusqlite::Result<Option<(String, Map<String, Value>)>> {
+    for table in tables {
+        match get_chip(conn, table, lot_name, serial) {
+            Ok(Some(row)) => return Ok(Some((table.clone(), row))),
+            Ok(None) => {}
+            // テーブルが未作成の場合は次のテーブルを探す
+            Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// 設備のイベントを新しい順に取得する(table_name を省略した場合は全テーブル、to は含まない)
+/// RECEIVED_AT は ISO 8601 で記録しているので、日時で指定された範囲は同じ書式にして比べる
+pub fn list_machine_events(conn: &Connection, table_name: Option<&str>, from: Option<&str>, to: Option<&str>, limit: usize) -> rusqlite::Result<Vec<MachineEventInfo>> {
+    let from = from.map(iso_bound);
+    let to = to.map(iso_bound);
+    let mut stmt = conn.prepare(
+        "SELECT RECEIVED_AT, TABLE_NAME, MACHINE_NAME, TYPE_NAME, LOT_NAME, UNIT, EVENT_TYPE, CODE FROM machine_events
+        WHERE (?1 IS NULL OR TABLE_NAME = ?1) AND (?2 IS NULL OR RECEIVED_AT >= ?2) AND (?3 IS NULL OR RECEIVED_AT < ?3)
+        ORDER BY RECEIVED_AT DESC, ID DESC
+        LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(params![table_name, from, to, limit as i64], |row| {
+        Ok(MachineEventInfo {
+            received_at: row.get(0)?,
+            table_name: row.get(1)?,
+            machine_name: row.get(2)?,
+            type_name: row.get(3)?,
+            lot_name: row.get(4)?,
+            unit: row.get(5)?,
+            event_type: row.get(6)?,
+            code: row.get(7)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// 範囲の指定を RECEIVED_AT と比べられる ISO 8601 にする(日付だけなど日時として読めないものはそのまま)
+fn iso_bound(value: &str) -> String {
+    clock::parse_local(value).map(|time| clock::to_iso(&time)).unwrap_or_else(|| value.to_string())
+}
+
+/// 設備のイベントをフロントエンドに返す
+#[command]
+pub async fn get_machine_events(plc_id: Option<u32>, from: Option<String>, to: Option<String>, limit: Option<usize>) -> Result<Vec<MachineEventInfo>, String> {
+    let table_name = plc_id.map(table_for_plc).transpose()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        list_machine_events(&conn, table_name.as_deref(), from.as_deref(), to.as_deref(), limit.unwrap_or(500))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// ロットの歩留まりを集計する
+pub fn lot_yield(conn: &Connection, table_name: &str, lot_name: &str, pass_bins: &[i64]) -> rusqlite::Result<YieldSummary> {
+    let bin_columns: Vec<&str> = BIN_COLUMNS.iter().map(|(_, column)| *column).collect();
+    let sql = format!("SELECT {} FROM {table_name} WHERE LOT_NAME = ?1", bin_columns.join(", "));
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params![lot_name])?;
+
+    let mut units: Vec<UnitYield> = BIN_COLUMNS
+        .iter()
+        .map(|(unit, _)| UnitYield {
+            unit: unit.to_string(),
+            tested: 0,
+            passed: 0,
+            bins: BTreeMap::new(),
+        })
+        .collect();
+    let mut chip_count = 0;
+    let mut tested = 0;
+    let mut passed = 0;
+
+    while let Some(row) = rows.next()? {
+        chip_count += 1;
+        let mut chip_tested = false;
+        let mut chip_passed = true;
+        for (i, unit) in units.iter_mut().enumerate() {
+            if let Some(bin) = row.get::<_, Option<i64>>(i)? {
+                chip_tested = true;
+                unit.tested += 1;
+                *unit.bins.entry(bin).or_insert(0) += 1;
+                if pass_bins.contains(&bin) {
+                    unit.passed += 1;
+                } else {
+                    chip_passed = false;
+                }
+            }
+        }
+        if chip_tested {
+            tested += 1;
+            if chip_passed {
+                passed += 1;
+            }
+        }
+    }
+
+    Ok(YieldSummary {
+        table_name: table_name.to_string(),
+        lot_name: lot_name.to_string(),
+        chip_count,
+        tested,
+        passed,
+        yield_percent: if tested > 0 { passed as f64 * 100.0 / tested as f64 } else { 0.0 },
+        units,
+    })
+}
+
+/// create_table.sql のカラムに合わせたチップ行のJSON Schema
+pub fn chip_json_schema() -> Value {
+    let mut properties = Map::new();
+    for (name, sql_type) in table_columns() {
+        let json_type = if sql_type.eq_ignore_ascii_case("INTEGER") { "integer" } else { "string" };
+        properties.insert(name, serde_json::json!({ "type": [json_type, "null"] }));
+    }
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "ChipRecord",
+        "type": "object",
+        "properties": properties,
+        "required": ["ID", "LOT_NAME", "SERIAL"],
+    })
+}
@@ -0,0 +1,594 @@
+///受信データをDB書き込みスレッドに渡すための上限付きキュー
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use parking_lot::{Condvar, Mutex};
+use serde::Serialize;
+use tauri::command;
+use tokio::sync::Notify;
+
+use crate::data_handler::DbWriteRequest;
+use crate::state::DbChannelState;
+use crate::types::{DbQueueConfig, OverflowPolicy};
+
+//書き戻しの確定待ちで退避データを読めないときに再確認する間隔
+const REPLAY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// キューへの追加結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// そのままキューに入った
+    Queued,
+    /// 空きを待ってからキューに入った
+    Blocked,
+    /// ファイルに退避した
+    Spilled,
+    /// 最も古いリクエストを破棄してキューに入れた
+    DroppedOldest,
+}
+
+/// キューの状態(フロントエンド返却用)
+#[derive(Serialize, Debug, Clone)]
+pub struct DbQueueStatus {
+    pub depth: usize,
+    pub capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub dropped_total: u64,
+    pub blocked_total: u64,
+    pub spilled_total: u64,
+    pub spilled_pending: u64,
+}
+
+/// 書き戻し中の退避ファイル
+/// 書き込みスレッドがバッチを確定した位置(バイト)を別ファイルに保存し、再起動時はそこから読み直す
+struct Replay {
+    reader: BufReader<File>,
+    /// 書き込みスレッドに渡した位置
+    read: u64,
+    /// バッチを確定して保存した位置
+    committed: u64,
+    /// 最後まで読んだか
+    eof: bool,
+}
+
+/// 上限付きのDB書き込みキュー
+/// 受信側(tokioタスク)から push し、DB書き込みスレッドから pop する
+/// Spill時はファイルから書き戻すため、受信順と書き込み順が前後することがある
+pub struct DbQueue {
+    queue: Mutex<VecDeque<DbWriteRequest>>,
+    not_empty: Condvar,
+    not_full: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+    spill_path: PathBuf,
+    spill_lock: Mutex<()>,
+    replay: Mutex<Option<Replay>>,
+    dropped_total: AtomicU64,
+    blocked_total: AtomicU64,
+    spilled_total: AtomicU64,
+    spilled_pending: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl DbQueue {
+    pub fn new(config: &DbQueueConfig) -> Self {
+        let spill_path = PathBuf::from(&config.spill_path);
+
+        // 前回終了時に書き戻しきれなかった退避データを引き継ぐ
+        let pending = count_lines(&spill_path, 0) + count_lines(&replay_path(&spill_path), read_offset(&spill_path));
+        if pending > 0 {
+            log::warn!("{} spilled DB requests found in {:?}, they will be replayed", pending, spill_path);
+        }
+
+        DbQueue {
+            queue: Mutex::new(VecDeque::with_capacity(config.capacity.min(65536))),
+            not_empty: Condvar::new(),
+            not_full: Notify::new(),
+            capacity: config.capacity.max(1),
+            policy: config.overflow_policy,
+            spill_path,
+            spill_lock: Mutex::new(()),
+            replay: Mutex::new(None),
+            dropped_total: AtomicU64::new(0),
+            blocked_total: AtomicU64::new(0),
+            spilled_total: AtomicU64::new(0),
+            spilled_pending: AtomicU64::new(pending),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// 新しいリクエストをキューに入れず退避ファイルに回すようにする(終了処理用)
+    /// 書き込みスレッドはキューに残っている分を書き終えると停止する
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _queue = self.queue.lock();
+        self.not_empty.notify_all();
+        self.not_full.notify_waiters();
+    }
+
+    /// キューに残っているリクエストを退避ファイルに書き出す(終了処理のタイムアウト時用)
+    /// 退避したリクエストは次回起動時に書き戻される
+    pub fn spill_remaining(&self) -> usize {
+        let remaining: Vec<DbWriteRequest> = self.queue.lock().drain(..).collect();
+        let mut spilled = 0;
+        for request in &remaining {
+            match self.spill(request) {
+                Ok(_) => spilled += 1,
+                Err(e) => log::error!("Failed to spill remaining DB request: {}", e),
+            }
+        }
+        spilled
+    }
+
+    /// リクエストをキューに追加する
+    /// 満杯の場合は設定されたポリシーに従う
+    pub async fn push(&self, request: DbWriteRequest) -> Result<PushOutcome, String> {
+        let mut request = Some(request);
+        let mut blocked = false;
+
+        loop {
+            // notify_waiters を取りこぼさないよう、空き確認の前に待ち受けを作っておく
+            let notified = self.not_full.notified();
+            {
+                let mut queue = self.queue.lock();
+                if self.closed.load(Ordering::SeqCst) {
+                    // 終了処理中(空き待ちの間に閉じられた場合も含む)は退避して次回起動時に書き戻す
+                    drop(queue);
+                    self.spill(request.as_ref().unwrap())?;
+                    return Ok(PushOutcome::Spilled);
+                }
+                if queue.len() < self.capacity {
+                    queue.push_back(request.take().unwrap());
+                    self.not_empty.notify_one();
+                    return Ok(if blocked { PushOutcome::Blocked } else { PushOutcome::Queued });
+                }
+
+                match self.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        if let Some(dropped) = queue.pop_front() {
+                            log::warn!("DB queue full, dropped oldest request from PLC ID {}", dropped.plc_id);
+                        }
+                        self.dropped_total.fetch_add(1, Ordering::Relaxed);
+                        queue.push_back(request.take().unwrap());
+                        self.not_empty.notify_one();
+                        return Ok(PushOutcome::DroppedOldest);
+                    }
+                    OverflowPolicy::Spill => {
+                        drop(queue);
+                        self.spill(request.as_ref().unwrap())?;
+                        // 書き込みスレッドが待機中なら起こして書き戻しさせる
+                        self.not_empty.notify_one();
+                        return Ok(PushOutcome::Spilled);
+                    }
+                }
+            }
+
+            if !blocked {
+                blocked = true;
+                self.blocked_total.fetch_add(1, Ordering::Relaxed);
+                log::warn!("DB queue full, waiting for free space");
+            }
+            notified.await;
+        }
+    }
+
+    /// リクエストを1件取り出す(キューが空なら届くまで待つ)
+    /// close後にキューが空になったら None を返す
+    pub fn pop_blocking(&self) -> Option<DbWriteRequest> {
+        let mut queue = self.queue.lock();
+        loop {
+            if let Some(request) = queue.pop_front() {
+                drop(queue);
+                self.not_full.notify_waiters();
+                return Some(request);
+            }
+
+            // 終了処理中は退避データを書き戻さず、次回起動時に回す
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            // キューが空になったら退避データを書き戻す
+            if self.spilled_pending.load(Ordering::Relaxed) > 0 {
+                drop(queue);
+                if let Some(request) = self.next_spilled() {
+                    return Some(request);
+                }
+                queue = self.queue.lock();
+                // 書き戻した分の確定を待っている場合があるので、間隔をあけて再確認する
+                if self.spilled_pending.load(Ordering::Relaxed) > 0 {
+                    self.not_empty.wait_for(&mut queue, REPLAY_RETRY_INTERVAL);
+                }
+                continue;
+            }
+
+            self.not_empty.wait(&mut queue);
+        }
+    }
+
+    /// リクエストを1件取り出す(キューが空なら None)
+    pub fn try_pop(&self) -> Option<DbWriteRequest> {
+        let request = self.queue.lock().pop_front();
+        if request.is_some() {
+            self.not_full.notify_waiters();
+        }
+        request
+    }
+
+    /// 現在のキューの状態を返す
+    pub fn status(&self) -> DbQueueStatus {
+        DbQueueStatus {
+            depth: self.queue.lock().len(),
+            capacity: self.capacity,
+            overflow_policy: self.policy,
+            dropped_total: self.dropped_total.load(Ordering::Relaxed),
+            blocked_total: self.blocked_total.load(Ordering::Relaxed),
+            spilled_total: self.spilled_total.load(Ordering::Relaxed),
+            spilled_pending: self.spilled_pending.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 終了処理中か
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// リクエストを退避ファイルに1行のJSONとして追記する
+    pub fn spill(&self, request: &DbWriteRequest) -> Result<(), String> {
+        let line = serde_json::to_string(request)
+            .map_err(|e| format!("Failed to serialize DB request for spill: {}", e))?;
+
+        let _guard = self.spill_lock.lock();
+        if let Some(parent) = self.spill_path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spill_path)
+            .map_err(|e| format!("Failed to open spill file {:?}: {}", self.spill_path, e))?;
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("Failed to write spill file {:?}: {}", self.spill_path, e))?;
+
+        self.spilled_total.fetch_add(1, Ordering::Relaxed);
+        self.spilled_pending.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 書き戻した退避データの読み出し位置を確定する
+    /// 書き込みスレッドがバッチを確定するたびに呼び、再起動時に書き込み済みの分を読み直さないようにする
+    pub fn commit_replayed(&self) {
+        let mut replay = self.replay.lock();
+        let Some(current) = replay.as_mut() else {
+            return;
+        };
+        if current.eof {
+            // 最後まで書き込んだファイルは削除する
+            *replay = None;
+            self.remove_replay();
+            return;
+        }
+        if current.read == current.committed {
+            return;
+        }
+        let offset_file = replay_offset_path(&self.spill_path);
+        match fs::write(&offset_file, current.read.to_string()) {
+            Ok(()) => current.committed = current.read,
+            Err(e) => log::error!("Failed to save spill replay offset {:?}: {}", offset_file, e),
+        }
+    }
+
+    /// 書き戻し中のファイルと読み出し位置のファイルを削除する
+    fn remove_replay(&self) {
+        fs::remove_file(replay_path(&self.spill_path)).ok();
+        fs::remove_file(replay_offset_path(&self.spill_path)).ok();
+    }
+
+    /// 退避ファイルから1件読み出す
+    /// 書き戻し中のファイルは別名に変えて読むので、その間も新しい退避は受け付けられる
+    fn next_spilled(&self) -> Option<DbWriteRequest> {
+        let mut replay = self.replay.lock();
+        loop {
+            if replay.is_none() {
+                let replay_file = replay_path(&self.spill_path);
+                if !replay_file.exists() {
+                    let _guard = self.spill_lock.lock();
+                    if fs::rename(&self.spill_path, &replay_file).is_err() {
+                        // 退避ファイルが無いのに件数が残っている場合はリセットする
+                        self.spilled_pending.store(0, Ordering::Relaxed);
+                        return None;
+                    }
+                    fs::remove_file(replay_offset_path(&self.spill_path)).ok();
+                }
+                // 前回の起動で確定した位置から読む
+                let offset = read_offset(&self.spill_path);
+                match File::open(&replay_file).and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file)) {
+                    Ok(file) => {
+                        *replay = Some(Replay {
+                            reader: BufReader::new(file),
+                            read: offset,
+                            committed: offset,
+                            eof: false,
+                        })
+                    }
+                    Err(e) => {
+                        log::error!("Failed to open spill replay file {:?}: {}", replay_file, e);
+                        self.spilled_pending.store(0, Ordering::Relaxed);
+                        return None;
+                    }
+                }
+            }
+
+            let current = replay.as_mut().unwrap();
+            if current.eof {
+                // 最後の分の確定前に次のファイルへ進むと、再起動時に読み直す位置が分からなくなる
+                if current.read != current.committed {
+                    return None;
+                }
+                *replay = None;
+                self.remove_replay();
+                if self.spilled_pending.load(Ordering::Relaxed) == 0 {
+                    return None;
+                }
+                continue;
+            }
+
+            let mut line = String::new();
+            match current.reader.read_line(&mut line) {
+                Ok(0) => current.eof = true,
+                Ok(n) => {
+                    current.read += n as u64;
+                    self.spilled_pending.fetch_sub(1, Ordering::Relaxed);
+                    match serde_json::from_str::<DbWriteRequest>(line.trim_end()) {
+                        Ok(request) => return Some(request),
+                        Err(e) => log::error!("Failed to parse spilled DB request: {}", e),
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to read spill replay file: {}", e);
+                    *replay = None;
+                    self.remove_replay();
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// 書き戻し中の退避ファイルのパス
+fn replay_path(spill_path: &Path) -> PathBuf {
+    spill_path.with_extension("replay")
+}
+
+/// 書き戻し中の退避ファイルの読み出し位置を保存するファイルのパス
+fn replay_offset_path(spill_path: &Path) -> PathBuf {
+    spill_path.with_extension("replay.offset")
+}
+
+/// 書き戻し中の退避ファイルの確定済みの位置(無い場合・読めない場合は先頭)
+fn read_offset(spill_path: &Path) -> u64 {
+    let replay_file = replay_path(spill_path);
+    if !replay_file.exists() {
+        return 0;
+    }
+    let offset = fs::read_to_string(replay_offset_path(spill_path))
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    // ファイルより後ろを指している場合は壊れているので先頭から読む
+    let len = fs::metadata(&replay_file).map(|m| m.len()).unwrap_or(0);
+    if offset > len {
+        log::warn!("Spill replay offset {} is beyond {:?}, replaying from the start", offset, replay_file);
+        return 0;
+    }
+    offset
+}
+
+/// ファイルの指定位置以降の行数を数える(存在しない場合は0)
+fn count_lines(path: &Path, offset: u64) -> u64 {
+    match File::open(path).and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file)) {
+        Ok(file) => BufReader::new(file).lines().count() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// DBキューの深さ・破棄件数などをフロントエンドに返す
+#[command]
+pub fn get_db_queue_status(db_channel: tauri::State<'_, DbChannelState>) -> DbQueueStatus {
+    db_channel.status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // テストごとに別の退避ファイルを使う
+    fn queue(name: &str, capacity: usize, policy: OverflowPolicy) -> (DbQueue, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("db_queue_test_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        (DbQueue::new(&config(&dir, capacity, policy)), dir)
+    }
+
+    fn config(dir: &Path, capacity: usize, policy: OverflowPolicy) -> DbQueueConfig {
+        DbQueueConfig {
+            capacity,
+            overflow_policy: policy,
+            spill_path: dir.join("spill.jsonl").to_string_lossy().into_owned(),
+        }
+    }
+
+    fn request(plc_id: u32) -> DbWriteRequest {
+        DbWriteRequest {
+            plc_id,
+            table_name: "PLC_1".to_string(),
+            timestamp: "2024-01-01 00:00:00".to_string(),
+            received_at_ms: 1_704_034_800_000 + plc_id as i64,
+            message: format!("{{\"ID\": {}}}", plc_id),
+            sinks: None,
+            event: None,
+            enqueued_at: Instant::now(),
+        }
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn block_waits_for_free_space() {
+        let (queue, dir) = queue("block", 1, OverflowPolicy::Block);
+        let queue = std::sync::Arc::new(queue);
+        runtime().block_on(async {
+            assert_eq!(queue.push(request(1)).await, Ok(PushOutcome::Queued));
+
+            // 満杯の間は待たされる
+            let pushing = tokio::spawn({
+                let queue = queue.clone();
+                async move { queue.push(request(2)).await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(!pushing.is_finished());
+            assert_eq!(queue.status().blocked_total, 1);
+
+            assert_eq!(queue.try_pop().map(|r| r.plc_id), Some(1));
+            assert_eq!(pushing.await.unwrap(), Ok(PushOutcome::Blocked));
+        });
+        assert_eq!(queue.try_pop().map(|r| r.plc_id), Some(2));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn drop_oldest_discards_front() {
+        let (queue, dir) = queue("drop_oldest", 2, OverflowPolicy::DropOldest);
+        runtime().block_on(async {
+            queue.push(request(1)).await.unwrap();
+            queue.push(request(2)).await.unwrap();
+            assert_eq!(queue.push(request(3)).await, Ok(PushOutcome::DroppedOldest));
+        });
+        let status = queue.status();
+        assert_eq!(status.depth, 2);
+        assert_eq!(status.dropped_total, 1);
+        assert_eq!(queue.try_pop().map(|r| r.plc_id), Some(2));
+        assert_eq!(queue.try_pop().map(|r| r.plc_id), Some(3));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn spill_writes_overflow_and_replays_when_empty() {
+        let (queue, dir) = queue("spill", 1, OverflowPolicy::Spill);
+        runtime().block_on(async {
+            assert_eq!(queue.push(request(1)).await, Ok(PushOutcome::Queued));
+            assert_eq!(queue.push(request(2)).await, Ok(PushOutcome::Spilled));
+            assert_eq!(queue.push(request(3)).await, Ok(PushOutcome::Spilled));
+        });
+        assert_eq!(count_lines(&queue.spill_path, 0), 2);
+        assert_eq!(queue.status().spilled_total, 2);
+        assert_eq!(queue.status().spilled_pending, 2);
+
+        // キューの分を先に取り出し、空になったら退避ファイルから書き戻す
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(1));
+        let replayed = queue.pop_blocking().unwrap();
+        assert_eq!(replayed.plc_id, 2);
+        assert_eq!(replayed.message, "{\"ID\": 2}");
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(3));
+        assert_eq!(queue.status().spilled_pending, 0);
+
+        // 確定すると読み終えた位置が保存され、再起動しても読み直さない
+        queue.commit_replayed();
+        assert_eq!(read_offset(&queue.spill_path), fs::metadata(replay_path(&queue.spill_path)).unwrap().len());
+        let restarted = DbQueue::new(&config(&dir, 1, OverflowPolicy::Spill));
+        assert_eq!(restarted.status().spilled_pending, 0);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn replay_resumes_from_saved_offset_after_restart() {
+        let (queue, dir) = queue("restart", 1, OverflowPolicy::Spill);
+        for plc_id in 1..=4 {
+            queue.spill(&request(plc_id)).unwrap();
+        }
+        // 1件目を書き込んで確定し、2件目は確定前に終了した
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(1));
+        queue.commit_replayed();
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(2));
+        drop(queue);
+
+        // 再起動後は確定した位置から読み直す
+        let restarted = DbQueue::new(&config(&dir, 1, OverflowPolicy::Spill));
+        assert_eq!(restarted.status().spilled_pending, 3);
+        assert_eq!(restarted.pop_blocking().map(|r| r.plc_id), Some(2));
+        assert_eq!(restarted.pop_blocking().map(|r| r.plc_id), Some(3));
+        assert_eq!(restarted.pop_blocking().map(|r| r.plc_id), Some(4));
+        assert_eq!(restarted.status().spilled_pending, 0);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn pop_blocking_after_close_drains_queue_and_keeps_spill() {
+        let (queue, dir) = queue("close", 2, OverflowPolicy::Spill);
+        runtime().block_on(async {
+            queue.push(request(1)).await.unwrap();
+            queue.push(request(2)).await.unwrap();
+            queue.push(request(3)).await.unwrap();
+        });
+        queue.close();
+        assert!(queue.is_closed());
+
+        // キューに残っている分は取り出せるが、退避データは次回起動時に回す
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(1));
+        assert_eq!(queue.pop_blocking().map(|r| r.plc_id), Some(2));
+        assert!(queue.pop_blocking().is_none());
+        assert_eq!(queue.status().spilled_pending, 1);
+        assert_eq!(count_lines(&queue.spill_path, 0), 1);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn push_after_close_spills_blocked_request() {
+        let (queue, dir) = queue("close_block", 1, OverflowPolicy::Block);
+        let queue = std::sync::Arc::new(queue);
+        runtime().block_on(async {
+            queue.push(request(1)).await.unwrap();
+
+            // 空き待ちの間に閉じられたリクエストは退避される
+            let pushing = tokio::spawn({
+                let queue = queue.clone();
+                async move { queue.push(request(2)).await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue.close();
+            assert_eq!(pushing.await.unwrap(), Ok(PushOutcome::Spilled));
+
+            // 閉じた後に届いたリクエストも退避される
+            assert_eq!(queue.push(request(3)).await, Ok(PushOutcome::Spilled));
+        });
+        assert_eq!(queue.status().depth, 1);
+        assert_eq!(queue.status().spilled_pending, 2);
+
+        // 次回起動時に書き戻される
+        queue.spill_remaining();
+        let restarted = DbQueue::new(&config(&dir, 1, OverflowPolicy::Block));
+        let replayed: Vec<u32> = (0..3).filter_map(|_| restarted.pop_blocking()).map(|r| r.plc_id).collect();
+        assert_eq!(replayed, vec![2, 3, 1]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn pop_blocking_wakes_on_close() {
+        let (queue, dir) = queue("close_wait", 1, OverflowPolicy::Block);
+        let queue = std::sync::Arc::new(queue);
+        let popping = std::thread::spawn({
+            let queue = queue.clone();
+            move || queue.pop_blocking()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        queue.close();
+        assert!(popping.join().unwrap().is_none());
+        fs::remove_dir_all(dir).ok();
+    }
+}
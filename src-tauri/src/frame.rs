@@ -0,0 +1,322 @@
+///PLCから受信したjson形式データの分解
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// ユニット情報の種類
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    /// LDトレイピックアップ (U1_TR_*)
+    TrayPickup,
+    /// 上流アームコレット (*_A1_*)
+    Arm1,
+    /// 下流アームコレット (*_A2_*)
+    Arm2,
+    /// DC1,ULD予熱テーブル (*_PH_*)
+    Preheat,
+    /// DC1~DC2検査テーブル (*_TS_*)
+    TestStage,
+    /// IP検査テーブル (U6_TS_*)
+    IpStage,
+    /// IP表面検査BIN (U6_T1_*)
+    IpSurfBin,
+    /// IP裏面検査BIN (U6_T2_*)
+    IpBackBin,
+    /// ULDポケット認識 (U7_PI_*)
+    UldPocket,
+    /// ULDポケット挿入 (U7_CI_*)
+    UldChip,
+    /// アラーム (*_AL_*)
+    Alarm,
+}
+
+impl RecordKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordKind::TrayPickup => "tray_pickup",
+            RecordKind::Arm1 => "arm1",
+            RecordKind::Arm2 => "arm2",
+            RecordKind::Preheat => "preheat",
+            RecordKind::TestStage => "test_stage",
+            RecordKind::IpStage => "ip_stage",
+            RecordKind::IpSurfBin => "ip_surf_bin",
+            RecordKind::IpBackBin => "ip_back_bin",
+            RecordKind::UldPocket => "uld_pocket",
+            RecordKind::UldChip => "uld_chip",
+            RecordKind::Alarm => "alarm",
+        }
+    }
+}
+
+/// 1ユニット分の情報
+#[derive(Serialize, Debug, Clone)]
+pub struct UnitRecord {
+    /// 受信データのキー(例: U2_TS_1)
+    pub key: String,
+    /// ユニット名(例: U2)
+    pub unit: String,
+    pub kind: RecordKind,
+    pub value: Value,
+}
+
+impl UnitRecord {
+    /// シリアル番号(アラームは最初の0でないシリアル)
+    pub fn serial(&self) -> Option<i64> {
+        match self.value.get("serial") {
+            Some(Value::Array(list)) => list.iter().filter_map(|v| v.as_i64()).find(|s| *s != 0),
+            Some(v) => v.as_i64(),
+            None => None,
+        }
+    }
+}
+
+/// 受信データ1件を分解したもの
+#[derive(Serialize, Debug, Clone)]
+pub struct ParsedFrame {
+    pub machine_name: String,
+    pub lot_name: String,
+    pub type_name: String,
+    pub records: Vec<UnitRecord>,
+}
+
+/// キー名からユニット名と種類を判定する
+pub fn classify_key(key: &str) -> Option<(String, RecordKind)> {
+    let unit = key.split('_').next()?.to_string();
+    let kind = if key.contains("U1_TR") {
+        RecordKind::TrayPickup
+    } else if key.contains("_A1_") {
+        RecordKind::Arm1
+    } else if key.contains("_A2_") {
+        RecordKind::Arm2
+    } else if key.contains("_PH_") {
+        RecordKind::Preheat
+    } else if key.contains("_TS_") && !key.contains("U6") {
+        RecordKind::TestStage
+    } else if key.contains("_TS_") && key.contains("U6") {
+        RecordKind::IpStage
+    } else if key.contains("U6_T1_") {
+        RecordKind::IpSurfBin
+    } else if key.contains("U6_T2_") {
+        RecordKind::IpBackBin
+    } else if key.contains("U7_PI_") {
+        RecordKind::UldPocket
+    } else if key.contains("U7_CI_") {
+        RecordKind::UldChip
+    } else if key.contains("_AL_") {
+        RecordKind::Alarm
+    } else {
+        return None;
+    };
+    Some((unit, kind))
+}
+
+//ユニット情報以外の受信データのキー
+const HEADER_FIELDS: [&str; 3] = ["MACHINE", "LOT", "TYPE"];
+
+/// 受信データ(json文字列)をユニット情報に分解する
+/// MACHINE・LOT・TYPE が無いか文字列でない場合と、ユニット情報として分類できないキーがある場合はErr
+pub fn parse_frame(message: &str) -> Result<ParsedFrame, String> {
+    let recv_data: Map<String, Value> = serde_json::from_str(message)
+        .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+
+    // 設備名・ロット名・品種は省略できない("unknown" で登録しない)
+    let text_field = |name: &str| match recv_data.get(name) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(value) => Err(format!("Field {} must be a string, got {}", name, value)),
+        None => Err(format!("Missing field {}", name)),
+    };
+
+    let mut records = Vec::new();
+    let mut unknown = Vec::new();
+    for (key, value) in &recv_data {
+        if let Some((unit, kind)) = classify_key(key) {
+            records.push(UnitRecord {
+                key: key.clone(),
+                unit,
+                kind,
+                value: value.clone(),
+            });
+        } else if !HEADER_FIELDS.contains(&key.as_str()) {
+            unknown.push(key.as_str());
+        }
+    }
+
+    let frame = ParsedFrame {
+        machine_name: text_field("MACHINE")?,
+        lot_name: text_field("LOT")?,
+        type_name: text_field("TYPE")?,
+        records,
+    };
+    // ファームウェアの変更やキー名の誤りを見逃さないよう、定義に無いキーがあれば登録しない
+    if !unknown.is_empty() {
+        return Err(format!("Unknown fields: {}", unknown.join(", ")));
+    }
+    Ok(frame)
+}
+
+//受信途中のフレームとして保持する最大サイズ(超えたら破棄する)
+const MAX_FRAME_BYTES: usize = 1024 * 1024;
+
+/// TCPで受信したバイト列をJSONのフレーム(最上位の {...})ごとに分ける
+/// 1回の受信に複数のフレームが入っている場合や、1つのフレームが複数回に分かれて届く場合がある
+/// フレームの間の空白・改行は読み飛ばし、それ以外の文字は1つのフレームとして返す(受信データの検証で隔離される)
+#[derive(Debug, Default)]
+pub struct FrameSplitter {
+    /// 未完成のフレーム(先頭はフレームの開始位置)
+    pending: Vec<u8>,
+    /// 読み終えた位置
+    scanned: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl FrameSplitter {
+    /// 受信したバイト列を追加し、完成したフレームを返す
+    /// 最大サイズを超えても完成しないフレームは破棄してErrを返す
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+        self.pending.extend_from_slice(data);
+        let mut frames = Vec::new();
+        let mut start = 0;
+
+        for i in self.scanned..self.pending.len() {
+            let b = self.pending[i];
+            if self.depth == 0 {
+                if b == b'{' {
+                    let between = &self.pending[start..i];
+                    let first = between.iter().position(|b| !b.is_ascii_whitespace());
+                    let last = between.iter().rposition(|b| !b.is_ascii_whitespace());
+                    if let (Some(first), Some(last)) = (first, last) {
+                        frames.push(Ok(between[first..=last].to_vec()));
+                    }
+                    start = i;
+                    self.depth = 1;
+                }
+                continue;
+            }
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if b == b'\\' {
+                    self.escaped = true;
+                } else if b == b'"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        frames.push(Ok(self.pending[start..=i].to_vec()));
+                        start = i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.pending.drain(..start);
+        self.scanned = self.pending.len();
+        if self.pending.len() > MAX_FRAME_BYTES {
+            frames.push(Err(format!("Discarded {} bytes without a complete frame", self.pending.len())));
+            *self = FrameSplitter::default();
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_frame_splits_units() {
+        let frame = parse_frame(
+            r#"{"MACHINE": "M1", "LOT": "L1", "TYPE": "T1", "U2_TS_1": {"serial": 1001}, "U7_CI_1": {"serial": 1001}, "U3_AL_1": {"alarm_num": 5}}"#,
+        )
+        .unwrap();
+        assert_eq!(frame.machine_name, "M1");
+        assert_eq!(frame.lot_name, "L1");
+        assert_eq!(frame.type_name, "T1");
+        let units: Vec<(&str, &str, RecordKind)> = frame.records.iter().map(|r| (r.key.as_str(), r.unit.as_str(), r.kind)).collect();
+        assert_eq!(
+            units,
+            vec![
+                ("U2_TS_1", "U2", RecordKind::TestStage),
+                ("U3_AL_1", "U3", RecordKind::Alarm),
+                ("U7_CI_1", "U7", RecordKind::UldChip),
+            ]
+        );
+        assert_eq!(frame.records[0].serial(), Some(1001));
+    }
+
+    #[test]
+    fn parse_frame_rejects_unknown_fields() {
+        let err = parse_frame(r#"{"MACHINE": "M1", "LOT": "L1", "TYPE": "T1", "U2_TS_1": {"serial": 1}, "U2_XX_1": {}, "TEMP": 25}"#).unwrap_err();
+        assert_eq!(err, "Unknown fields: TEMP, U2_XX_1");
+    }
+
+    #[test]
+    fn parse_frame_requires_machine_lot_and_type() {
+        for (message, expected) in [
+            (r#"{"LOT": "L1", "TYPE": "T1"}"#, "Missing field MACHINE"),
+            (r#"{"MACHINE": "M1", "TYPE": "T1"}"#, "Missing field LOT"),
+            (r#"{"MACHINE": "M1", "LOT": "L1"}"#, "Missing field TYPE"),
+            (r#"{"MACHINE": "M1", "LOT": 12, "TYPE": "T1"}"#, "Field LOT must be a string, got 12"),
+        ] {
+            assert_eq!(parse_frame(message).unwrap_err(), expected, "{}", message);
+        }
+        assert!(parse_frame("ERROR 12").unwrap_err().starts_with("Failed to parse JSON"));
+    }
+
+    fn texts(frames: Vec<Result<Vec<u8>, String>>) -> Vec<String> {
+        frames.into_iter().map(|frame| String::from_utf8(frame.unwrap()).unwrap()).collect()
+    }
+
+    #[test]
+    fn splitter_separates_frames_in_one_read() {
+        let mut splitter = FrameSplitter::default();
+        let frames = splitter.push(b"{\"ID\": 1}\r\n{\"ID\": 2, \"U2_TS_1\": {\"bin\": [1, 2]}}\n");
+        assert_eq!(texts(frames), vec!["{\"ID\": 1}", "{\"ID\": 2, \"U2_TS_1\": {\"bin\": [1, 2]}}"]);
+        assert!(splitter.push(b"\r\n").is_empty());
+    }
+
+    #[test]
+    fn splitter_joins_frame_split_across_reads() {
+        let mut splitter = FrameSplitter::default();
+        assert!(splitter.push(b"{\"ID\": 1, \"U2_TS_1\": {").is_empty());
+        assert!(splitter.push(b"\"serial\": 10").is_empty());
+        let frames = splitter.push(b"01}}{\"ID\"");
+        assert_eq!(texts(frames), vec!["{\"ID\": 1, \"U2_TS_1\": {\"serial\": 1001}}"]);
+        assert_eq!(texts(splitter.push(b": 2}")), vec!["{\"ID\": 2}"]);
+    }
+
+    #[test]
+    fn splitter_ignores_braces_in_strings() {
+        let mut splitter = FrameSplitter::default();
+        let frames = splitter.push(b"{\"LOT\": \"L}{1\", \"TYPE\": \"\\\"}\"}");
+        assert_eq!(texts(frames), vec!["{\"LOT\": \"L}{1\", \"TYPE\": \"\\\"}\"}"]);
+    }
+
+    #[test]
+    fn splitter_returns_text_between_frames() {
+        let mut splitter = FrameSplitter::default();
+        let frames = splitter.push(b"ERROR 12\n{\"ID\": 1}");
+        assert_eq!(texts(frames), vec!["ERROR 12", "{\"ID\": 1}"]);
+    }
+
+    #[test]
+    fn splitter_discards_oversized_frame() {
+        let mut splitter = FrameSplitter::default();
+        assert!(splitter.push(b"{\"DATA\": \"").is_empty());
+        let frames = splitter.push(&vec![b'x'; MAX_FRAME_BYTES]);
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].is_err());
+
+        // 破棄した後は次のフレームから読める
+        assert_eq!(texts(splitter.push(b"{\"ID\": 1}")), vec!["{\"ID\": 1}"]);
+    }
+}
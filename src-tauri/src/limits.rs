@@ -0,0 +1,335 @@
+///登録する測定値(アライメント補正量・プローブ座標・ステージ高さなど)の規格チェック
+///設定の規格(上下限)と、SPCの連続ルール(一方向への連続増減・中心値の片側への連続)で判定し、
+///違反は limit_violations テーブルに記録して plc-limit-violation イベントでフロントエンドに通知する
+use std::collections::{HashMap, VecDeque};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::chip_store::{ChipWrite, ColumnValue};
+use crate::output_sink::IngestFrame;
+use crate::types::{Config, LimitRule, LimitsConfig};
+
+/// 違反の種類
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// 下限未満
+    BelowMin,
+    /// 上限超過
+    AboveMax,
+    /// 一方向への連続増減
+    Trend,
+    /// 中心値の片側への連続
+    Run,
+}
+
+impl ViolationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationKind::BelowMin => "below_min",
+            ViolationKind::AboveMax => "above_max",
+            ViolationKind::Trend => "trend",
+            ViolationKind::Run => "run",
+        }
+    }
+}
+
+/// 規格違反1件
+#[derive(Serialize, Debug, Clone)]
+pub struct LimitViolation {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub timestamp: String,
+    pub machine: String,
+    pub type_name: String,
+    pub lot: String,
+    pub serial: i64,
+    pub column: String,
+    pub value: i64,
+    pub rule: ViolationKind,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub detail: String,
+}
+
+/// 測定値の規格チェック
+pub struct LimitChecker {
+    config: LimitsConfig,
+    /// (テーブル名, カラム名, ルール番号) -> 直近の測定値(連続ルールの判定用)
+    history: HashMap<(String, String, usize), VecDeque<i64>>,
+    violations: UnboundedSender<LimitViolation>,
+}
+
+impl LimitChecker {
+    pub fn from_config(config: &Config, violations: UnboundedSender<LimitViolation>) -> Self {
+        if config.limits.enabled {
+            log::info!("Limit checks enabled ({} rules)", config.limits.rules.len());
+        }
+        LimitChecker {
+            config: config.limits.clone(),
+            history: HashMap::new(),
+            violations,
+        }
+    }
+
+    /// 受信データの測定値を規格と照合し、違反を返す
+    pub fn check(&mut self, input: &IngestFrame) -> Vec<LimitViolation> {
+        let mut found = Vec::new();
+        if !self.config.enabled || self.config.rules.is_empty() {
+            return found;
+        }
+        let frame = &input.frame;
+
+        // 変換できないユニット情報は登録されないので対象外
+        for writes in input.writes.iter().flatten() {
+            for row in writes.iter().filter_map(|write| match write {
+                ChipWrite::Upsert(row) => Some(row),
+                ChipWrite::Insert(_) => None,
+            }) {
+                for (column, value) in &row.columns {
+                    let ColumnValue::Int(value) = value else {
+                        continue;
+                    };
+                    for (index, rule) in self.config.rules.iter().enumerate() {
+                        if !rule_applies(rule, column, &frame.machine_name, &frame.type_name) {
+                            continue;
+                        }
+                        let key = (input.table_name.clone(), column.clone(), index);
+                        let history = self.history.entry(key).or_default();
+                        for (kind, detail) in evaluate(rule, *value, history) {
+                            found.push(LimitViolation {
+                                plc_id: input.plc_id,
+                                table_name: input.table_name.clone(),
+                                timestamp: input.timestamp.clone(),
+                                machine: frame.machine_name.clone(),
+                                type_name: frame.type_name.clone(),
+                                lot: frame.lot_name.clone(),
+                                serial: row.serial,
+                                column: column.clone(),
+                                value: *value,
+                                rule: kind,
+                                lower: rule.min,
+                                upper: rule.max,
+                                detail,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// 違反をフロントエンドに通知する
+    pub fn notify(&self, violation: LimitViolation) {
+        // 受信側が無い場合(起動前・終了後)は通知しない
+        let _ = self.violations.send(violation);
+    }
+}
+
+fn rule_applies(rule: &LimitRule, column: &str, machine: &str, type_name: &str) -> bool {
+    column_matches(&rule.column, column)
+        && rule.machine.as_deref().map_or(true, |m| m == machine)
+        && rule.type_name.as_deref().map_or(true, |t| t == type_name)
+}
+
+/// カラム名の一致判定(* を1つまで使える)
+fn column_matches(pattern: &str, column: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            column.len() >= prefix.len() + suffix.len() && column.starts_with(prefix) && column.ends_with(suffix)
+        }
+        None => pattern == column,
+    }
+}
+
+/// 1つの測定値をルールで判定する(連続ルールのために直近の値を history に残す)
+fn evaluate(rule: &LimitRule, value: i64, history: &mut VecDeque<i64>) -> Vec<(ViolationKind, String)> {
+    let mut found = Vec::new();
+    let v = value as f64;
+    if let Some(min) = rule.min {
+        if v < min {
+            found.push((ViolationKind::BelowMin, format!("{} < min {}", value, min)));
+        }
+    }
+    if let Some(max) = rule.max {
+        if v > max {
+            found.push((ViolationKind::AboveMax, format!("{} > max {}", value, max)));
+        }
+    }
+
+    let window = rule.trend_points.unwrap_or(0).max(rule.run_points.unwrap_or(0));
+    if window < 2 {
+        return found;
+    }
+    history.push_back(value);
+    while history.len() > window {
+        history.pop_front();
+    }
+
+    let mut fired = false;
+    if let Some(points) = rule.trend_points.filter(|p| *p >= 2) {
+        if history.len() >= points {
+            let recent: Vec<i64> = history.iter().skip(history.len() - points).copied().collect();
+            let increasing = recent.windows(2).all(|w| w[1] > w[0]);
+            let decreasing = recent.windows(2).all(|w| w[1] < w[0]);
+            if increasing || decreasing {
+                let direction = if increasing { "increasing" } else { "decreasing" };
+                found.push((ViolationKind::Trend, format!("{} points {}: {:?}", points, direction, recent)));
+                fired = true;
+            }
+        }
+    }
+    if let Some(points) = rule.run_points.filter(|p| *p >= 2) {
+        let center = rule.center.or(match (rule.min, rule.max) {
+            (Some(min), Some(max)) => Some((min + max) / 2.0),
+            _ => None,
+        });
+        if let Some(center) = center {
+            if history.len() >= points {
+                let recent: Vec<i64> = history.iter().skip(history.len() - points).copied().collect();
+                let above = recent.iter().all(|v| *v as f64 > center);
+                let below = recent.iter().all(|v| (*v as f64) < center);
+                if above || below {
+                    let side = if above { "above" } else { "below" };
+                    found.push((ViolationKind::Run, format!("{} points {} center {}: {:?}", points, side, center, recent)));
+                    fired = true;
+                }
+            }
+        }
+    }
+    // 同じ並びで違反を出し続けないよう、検出したら数え直す
+    if fired {
+        history.clear();
+    }
+    found
+}
+
+/// 規格違反をフロントエンドに通知する(plc-limit-violation)
+pub async fn forward_violations(app: AppHandle, mut violations: UnboundedReceiver<LimitViolation>) {
+    while let Some(violation) = violations.recv().await {
+        if let Err(e) = app.emit("plc-limit-violation", &violation) {
+            log::error!("Failed to emit limit violation: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(column: &str) -> LimitRule {
+        LimitRule {
+            column: column.to_string(),
+            machine: None,
+            type_name: None,
+            min: None,
+            max: None,
+            trend_points: None,
+            run_points: None,
+            center: None,
+        }
+    }
+
+    fn kinds(found: &[(ViolationKind, String)]) -> Vec<ViolationKind> {
+        found.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    #[test]
+    fn column_matches_exact_and_wildcard() {
+        assert!(column_matches("DC1_CHIP_ALIGN_X", "DC1_CHIP_ALIGN_X"));
+        assert!(!column_matches("DC1_CHIP_ALIGN_X", "DC2_CHIP_ALIGN_X"));
+        assert!(column_matches("*_CHIP_ALIGN_X", "DC2_CHIP_ALIGN_X"));
+        assert!(!column_matches("*_CHIP_ALIGN_X", "DC2_CHIP_ALIGN_Y"));
+        assert!(column_matches("DC1_*", "DC1_STAGE_Z"));
+        assert!(column_matches("DC*_Z", "DC1_STAGE_Z"));
+        assert!(column_matches("*", "ANY"));
+        // 前方と後方が重なる場合は一致としない
+        assert!(!column_matches("AB*BC", "ABC"));
+    }
+
+    #[test]
+    fn evaluate_min_max() {
+        let mut limit = rule("X");
+        limit.min = Some(-10.0);
+        limit.max = Some(10.0);
+        let mut history = VecDeque::new();
+        assert!(evaluate(&limit, 0, &mut history).is_empty());
+        assert!(evaluate(&limit, 10, &mut history).is_empty());
+        assert_eq!(kinds(&evaluate(&limit, 11, &mut history)), vec![ViolationKind::AboveMax]);
+        assert_eq!(kinds(&evaluate(&limit, -11, &mut history)), vec![ViolationKind::BelowMin]);
+        // 連続ルールが無い場合は履歴を残さない
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn evaluate_trend_and_history_reset() {
+        let mut limit = rule("X");
+        limit.trend_points = Some(3);
+        let mut history = VecDeque::new();
+        assert!(evaluate(&limit, 1, &mut history).is_empty());
+        assert!(evaluate(&limit, 2, &mut history).is_empty());
+        let found = evaluate(&limit, 3, &mut history);
+        assert_eq!(kinds(&found), vec![ViolationKind::Trend]);
+        assert!(found[0].1.contains("increasing"));
+        // 検出後は数え直すので、続けて増加しても3点揃うまでは出ない
+        assert!(history.is_empty());
+        assert!(evaluate(&limit, 4, &mut history).is_empty());
+        assert!(evaluate(&limit, 5, &mut history).is_empty());
+        assert_eq!(kinds(&evaluate(&limit, 6, &mut history)), vec![ViolationKind::Trend]);
+
+        // 同じ値が入ると増加・減少とはみなさない
+        for value in [5, 5, 4] {
+            assert!(evaluate(&limit, value, &mut history).is_empty());
+        }
+        let found = evaluate(&limit, 3, &mut history);
+        assert_eq!(kinds(&found), vec![ViolationKind::Trend]);
+        assert!(found[0].1.contains("decreasing"));
+    }
+
+    #[test]
+    fn evaluate_run_uses_center_or_midpoint() {
+        let mut limit = rule("X");
+        limit.min = Some(0.0);
+        limit.max = Some(10.0);
+        limit.run_points = Some(3);
+        let mut history = VecDeque::new();
+        // 中心値(min と max の中間)は 5
+        assert!(evaluate(&limit, 6, &mut history).is_empty());
+        assert!(evaluate(&limit, 4, &mut history).is_empty());
+        assert!(evaluate(&limit, 6, &mut history).is_empty());
+        assert!(evaluate(&limit, 7, &mut history).is_empty());
+        let found = evaluate(&limit, 8, &mut history);
+        assert_eq!(kinds(&found), vec![ViolationKind::Run]);
+        assert!(found[0].1.contains("above"));
+        assert!(history.is_empty());
+
+        limit.center = Some(2.0);
+        for value in [1, 0] {
+            assert!(evaluate(&limit, value, &mut history).is_empty());
+        }
+        assert_eq!(kinds(&evaluate(&limit, 1, &mut history)), vec![ViolationKind::Run]);
+
+        // 中心値が決まらない場合は判定しない
+        let mut limit = rule("X");
+        limit.run_points = Some(2);
+        let mut history = VecDeque::new();
+        for value in [1, 1, 1] {
+            assert!(evaluate(&limit, value, &mut history).is_empty());
+        }
+    }
+
+    #[test]
+    fn evaluate_keeps_only_the_window() {
+        let mut limit = rule("X");
+        limit.trend_points = Some(2);
+        limit.run_points = Some(4);
+        limit.center = Some(100.0);
+        let mut history = VecDeque::new();
+        for value in [3, 3, 3, 3, 3] {
+            assert!(evaluate(&limit, value, &mut history).len() <= 1);
+            assert!(history.len() <= 4);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+///他PCのダッシュボードやMQTTブローカー向けに、分解済みの受信データと接続状態を配信する
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+use tokio::sync::broadcast;
+
+use crate::clock;
+use crate::frame::{ParsedFrame, RecordKind};
+use crate::mqtt_publisher;
+use crate::state::{LiveFeedState, MqttState};
+
+/// 接続状態の種類
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveStatus {
+    Connected,
+    Disconnected,
+    Alarm,
+}
+
+/// 配信するイベント
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    /// 1ユニット分の受信データ
+    Frame {
+        plc_id: u32,
+        machine: String,
+        lot: String,
+        type_name: String,
+        unit: String,
+        kind: RecordKind,
+        serial: Option<i64>,
+        fields: Value,
+        timestamp: String,
+    },
+    /// 接続・切断・アラーム
+    Status {
+        plc_id: u32,
+        status: LiveStatus,
+        unit: Option<String>,
+        detail: Value,
+        timestamp: String,
+    },
+}
+
+impl LiveEvent {
+    fn plc_id(&self) -> u32 {
+        match self {
+            LiveEvent::Frame { plc_id, .. } | LiveEvent::Status { plc_id, .. } => *plc_id,
+        }
+    }
+
+    fn unit(&self) -> Option<&str> {
+        match self {
+            LiveEvent::Frame { unit, .. } => Some(unit),
+            LiveEvent::Status { unit, .. } => unit.as_deref(),
+        }
+    }
+}
+
+/// 購読側の絞り込み条件(空の項目は全て通す)
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LiveFilter {
+    pub plc: Vec<u32>,
+    pub unit: Vec<String>,
+    /// frame の kind と同じ表記(接続状態は "status")
+    pub kind: Vec<String>,
+}
+
+impl LiveFilter {
+    /// クエリパラメータ(カンマ区切り)から作成する
+    pub fn from_query(plc: Option<&str>, unit: Option<&str>, kind: Option<&str>) -> Result<Self, String> {
+        let split = |s: Option<&str>| -> Vec<String> {
+            s.map(|s| {
+                s.split(',')
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+        };
+        let plc = split(plc)
+            .iter()
+            .map(|id| id.parse::<u32>().map_err(|_| format!("Invalid PLC id: {}", id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(LiveFilter {
+            plc,
+            unit: split(unit),
+            kind: split(kind),
+        })
+    }
+
+    pub fn matches(&self, event: &LiveEvent) -> bool {
+        if !self.plc.is_empty() && !self.plc.contains(&event.plc_id()) {
+            return false;
+        }
+        // 接続・切断はユニットに紐づかないので、ユニット指定があっても通す
+        if !self.unit.is_empty() {
+            if let Some(unit) = event.unit() {
+                if !self.unit.iter().any(|u| u == unit) {
+                    return false;
+                }
+            }
+        }
+        if !self.kind.is_empty() {
+            let kind = match event {
+                LiveEvent::Frame { kind, .. } => kind.as_str(),
+                LiveEvent::Status { .. } => "status",
+            };
+            if !self.kind.iter().any(|k| k == kind) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 配信チャネルを作成する
+/// buffer は購読者ごとに溜められるイベント数で、遅い購読者は古いイベントから読み飛ばす
+pub fn init_live_feed(buffer: usize) -> LiveFeedState {
+    let (tx, _) = broadcast::channel(buffer.max(1));
+    tx
+}
+
+/// 購読者がいるか(いない場合は分解処理を省略する)
+pub fn has_subscribers(app: &AppHandle) -> bool {
+    app.state::<LiveFeedState>().receiver_count() > 0
+}
+
+fn now() -> String {
+    clock::now_string()
+}
+
+/// 受信データをユニットごとに配信する(アラームは状態イベントとしても配信する)
+/// MQTTへのユニット情報の送信は出力先(mqtt)としてDB書き込みスレッドから行う
+pub fn publish_frame(app: &AppHandle, plc_id: u32, frame: &ParsedFrame, timestamp: &str) {
+    let feed = app.state::<LiveFeedState>();
+    for record in &frame.records {
+        // 送信先がいない場合のエラーは無視する
+        let _ = feed.send(LiveEvent::Frame {
+            plc_id,
+            machine: frame.machine_name.clone(),
+            lot: frame.lot_name.clone(),
+            type_name: frame.type_name.clone(),
+            unit: record.unit.clone(),
+            kind: record.kind,
+            serial: record.serial(),
+            fields: record.value.clone(),
+            timestamp: timestamp.to_string(),
+        });
+
+        if record.kind == RecordKind::Alarm {
+            let _ = feed.send(LiveEvent::Status {
+                plc_id,
+                status: LiveStatus::Alarm,
+                unit: Some(record.unit.clone()),
+                detail: record.value.clone(),
+                timestamp: timestamp.to_string(),
+            });
+        }
+    }
+}
+
+/// 接続・切断をWebSocketの購読者とMQTTブローカーに配信する
+pub fn publish_status(app: &AppHandle, plc_id: u32, status: LiveStatus, reason: &str) {
+    let event = LiveEvent::Status {
+        plc_id,
+        status,
+        unit: None,
+        detail: serde_json::json!({ "reason": reason }),
+        timestamp: now(),
+    };
+    mqtt_publisher::publish_event(&app.state::<MqttState>(), &event);
+    // 送信先がいない場合のエラーは無視する
+    let _ = app.state::<LiveFeedState>().send(event);
+}
@@ -0,0 +1,492 @@
+///ロットの追跡(受信データの LOT・TYPE の切り替わりでロットの開始・完了を判定する)
+///ロットごとの開始・終了時刻、フレーム数、チップ数を lots テーブルに記録し、
+///lot-started・lot-completed イベントでフロントエンドに通知する(設定により完了時に歩留まり集計を出力する)
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+use tauri::{command, AppHandle, Emitter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::consumable_trace::csv_field;
+use crate::data_handler::{is_valid_identifier, open_read_connection};
+use crate::db_query::{lot_yield, table_for_plc};
+use crate::output_sink::IngestFrame;
+use crate::types::{Config, LotTrackingConfig};
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_COMPLETED: &str = "completed";
+
+/// ロットの終了理由
+const END_LOT_CHANGED: &str = "lot_changed";
+const END_TYPE_CHANGED: &str = "type_changed";
+/// 起動時に同じテーブルで実行中のロットが複数残っていた場合(最新以外を完了にする)
+const END_SUPERSEDED: &str = "superseded";
+
+const LOT_COLUMNS: &str = "ID, PLC_ID, TABLE_NAME, MACHINE_NAME, TYPE_NAME, LOT_NAME, STATUS, STARTED_AT, LAST_SEEN_AT, ENDED_AT, END_REASON, FRAME_COUNT, CHIP_COUNT";
+
+/// ロット1件(lots テーブルの1行)
+#[derive(Serialize, Debug, Clone)]
+pub struct LotRecord {
+    /// 登録前は None
+    pub id: Option<i64>,
+    pub plc_id: u32,
+    pub table_name: String,
+    pub machine_name: String,
+    pub type_name: String,
+    pub lot_name: String,
+    pub status: String,
+    pub started_at: String,
+    /// 最後にこのロットの受信データを受け取った時刻
+    pub last_seen_at: String,
+    pub ended_at: Option<String>,
+    pub end_reason: Option<String>,
+    pub frame_count: i64,
+    /// 完了時にPLCのテーブルから数える(SQLiteに登録していない場合は None)
+    pub chip_count: Option<i64>,
+}
+
+/// lots テーブルへの反映内容
+#[derive(Debug, Clone)]
+pub enum LotChange {
+    Started(LotRecord),
+    /// 実行中のロットのフレーム数・最終受信時刻の更新
+    Progress(LotRecord),
+    Completed(LotRecord),
+}
+
+impl LotChange {
+    fn record_mut(&mut self) -> &mut LotRecord {
+        match self {
+            LotChange::Started(record) | LotChange::Progress(record) | LotChange::Completed(record) => record,
+        }
+    }
+}
+
+/// テーブル(PLC)ごとの実行中ロットの追跡
+pub struct LotTracker {
+    config: LotTrackingConfig,
+    /// テーブル名 -> 実行中のロット
+    open: HashMap<String, LotRecord>,
+    /// 次のコミット後に反映する開始・完了
+    pending: Vec<LotChange>,
+    /// 前回の反映以降に受信データのあったテーブル
+    touched: Vec<String>,
+    events: UnboundedSender<LotChange>,
+}
+
+impl LotTracker {
+    pub fn from_config(config: &Config, events: UnboundedSender<LotChange>) -> Self {
+        LotTracker {
+            config: config.lots.clone(),
+            open: HashMap::new(),
+            pending: Vec::new(),
+            touched: Vec::new(),
+            events,
+        }
+    }
+
+    /// 前回の起動時に実行中だったロットを読み込む(再起動で同じロットを開始し直さないため)
+    pub fn restore(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {LOT_COLUMNS} FROM lots WHERE STATUS = ?1 ORDER BY STARTED_AT, ID"
+        ))?;
+        let rows = stmt.query_map(params![STATUS_RUNNING], read_lot)?;
+        for record in rows {
+            let record = record?;
+            if let Some(mut previous) = self.open.insert(record.table_name.clone(), record) {
+                complete(&mut previous, END_SUPERSEDED);
+                self.pending.push(LotChange::Completed(previous));
+            }
+        }
+        if !self.open.is_empty() {
+            log::info!("Restored {} running lots", self.open.len());
+        }
+        Ok(())
+    }
+
+    /// 受信データの LOT・TYPE を見て、切り替わっていれば前のロットを完了にして新しいロットを開始する
+    pub fn observe(&mut self, input: &IngestFrame) {
+        if !self.config.enabled {
+            return;
+        }
+        let frame = &input.frame;
+        // LOT の無い受信データ(段取り中など)ではロットを切り替えない
+        if frame.lot_name.is_empty() {
+            return;
+        }
+        if let Some(current) = self.open.get_mut(&input.table_name) {
+            if current.lot_name == frame.lot_name && current.type_name == frame.type_name {
+                current.frame_count += 1;
+                current.last_seen_at = input.timestamp.clone();
+                if !self.touched.contains(&input.table_name) {
+                    self.touched.push(input.table_name.clone());
+                }
+                return;
+            }
+        }
+
+        if let Some(mut previous) = self.open.remove(&input.table_name) {
+            let reason = if previous.lot_name != frame.lot_name { END_LOT_CHANGED } else { END_TYPE_CHANGED };
+            complete(&mut previous, reason);
+            log::info!(
+                "Lot {} on {} completed ({}, {} frames)",
+                previous.lot_name,
+                previous.table_name,
+                reason,
+                previous.frame_count
+            );
+            self.pending.push(LotChange::Completed(previous));
+        }
+        let record = LotRecord {
+            id: None,
+            plc_id: input.plc_id,
+            table_name: input.table_name.clone(),
+            machine_name: frame.machine_name.clone(),
+            type_name: frame.type_name.clone(),
+            lot_name: frame.lot_name.clone(),
+            status: STATUS_RUNNING.to_string(),
+            started_at: input.timestamp.clone(),
+            last_seen_at: input.timestamp.clone(),
+            ended_at: None,
+            end_reason: None,
+            frame_count: 1,
+            chip_count: None,
+        };
+        log::info!("Lot {} ({}) started on {}", record.lot_name, record.type_name, record.table_name);
+        self.pending.push(LotChange::Started(record.clone()));
+        self.open.insert(input.table_name.clone(), record);
+    }
+
+    /// コミット後に lots テーブルへ反映する内容を取り出す
+    pub fn take_changes(&mut self) -> Vec<LotChange> {
+        let mut changes = std::mem::take(&mut self.pending);
+        for table in self.touched.drain(..) {
+            if let Some(record) = self.open.get(&table) {
+                changes.push(LotChange::Progress(record.clone()));
+            }
+        }
+        changes
+    }
+
+    /// ロットの開始・完了をフロントエンドに通知する
+    pub fn notify(&self, change: LotChange) {
+        if matches!(change, LotChange::Progress(_)) {
+            return;
+        }
+        // 受信側が無い場合(起動前・終了後)は通知しない
+        let _ = self.events.send(change);
+    }
+}
+
+fn complete(record: &mut LotRecord, reason: &str) {
+    record.status = STATUS_COMPLETED.to_string();
+    record.ended_at = Some(record.last_seen_at.clone());
+    record.end_reason = Some(reason.to_string());
+}
+
+fn read_lot(row: &Row) -> rusqlite::Result<LotRecord> {
+    Ok(LotRecord {
+        id: row.get(0)?,
+        plc_id: row.get(1)?,
+        table_name: row.get(2)?,
+        machine_name: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+        type_name: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+        lot_name: row.get(5)?,
+        status: row.get(6)?,
+        started_at: row.get(7)?,
+        last_seen_at: row.get(8)?,
+        ended_at: row.get(9)?,
+        end_reason: row.get(10)?,
+        frame_count: row.get(11)?,
+        chip_count: row.get(12)?,
+    })
+}
+
+/// ロットの開始・更新・完了を lots テーブルに反映する(登録したIDと完了時のチップ数を changes に書き戻す)
+/// 開始の登録に失敗していても後の更新で行ができるよう、全て (TABLE_NAME, LOT_NAME, STARTED_AT) での UPSERT にする
+pub fn save_changes(conn: &Connection, changes: &mut [LotChange]) -> rusqlite::Result<()> {
+    for change in changes.iter_mut() {
+        let completed = matches!(change, LotChange::Completed(_));
+        let record = change.record_mut();
+        if completed && is_valid_identifier(&record.table_name) {
+            // SQLiteに登録していないPLCはテーブルが無いので数えない
+            record.chip_count = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE LOT_NAME = ?1", record.table_name),
+                    params![record.lot_name],
+                    |row| row.get(0),
+                )
+                .ok();
+        }
+        let id = conn
+            .prepare_cached(
+                "INSERT INTO lots (\"PLC_ID\", \"TABLE_NAME\", \"MACHINE_NAME\", \"TYPE_NAME\", \"LOT_NAME\", \"STATUS\", \"STARTED_AT\", \"LAST_SEEN_AT\", \"ENDED_AT\", \"END_REASON\", \"FRAME_COUNT\", \"CHIP_COUNT\")
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                 ON CONFLICT (\"TABLE_NAME\", \"LOT_NAME\", \"STARTED_AT\") DO UPDATE SET
+                    \"STATUS\" = excluded.\"STATUS\", \"LAST_SEEN_AT\" = excluded.\"LAST_SEEN_AT\", \"ENDED_AT\" = excluded.\"ENDED_AT\",
+                    \"END_REASON\" = excluded.\"END_REASON\", \"FRAME_COUNT\" = excluded.\"FRAME_COUNT\", \"CHIP_COUNT\" = COALESCE(excluded.\"CHIP_COUNT\", \"CHIP_COUNT\")
+                 RETURNING \"ID\"",
+            )?
+            .query_row(
+                params![
+                    record.plc_id,
+                    record.table_name,
+                    record.machine_name,
+                    record.type_name,
+                    record.lot_name,
+                    record.status,
+                    record.started_at,
+                    record.last_seen_at,
+                    record.ended_at,
+                    record.end_reason,
+                    record.frame_count,
+                    record.chip_count,
+                ],
+                |row| row.get(0),
+            )?;
+        record.id = Some(id);
+    }
+    Ok(())
+}
+
+/// lots テーブルを開始の新しい順に取得する(table_name・status を省略した場合は全て)
+pub fn list_lot_records(conn: &Connection, table_name: Option<&str>, status: Option<&str>, limit: usize) -> rusqlite::Result<Vec<LotRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {LOT_COLUMNS} FROM lots
+        WHERE (?1 IS NULL OR TABLE_NAME = ?1) AND (?2 IS NULL OR STATUS = ?2)
+        ORDER BY STARTED_AT DESC, ID DESC
+        LIMIT ?3"
+    ))?;
+    let rows = stmt.query_map(params![table_name, status, limit as i64], read_lot)?;
+    rows.collect()
+}
+
+/// ファイル名に使えない文字を _ に置き換える
+fn file_name_part(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// ロットのチップ一覧をCSVにする(Excelで開けるようBOM付き)
+fn chips_csv(conn: &Connection, table_name: &str, lot_name: &str) -> rusqlite::Result<String> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {table_name} WHERE LOT_NAME = ?1 ORDER BY SERIAL"))?;
+    let column_names: Vec<String> = stmt.column_names().iter().map(|name| name.to_string()).collect();
+    let mut csv = format!("\u{feff}{}\r\n", column_names.join(","));
+    let mut rows = stmt.query(params![lot_name])?;
+    while let Some(row) = rows.next()? {
+        let mut fields = Vec::with_capacity(column_names.len());
+        for i in 0..column_names.len() {
+            fields.push(match row.get_ref(i)? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(v) => v.to_string(),
+                ValueRef::Real(v) => v.to_string(),
+                ValueRef::Text(v) => csv_field(&String::from_utf8_lossy(v)),
+                ValueRef::Blob(_) => String::new(),
+            });
+        }
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    Ok(csv)
+}
+
+/// 完了したロットの歩留まり集計(JSON)とチップ一覧(CSV)を出力する(出力したファイルを返す)
+pub fn export_lot(conn: &Connection, record: &LotRecord, dir: &str, pass_bins: &[i64]) -> Result<Vec<PathBuf>, String> {
+    if !is_valid_identifier(&record.table_name) {
+        return Err(format!("Invalid table name: {}", record.table_name));
+    }
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    let stem = format!(
+        "{}_{}_{}",
+        record.table_name,
+        file_name_part(&record.lot_name),
+        file_name_part(&record.started_at)
+    );
+
+    let summary = lot_yield(conn, &record.table_name, &record.lot_name, pass_bins).map_err(|e| e.to_string())?;
+    let json = serde_json::json!({ "lot": record, "yield": summary });
+    let summary_path = dir.join(format!("{}_yield.json", stem));
+    let content = serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?;
+    std::fs::write(&summary_path, content).map_err(|e| format!("Failed to write {:?}: {}", summary_path, e))?;
+
+    let csv = chips_csv(conn, &record.table_name, &record.lot_name).map_err(|e| e.to_string())?;
+    let chips_path = dir.join(format!("{}_chips.csv", stem));
+    std::fs::write(&chips_path, csv).map_err(|e| format!("Failed to write {:?}: {}", chips_path, e))?;
+
+    Ok(vec![summary_path, chips_path])
+}
+
+/// ロットの開始・完了をフロントエンドに通知する(lot-started, lot-completed)
+/// auto_export の場合は完了したロットの歩留まり集計とチップ一覧を出力する
+pub async fn forward_lot_events(app: AppHandle, mut events: UnboundedReceiver<LotChange>, config: LotTrackingConfig, pass_bins: Vec<i64>) {
+    while let Some(change) = events.recv().await {
+        match change {
+            LotChange::Started(record) => {
+                if let Err(e) = app.emit("lot-started", &record) {
+                    log::error!("Failed to emit lot-started: {}", e);
+                }
+            }
+            LotChange::Completed(record) => {
+                if let Err(e) = app.emit("lot-completed", &record) {
+                    log::error!("Failed to emit lot-completed: {}", e);
+                }
+                if !config.auto_export {
+                    continue;
+                }
+                let dir = config.export_dir.clone();
+                let pass_bins = pass_bins.clone();
+                let lot_name = record.lot_name.clone();
+                let result = tauri::async_runtime::spawn_blocking(move || {
+                    let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+                    export_lot(&conn, &record, &dir, &pass_bins)
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result);
+                match result {
+                    Ok(paths) => log::info!("Exported lot {} to {:?}", lot_name, paths),
+                    Err(e) => log::error!("Failed to export lot {}: {}", lot_name, e),
+                }
+            }
+            LotChange::Progress(_) => {}
+        }
+    }
+}
+
+/// ロットの履歴をフロントエンドに返す
+#[command]
+pub async fn get_lot_records(plc_id: Option<u32>, status: Option<String>, limit: Option<usize>) -> Result<Vec<LotRecord>, String> {
+    let table_name = plc_id.map(table_for_plc).transpose()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        list_lot_records(&conn, table_name.as_deref(), status.as_deref(), limit.unwrap_or(500)).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::frame::ParsedFrame;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("sql/create_lots.sql")).unwrap();
+        conn.execute_batch("CREATE TABLE plc1 (\"LOT_NAME\" VARCHAR, \"SERIAL\" INTEGER)").unwrap();
+        conn
+    }
+
+    fn tracker() -> (LotTracker, UnboundedReceiver<LotChange>) {
+        let (sender, receiver) = unbounded_channel();
+        (LotTracker::from_config(&Config::default(), sender), receiver)
+    }
+
+    fn ingest(lot_name: &str, type_name: &str, timestamp: &str) -> IngestFrame {
+        IngestFrame {
+            plc_id: 1,
+            table_name: "plc1".to_string(),
+            timestamp: timestamp.to_string(),
+            received_at: clock::now(),
+            frame: ParsedFrame {
+                machine_name: "M1".to_string(),
+                lot_name: lot_name.to_string(),
+                type_name: type_name.to_string(),
+                records: Vec::new(),
+            },
+            message: String::new(),
+            sinks: None,
+            writes: Vec::new(),
+        }
+    }
+
+    /// 受信データを追跡してコミット後と同じように lots テーブルへ反映する
+    fn observe(tracker: &mut LotTracker, conn: &Connection, input: &IngestFrame) -> Vec<LotChange> {
+        tracker.observe(input);
+        let mut changes = tracker.take_changes();
+        save_changes(conn, &mut changes).unwrap();
+        changes
+    }
+
+    fn lot(conn: &Connection, lot_name: &str) -> LotRecord {
+        list_lot_records(conn, Some("plc1"), None, 10)
+            .unwrap()
+            .into_iter()
+            .find(|record| record.lot_name == lot_name)
+            .unwrap()
+    }
+
+    #[test]
+    fn lot_starts_and_completes_when_lot_changes() {
+        let conn = database();
+        let (mut tracker, _events) = tracker();
+
+        let changes = observe(&mut tracker, &conn, &ingest("L1", "T1", "2026-01-15T08:00:00.000+09:00"));
+        assert!(matches!(changes.as_slice(), [LotChange::Started(_)]));
+        let started = lot(&conn, "L1");
+        assert_eq!(started.status, STATUS_RUNNING);
+        assert_eq!(started.frame_count, 1);
+
+        let changes = observe(&mut tracker, &conn, &ingest("L1", "T1", "2026-01-15T08:05:00.000+09:00"));
+        assert!(matches!(changes.as_slice(), [LotChange::Progress(_)]));
+        conn.execute_batch("INSERT INTO plc1 VALUES ('L1', 1), ('L1', 2), ('L2', 1)").unwrap();
+
+        let changes = observe(&mut tracker, &conn, &ingest("L2", "T1", "2026-01-15T09:00:00.000+09:00"));
+        assert!(matches!(changes.as_slice(), [LotChange::Completed(_), LotChange::Started(_)]));
+
+        // 開始時に登録した行が完了に更新される
+        let completed = lot(&conn, "L1");
+        assert_eq!(completed.id, started.id);
+        assert_eq!(completed.status, STATUS_COMPLETED);
+        assert_eq!(completed.frame_count, 2);
+        assert_eq!(completed.last_seen_at, "2026-01-15T08:05:00.000+09:00");
+        assert_eq!(completed.ended_at.as_deref(), Some("2026-01-15T08:05:00.000+09:00"));
+        assert_eq!(completed.end_reason.as_deref(), Some(END_LOT_CHANGED));
+        assert_eq!(completed.chip_count, Some(2));
+
+        let running = lot(&conn, "L2");
+        assert_eq!(running.status, STATUS_RUNNING);
+        assert_eq!(running.started_at, "2026-01-15T09:00:00.000+09:00");
+    }
+
+    #[test]
+    fn type_change_completes_lot_and_empty_lot_is_ignored() {
+        let conn = database();
+        let (mut tracker, _events) = tracker();
+        observe(&mut tracker, &conn, &ingest("L1", "T1", "2026-01-15T08:00:00.000+09:00"));
+        assert!(observe(&mut tracker, &conn, &ingest("", "T1", "2026-01-15T08:01:00.000+09:00")).is_empty());
+
+        observe(&mut tracker, &conn, &ingest("L1", "T2", "2026-01-15T08:02:00.000+09:00"));
+        let records = list_lot_records(&conn, Some("plc1"), Some(STATUS_COMPLETED), 10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].type_name, "T1");
+        assert_eq!(records[0].end_reason.as_deref(), Some(END_TYPE_CHANGED));
+        assert_eq!(list_lot_records(&conn, Some("plc1"), Some(STATUS_RUNNING), 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restored_lot_continues_after_restart() {
+        let conn = database();
+        let (mut before, _events) = tracker();
+        observe(&mut before, &conn, &ingest("L1", "T1", "2026-01-15T08:00:00.000+09:00"));
+
+        // 再起動後は同じロットを開始し直さずに続ける
+        let (mut restarted, _events) = tracker();
+        restarted.restore(&conn).unwrap();
+        let changes = observe(&mut restarted, &conn, &ingest("L1", "T1", "2026-01-15T08:10:00.000+09:00"));
+        assert!(matches!(changes.as_slice(), [LotChange::Progress(_)]));
+        let records = list_lot_records(&conn, Some("plc1"), None, 10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].frame_count, 2);
+    }
+}
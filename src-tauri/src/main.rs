@@ -8,10 +8,34 @@ mod config;
 mod plc_commands;
 mod tray;
 mod data_handler;
+mod db_queue;
 mod regist_data_to_db;
+mod shutdown;
+mod metrics_server;
+mod db_query;
+mod api_server;
+mod frame;
+mod records;
+mod live_feed;
+mod mqtt_publisher;
+mod output_sink;
+mod chip_store;
+mod postgres_store;
+mod replication;
+mod validation;
+mod limits;
+mod spc;
+mod traceability;
+mod consumable_trace;
+mod tray_map;
+mod wafer_map;
+mod lot_tracker;
+mod production_metrics;
+mod clock;
+mod retention;
 
 use tauri::{
-    Manager,
+    Emitter, Manager,
 };
 use tauri::menu::MenuBuilder;
 use tauri_plugin_dialog::{DialogExt,MessageDialogKind};
@@ -19,16 +43,70 @@ use tauri_plugin_log::{fern, Target, TargetKind};
 use tauri_plugin_single_instance::init as single_instance;
 
 // モジュールからのインポート
-use config::{init_socket, add_plc, edit_plc, delete_plc};
-use plc_commands::{connect_plc, disconnect_plc};
+use config::{init_socket, add_plc, edit_plc, delete_plc, load_config};
+use plc_commands::{connect_plc, disconnect_plc, get_plc_status, collect_plc_status};
 use state::init_connection_state;
-use data_handler::init_database;
+use data_handler::{init_database, get_db_writer_metrics};
+use db_queue::get_db_queue_status;
+use state::{ConnectionState, DbChannelState};
+use shutdown::{request_shutdown, watch_session_end, ShutdownCoordinator, ShutdownState};
+use live_feed::init_live_feed;
+use mqtt_publisher::MqttPublisher;
+use output_sink::{get_output_sink_status, SinkSet};
+use replication::get_replication_status;
+use validation::FrameValidator;
+use limits::LimitChecker;
+use spc::{get_spc_chart, get_spc_columns};
+use traceability::{get_chip_genealogy, get_chip_report};
+use consumable_trace::{export_chips_by_consumable, find_chips_by_consumable};
+use tray_map::{export_tray_map, get_tray_map};
+use wafer_map::{export_wafer_maps, get_wafer_maps};
+use db_query::get_machine_events;
+use lot_tracker::{get_lot_records, LotTracker};
+use production_metrics::{get_production_metrics, get_shift_windows};
+use clock::ClockSkewMonitor;
+use retention::{attach_archives, list_archives, preview_archival, run_archival};
+use state::MqttState;
+use std::sync::Arc;
 
 fn main() {
     let connection_state = init_connection_state();
 
-    // データベースを初期化し、チャネルの送信側を取得
-    let db_channel = match init_database() {
+    // 設定ファイルを読み込む(読めない場合はデフォルト設定でDBを初期化する)
+    let app_config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config, using default DB settings: {}", e);
+            Default::default()
+        }
+    };
+
+    // 受信時刻・PLCの日時を拠点のタイムゾーンで扱う(設定が不正な場合は Asia/Tokyo のまま)
+    if let Err(e) = clock::init(&app_config.time) {
+        eprintln!("Failed to apply time settings: {}", e);
+    }
+
+    // PLCごとの出力先(SQLite・ファイル・MQTT)を作成
+    let mqtt_state: MqttState = Arc::new(MqttPublisher::new(app_config.mqtt.clone()));
+    let sinks = SinkSet::from_config(&app_config, mqtt_state.clone());
+
+    // 受信データの検証(失敗時の通知はアプリ起動後にフロントエンドへ送る)
+    let (quarantine_tx, quarantine_rx) = tokio::sync::mpsc::unbounded_channel();
+    let validator = FrameValidator::from_config(&app_config, quarantine_tx);
+    let (violation_tx, violation_rx) = tokio::sync::mpsc::unbounded_channel();
+    let limits = LimitChecker::from_config(&app_config, violation_tx);
+    let (lot_tx, lot_rx) = tokio::sync::mpsc::unbounded_channel();
+    let lots = LotTracker::from_config(&app_config, lot_tx);
+    let (clock_skew_tx, clock_skew_rx) = tokio::sync::mpsc::unbounded_channel();
+    let clock_skew = ClockSkewMonitor::from_config(&app_config, clock_skew_tx);
+
+    // 保管後にDBを少しずつ縮小できるようにする(書き込みスレッドの開始前に行う)
+    if let Err(e) = retention::prepare_database(&app_config.retention) {
+        eprintln!("Failed to prepare database for retention: {}", e);
+    }
+
+    // データベースを初期化し、書き込みキューを取得
+    let db_channel = match init_database(app_config.db_writer, app_config.db_queue, sinks, validator, limits, lots, clock_skew, connection_state.clone(), app_config.replication.enabled) {
         Ok(tx) => tx,
         Err(e) => {
             eprintln!("Failed to initialize database: {}", e);
@@ -36,10 +114,23 @@ fn main() {
         }
     };
 
+    let shutdown_state: ShutdownState = Arc::new(ShutdownCoordinator::new(app_config.shutdown.drain_timeout_ms));
+    let live_feed = init_live_feed(app_config.api.live_feed_buffer);
+    let metrics_config = app_config.metrics.clone();
+    let api_config = app_config.api.clone();
+    let quality_config = app_config.quality.clone();
+    let lot_config = app_config.lots.clone();
+    let replication_config = app_config.replication.clone();
+    let postgres_config = app_config.postgres.clone();
+    let retention_config = app_config.retention.clone();
+
     tauri::Builder::default()
         .manage(connection_state)
-        .manage(db_channel) // DB チャネルを状態として管理
-        .invoke_handler(tauri::generate_handler![init_socket, connect_plc, disconnect_plc, add_plc, edit_plc, delete_plc])
+        .manage(shutdown_state)
+        .manage(live_feed) // 受信データのライブ配信チャネル
+        .manage(mqtt_state)
+        .manage(db_channel) // DB 書き込みキューを状態として管理
+        .invoke_handler(tauri::generate_handler![init_socket, connect_plc, disconnect_plc, add_plc, edit_plc, delete_plc, get_db_writer_metrics, get_db_queue_status, get_plc_status, get_output_sink_status, get_replication_status, get_spc_columns, get_spc_chart, get_chip_genealogy, get_chip_report, find_chips_by_consumable, export_chips_by_consumable, get_tray_map, export_tray_map, get_wafer_maps, export_wafer_maps, get_machine_events, get_lot_records, get_production_metrics, get_shift_windows, preview_archival, run_archival, list_archives, attach_archives])
         .plugin(tauri_plugin_dialog::init())
         .plugin(
             tauri_plugin_log::Builder::new()
@@ -63,7 +154,7 @@ fn main() {
                 let _ = window.set_focus();
             }
         }))
-        .setup(|app| {
+        .setup(move |app| {
             // ログディレクトリを作成
             if let Err(e) = std::fs::create_dir_all("logs") {
                 eprintln!("Failed to create logs directory: {}", e);
@@ -73,6 +164,79 @@ fn main() {
             tray::setup_tray_icon(app)?;
             log::info!("アプリを起動しました");
 
+            // DBキューの状態を定期的にフロントエンドへ通知
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+                loop {
+                    interval.tick().await;
+                    let status = app_handle.state::<DbChannelState>().status();
+                    if let Err(e) = app_handle.emit("db-queue-status", status) {
+                        eprintln!("Failed to emit db queue status: {}", e);
+                    }
+                }
+            });
+
+            // PLCごとの受信統計を定期的にフロントエンドへ通知
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
+                loop {
+                    interval.tick().await;
+                    let status = collect_plc_status(&app_handle.state::<ConnectionState>());
+                    if let Err(e) = app_handle.emit("plc-stats", status) {
+                        eprintln!("Failed to emit plc stats: {}", e);
+                    }
+                }
+            });
+
+            // 検証に失敗した受信データをフロントエンドへ通知
+            tauri::async_runtime::spawn(validation::forward_reports(app.handle().clone(), quarantine_rx));
+
+            // 規格外の測定値をフロントエンドへ通知
+            tauri::async_runtime::spawn(limits::forward_violations(app.handle().clone(), violation_rx));
+
+            // ロットの開始・完了をフロントエンドへ通知(設定により完了時に集計を出力)
+            tauri::async_runtime::spawn(lot_tracker::forward_lot_events(app.handle().clone(), lot_rx, lot_config, quality_config.pass_bins.clone()));
+
+            // PLCの時計のずれが許容範囲を出入りしたらフロントエンドへ通知
+            tauri::async_runtime::spawn(clock::forward_reports(app.handle().clone(), clock_skew_rx));
+
+            // Prometheus用の /metrics エンドポイントを起動
+            if metrics_config.enabled {
+                tauri::async_runtime::spawn(metrics_server::serve(app.handle().clone(), metrics_config));
+            }
+
+            // MES等向けの読み出し専用HTTP APIとライブ配信(/ws/live)を起動
+            if api_config.enabled {
+                tauri::async_runtime::spawn(api_server::serve(app.handle().clone(), api_config, quality_config));
+            }
+
+            // MQTTブローカーへの送信を開始
+            if app.state::<MqttState>().is_enabled() {
+                tauri::async_runtime::spawn(mqtt_publisher::run(app.handle().clone()));
+            }
+
+            // 中央サーバーへの複製を開始
+            if replication_config.enabled {
+                tauri::async_runtime::spawn(replication::run(app.handle().clone(), replication_config, postgres_config));
+            }
+
+            // 保持期間を過ぎたロットを定期的に保管用DBへ移す
+            if retention_config.enabled {
+                tauri::async_runtime::spawn(retention::run(retention_config));
+            }
+
+            // Windowsのログオフ・シャットダウンでも終了処理を行う
+            watch_session_end(app.handle());
+
+            // OSからの終了シグナルを受けたら終了処理を行う
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                wait_for_os_signal().await;
+                request_shutdown(&app_handle, "OS signal");
+            });
+
             //メニューバーを追加
             let menu = MenuBuilder::new(app)
                 .text("version", "Version")
@@ -106,6 +270,56 @@ fn main() {
                 api.prevent_close();
             }
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            // ウィンドウマネージャ等からの終了要求は一旦止めて、終了処理を経由させる
+            if let tauri::RunEvent::ExitRequested { api, .. } = event {
+                let finished = app_handle.state::<ShutdownState>().is_finished();
+                if !finished {
+                    api.prevent_exit();
+                    request_shutdown(app_handle, "exit request");
+                }
+            }
+        });
+}
+
+/// OSからの終了シグナル(Ctrl+C、SIGTERM、Windowsのコンソール終了・シャットダウン)を待つ
+/// Windowsのコンソールのシグナルはコンソールで動かしている場合(デバッグビルド)だけ届く
+/// リリースビルドのログオフ・シャットダウンは watch_session_end で受ける
+async fn wait_for_os_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut shutdown)) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = close.recv() => {}
+                    _ = shutdown.recv() => {}
+                }
+            }
+            _ => {
+                log::error!("Failed to listen for console close/shutdown signals");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
 }
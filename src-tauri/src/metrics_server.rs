@@ -0,0 +1,239 @@
+///Prometheus/OpenMetrics形式で監視用メトリクスを公開するHTTPサーバー
+use std::fmt::Write;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use tauri::{AppHandle, Manager};
+
+use crate::config::load_config;
+use crate::data_handler::{
+    get_writer_histograms, get_writer_metrics_snapshot, is_valid_identifier, open_read_connection, table_columns,
+    LatencyHistogram, LATENCY_BUCKETS_MS,
+};
+use crate::plc_commands::collect_plc_status;
+use crate::replication::get_replication_snapshot;
+use crate::shutdown::ShutdownState;
+use crate::state::{ConnectionState, DbChannelState, MqttState};
+use crate::types::MetricsConfig;
+
+/// /metrics エンドポイントを起動する(終了処理が始まったら停止する)
+pub async fn serve(app: AppHandle, config: MetricsConfig) {
+    let addr = format!("{}:{}", config.bind_addr, config.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind metrics endpoint to {}: {}", addr, e);
+            return;
+        }
+    };
+    log::info!("Metrics endpoint listening on http://{}/metrics", addr);
+
+    let mut shutdown_rx = app.state::<ShutdownState>().subscribe();
+    let router = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(app);
+
+    let result = axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown_rx.changed().await;
+        })
+        .await;
+    if let Err(e) = result {
+        log::error!("Metrics endpoint stopped with error: {}", e);
+    }
+}
+
+/// GET /metrics
+async fn metrics_handler(State(app): State<AppHandle>) -> impl IntoResponse {
+    let mut body = String::new();
+    render_connection_metrics(&app, &mut body);
+    render_writer_metrics(&app, &mut body);
+
+    // 消耗品カウントはDBを読むので別スレッドで集計する
+    match tauri::async_runtime::spawn_blocking(render_consumable_metrics).await {
+        Ok(consumables) => body.push_str(&consumables),
+        Err(e) => log::error!("Failed to collect consumable metrics: {}", e),
+    }
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        body,
+    )
+}
+
+/// PLCごとの接続状態・受信統計
+fn render_connection_metrics(app: &AppHandle, out: &mut String) {
+    let status = collect_plc_status(&app.state::<ConnectionState>());
+
+    type Getter = fn(&crate::types::PlcStatus) -> f64;
+    let series: [(&str, &str, &str, Getter); 10] = [
+        ("plc_connected", "gauge", "1 if the PLC link is connected", |s| if s.is_connected { 1.0 } else { 0.0 }),
+        ("plc_frames_received_total", "counter", "Frames received from the PLC", |s| s.stats.frames_received as f64),
+        ("plc_bytes_received_total", "counter", "Bytes received from the PLC", |s| s.stats.bytes_received as f64),
+        ("plc_parse_failures_total", "counter", "Frames that could not be parsed", |s| s.stats.parse_failures as f64),
+        ("plc_db_failures_total", "counter", "Unit records that failed to be written to the DB", |s| s.stats.db_failures as f64),
+        ("plc_quarantined_total", "counter", "Frames quarantined by validation", |s| s.stats.quarantined as f64),
+        ("plc_limit_violations_total", "counter", "Measured values outside limits or SPC rules", |s| s.stats.limit_violations as f64),
+        ("plc_clock_skew_seconds", "gauge", "PLC clock minus host receive time on the latest dated unit record", |s| {
+            s.stats.clock_skew_ms.map(|ms| ms as f64 / 1000.0).unwrap_or(0.0)
+        }),
+        ("plc_reconnects_total", "counter", "Reconnects since the application started", |s| s.stats.reconnect_count as f64),
+        ("plc_last_frame_timestamp_seconds", "gauge", "Unix time of the last received frame", |s| {
+            s.stats.last_frame_at.map(|t| t.timestamp_millis() as f64 / 1000.0).unwrap_or(0.0)
+        }),
+    ];
+
+    for (name, kind, help, getter) in series {
+        writeln!(out, "# HELP {} {}", name, help).ok();
+        writeln!(out, "# TYPE {} {}", name, kind).ok();
+        for s in &status {
+            writeln!(
+                out,
+                "{}{{plc_id=\"{}\",table=\"{}\"}} {}",
+                name,
+                s.plc_id,
+                escape_label(&s.table_name),
+                getter(s)
+            )
+            .ok();
+        }
+    }
+}
+
+/// DB書き込みキューと書き込みスレッドのメトリクス
+fn render_writer_metrics(app: &AppHandle, out: &mut String) {
+    let queue = app.state::<DbChannelState>().status();
+    write_single(out, "db_queue_depth", "gauge", "Requests waiting in the DB write queue", queue.depth as f64);
+    write_single(out, "db_queue_capacity", "gauge", "Capacity of the DB write queue", queue.capacity as f64);
+    write_single(out, "db_queue_dropped_total", "counter", "Requests dropped by the drop_oldest policy", queue.dropped_total as f64);
+    write_single(out, "db_queue_blocked_total", "counter", "Pushes that had to wait for free space", queue.blocked_total as f64);
+    write_single(out, "db_queue_spilled_total", "counter", "Requests spilled to disk", queue.spilled_total as f64);
+    write_single(out, "db_queue_spilled_pending", "gauge", "Spilled requests not yet replayed", queue.spilled_pending as f64);
+
+    let writer = get_writer_metrics_snapshot();
+    write_single(out, "db_writer_requests_total", "counter", "Requests processed by the DB writer", writer.total_requests as f64);
+    write_single(out, "db_writer_failed_requests_total", "counter", "Requests with at least one failed unit record", writer.failed_requests as f64);
+    write_single(out, "db_writer_batches_total", "counter", "Transactions committed by the DB writer", writer.total_batches as f64);
+    write_single(out, "db_writer_commit_failures_total", "counter", "Transactions that failed to commit", writer.failed_commits as f64);
+
+    let (commit, queue_latency) = get_writer_histograms();
+    write_histogram(out, "db_writer_commit_duration_seconds", "Time spent committing a transaction", &commit);
+    write_histogram(out, "db_writer_queue_latency_seconds", "Time from enqueue to registration", &queue_latency);
+
+    let mqtt = app.state::<MqttState>();
+    if mqtt.is_enabled() {
+        let (published, dropped) = mqtt.counters();
+        write_single(out, "mqtt_connected", "gauge", "1 if the MQTT broker is connected", if mqtt.is_connected() { 1.0 } else { 0.0 });
+        write_single(out, "mqtt_published_total", "counter", "Messages queued for the MQTT broker", published as f64);
+        write_single(out, "mqtt_dropped_total", "counter", "Messages dropped because the offline buffer was full", dropped as f64);
+    }
+
+    let replication = get_replication_snapshot();
+    if replication.enabled {
+        write_single(out, "replication_online", "gauge", "1 if the last replication attempt succeeded", if replication.online { 1.0 } else { 0.0 });
+        write_single(out, "replication_backlog", "gauge", "Changes not yet sent to the central server", replication.backlog as f64);
+        write_single(out, "replication_sent_rows_total", "counter", "Rows sent to the central server", replication.sent_total as f64);
+    }
+}
+
+/// テーブルに記録されている消耗品(プローブ・ステージ・コレット)の最新カウント
+fn render_consumable_metrics() -> String {
+    let mut out = String::new();
+    let tables = match load_config() {
+        Ok(config) => config.plcs.into_iter().map(|plc| plc.table_name).collect::<Vec<_>>(),
+        Err(e) => {
+            log::error!("Failed to load config for consumable metrics: {}", e);
+            return out;
+        }
+    };
+    let conn = match open_read_connection() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::error!("Failed to open DB for consumable metrics: {}", e);
+            return out;
+        }
+    };
+
+    let columns: Vec<String> = table_columns()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.ends_with("_COUNT") || name.ends_with("_COLLET"))
+        .collect();
+
+    // 各カラムについてNULLでない最新の値を取得する
+    let selects: Vec<String> = columns
+        .iter()
+        .map(|c| format!("(SELECT {c} FROM {{t}} WHERE {c} IS NOT NULL ORDER BY ID DESC LIMIT 1)"))
+        .collect();
+    let sql_template = format!(
+        "SELECT (SELECT MACHINE_NAME FROM {{t}} ORDER BY ID DESC LIMIT 1), {}",
+        selects.join(", ")
+    );
+
+    writeln!(out, "# HELP plc_consumable_count Latest usage count of probe cards, stages and collets stored in the tables").ok();
+    writeln!(out, "# TYPE plc_consumable_count gauge").ok();
+    for table in &tables {
+        if !is_valid_identifier(table) {
+            continue;
+        }
+        let sql = sql_template.replace("{t}", table);
+        let result = conn.query_row(&sql, [], |row| {
+            let machine: Option<String> = row.get(0)?;
+            let mut values = Vec::with_capacity(columns.len());
+            for i in 0..columns.len() {
+                values.push(row.get::<_, Option<i64>>(i + 1)?);
+            }
+            Ok((machine, values))
+        });
+
+        // テーブルが未作成の場合などはスキップ
+        let (machine, values) = match result {
+            Ok(v) => v,
+            Err(e) => {
+                log::debug!("Skipping consumable metrics for {}: {}", table, e);
+                continue;
+            }
+        };
+        let machine = machine.unwrap_or_default();
+        for (column, value) in columns.iter().zip(values) {
+            if let Some(value) = value {
+                let (unit, consumable) = column.split_once('_').unwrap_or(("", column));
+                writeln!(
+                    out,
+                    "plc_consumable_count{{table=\"{}\",machine=\"{}\",unit=\"{}\",consumable=\"{}\"}} {}",
+                    escape_label(table),
+                    escape_label(&machine),
+                    unit,
+                    consumable.to_lowercase(),
+                    value
+                )
+                .ok();
+            }
+        }
+    }
+    out
+}
+
+/// ラベルのない単一のメトリクスを書き出す
+fn write_single(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} {}", name, kind).ok();
+    writeln!(out, "{} {}", name, value).ok();
+}
+
+/// ミリ秒のヒストグラムを秒単位の累積バケットとして書き出す
+fn write_histogram(out: &mut String, name: &str, help: &str, histogram: &LatencyHistogram) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} histogram", name).ok();
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(histogram.counts.iter()) {
+        cumulative += count;
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound / 1000.0, cumulative).ok();
+    }
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count).ok();
+    writeln!(out, "{}_sum {}", name, histogram.sum_ms / 1000.0).ok();
+    writeln!(out, "{}_count {}", name, histogram.count).ok();
+}
+
+/// ラベル値のエスケープ
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
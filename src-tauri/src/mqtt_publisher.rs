@@ -0,0 +1,236 @@
+///分解済みの受信データと接続状態をMQTTブローカーへ送信する
+///ローカルで確認する場合: mosquitto -v を起動し、mosquitto_sub -t 'site/line/#' -v で受信内容を見る
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use parking_lot::Mutex;
+use rumqttc::{AsyncClient, ClientError, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use tauri::{AppHandle, Manager};
+
+use crate::live_feed::{LiveEvent, LiveStatus};
+use crate::output_sink::{IngestFrame, OutputSink};
+use crate::shutdown::ShutdownState;
+use crate::state::MqttState;
+use crate::types::MqttConfig;
+
+//ブローカーに繋がらない場合の再接続間隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// MQTT送信の管理
+/// 送信は非ブロッキングで、ブローカーに繋がらない間は offline_buffer 件まで溜めて再接続後に送る
+pub struct MqttPublisher {
+    config: MqttConfig,
+    client: Option<AsyncClient>,
+    event_loop: Mutex<Option<EventLoop>>,
+    qos: QoS,
+    connected: AtomicBool,
+    published: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl MqttPublisher {
+    pub fn new(config: MqttConfig) -> Self {
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::AtLeastOnce,
+        };
+
+        let (client, event_loop) = if config.enabled {
+            let mut options = MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+            options.set_keep_alive(Duration::from_secs(config.keep_alive_secs.max(5)));
+            // 再接続後も未送信のQoS1/2メッセージを送れるようにセッションを維持する
+            options.set_clean_session(false);
+            options.set_last_will(LastWill::new(&config.client_status_topic, "offline", QoS::AtLeastOnce, true));
+            if let (Some(username), Some(password)) = (&config.username, &config.password) {
+                options.set_credentials(username.clone(), password.clone());
+            }
+            let (client, event_loop) = AsyncClient::new(options, config.offline_buffer.max(1));
+            (Some(client), Some(event_loop))
+        } else {
+            (None, None)
+        };
+
+        MqttPublisher {
+            config,
+            client,
+            event_loop: Mutex::new(event_loop),
+            qos,
+            connected: AtomicBool::new(false),
+            published: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.client.is_some()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// (送信要求数, 破棄数)
+    pub fn counters(&self) -> (u64, u64) {
+        (self.published.load(Ordering::Relaxed), self.dropped.load(Ordering::Relaxed))
+    }
+
+    /// 送信要求をキューに積む(満杯の場合は破棄する)
+    fn try_publish(&self, topic: String, payload: Vec<u8>, retain: bool) {
+        let Some(client) = &self.client else {
+            return;
+        };
+        match client.try_publish(topic.clone(), self.qos, retain, payload) {
+            Ok(()) => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(ClientError::TryRequest(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                // 大量に出力しないよう間引いてログに残す
+                if dropped == 1 || dropped % 1000 == 0 {
+                    log::warn!("MQTT offline buffer is full, {} messages dropped (last topic: {})", dropped, topic);
+                }
+            }
+            Err(e) => log::error!("Failed to publish MQTT message to {}: {}", topic, e),
+        }
+    }
+}
+
+/// トピック内の値に使えない文字を置き換える
+fn topic_segment(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if matches!(c, '/' | '+' | '#') || c.is_control() { '_' } else { c })
+        .collect();
+    if value.is_empty() { "unknown".to_string() } else { value }
+}
+
+/// 配信イベントをトピックに振り分けて送信する
+/// アラームはユニット情報(kind=alarm)として送るので、状態イベントとしては送らない
+pub fn publish_event(publisher: &MqttPublisher, event: &LiveEvent) {
+    if !publisher.is_enabled() {
+        return;
+    }
+
+    let (topic, retain) = match event {
+        LiveEvent::Frame { plc_id, machine, lot, unit, kind, .. } => {
+            let topic = publisher
+                .config
+                .topic_template
+                .replace("{machine}", &topic_segment(machine))
+                .replace("{unit}", &topic_segment(unit))
+                .replace("{kind}", kind.as_str())
+                .replace("{plc_id}", &plc_id.to_string())
+                .replace("{lot}", &topic_segment(lot));
+            (topic, false)
+        }
+        LiveEvent::Status { status: LiveStatus::Alarm, .. } => return,
+        LiveEvent::Status { plc_id, .. } => {
+            let topic = publisher
+                .config
+                .status_topic_template
+                .replace("{plc_id}", &plc_id.to_string());
+            (topic, true)
+        }
+    };
+
+    match serde_json::to_vec(event) {
+        Ok(payload) => publisher.try_publish(topic, payload, retain),
+        Err(e) => log::error!("Failed to serialize MQTT payload: {}", e),
+    }
+}
+
+/// ユニット情報をMQTTに送る出力先(PLCの sinks に "mqtt" を指定したときに使う)
+/// 送信はオフラインバッファに積むだけなので、ブローカーの状態で書き込みスレッドを止めない
+pub struct MqttSink {
+    publisher: MqttState,
+}
+
+impl MqttSink {
+    pub fn new(publisher: MqttState) -> Self {
+        MqttSink { publisher }
+    }
+}
+
+impl OutputSink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn write(&mut self, input: &IngestFrame) -> Result<usize, String> {
+        if !self.publisher.is_enabled() {
+            return Err("MQTT is not enabled in config".to_string());
+        }
+        let frame = &input.frame;
+        for record in &frame.records {
+            let event = LiveEvent::Frame {
+                plc_id: input.plc_id,
+                machine: frame.machine_name.clone(),
+                lot: frame.lot_name.clone(),
+                type_name: frame.type_name.clone(),
+                unit: record.unit.clone(),
+                kind: record.kind,
+                serial: record.serial(),
+                fields: record.value.clone(),
+                timestamp: input.timestamp.clone(),
+            };
+            publish_event(&self.publisher, &event);
+        }
+        Ok(0)
+    }
+}
+
+/// ブローカーとの通信を行う(終了処理が始まったら切断する)
+pub async fn run(app: AppHandle) {
+    let publisher = app.state::<MqttState>().inner().clone();
+    let Some(mut event_loop) = publisher.event_loop.lock().take() else {
+        return;
+    };
+    let mut shutdown_rx = app.state::<ShutdownState>().subscribe();
+    log::info!("MQTT publisher connecting to {}:{}", publisher.config.host, publisher.config.port);
+
+    loop {
+        let event = tokio::select! {
+            _ = shutdown_rx.changed() => break,
+            event = event_loop.poll() => event,
+        };
+
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                log::info!("MQTT connected to {}:{}", publisher.config.host, publisher.config.port);
+                publisher.connected.store(true, Ordering::Relaxed);
+                publisher.try_publish(publisher.config.client_status_topic.clone(), b"online".to_vec(), true);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // 送信途中のメッセージは次の接続で再送される
+                if publisher.connected.swap(false, Ordering::Relaxed) {
+                    log::warn!("MQTT connection lost: {}", e);
+                } else {
+                    log::debug!("MQTT connection failed: {}", e);
+                }
+                tokio::select! {
+                    _ = shutdown_rx.changed() => break,
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                }
+            }
+        }
+    }
+
+    // 正常終了時は各PLCの切断通知を送り終えてからofflineを送って切断する
+    if publisher.is_connected() {
+        let _ = tokio::time::timeout(Duration::from_millis(500), async {
+            while event_loop.poll().await.is_ok() {}
+        })
+        .await;
+        publisher.try_publish(publisher.config.client_status_topic.clone(), b"offline".to_vec(), true);
+        if let Some(client) = &publisher.client {
+            let _ = client.try_disconnect();
+        }
+        // 残りの送信を流しきるまで少し待つ
+        let _ = tokio::time::timeout(Duration::from_secs(2), async {
+            while event_loop.poll().await.is_ok() {}
+        })
+        .await;
+    }
+    log::info!("MQTT publisher stopped");
+}
@@ -0,0 +1,838 @@
+///受信データの出力先(SQLite・PostgreSQL・ファイル・MQTTなど)の共通処理
+///出力先はDB書き込みスレッドから呼ばれ、PLCごとに設定された出力先にだけ書き込む
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset, Local};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tauri::command;
+
+use crate::config::load_config;
+use crate::chip_store::{ChipWrite, StoreSink};
+use crate::data_handler::{SqliteStore, SQLITE_SINK};
+use crate::postgres_store::PostgresStore;
+use crate::frame::{ParsedFrame, UnitRecord};
+use crate::mqtt_publisher::MqttSink;
+use crate::state::MqttState;
+use crate::types::{default_plc_sinks, Config, CsvSinkConfig, JsonlSinkConfig, SinkRetryConfig};
+
+lazy_static! {
+    static ref SINK_STATUS: parking_lot::Mutex<Vec<SinkStatus>> = parking_lot::Mutex::new(Vec::new());
+}
+
+/// 出力先に渡す受信データ1件(ユニットごとに分解済み)
+#[derive(Debug, Clone)]
+pub struct IngestFrame {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub timestamp: String,
+    /// 受信時刻(拠点のタイムゾーン)
+    pub received_at: DateTime<FixedOffset>,
+    pub frame: ParsedFrame,
+    /// 受信データ(JSON文字列)、SQLiteに書けなかった分を退避ファイルに書き出すときに使う
+    pub message: String,
+    /// 書き込む出力先を限定する場合の出力先名(退避ファイルから書き戻したSQLiteへの再送など)
+    pub sinks: Option<Vec<String>>,
+    /// ユニット情報ごとの登録内容(frame.records と同じ順、変換できなかったものはErr)
+    /// 検証・規格照合・時計のずれの確認・DBへの登録で同じものを使う
+    pub writes: Vec<Result<Vec<ChipWrite>, String>>,
+}
+
+impl IngestFrame {
+    /// ユニット情報と登録内容の組
+    pub fn unit_writes(&self) -> impl Iterator<Item = (&UnitRecord, &Result<Vec<ChipWrite>, String>)> {
+        self.frame.records.iter().zip(&self.writes)
+    }
+}
+
+/// 出力先
+/// write の Ok(n) はユニット単位で登録できなかった件数(再送しない)、
+/// Err は出力先自体の異常として再送の対象にする
+pub trait OutputSink: Send {
+    fn name(&self) -> &'static str;
+
+    /// バッチの開始(トランザクション開始など)
+    fn begin_batch(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn write(&mut self, input: &IngestFrame) -> Result<usize, String>;
+
+    /// バッチの確定(コミット・フラッシュなど)
+    /// 失敗した場合はバッチ内で書き込んだ分を全て再送する
+    fn end_batch(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// 出力先ごとの状態(フロントエンド返却用)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SinkStatus {
+    pub name: String,
+    pub written: u64,
+    pub unit_failures: u64,
+    pub retried: u64,
+    pub dropped: u64,
+    pub pending_retry: usize,
+    pub consecutive_errors: u32,
+    pub last_error: Option<String>,
+}
+
+/// 1バッチ分の結果
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// (出力先名, PLC ID) -> 登録できなかった件数(ユニット単位の失敗と再送を諦めた件数)
+    pub failures: HashMap<(&'static str, u32), u64>,
+    /// バッチを確定できなかった出力先
+    pub failed_sinks: Vec<&'static str>,
+    /// SQLiteの再送待ちが保持件数を超えた分(破棄せず退避ファイルに書き出す)
+    pub overflow: Vec<Arc<IngestFrame>>,
+}
+
+/// 再送待ちの受信データ
+struct Pending {
+    input: Arc<IngestFrame>,
+    attempts: u32,
+}
+
+/// 出力先1つ分の実行状態
+/// 異常が起きた出力先は待ち時間を置いて再送し、他の出力先には影響させない
+struct SinkRunner {
+    sink: Box<dyn OutputSink>,
+    began: bool,
+    healthy: bool,
+    batch: Vec<Pending>,
+    retry: VecDeque<Pending>,
+    backoff_until: Option<Instant>,
+    status: SinkStatus,
+}
+
+impl SinkRunner {
+    fn new(sink: Box<dyn OutputSink>) -> Self {
+        let status = SinkStatus {
+            name: sink.name().to_string(),
+            ..Default::default()
+        };
+        SinkRunner {
+            sink,
+            began: false,
+            healthy: false,
+            batch: Vec::new(),
+            retry: VecDeque::new(),
+            backoff_until: None,
+            status,
+        }
+    }
+}
+
+/// 有効な出力先の集合
+pub struct SinkSet {
+    runners: Vec<SinkRunner>,
+    routes: HashMap<u32, Vec<String>>,
+    retry_config: SinkRetryConfig,
+    report: BatchReport,
+}
+
+impl SinkSet {
+    /// PLCごとの設定で使われている出力先だけを有効にする
+    pub fn new(routes: HashMap<u32, Vec<String>>, retry_config: SinkRetryConfig, sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        // 起動後に追加されたPLCはデフォルトの出力先を使うので常に有効にする
+        let defaults = default_plc_sinks();
+        let mut used: Vec<&String> = routes.values().flatten().collect();
+        used.extend(defaults.iter());
+
+        for name in &used {
+            if !sinks.iter().any(|sink| sink.name() == name.as_str()) {
+                log::warn!("Unknown output sink '{}' in config, ignored", name);
+            }
+        }
+        let runners: Vec<SinkRunner> = sinks
+            .into_iter()
+            .filter(|sink| used.iter().any(|name| name.as_str() == sink.name()))
+            .map(|sink| {
+                log::info!("Output sink '{}' enabled", sink.name());
+                SinkRunner::new(sink)
+            })
+            .collect();
+
+        let set = SinkSet {
+            runners,
+            routes,
+            retry_config,
+            report: BatchReport::default(),
+        };
+        set.publish_status();
+        set
+    }
+
+    /// 設定ファイルから出力先を作成する
+    pub fn from_config(config: &Config, mqtt: MqttState) -> Self {
+        let routes = config.plcs.iter().map(|plc| (plc.id, plc.sinks.clone())).collect();
+        let sinks: Vec<Box<dyn OutputSink>> = vec![
+            Box::new(StoreSink::new(SqliteStore::new(config.replication.enabled), config.history.enabled)),
+            Box::new(StoreSink::new(PostgresStore::new(&config.postgres), config.history.enabled)),
+            Box::new(JsonlSink::new(&config.sinks.jsonl)),
+            Box::new(CsvSink::new(&config.sinks.csv)),
+            Box::new(MqttSink::new(mqtt)),
+        ];
+        SinkSet::new(routes, config.sinks.retry.clone(), sinks)
+    }
+
+    /// PLCの出力先を返す(起動後に追加されたPLCは設定ファイルを読み直す)
+    fn sinks_for(&mut self, plc_id: u32) -> &[String] {
+        self.routes.entry(plc_id).or_insert_with(|| {
+            load_config()
+                .ok()
+                .and_then(|config| config.plcs.into_iter().find(|plc| plc.id == plc_id))
+                .map(|plc| plc.sinks)
+                .unwrap_or_else(default_plc_sinks)
+        })
+    }
+
+    /// バッチを開始し、待ち時間が過ぎた出力先は再送待ちの分から書き込む
+    pub fn begin_batch(&mut self) {
+        self.report = BatchReport::default();
+        let now = Instant::now();
+        for i in 0..self.runners.len() {
+            let runner = &mut self.runners[i];
+            runner.began = false;
+            runner.healthy = false;
+            if runner.backoff_until.is_some_and(|until| now < until) {
+                continue;
+            }
+            match runner.sink.begin_batch() {
+                Ok(()) => {
+                    runner.began = true;
+                    runner.healthy = true;
+                }
+                Err(e) => {
+                    self.fail(i, e);
+                    continue;
+                }
+            }
+
+            let retry: Vec<Pending> = self.runners[i].retry.drain(..).collect();
+            self.runners[i].status.retried += retry.len() as u64;
+            for pending in retry {
+                self.write_to(i, pending);
+            }
+        }
+    }
+
+    /// 受信データ1件をPLCに設定された出力先へ書き込む
+    pub fn write(&mut self, input: IngestFrame) {
+        let input = Arc::new(input);
+        let sinks = match &input.sinks {
+            Some(sinks) => sinks.clone(),
+            None => self.sinks_for(input.plc_id).to_vec(),
+        };
+        for i in 0..self.runners.len() {
+            if sinks.iter().any(|s| s == self.runners[i].sink.name()) {
+                self.write_to(i, Pending { input: Arc::clone(&input), attempts: 0 });
+            }
+        }
+    }
+
+    fn write_to(&mut self, i: usize, pending: Pending) {
+        let runner = &mut self.runners[i];
+        // 異常中・待機中の出力先には書かずに再送待ちにする
+        if !runner.healthy {
+            self.defer(i, pending, false);
+            return;
+        }
+        match runner.sink.write(&pending.input) {
+            Ok(unit_failures) => {
+                if unit_failures > 0 {
+                    runner.status.unit_failures += unit_failures as u64;
+                    *self.report.failures.entry((runner.sink.name(), pending.input.plc_id)).or_default() += unit_failures as u64;
+                }
+                runner.batch.push(pending);
+            }
+            Err(e) => {
+                self.fail(i, e);
+                self.defer(i, pending, true);
+            }
+        }
+    }
+
+    /// バッチを確定する
+    pub fn end_batch(&mut self) -> BatchReport {
+        for i in 0..self.runners.len() {
+            let runner = &mut self.runners[i];
+            if !runner.began {
+                continue;
+            }
+            let result = runner.sink.end_batch();
+            let batch: Vec<Pending> = runner.batch.drain(..).collect();
+            match result {
+                Ok(()) => {
+                    runner.status.written += batch.len() as u64;
+                    if runner.healthy {
+                        runner.status.consecutive_errors = 0;
+                        runner.backoff_until = None;
+                    }
+                }
+                Err(e) => {
+                    self.report.failed_sinks.push(self.runners[i].sink.name());
+                    self.fail(i, e);
+                    for pending in batch {
+                        self.defer(i, pending, true);
+                    }
+                }
+            }
+        }
+        self.publish_status();
+        std::mem::take(&mut self.report)
+    }
+
+    /// 出力先の異常を記録し、次に試すまでの待ち時間を決める
+    fn fail(&mut self, i: usize, error: String) {
+        let runner = &mut self.runners[i];
+        runner.healthy = false;
+        runner.status.consecutive_errors += 1;
+        let exponent = runner.status.consecutive_errors.saturating_sub(1).min(16);
+        let backoff_ms = self
+            .retry_config
+            .initial_backoff_ms
+            .saturating_mul(1 << exponent)
+            .min(self.retry_config.max_backoff_ms);
+        runner.backoff_until = Some(Instant::now() + Duration::from_millis(backoff_ms));
+        if runner.status.consecutive_errors == 1 {
+            log::error!("Output sink '{}' failed: {}", runner.sink.name(), error);
+        } else {
+            log::warn!(
+                "Output sink '{}' failed ({} times in a row, retry in {}ms): {}",
+                runner.sink.name(),
+                runner.status.consecutive_errors,
+                backoff_ms,
+                error
+            );
+        }
+        runner.status.last_error = Some(error);
+    }
+
+    /// 再送待ちにする(試行回数か保持件数の上限を超えたら破棄する)
+    /// SQLiteは破棄せず、保持件数を超えた分は退避ファイルに書き出すために overflow に回す
+    /// attempted は実際に書き込みを試して失敗したか
+    fn defer(&mut self, i: usize, mut pending: Pending, attempted: bool) {
+        let runner = &mut self.runners[i];
+        if attempted {
+            pending.attempts += 1;
+        }
+        let name = runner.sink.name();
+        let primary = name == SQLITE_SINK;
+        if !primary && pending.attempts >= self.retry_config.max_attempts {
+            log::error!(
+                "Output sink '{}' gave up on data from PLC {} after {} attempts",
+                name,
+                pending.input.plc_id,
+                pending.attempts
+            );
+            runner.status.dropped += 1;
+            *self.report.failures.entry((name, pending.input.plc_id)).or_default() += 1;
+            return;
+        }
+        if runner.retry.len() >= self.retry_config.buffer.max(1) {
+            if let Some(oldest) = runner.retry.pop_front() {
+                if primary {
+                    self.report.overflow.push(oldest.input);
+                } else {
+                    runner.status.dropped += 1;
+                    *self.report.failures.entry((name, oldest.input.plc_id)).or_default() += 1;
+                }
+            }
+        }
+        runner.retry.push_back(pending);
+    }
+
+    /// SQLiteに再送待ちがあり、次に試すまでの待ち時間中であればその時刻を返す
+    /// DB書き込みスレッドはこの間キューから新しいリクエストを取り出さない
+    pub fn sqlite_backoff(&self) -> Option<Instant> {
+        let runner = self.runners.iter().find(|runner| runner.sink.name() == SQLITE_SINK)?;
+        if runner.retry.is_empty() {
+            return None;
+        }
+        runner.backoff_until.filter(|until| Instant::now() < *until)
+    }
+
+    /// SQLiteの再送待ちを全て取り出す(終了時に退避ファイルへ書き出す用)
+    pub fn take_sqlite_retry(&mut self) -> Vec<Arc<IngestFrame>> {
+        let retry: Vec<Arc<IngestFrame>> = self
+            .runners
+            .iter_mut()
+            .filter(|runner| runner.sink.name() == SQLITE_SINK)
+            .flat_map(|runner| runner.retry.drain(..).map(|pending| pending.input))
+            .collect();
+        self.publish_status();
+        retry
+    }
+
+    fn publish_status(&self) {
+        let status = self
+            .runners
+            .iter()
+            .map(|runner| SinkStatus {
+                pending_retry: runner.retry.len(),
+                ..runner.status.clone()
+            })
+            .collect();
+        *SINK_STATUS.lock() = status;
+    }
+}
+
+/// 出力先ごとの書き込み状況をフロントエンドに返す
+#[command]
+pub fn get_output_sink_status() -> Vec<SinkStatus> {
+    SINK_STATUS.lock().clone()
+}
+
+/// ファイル出力で1行分として書き出すユニット情報
+#[derive(Serialize)]
+struct OutputLine<'a> {
+    timestamp: &'a str,
+    plc_id: u32,
+    table_name: &'a str,
+    machine: &'a str,
+    lot: &'a str,
+    type_name: &'a str,
+    unit: &'a str,
+    kind: &'static str,
+    key: &'a str,
+    serial: Option<i64>,
+    fields: &'a serde_json::Value,
+}
+
+impl<'a> OutputLine<'a> {
+    fn new(input: &'a IngestFrame, record: &'a UnitRecord) -> Self {
+        OutputLine {
+            timestamp: &input.timestamp,
+            plc_id: input.plc_id,
+            table_name: &input.table_name,
+            machine: &input.frame.machine_name,
+            lot: &input.frame.lot_name,
+            type_name: &input.frame.type_name,
+            unit: &record.unit,
+            kind: record.kind.as_str(),
+            key: &record.key,
+            serial: record.serial(),
+            fields: &record.value,
+        }
+    }
+}
+
+/// 開いている出力ファイル
+struct OpenFile {
+    path: PathBuf,
+    file: File,
+    date: String,
+    bytes: u64,
+}
+
+impl OpenFile {
+    /// 受信データ1件分の行をまとめて書き込む
+    /// 途中で失敗した場合は書き込み前の長さに切り詰め、再送で同じ行が重複しないようにする
+    fn append(&mut self, lines: &str) -> Result<(), String> {
+        if let Err(e) = self.file.write_all(lines.as_bytes()) {
+            if let Err(te) = self.file.set_len(self.bytes) {
+                log::error!("Failed to truncate {:?} after write error: {}", self.path, te);
+            }
+            return Err(format!("Failed to write {:?}: {}", self.path, e));
+        }
+        self.bytes += lines.len() as u64;
+        Ok(())
+    }
+}
+
+fn open_append(path: &PathBuf) -> Result<(File, u64), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    let bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((file, bytes))
+}
+
+/// テーブル・日付ごとのJSON Linesファイルに出力する
+pub struct JsonlSink {
+    dir: PathBuf,
+    files: HashMap<String, OpenFile>,
+}
+
+impl JsonlSink {
+    pub fn new(config: &JsonlSinkConfig) -> Self {
+        JsonlSink {
+            dir: PathBuf::from(&config.dir),
+            files: HashMap::new(),
+        }
+    }
+
+    fn file_for(&mut self, table_name: &str) -> Result<&mut OpenFile, String> {
+        let date = Local::now().format("%Y%m%d").to_string();
+        let reopen = self.files.get(table_name).map(|f| f.date != date).unwrap_or(true);
+        if reopen {
+            let path = self.dir.join(format!("{}_{}.jsonl", table_name, date));
+            let (file, bytes) = open_append(&path)?;
+            self.files.insert(table_name.to_string(), OpenFile { path, file, date, bytes });
+        }
+        Ok(self.files.get_mut(table_name).unwrap())
+    }
+}
+
+impl OutputSink for JsonlSink {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn write(&mut self, input: &IngestFrame) -> Result<usize, String> {
+        let mut lines = String::new();
+        for record in &input.frame.records {
+            let line = serde_json::to_string(&OutputLine::new(input, record)).map_err(|e| e.to_string())?;
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+        let result = self.file_for(&input.table_name)?.append(&lines);
+        if result.is_err() {
+            // 次の書き込みで開き直す
+            self.files.remove(&input.table_name);
+        }
+        result.map(|_| 0)
+    }
+}
+
+//CSVのヘッダー
+const CSV_HEADER: &str = "timestamp,plc_id,table_name,machine,lot,type_name,unit,kind,key,serial,fields";
+
+/// CSVの1項目をエスケープする
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// テーブルごとのCSVファイルに出力する(日付が変わるか最大サイズを超えたら新しいファイルにする)
+pub struct CsvSink {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    files: HashMap<String, OpenFile>,
+}
+
+impl CsvSink {
+    pub fn new(config: &CsvSinkConfig) -> Self {
+        CsvSink {
+            dir: PathBuf::from(&config.dir),
+            max_file_bytes: config.max_file_bytes.max(1024),
+            files: HashMap::new(),
+        }
+    }
+
+    fn file_for(&mut self, table_name: &str) -> Result<&mut OpenFile, String> {
+        let now = Local::now();
+        let date = now.format("%Y%m%d").to_string();
+        let roll = match self.files.get(table_name) {
+            Some(f) => f.date != date || f.bytes >= self.max_file_bytes,
+            None => true,
+        };
+        if roll {
+            self.files.remove(table_name);
+            let path = roll_path(&self.dir, table_name, &now);
+            let (file, bytes) = open_append(&path)?;
+            let mut file = OpenFile { path, file, date, bytes };
+            if file.bytes == 0 {
+                file.append(&format!("{}\n", CSV_HEADER))?;
+            }
+            self.files.insert(table_name.to_string(), file);
+        }
+        Ok(self.files.get_mut(table_name).unwrap())
+    }
+}
+
+/// 切り替え後のCSVファイルのパス(ミリ秒まで付け、同じ名前のファイルがあれば連番を付ける)
+fn roll_path(dir: &Path, table_name: &str, now: &DateTime<Local>) -> PathBuf {
+    let stem = format!("{}_{}", table_name, now.format("%Y%m%d_%H%M%S_%3f"));
+    let mut path = dir.join(format!("{}.csv", stem));
+    let mut seq = 1;
+    while path.exists() {
+        path = dir.join(format!("{}_{}.csv", stem, seq));
+        seq += 1;
+    }
+    path
+}
+
+impl OutputSink for CsvSink {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn write(&mut self, input: &IngestFrame) -> Result<usize, String> {
+        let mut rows = String::new();
+        for record in &input.frame.records {
+            let line = OutputLine::new(input, record);
+            let row = [
+                csv_field(line.timestamp),
+                line.plc_id.to_string(),
+                csv_field(line.table_name),
+                csv_field(line.machine),
+                csv_field(line.lot),
+                csv_field(line.type_name),
+                csv_field(line.unit),
+                line.kind.to_string(),
+                csv_field(line.key),
+                line.serial.map(|s| s.to_string()).unwrap_or_default(),
+                csv_field(&line.fields.to_string()),
+            ]
+            .join(",");
+            rows.push_str(&row);
+            rows.push('\n');
+        }
+        let result = self.file_for(&input.table_name)?.append(&rows);
+        if result.is_err() {
+            self.files.remove(&input.table_name);
+        }
+        result.map(|_| 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::RecordKind;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // 失敗させるかどうかを外から切り替えられる出力先
+    struct FakeSink {
+        name: &'static str,
+        down: Arc<AtomicBool>,
+        written: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl OutputSink for FakeSink {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn write(&mut self, input: &IngestFrame) -> Result<usize, String> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err("sink is down".to_string());
+            }
+            self.written.lock().push(input.plc_id);
+            Ok(0)
+        }
+    }
+
+    fn sink_set(name: &'static str, retry: SinkRetryConfig) -> (SinkSet, Arc<AtomicBool>, Arc<Mutex<Vec<u32>>>) {
+        let down = Arc::new(AtomicBool::new(false));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink = FakeSink { name, down: down.clone(), written: written.clone() };
+        let routes = (1..=5).map(|plc_id| (plc_id, vec![name.to_string()])).collect();
+        (SinkSet::new(routes, retry, vec![Box::new(sink)]), down, written)
+    }
+
+    fn retry_config(max_attempts: u32, buffer: usize) -> SinkRetryConfig {
+        SinkRetryConfig {
+            max_attempts,
+            initial_backoff_ms: 20,
+            max_backoff_ms: 40,
+            buffer,
+        }
+    }
+
+    fn frame(plc_id: u32, table_name: &str, units: &[&str]) -> IngestFrame {
+        let records = units
+            .iter()
+            .map(|unit| UnitRecord {
+                key: format!("{}_TS_1", unit),
+                unit: unit.to_string(),
+                kind: RecordKind::TestStage,
+                value: serde_json::json!({"serial": 1001, "bin": "1,2"}),
+            })
+            .collect();
+        IngestFrame {
+            plc_id,
+            table_name: table_name.to_string(),
+            timestamp: "2024-01-01 00:00:00".to_string(),
+            received_at: DateTime::parse_from_rfc3339("2024-01-01T00:00:00.000+09:00").unwrap(),
+            frame: ParsedFrame {
+                machine_name: "M1".to_string(),
+                lot_name: "L1".to_string(),
+                type_name: "T1".to_string(),
+                records,
+            },
+            message: "{}".to_string(),
+            sinks: None,
+            writes: Vec::new(),
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("output_sink_test_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn failed_sink_retries_after_backoff() {
+        let (mut sinks, down, written) = sink_set("jsonl", retry_config(5, 10));
+
+        down.store(true, Ordering::SeqCst);
+        sinks.begin_batch();
+        sinks.write(frame(1, "PLC_1", &["U2"]));
+        sinks.write(frame(2, "PLC_2", &["U2"]));
+        let report = sinks.end_batch();
+        assert!(report.failures.is_empty());
+        let runner = &sinks.runners[0];
+        assert_eq!(runner.retry.len(), 2);
+        assert_eq!(runner.status.consecutive_errors, 1);
+        assert!(runner.backoff_until.is_some());
+
+        // 待ち時間中は書き込みを試さない
+        down.store(false, Ordering::SeqCst);
+        sinks.begin_batch();
+        sinks.write(frame(3, "PLC_3", &["U2"]));
+        sinks.end_batch();
+        assert!(written.lock().is_empty());
+        assert_eq!(sinks.runners[0].retry.len(), 3);
+
+        // 待ち時間が過ぎたら再送待ちの分から書き込む
+        std::thread::sleep(Duration::from_millis(30));
+        sinks.begin_batch();
+        sinks.write(frame(4, "PLC_4", &["U2"]));
+        sinks.end_batch();
+        assert_eq!(*written.lock(), vec![1, 2, 3, 4]);
+        let status = &sinks.runners[0].status;
+        assert_eq!(status.retried, 3);
+        assert_eq!(status.written, 4);
+        assert_eq!(status.consecutive_errors, 0);
+        assert!(sinks.runners[0].retry.is_empty());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let (mut sinks, down, _) = sink_set("jsonl", retry_config(10, 10));
+        down.store(true, Ordering::SeqCst);
+
+        let mut waits = Vec::new();
+        for _ in 0..3 {
+            sinks.runners[0].backoff_until = None;
+            sinks.begin_batch();
+            sinks.write(frame(1, "PLC_1", &["U2"]));
+            sinks.end_batch();
+            let until = sinks.runners[0].backoff_until.unwrap();
+            waits.push(until.saturating_duration_since(Instant::now()).as_millis());
+        }
+        // 20ms → 40ms → 上限の40ms
+        assert!(waits[0] <= 20 && waits[0] > 10, "{:?}", waits);
+        assert!(waits[1] <= 40 && waits[1] > 20, "{:?}", waits);
+        assert!(waits[2] <= 40 && waits[2] > 20, "{:?}", waits);
+    }
+
+    #[test]
+    fn non_primary_sink_gives_up_after_max_attempts() {
+        let (mut sinks, down, _) = sink_set("jsonl", retry_config(2, 10));
+        down.store(true, Ordering::SeqCst);
+
+        sinks.begin_batch();
+        sinks.write(frame(1, "PLC_1", &["U2"]));
+        assert!(sinks.end_batch().failures.is_empty());
+
+        std::thread::sleep(Duration::from_millis(30));
+        sinks.begin_batch();
+        let report = sinks.end_batch();
+        assert_eq!(report.failures.get(&("jsonl", 1)), Some(&1));
+        assert_eq!(sinks.runners[0].status.dropped, 1);
+        assert!(sinks.runners[0].retry.is_empty());
+    }
+
+    #[test]
+    fn sqlite_retry_overflow_is_spilled_not_dropped() {
+        let (mut sinks, down, _) = sink_set(SQLITE_SINK, retry_config(1, 2));
+        down.store(true, Ordering::SeqCst);
+
+        sinks.begin_batch();
+        for plc_id in 1..=4 {
+            sinks.write(frame(plc_id, "PLC_1", &["U2"]));
+        }
+        let report = sinks.end_batch();
+
+        // SQLiteは試行回数の上限で破棄せず、保持件数を超えた古いものを退避ファイル用に返す
+        let overflow: Vec<u32> = report.overflow.iter().map(|input| input.plc_id).collect();
+        assert_eq!(overflow, vec![1, 2]);
+        assert!(report.failures.is_empty());
+        assert_eq!(sinks.runners[0].status.dropped, 0);
+        assert!(sinks.sqlite_backoff().is_some());
+
+        let retry: Vec<u32> = sinks.take_sqlite_retry().iter().map(|input| input.plc_id).collect();
+        assert_eq!(retry, vec![3, 4]);
+        assert!(sinks.sqlite_backoff().is_none());
+    }
+
+    #[test]
+    fn jsonl_sink_writes_one_line_per_unit() {
+        let dir = test_dir("jsonl");
+        let mut sink = JsonlSink::new(&JsonlSinkConfig { dir: dir.to_string_lossy().into_owned() });
+        assert_eq!(sink.write(&frame(1, "PLC_1", &["U2", "U3"])), Ok(0));
+        sink.write(&frame(1, "PLC_1", &["U4"])).unwrap();
+
+        let path = &sink.files["PLC_1"].path;
+        let text = fs::read_to_string(path).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["unit"], "U3");
+        assert_eq!(lines[1]["kind"], "test_stage");
+        assert_eq!(lines[1]["serial"], 1001);
+        assert_eq!(sink.files["PLC_1"].bytes, text.len() as u64);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn csv_sink_rolls_to_new_file_with_header() {
+        let dir = test_dir("csv");
+        let mut sink = CsvSink::new(&CsvSinkConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            max_file_bytes: 1024,
+        });
+        let units: Vec<String> = (1..=8).map(|i| format!("U{}", i)).collect();
+        let units: Vec<&str> = units.iter().map(|u| u.as_str()).collect();
+        for _ in 0..3 {
+            sink.write(&frame(1, "PLC_1", &units)).unwrap();
+        }
+
+        // 最大サイズを超えたら同じ時刻でも別のファイルに切り替わる
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        assert!(files.len() >= 2, "{:?}", files);
+        let mut rows = 0;
+        for path in &files {
+            let text = fs::read_to_string(path).unwrap();
+            assert_eq!(text.lines().next(), Some(CSV_HEADER));
+            rows += text.lines().count() - 1;
+        }
+        assert_eq!(rows, 24);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn csv_roll_path_is_unique() {
+        let dir = test_dir("csv_roll");
+        fs::create_dir_all(&dir).unwrap();
+        let now = DateTime::parse_from_rfc3339("2024-01-01T12:34:56.789+09:00").unwrap().with_timezone(&Local);
+
+        let first = roll_path(&dir, "PLC_1", &now);
+        assert!(first.to_string_lossy().ends_with(&format!("PLC_1_{}.csv", now.format("%Y%m%d_%H%M%S_789"))));
+        fs::write(&first, "").unwrap();
+        let second = roll_path(&dir, "PLC_1", &now);
+        assert_ne!(first, second);
+        assert!(second.to_string_lossy().ends_with("_789_1.csv"));
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -2,10 +2,16 @@ use tauri::{AppHandle, command,Emitter};
 use tokio::net::{TcpStream, TcpListener};
 use std::sync::Arc;
 use tokio::io::AsyncReadExt;
-use crate::types::PlcConnection;
+use tokio::sync::watch;
+use crate::types::{PlcConnection, PlcStats, PlcStatus};
 use crate::state::{ConnectionState, DbChannelState};
-use chrono::{DateTime, Utc};
-use crate::data_handler::{create_table_for_plc, save_plc_data};
+use crate::shutdown::{ReceiverGuard, ShutdownState};
+use chrono::Local;
+use crate::clock;
+use crate::data_handler::{create_table_for_plc, save_machine_event, save_plc_data, MACHINE_EVENT_CONNECTED, MACHINE_EVENT_DISCONNECTED};
+use crate::db_queue::PushOutcome;
+use crate::frame::{parse_frame, FrameSplitter};
+use crate::live_feed::{has_subscribers, publish_frame, publish_status, LiveStatus};
 
 /// PLCに接続する(フロントエンドから呼び出し)
 #[command]
@@ -17,10 +23,16 @@ pub async fn connect_plc(
     pc_ip: String,
     state: tauri::State<'_, ConnectionState>,
     db_channel: tauri::State<'_, DbChannelState>,
+    shutdown: tauri::State<'_, ShutdownState>,
     app: AppHandle,
 ) -> Result<String, String> {
      log::info!("Connecting to PLC ID: {}, IP: {}:{}", plc_id, plc_ip, plc_port);
 
+    // 終了処理中は新しい接続を受け付けない
+    if shutdown.is_shutting_down() {
+        return Err("Application is shutting down".to_string());
+    }
+
     // 既に接続されているかチェック
     {
         let connections = state.lock();
@@ -61,10 +73,22 @@ pub async fn connect_plc(
         .map_err(|e| format!("Failed to connect to PLC at {}: {}", plc_addr, e))?;
 
     log::info!("Connected to PLC at {}", plc_addr);
+    publish_status(&app, plc_id, LiveStatus::Connected, &plc_addr);
 
-    // 接続情報を保存
+    // 接続情報を保存(以前の接続の統計は引き継いで再接続回数を数える)
     {
         let mut connections = state.lock();
+        let mut stats = match connections.get(&plc_id) {
+            Some(prev) => {
+                let mut stats = prev.stats.clone();
+                stats.reconnect_count += 1;
+                stats
+            }
+            None => PlcStats::default(),
+        };
+        stats.connected_since = Some(Local::now());
+        stats.frames_since_connect = 0;
+
         connections.insert(
             plc_id,
             PlcConnection {
@@ -74,6 +98,7 @@ pub async fn connect_plc(
                 plc_port,
                 pc_ip: pc_ip.clone(),
                 is_connected: true,
+                stats,
             },
         );
     }
@@ -87,24 +112,32 @@ pub async fn connect_plc(
     // DB チャネルをクローンして渡す（ロックフリー）
     let state_clone = Arc::clone(&state.inner());
     let db_tx = db_channel.inner().clone();
+    record_connection(&db_tx, plc_id, &table_name, MACHINE_EVENT_CONNECTED).await;
+    let receiver_guard = shutdown.register_receiver();
+    let shutdown_rx = shutdown.subscribe();
     tokio::spawn(async move {
-        receive_data_from_plc(plc_id, &table_name,stream, state_clone, db_tx, app).await;
+        receive_data_from_plc(plc_id, &table_name,stream, state_clone, db_tx, shutdown_rx, receiver_guard, app).await;
     });
 
     Ok(format!("Connected to PLC {}:{}", plc_ip, plc_port))
 }
 
 /// PLCからデータを受信する
+/// 終了処理が始まったらソケットを閉じて抜ける
+#[allow(clippy::too_many_arguments)]
 async fn receive_data_from_plc(
     plc_id: u32,
     table_name:&str,
     mut stream: TcpStream,
     state: ConnectionState,
     db_tx: DbChannelState,
+    mut shutdown_rx: watch::Receiver<bool>,
+    _receiver_guard: ReceiverGuard,
     app: AppHandle,
 ) {
     println!("Starting receive loop for PLC ID: {}", plc_id);
     let mut buffer = vec![0u8; 4096];
+    let mut splitter = FrameSplitter::default();
 
     loop {
         // 接続状態をチェック
@@ -121,8 +154,32 @@ async fn receive_data_from_plc(
             }
         }
 
-        // データを受信
-        match stream.read(&mut buffer).await {
+        // データを受信(終了通知が来たら待ち受けを中断する)
+        let read_result = tokio::select! {
+            _ = shutdown_rx.changed() => {
+                println!("PLC ID {} receive loop stopped for shutdown", plc_id);
+                {
+                    let mut connections = state.lock();
+                    if let Some(conn) = connections.get_mut(&plc_id) {
+                        conn.is_connected = false;
+                    }
+                }
+
+                publish_status(&app, plc_id, LiveStatus::Disconnected, "Application shutdown");
+                record_connection(&db_tx, plc_id, table_name, MACHINE_EVENT_DISCONNECTED).await;
+                let payload = serde_json::json!({
+                    "plc_id": plc_id,
+                    "reason": "Application shutdown",
+                });
+                if let Err(e) = app.emit("plc-disconnected", payload) {
+                    eprintln!("Failed to emit disconnection event: {}", e);
+                }
+                break;
+            }
+            result = stream.read(&mut buffer) => result,
+        };
+
+        match read_result {
             Ok(0) => {
                 println!("PLC ID {} connection closed by remote", plc_id);
                 // 接続が閉じられた場合
@@ -134,6 +191,8 @@ async fn receive_data_from_plc(
                 }
 
                 // フロントエンドに切断イベントを送信
+                publish_status(&app, plc_id, LiveStatus::Disconnected, "Connection closed by remote");
+                record_connection(&db_tx, plc_id, table_name, MACHINE_EVENT_DISCONNECTED).await;
                 let payload = serde_json::json!({
                     "plc_id": plc_id,
                     "reason": "Connection closed by remote",
@@ -147,9 +206,25 @@ async fn receive_data_from_plc(
             }
             Ok(n) => {
                 println!("Received {} bytes from PLC ID {}", n, plc_id);
-                // 受信したデータを処理
-                let received_data = &buffer[..n];
-                process_received_data(plc_id, table_name,received_data, &db_tx, &app);
+                // 受信統計を更新
+                {
+                    let mut connections = state.lock();
+                    if let Some(conn) = connections.get_mut(&plc_id) {
+                        conn.stats.bytes_received += n as u64;
+                    }
+                }
+                // 受信したデータをフレームごとに分けて処理
+                for frame in splitter.push(&buffer[..n]) {
+                    match frame {
+                        Ok(received_data) => process_received_data(plc_id, table_name, &received_data, &state, &db_tx, &app).await,
+                        Err(e) => {
+                            log::error!("PLC ID {}: {}", plc_id, e);
+                            if let Some(conn) = state.lock().get_mut(&plc_id) {
+                                conn.stats.parse_failures += 1;
+                            }
+                        }
+                    }
+                }
             }
             Err(e) => {
                 eprintln!("Error reading from PLC ID {}: {}", plc_id, e);
@@ -162,9 +237,12 @@ async fn receive_data_from_plc(
                 }
 
                 // フロントエンドに切断イベントを送信
+                let reason = format!("Error: {}", e);
+                publish_status(&app, plc_id, LiveStatus::Disconnected, &reason);
+                record_connection(&db_tx, plc_id, table_name, MACHINE_EVENT_DISCONNECTED).await;
                 let payload = serde_json::json!({
                     "plc_id": plc_id,
-                    "reason": format!("Error: {}", e),
+                    "reason": reason,
                 });
                 if let Err(e) = app.emit("plc-disconnected", payload) {
                     eprintln!("Failed to emit disconnection event: {}", e);
@@ -178,19 +256,34 @@ async fn receive_data_from_plc(
     println!("Receive loop ended for PLC ID: {}", plc_id);
 }
 
-/// 受信したデータを処理する
-fn process_received_data(plc_id: u32, table_name:&str,data: &[u8], db_tx: &DbChannelState, app: &AppHandle) {
+/// 接続・切断を machine_events に記録する(稼働率の集計用、DB書き込みキュー経由)
+async fn record_connection(db_tx: &DbChannelState, plc_id: u32, table_name: &str, event_type: &str) {
+    if let Err(e) = save_machine_event(db_tx, plc_id, table_name, &clock::now(), event_type).await {
+        log::error!("Failed to record {} event for {}: {}", event_type, table_name, e);
+    }
+}
+
+/// 受信したデータ(JSONのフレーム1つ分)を処理する
+async fn process_received_data(plc_id: u32, table_name:&str,data: &[u8], state: &ConnectionState, db_tx: &DbChannelState, app: &AppHandle) {
     println!("Processing data for PLC ID {}: {:?}", plc_id, data);
 
+    // 受信統計を更新(フレームごと)
+    {
+        let mut connections = state.lock();
+        if let Some(conn) = connections.get_mut(&plc_id) {
+            conn.stats.frames_received += 1;
+            conn.stats.frames_since_connect += 1;
+            conn.stats.last_frame_at = Some(Local::now());
+        }
+    }
+
     // UTF-8としてデコード
     match std::str::from_utf8(data) {
         Ok(text) => {
             println!("Received text from PLC ID {}: {}", plc_id, text);
             //フロントエンドに送信する
-            // JST（ローカル時刻）に変換
-            let utc_now: DateTime<Utc> = Utc::now();
-            let jst_now = utc_now.with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).unwrap());
-            let formatted_date = jst_now.format("%Y-%m-%d %H:%M:%S").to_string();
+            let received_at = clock::now();
+            let formatted_date = clock::format_local(&received_at);
 
             let payload = serde_json::json!({
                 "plc_id": plc_id,
@@ -202,15 +295,33 @@ fn process_received_data(plc_id: u32, table_name:&str,data: &[u8], db_tx: &DbCha
                 eprintln!("Failed to emit event: {}", e);
             }
 
-            /*----受信データをデータベースに保存（チャネル経由で送信）---- */
-            // 各タスクが独自のクローンを持っているので、ロック不要で高速
-            if let Err(e) = save_plc_data(db_tx, plc_id, table_name,&formatted_date, text) {
-                eprintln!("Failed to send data to DB writer for PLC {}: {}", plc_id, e);
+            // ライブ配信の購読者がいればユニットごとに分解して配信する
+            if has_subscribers(app) {
+                match parse_frame(text) {
+                    Ok(frame) => publish_frame(app, plc_id, &frame, &formatted_date),
+                    Err(e) => log::debug!("Live feed skipped frame from PLC {}: {}", plc_id, e),
+                }
+            }
+
+            /*----受信データをデータベースに保存（キュー経由で送信）---- */
+            // キューが満杯の場合は設定に応じて待機・退避・破棄される
+            match save_plc_data(db_tx, plc_id, table_name,&received_at, text).await {
+                Ok(PushOutcome::Queued) => {}
+                Ok(outcome) => {
+                    log::warn!("DB queue overflow for PLC {}: {:?}", plc_id, outcome);
+                }
+                Err(e) => {
+                    eprintln!("Failed to send data to DB writer for PLC {}: {}", plc_id, e);
+                }
             }
 
         }
         Err(e) => {
             log::error!("Failed to decode UTF-8 from PLC ID {}: {}", plc_id, e);
+            let mut connections = state.lock();
+            if let Some(conn) = connections.get_mut(&plc_id) {
+                conn.stats.parse_failures += 1;
+            }
         }
     }
 }
@@ -220,35 +331,63 @@ fn process_received_data(plc_id: u32, table_name:&str,data: &[u8], db_tx: &DbCha
 pub async fn disconnect_plc(
     plc_id: u32,
     state: tauri::State<'_, ConnectionState>,
+    db_channel: tauri::State<'_, DbChannelState>,
     app: AppHandle,
 ) -> Result<String, String> {
     println!("Disconnecting from PLC ID: {}", plc_id);
 
-    let mut connections = state.lock();
-
-    if let Some(conn) = connections.get_mut(&plc_id) {
+    // 接続状態のロックは切断の記録を待つ前に外す
+    let table_name = {
+        let mut connections = state.lock();
+        let Some(conn) = connections.get_mut(&plc_id) else {
+            return Err("PLC not found".to_string());
+        };
         if !conn.is_connected {
             return Err("Not connected".to_string());
         }
 
         conn.is_connected = false;
+        conn.table_name.clone()
+    };
 
-        // TODO: ソケットを閉じる処理
+    // TODO: ソケットを閉じる処理
 
-        println!("Disconnected from PLC ID: {}", plc_id);
+    println!("Disconnected from PLC ID: {}", plc_id);
 
-        // フロントエンドに切断イベントを送信
-        let payload = serde_json::json!({
-            "plc_id": plc_id,
-            "reason": "Manually disconnected",
-        });
-
-        if let Err(e) = app.emit("plc-disconnected", payload) {
-            eprintln!("Failed to emit disconnection event: {}", e);
-        }
+    // フロントエンドに切断イベントを送信
+    publish_status(&app, plc_id, LiveStatus::Disconnected, "Manually disconnected");
+    record_connection(db_channel.inner(), plc_id, &table_name, MACHINE_EVENT_DISCONNECTED).await;
+    let payload = serde_json::json!({
+        "plc_id": plc_id,
+        "reason": "Manually disconnected",
+    });
 
-        Ok(format!("Disconnected from PLC {}", plc_id))
-    } else {
-        Err("PLC not found".to_string())
+    if let Err(e) = app.emit("plc-disconnected", payload) {
+        eprintln!("Failed to emit disconnection event: {}", e);
     }
+
+    Ok(format!("Disconnected from PLC {}", plc_id))
+}
+
+/// 接続状態から各PLCの統計を集める
+pub fn collect_plc_status(state: &ConnectionState) -> Vec<PlcStatus> {
+    let connections = state.lock();
+    let mut status: Vec<PlcStatus> = connections
+        .values()
+        .map(|conn| PlcStatus {
+            plc_id: conn.plc_id,
+            table_name: conn.table_name.clone(),
+            is_connected: conn.is_connected,
+            frames_per_minute: conn.stats.frames_per_minute(),
+            stats: conn.stats.clone(),
+        })
+        .collect();
+    status.sort_by_key(|s| s.plc_id);
+    status
+}
+
+/// 各PLCの接続状態と受信統計を返す(一度も接続していないPLCは含まない)
+#[command]
+pub fn get_plc_status(state: tauri::State<'_, ConnectionState>) -> Vec<PlcStatus> {
+    collect_plc_status(&state)
 }
@@ -0,0 +1,246 @@
+///複数ラインの解析ツールから共有するためのPostgreSQL保存先
+///ローカルで確認する場合: docker run -e POSTGRES_PASSWORD=postgres -e POSTGRES_DB=plc_data -p 5432:5432 postgres
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+use postgres::types::ToSql;
+use postgres::{Client, NoTls, Statement};
+
+use crate::chip_store::{insert_sql, upsert_sql, ChipStore, ChipUpsert, ColumnValue, HistoryInsert, StoreError};
+use crate::data_handler::{is_valid_identifier, parse_columns};
+use crate::types::PostgresConfig;
+
+//テーブルを作成するためのsql文を読み込み
+static CREATE_TABLE_PG_SQL: &str = include_str!("sql/create_table_pg.sql");
+static CREATE_ALARM_TABLE_PG_SQL: &str = include_str!("sql/create_alarm_table_pg.sql");
+static CREATE_HISTORY_TABLE_PG_SQL: &str = include_str!("sql/create_history_table_pg.sql");
+static CREATE_MACHINE_EVENTS_PG_SQL: &str = include_str!("sql/create_machine_events_pg.sql");
+
+/// PostgreSQLの保存先
+/// 接続は最初のバッチで開き、切断された場合は次のバッチで繋ぎ直す
+pub struct PostgresStore {
+    config: PostgresConfig,
+    client: Option<Client>,
+    statements: HashMap<String, Statement>,
+    created_tables: HashSet<String>,
+}
+
+impl PostgresStore {
+    pub fn new(config: &PostgresConfig) -> Self {
+        PostgresStore {
+            config: config.clone(),
+            client: None,
+            statements: HashMap::new(),
+            created_tables: HashSet::new(),
+        }
+    }
+
+    fn connect(&mut self) -> Result<&mut Client, String> {
+        if self.client.as_ref().map(|c| c.is_closed()).unwrap_or(true) {
+            let mut pg_config = postgres::Config::from_str(&self.config.url)
+                .map_err(|e| format!("Invalid PostgreSQL connection string: {}", e))?;
+            pg_config.connect_timeout(Duration::from_secs(self.config.connect_timeout_secs.max(1)));
+            let mut client = pg_config
+                .connect(NoTls)
+                .map_err(|e| format!("Failed to connect to PostgreSQL: {}", e))?;
+            log::info!("Connected to PostgreSQL");
+            // 全PLC共通の設備イベントのテーブルはPLCごとのテーブルとは別に、接続ごとに1回だけ作成する
+            client
+                .batch_execute(CREATE_MACHINE_EVENTS_PG_SQL)
+                .map_err(|e| format!("Failed to create machine_events: {}", e))?;
+            // 準備済みの文は接続ごとなので作り直す
+            self.statements.clear();
+            self.created_tables.clear();
+            self.client = Some(client);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    fn client(&mut self) -> Result<&mut Client, String> {
+        self.client.as_mut().ok_or_else(|| "PostgreSQL is not connected".to_string())
+    }
+
+    /// トランザクションが中断された状態で残らないよう、接続を閉じて次のバッチで繋ぎ直す
+    fn backend_error(&mut self, context: &str, e: postgres::Error) -> String {
+        self.client = None;
+        format!("{}: {}", context, e)
+    }
+
+    /// 1行分の文を実行する
+    /// 1行の失敗でトランザクション全体が無効にならないようにセーブポイントで囲む
+    fn execute_row(&mut self, sql: String, values: Vec<ColumnValue>) -> Result<(), StoreError> {
+        let params: Vec<Box<dyn ToSql + Sync>> = values
+            .into_iter()
+            .map(|value| -> Box<dyn ToSql + Sync> {
+                match value {
+                    ColumnValue::Int(v) => Box::new(v),
+                    ColumnValue::Text(v) => Box::new(v),
+                    ColumnValue::Null => Box::new(None::<String>),
+                    ColumnValue::Increment => Box::new(1i64),
+                }
+            })
+            .collect();
+        let param_refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+
+        let Some(client) = self.client.as_mut() else {
+            return Err(StoreError::Backend("PostgreSQL is not connected".to_string()));
+        };
+        match execute_in_savepoint(client, &mut self.statements, sql, &param_refs) {
+            Ok(()) => Ok(()),
+            Err(e) if e.as_db_error().is_some() => {
+                let message = e.to_string();
+                let result = client.batch_execute("ROLLBACK TO SAVEPOINT chip_row");
+                result.map_err(|e| StoreError::Backend(self.backend_error("Failed to roll back savepoint", e)))?;
+                Err(StoreError::Record(message))
+            }
+            Err(e) => Err(StoreError::Backend(self.backend_error("Failed to write row", e))),
+        }
+    }
+}
+
+impl ChipStore for PostgresStore {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        let result = self.connect()?.batch_execute("BEGIN");
+        result.map_err(|e| self.backend_error("Failed to begin transaction", e))
+    }
+
+    fn ensure_table(&mut self, table_name: &str) -> Result<(), String> {
+        if self.created_tables.contains(table_name) {
+            return Ok(());
+        }
+        if !is_valid_identifier(table_name) {
+            return Err(format!("Invalid table name: {}", table_name));
+        }
+        let sql = create_tables_sql(table_name);
+        let result = self.client()?.batch_execute(&sql);
+        result.map_err(|e| self.backend_error(&format!("Failed to create table {}", table_name), e))?;
+        self.created_tables.insert(table_name.to_string());
+        Ok(())
+    }
+
+    fn upsert(&mut self, table_name: &str, row: &ChipUpsert) -> Result<(), StoreError> {
+        let sql = upsert_sql(table_name, row, |n| format!("${}", n));
+        self.execute_row(sql, row.params())
+    }
+
+    fn insert(&mut self, row: &HistoryInsert) -> Result<(), StoreError> {
+        let sql = insert_sql(row, |n| format!("${}", n));
+        self.execute_row(sql, row.params())
+    }
+
+    fn begin_frame(&mut self) -> Result<(), String> {
+        let result = self.client()?.batch_execute("SAVEPOINT frame");
+        result.map_err(|e| self.backend_error("Failed to create savepoint", e))
+    }
+
+    fn end_frame(&mut self) -> Result<(), String> {
+        let result = self.client()?.batch_execute("RELEASE SAVEPOINT frame");
+        result.map_err(|e| self.backend_error("Failed to release savepoint", e))
+    }
+
+    fn rollback_frame(&mut self) {
+        // 接続を閉じた場合はトランザクションごと破棄されている
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        if let Err(e) = client.batch_execute("ROLLBACK TO SAVEPOINT frame; RELEASE SAVEPOINT frame") {
+            log::error!("{}", self.backend_error("Failed to roll back frame", e));
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        let result = self.client()?.batch_execute("COMMIT");
+        result.map_err(|e| self.backend_error("Failed to commit transaction", e))
+    }
+}
+
+/// PLCごとのテーブル(アラーム履歴・更新履歴を含む)を作成するSQL文
+/// 以前のバージョンで作成したテーブルには後から追加したカラムを足す
+fn create_tables_sql(table_name: &str) -> String {
+    let mut sql = format!(
+        "{};\n{}{}",
+        CREATE_TABLE_PG_SQL.replace("{TABLE_NAME}", table_name),
+        CREATE_ALARM_TABLE_PG_SQL.replace("{TABLE_NAME}", table_name),
+        CREATE_HISTORY_TABLE_PG_SQL.replace("{TABLE_NAME}", table_name)
+    );
+    for (name, sql_type) in parse_columns(CREATE_TABLE_PG_SQL).into_iter().filter(|(name, _)| name != "ID") {
+        sql.push_str(&format!("ALTER TABLE \"{}\" ADD COLUMN IF NOT EXISTS \"{}\" {};\n", table_name, name, sql_type));
+    }
+    sql
+}
+
+/// セーブポイント内で文を準備(初回のみ)して実行する
+fn execute_in_savepoint(
+    client: &mut Client,
+    statements: &mut HashMap<String, Statement>,
+    sql: String,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<(), postgres::Error> {
+    client.batch_execute("SAVEPOINT chip_row")?;
+    let statement = match statements.get(&sql) {
+        Some(statement) => statement.clone(),
+        None => {
+            let statement = client.prepare(&sql)?;
+            statements.insert(sql, statement.clone());
+            statement
+        }
+    };
+    client.execute(&statement, params)?;
+    client.batch_execute("RELEASE SAVEPOINT chip_row")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_store::{ChipUpsert, HistoryInsert};
+
+    fn placeholder(n: usize) -> String {
+        format!("${}", n)
+    }
+
+    #[test]
+    fn create_tables_sql_uses_table_name() {
+        let sql = create_tables_sql("PLC_1");
+        assert!(!sql.contains("{TABLE_NAME}"));
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS \"PLC_1\""));
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS \"PLC_1_alarms\""));
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS \"PLC_1_history\""));
+        assert!(sql.contains("CREATE UNIQUE INDEX \"uix_PLC_1_alarm_key\" ON \"PLC_1_alarms\""));
+        assert!(sql.contains("ALTER TABLE \"PLC_1\" ADD COLUMN IF NOT EXISTS \"LOT_NAME\" "));
+        assert!(!sql.contains("ADD COLUMN IF NOT EXISTS \"ID\""));
+        // 全PLC共通のテーブルはPLCごとには作らない
+        assert!(!sql.contains("machine_events"));
+        assert!(!CREATE_MACHINE_EVENTS_PG_SQL.contains("{TABLE_NAME}"));
+    }
+
+    #[test]
+    fn upsert_uses_numbered_placeholders() {
+        let row = ChipUpsert::new("M1", "T1", "L1", 1001)
+            .int("DC1_BIN", 3)
+            .text("DC1_PROBE_SERIAL", None)
+            .increment("DC1_COUNT")
+            .text("DC1_STAGE_SERIAL", "S-1");
+        assert_eq!(
+            upsert_sql("PLC_1", &row, placeholder),
+            "INSERT INTO \"PLC_1\" (\"MACHINE_NAME\", \"TYPE_NAME\", \"LOT_NAME\", \"SERIAL\", \"DC1_BIN\", \"DC1_PROBE_SERIAL\", \"DC1_COUNT\", \"DC1_STAGE_SERIAL\") \
+             VALUES ($1, $2, $3, $4, $5, NULL, $6, $7) \
+             ON CONFLICT (\"LOT_NAME\", \"SERIAL\") DO UPDATE SET \"MACHINE_NAME\" = excluded.\"MACHINE_NAME\", \"TYPE_NAME\" = excluded.\"TYPE_NAME\", \
+             \"DC1_BIN\" = excluded.\"DC1_BIN\", \"DC1_PROBE_SERIAL\" = excluded.\"DC1_PROBE_SERIAL\", \
+             \"DC1_COUNT\" = COALESCE(\"PLC_1\".\"DC1_COUNT\", 0) + 1, \"DC1_STAGE_SERIAL\" = excluded.\"DC1_STAGE_SERIAL\""
+        );
+        assert_eq!(row.params().len(), 7);
+    }
+
+    #[test]
+    fn insert_uses_numbered_placeholders() {
+        let row = HistoryInsert::new("PLC_1_alarms").text("RECEIVED_AT", "2024-01-01T00:00:00.000+09:00").int("SERIAL", None).int("ALARM_NUM", 5);
+        assert_eq!(
+            insert_sql(&row, placeholder),
+            "INSERT INTO \"PLC_1_alarms\" (\"RECEIVED_AT\", \"SERIAL\", \"ALARM_NUM\") VALUES ($1, NULL, $2) ON CONFLICT DO NOTHING"
+        );
+    }
+}
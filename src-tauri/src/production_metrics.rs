@@ -0,0 +1,487 @@
+///生産実績の集計(UPH・サイクルタイム分布・停止時間・稼働率)
+///チップのLDピックアップ・ULD挿入の日時、machine_events の接続・切断とアラーム、アラーム履歴から
+///PLC(設備)ごと・シフトごとに時間稼働率・性能稼働率・良品率とその積(OEE)を計算する
+use std::collections::BTreeMap;
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tauri::command;
+
+use crate::clock;
+use crate::config::load_config;
+use crate::consumable_trace::existing_tables;
+use crate::data_handler::{open_read_connection, MACHINE_EVENT_CONNECTED, MACHINE_EVENT_DISCONNECTED};
+use crate::db_query::{table_for_plc, BIN_COLUMNS};
+use crate::regist_data_to_db::{alarm_table, MACHINE_EVENT_ALARM};
+use crate::types::{ProductionConfig, ShiftConfig};
+
+/// 停止の種類(重なった時間は番号の小さい方に数える)
+const STOP_DISCONNECTED: usize = 0;
+const STOP_ALARM: usize = 1;
+const STOP_IDLE: usize = 2;
+
+/// 拠点のタイムゾーンの日時の文字列を読む(読めない場合は None)
+pub fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    clock::parse_local(value).map(|time| clock::local_naive(&time))
+}
+
+/// PLCの日時を拠点のタイムゾーンで読む
+/// 変換済みのエポックミリ秒があればそれを使い、無い(変換前に登録した)チップは元の文字列から求める
+fn plc_timestamp(epoch_ms: Option<i64>, raw: Option<String>) -> Option<NaiveDateTime> {
+    epoch_ms
+        .and_then(clock::from_epoch_ms)
+        .or_else(|| raw.as_deref().and_then(clock::parse_plc_date))
+        .map(|time| clock::local_naive(&time))
+}
+
+fn format_timestamp(value: NaiveDateTime) -> String {
+    value.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
+/// 受信時刻と同じ基準(拠点のタイムゾーン)の現在時刻
+fn site_now() -> NaiveDateTime {
+    clock::local_naive(&clock::now())
+}
+
+/// シフト1回分(集計範囲で切り詰めたもの)
+#[derive(Serialize, Debug, Clone)]
+pub struct ShiftWindow {
+    pub name: String,
+    pub start: String,
+    pub end: String,
+    #[serde(skip)]
+    start_at: NaiveDateTime,
+    #[serde(skip)]
+    end_at: NaiveDateTime,
+}
+
+/// 停止時間の内訳(重なる場合は 切断 > アラーム > 払い出し待ち の順に一方だけに数える)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Downtime {
+    pub disconnected_secs: f64,
+    /// アラームから次にチップを取り出す・払い出すまで
+    pub alarm_secs: f64,
+    /// 払い出し間隔が stop_threshold_secs を超えた時間
+    pub idle_secs: f64,
+}
+
+impl Downtime {
+    fn total(&self) -> f64 {
+        self.disconnected_secs + self.alarm_secs + self.idle_secs
+    }
+}
+
+/// 分布の1区間(from_secs 以上、from_secs + 刻み幅 未満)
+#[derive(Serialize, Debug, Clone)]
+pub struct HistogramBucket {
+    pub from_secs: f64,
+    pub count: u64,
+}
+
+/// 時間の分布
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TimeStats {
+    pub count: usize,
+    pub mean_secs: Option<f64>,
+    pub median_secs: Option<f64>,
+    pub p90_secs: Option<f64>,
+    pub min_secs: Option<f64>,
+    pub max_secs: Option<f64>,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+/// 設備1台・シフト1回分の生産実績
+#[derive(Serialize, Debug, Clone)]
+pub struct ShiftMetrics {
+    pub table_name: String,
+    pub machine_name: Option<String>,
+    pub shift: String,
+    pub start: String,
+    pub end: String,
+    /// シフトの時間(実行中のシフトは現在時刻まで)
+    pub planned_secs: f64,
+    pub run_secs: f64,
+    pub downtime: Downtime,
+    pub alarm_count: u64,
+    pub disconnect_count: u64,
+    /// ULDに払い出したチップ数
+    pub output: u64,
+    /// 払い出したチップのうち1つ以上のBINが記録されているチップ数
+    pub tested: u64,
+    /// 記録されたBINが全て良品BINのチップ数
+    pub passed: u64,
+    /// 稼働時間あたりの払い出し数
+    pub units_per_hour: Option<f64>,
+    /// ULDへの払い出し間隔(停止とみなす間隔は除く)
+    pub cycle_time: TimeStats,
+    /// LDピックアップからULD払い出しまでの時間
+    pub lead_time: TimeStats,
+    /// 時間稼働率(稼働時間 / シフトの時間)
+    pub availability: Option<f64>,
+    /// 性能稼働率(理想サイクルタイム × 払い出し数 / 稼働時間)
+    pub performance: Option<f64>,
+    /// 良品率(passed / tested)
+    pub quality: Option<f64>,
+    pub oee: Option<f64>,
+}
+
+/// チップ1件の時刻と判定
+struct ChipTimes {
+    machine_name: Option<String>,
+    type_name: Option<String>,
+    pickup: Option<NaiveDateTime>,
+    put: Option<NaiveDateTime>,
+    tested: bool,
+    passed: bool,
+}
+
+/// 設備のイベント
+#[derive(Default)]
+struct MachineHistory {
+    /// (時刻, 接続したか)
+    connections: Vec<(NaiveDateTime, bool)>,
+    alarms: Vec<NaiveDateTime>,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| format!("Invalid shift time: {}", value))
+}
+
+/// 範囲と重なるシフトを開始順に列挙する(範囲外の部分は切り詰める)
+pub fn shift_windows(shifts: &[ShiftConfig], from: NaiveDateTime, to: NaiveDateTime) -> Result<Vec<ShiftWindow>, String> {
+    let mut windows = Vec::new();
+    for shift in shifts {
+        let start = parse_time(&shift.start)?;
+        let end = parse_time(&shift.end)?;
+        let days = shift
+            .days
+            .iter()
+            .map(|day| day.parse::<Weekday>().map_err(|_| format!("Invalid shift day: {}", day)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // 前日に始まって範囲内に終わるシフトも含める
+        let mut date = from.date() - Duration::days(1);
+        while date <= to.date() {
+            if days.is_empty() || days.contains(&date.weekday()) {
+                let start_at = date.and_time(start);
+                let end_at = if end <= start { (date + Duration::days(1)).and_time(end) } else { date.and_time(end) };
+                let start_at = start_at.max(from);
+                let end_at = end_at.min(to);
+                if start_at < end_at {
+                    windows.push(ShiftWindow {
+                        name: shift.name.clone(),
+                        start: format_timestamp(start_at),
+                        end: format_timestamp(end_at),
+                        start_at,
+                        end_at,
+                    });
+                }
+            }
+            date += Duration::days(1);
+        }
+    }
+    windows.sort_by_key(|window| window.start_at);
+    Ok(windows)
+}
+
+fn load_chips(conn: &Connection, table_name: &str, pass_bins: &[i64]) -> rusqlite::Result<Vec<ChipTimes>> {
+    let bin_columns: Vec<&str> = BIN_COLUMNS.iter().map(|(_, column)| *column).collect();
+    let sql = format!(
+        "SELECT MACHINE_NAME, TYPE_NAME, LD_PICKUP_DATE, ULD_PUT_DATE, LD_PICKUP_EPOCH_MS, ULD_PUT_EPOCH_MS, {} FROM {table_name}
+        WHERE LD_PICKUP_DATE IS NOT NULL OR ULD_PUT_DATE IS NOT NULL",
+        bin_columns.join(", ")
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query([])?;
+    let mut chips = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut tested = false;
+        let mut passed = true;
+        for i in 0..bin_columns.len() {
+            if let Some(bin) = row.get::<_, Option<i64>>(6 + i)? {
+                tested = true;
+                passed &= pass_bins.contains(&bin);
+            }
+        }
+        chips.push(ChipTimes {
+            machine_name: row.get(0)?,
+            type_name: row.get(1)?,
+            pickup: plc_timestamp(row.get(4)?, row.get(2)?),
+            put: plc_timestamp(row.get(5)?, row.get(3)?),
+            tested,
+            passed: tested && passed,
+        });
+    }
+    Ok(chips)
+}
+
+fn load_history(conn: &Connection, table_name: &str) -> rusqlite::Result<MachineHistory> {
+    let mut history = MachineHistory::default();
+    let mut stmt = conn.prepare(
+        "SELECT RECEIVED_AT, EVENT_TYPE FROM machine_events
+        WHERE TABLE_NAME = ?1 AND EVENT_TYPE IN (?2, ?3, ?4)
+        ORDER BY RECEIVED_AT, ID",
+    )?;
+    let mut rows = stmt.query(params![table_name, MACHINE_EVENT_CONNECTED, MACHINE_EVENT_DISCONNECTED, MACHINE_EVENT_ALARM])?;
+    while let Some(row) = rows.next()? {
+        let Some(at) = parse_timestamp(&row.get::<_, String>(0)?) else {
+            continue;
+        };
+        match row.get::<_, String>(1)?.as_str() {
+            MACHINE_EVENT_ALARM => history.alarms.push(at),
+            event_type => history.connections.push((at, event_type == MACHINE_EVENT_CONNECTED)),
+        }
+    }
+
+    // チップに紐付くアラームは同じ受信データで複数のチップに記録されるので、受信時刻・ユニット・番号でまとめる
+    let sql = format!("SELECT DISTINCT RECEIVED_AT, UNIT, ALARM_NUM FROM {}", alarm_table(table_name));
+    match conn.prepare(&sql) {
+        Ok(mut stmt) => {
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                if let Some(at) = parse_timestamp(&row.get::<_, String>(0)?) {
+                    history.alarms.push(at);
+                }
+            }
+        }
+        // アラーム履歴の記録前に作成したテーブルには履歴が無い
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => {}
+        Err(e) => return Err(e),
+    }
+    history.alarms.sort();
+    Ok(history)
+}
+
+/// 停止区間(種類, 開始, 終了)を窓の中で切り詰めて、重なりを除いた種類ごとの時間にする
+fn attribute_stops(stops: &[(usize, NaiveDateTime, NaiveDateTime)], start: NaiveDateTime, end: NaiveDateTime) -> Downtime {
+    let mut points: Vec<(NaiveDateTime, usize, i32)> = Vec::new();
+    for (kind, from, to) in stops {
+        let from = (*from).max(start);
+        let to = (*to).min(end);
+        if from < to {
+            points.push((from, *kind, 1));
+            points.push((to, *kind, -1));
+        }
+    }
+    points.sort_by_key(|(at, _, _)| *at);
+
+    let mut totals = [0.0; 3];
+    let mut active = [0i32; 3];
+    let mut previous: Option<NaiveDateTime> = None;
+    for (at, kind, delta) in points {
+        if let Some(previous) = previous {
+            if let Some(top) = active.iter().position(|count| *count > 0) {
+                totals[top] += secs(at - previous);
+            }
+        }
+        active[kind] += delta;
+        previous = Some(at);
+    }
+    Downtime {
+        disconnected_secs: totals[STOP_DISCONNECTED],
+        alarm_secs: totals[STOP_ALARM],
+        idle_secs: totals[STOP_IDLE],
+    }
+}
+
+/// 値の一覧から分布を作る(パーセンタイルは最近傍順位)
+fn time_stats(mut values: Vec<f64>, bucket_secs: f64) -> TimeStats {
+    if values.is_empty() {
+        return TimeStats::default();
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+    let bucket_secs = bucket_secs.max(0.001);
+    let mut buckets: BTreeMap<i64, u64> = BTreeMap::new();
+    for value in &values {
+        *buckets.entry((value / bucket_secs).floor() as i64).or_insert(0) += 1;
+    }
+    TimeStats {
+        count: values.len(),
+        mean_secs: Some(values.iter().sum::<f64>() / values.len() as f64),
+        median_secs: Some(percentile(0.5)),
+        p90_secs: Some(percentile(0.9)),
+        min_secs: values.first().copied(),
+        max_secs: values.last().copied(),
+        histogram: buckets
+            .into_iter()
+            .map(|(bucket, count)| HistogramBucket {
+                from_secs: bucket as f64 * bucket_secs,
+                count,
+            })
+            .collect(),
+    }
+}
+
+/// 理想サイクルタイム(一致する設定が無い場合は None)
+fn ideal_cycle_secs(config: &ProductionConfig, chip: &ChipTimes) -> Option<f64> {
+    config
+        .targets
+        .iter()
+        .find(|target| {
+            target.machine.as_deref().map_or(true, |m| chip.machine_name.as_deref() == Some(m))
+                && target.type_name.as_deref().map_or(true, |t| chip.type_name.as_deref() == Some(t))
+        })
+        .map(|target| target.ideal_cycle_secs)
+}
+
+/// 設備1台分のシフトごとの生産実績
+fn table_metrics(conn: &Connection, table_name: &str, windows: &[ShiftWindow], config: &ProductionConfig, pass_bins: &[i64], now: NaiveDateTime) -> rusqlite::Result<Vec<ShiftMetrics>> {
+    let chips = load_chips(conn, table_name, pass_bins)?;
+    let history = load_history(conn, table_name)?;
+    let machine_name = chips.iter().rev().find_map(|chip| chip.machine_name.clone());
+    let threshold = Duration::milliseconds((config.stop_threshold_secs * 1000.0) as i64);
+
+    let mut puts: Vec<NaiveDateTime> = chips.iter().filter_map(|chip| chip.put).collect();
+    puts.sort();
+    let mut activity: Vec<NaiveDateTime> = chips.iter().flat_map(|chip| [chip.pickup, chip.put]).flatten().collect();
+    activity.sort();
+
+    // 切断とアラームの停止区間(最初の接続・切断より前は状態が分からないので数えない)
+    let mut stops = Vec::new();
+    for (i, (at, connected)) in history.connections.iter().enumerate() {
+        if !connected {
+            let until = history.connections.get(i + 1).map_or(now, |(next, _)| *next);
+            stops.push((STOP_DISCONNECTED, *at, until));
+        }
+    }
+    for alarm in &history.alarms {
+        let resumed = activity.get(activity.partition_point(|at| at <= alarm)).copied().unwrap_or(now);
+        stops.push((STOP_ALARM, *alarm, resumed));
+    }
+
+    let mut metrics = Vec::new();
+    for window in windows {
+        let start = window.start_at;
+        let end = window.end_at.min(now);
+        if start >= end {
+            continue;
+        }
+
+        // 払い出し間隔が閾値を超えた区間(窓の前の最後の払い出しから数える)
+        let mut window_stops = stops.clone();
+        let first = puts.partition_point(|at| *at < start);
+        let last = puts.partition_point(|at| *at < end);
+        let mut cursor = if first > 0 { puts[first - 1] } else { start };
+        for at in puts[first..last].iter().copied().chain(std::iter::once(end)) {
+            if at - cursor > threshold {
+                window_stops.push((STOP_IDLE, cursor, at));
+            }
+            cursor = at;
+        }
+        let downtime = attribute_stops(&window_stops, start, end);
+
+        let mut cycles = Vec::new();
+        for i in first.max(1)..last {
+            let gap = puts[i] - puts[i - 1];
+            if gap <= threshold {
+                cycles.push(secs(gap));
+            }
+        }
+
+        let mut output = 0;
+        let mut tested = 0;
+        let mut passed = 0;
+        let mut lead_times = Vec::new();
+        let mut ideal_total = Some(0.0);
+        for chip in chips.iter().filter(|chip| chip.put.is_some_and(|at| at >= start && at < end)) {
+            output += 1;
+            if chip.tested {
+                tested += 1;
+            }
+            if chip.passed {
+                passed += 1;
+            }
+            if let (Some(pickup), Some(put)) = (chip.pickup, chip.put) {
+                if put >= pickup {
+                    lead_times.push(secs(put - pickup));
+                }
+            }
+            ideal_total = ideal_total.zip(ideal_cycle_secs(config, chip)).map(|(total, ideal)| total + ideal);
+        }
+
+        let planned_secs = secs(end - start);
+        let run_secs = (planned_secs - downtime.total()).max(0.0);
+        let availability = (planned_secs > 0.0).then(|| run_secs / planned_secs);
+        let performance = ideal_total.filter(|_| run_secs > 0.0 && output > 0).map(|ideal| ideal / run_secs);
+        let quality = (tested > 0).then(|| passed as f64 / tested as f64);
+        let oee = match (availability, performance, quality) {
+            (Some(a), Some(p), Some(q)) => Some(a * p * q),
+            _ => None,
+        };
+
+        metrics.push(ShiftMetrics {
+            table_name: table_name.to_string(),
+            machine_name: machine_name.clone(),
+            shift: window.name.clone(),
+            start: window.start.clone(),
+            end: format_timestamp(end),
+            planned_secs,
+            run_secs,
+            downtime,
+            alarm_count: history.alarms.iter().filter(|at| **at >= start && **at < end).count() as u64,
+            disconnect_count: history
+                .connections
+                .iter()
+                .filter(|(at, connected)| !connected && *at >= start && *at < end)
+                .count() as u64,
+            output,
+            tested,
+            passed,
+            units_per_hour: (run_secs > 0.0).then(|| output as f64 * 3600.0 / run_secs),
+            cycle_time: time_stats(cycles, config.cycle_bucket_secs),
+            lead_time: time_stats(lead_times, config.cycle_bucket_secs),
+            availability,
+            performance,
+            quality,
+            oee,
+        });
+    }
+    Ok(metrics)
+}
+
+/// 範囲内のシフトごとに各設備の生産実績を集計する(to は含まない)
+pub fn production_metrics(conn: &Connection, tables: &[String], from: &str, to: &str, config: &ProductionConfig, pass_bins: &[i64]) -> Result<Vec<ShiftMetrics>, String> {
+    let from = parse_timestamp(from).ok_or_else(|| format!("Invalid from: {}", from))?;
+    let to = parse_timestamp(to).ok_or_else(|| format!("Invalid to: {}", to))?;
+    if from >= to {
+        return Err("from must be earlier than to".to_string());
+    }
+    let windows = shift_windows(&config.shifts, from, to)?;
+    let now = site_now();
+    let mut metrics = Vec::new();
+    for table in tables {
+        metrics.extend(table_metrics(conn, table, &windows, config, pass_bins, now).map_err(|e| format!("{}: {}", table, e))?);
+    }
+    Ok(metrics)
+}
+
+/// 設備ごと・シフトごとの生産実績をフロントエンドに返す(plc_id を省略した場合は全設備)
+#[command]
+pub async fn get_production_metrics(plc_id: Option<u32>, from: String, to: String) -> Result<Vec<ShiftMetrics>, String> {
+    let config = load_config()?;
+    let table_name = plc_id.map(table_for_plc).transpose()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        let tables = match table_name {
+            Some(table_name) => vec![table_name],
+            None => existing_tables(&conn)?,
+        };
+        production_metrics(&conn, &tables, &from, &to, &config.production, &config.quality.pass_bins)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// 範囲内のシフトの一覧をフロントエンドに返す
+#[command]
+pub fn get_shift_windows(from: String, to: String) -> Result<Vec<ShiftWindow>, String> {
+    let config = load_config()?;
+    let from = parse_timestamp(&from).ok_or_else(|| format!("Invalid from: {}", from))?;
+    let to = parse_timestamp(&to).ok_or_else(|| format!("Invalid to: {}", to))?;
+    shift_windows(&config.production.shifts, from, to)
+}
@@ -0,0 +1,210 @@
+///受信データの各ユニット情報の型定義
+///シリアル番号以外の項目は省略可能で、省略された項目はNULLとして登録する(0や"unknown"で埋めない)
+///定義に無い項目はファームウェアの変更を見逃さないようエラーにする
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// LDトレイピックアップ (U1_TR_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TrayPickupRecord {
+    pub serial: i64,
+    /// wa番号
+    pub wano: Option<i64>,
+    /// waX座標
+    pub wax: Option<i64>,
+    /// waY座標
+    pub way: Option<i64>,
+    /// トレイID
+    pub trayid: Option<String>,
+    /// トレイアーム位置
+    pub trayarm: Option<String>,
+    /// ポケット座標X
+    pub px: Option<i64>,
+    /// ポケット座標Y
+    pub py: Option<i64>,
+    /// ポケット補正量X
+    pub pax: Option<i64>,
+    /// ポケット補正量Y
+    pub pay: Option<i64>,
+    /// ピックアップ時刻
+    pub date: Option<String>,
+}
+
+/// 上流・下流アームコレット (*_A1_*, *_A2_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ArmColletRecord {
+    pub serial: i64,
+    /// コレットの使用回数
+    pub count: Option<i64>,
+}
+
+/// DC1,ULD予熱テーブル (*_PH_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PreheatRecord {
+    pub serial: i64,
+    /// 補正量X
+    pub ax: Option<i64>,
+    /// 補正量Y
+    pub ay: Option<i64>,
+    /// 補正量Θ
+    pub at: Option<i64>,
+}
+
+/// DC1~DC2検査テーブル (*_TS_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TestStageRecord {
+    pub serial: i64,
+    pub stage_serial: Option<String>,
+    pub stage_count: Option<i64>,
+    pub stage_z: Option<i64>,
+    pub pin_z: Option<i64>,
+    pub probe_serial: Option<String>,
+    pub probe_count: Option<i64>,
+    pub probe_x1: Option<i64>,
+    pub probe_y1: Option<i64>,
+    pub probe_x2: Option<i64>,
+    pub probe_y2: Option<i64>,
+    /// チップ補正量X
+    pub ax: Option<i64>,
+    /// チップ補正量Y
+    pub ay: Option<i64>,
+    /// チップ補正量Θ
+    pub at: Option<i64>,
+    pub bin: Option<i64>,
+}
+
+/// IP検査テーブル (U6_TS_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IpStageRecord {
+    pub serial: i64,
+    pub stage_count: Option<i64>,
+}
+
+/// IP表面・裏面検査のBIN (U6_T1_*, U6_T2_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IpBinRecord {
+    pub serial: i64,
+    pub bin: Option<i64>,
+}
+
+/// ULDポケット認識 (U7_PI_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UldPocketRecord {
+    pub serial: i64,
+    /// トレイID
+    pub trayid: Option<String>,
+    /// ポケット座標X
+    pub px: Option<i64>,
+    /// ポケット座標Y
+    pub py: Option<i64>,
+    /// ポケット補正量X
+    pub pax: Option<i64>,
+    /// ポケット補正量Y
+    pub pay: Option<i64>,
+}
+
+/// ULDポケット挿入 (U7_CI_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UldChipRecord {
+    pub serial: i64,
+    /// ポケット座標X
+    pub px: Option<i64>,
+    /// ポケット座標Y
+    pub py: Option<i64>,
+    /// チップ補正量X
+    pub cax: Option<i64>,
+    /// チップ補正量Y
+    pub cay: Option<i64>,
+    /// 挿入時刻
+    pub date: Option<String>,
+}
+
+/// アラーム (*_AL_*)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmRecord {
+    /// アラーム発生時にユニット内にあったチップのシリアル(空きは0、設備のアラームは空または省略)
+    #[serde(default)]
+    pub serial: Vec<i64>,
+    pub alarm_num: Option<i64>,
+}
+
+impl AlarmRecord {
+    /// 0でないシリアル(重複は除く)
+    pub fn serials(&self) -> Vec<i64> {
+        let mut serials: Vec<i64> = Vec::new();
+        for serial in self.serial.iter().copied().filter(|s| *s != 0) {
+            if !serials.contains(&serial) {
+                serials.push(serial);
+            }
+        }
+        serials
+    }
+}
+
+/// ユニット情報を型に変換する
+/// 失敗した場合はどの項目が原因かをエラーに含める(例: "probe_count: invalid type: string \"a\", expected i64")
+pub fn decode<T: DeserializeOwned>(value: &Value) -> Result<T, String> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            e.inner().to_string()
+        } else {
+            format!("{}: {}", path, e.inner())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_store::ColumnValue;
+    use crate::regist_data_to_db::regist_ts_info;
+    use serde_json::json;
+
+    #[test]
+    fn missing_fields_are_none() {
+        let record: TrayPickupRecord = decode(&json!({"serial": 1001, "trayid": "T-01"})).unwrap();
+        assert_eq!(record.serial, 1001);
+        assert_eq!(record.trayid.as_deref(), Some("T-01"));
+        assert_eq!(record.px, None);
+        assert_eq!(record.date, None);
+
+        let alarm: AlarmRecord = decode(&json!({"alarm_num": 5})).unwrap();
+        assert!(alarm.serials().is_empty());
+        assert_eq!(decode::<AlarmRecord>(&json!({"serial": [0, 7, 7, 8]})).unwrap().serials(), vec![7, 8]);
+    }
+
+    #[test]
+    fn missing_fields_are_stored_as_null() {
+        let row = regist_ts_info("M1", "L1", "T1", "U2", &json!({"serial": 1001, "probe_count": 12})).unwrap();
+        let value = |column: &str| row.columns.iter().find(|(c, _)| c == column).map(|(_, v)| v.clone());
+        assert_eq!(value("DC1_PROBE_COUNT"), Some(ColumnValue::Int(12)));
+        assert_eq!(value("DC1_PROBE_X1"), Some(ColumnValue::Null));
+        assert_eq!(value("DC1_STAGE_SERIAL"), Some(ColumnValue::Null));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let err = decode::<ArmColletRecord>(&json!({"serial": 1001, "count": 3, "cnt": 3})).unwrap_err();
+        assert!(err.contains("unknown field `cnt`"), "{}", err);
+    }
+
+    #[test]
+    fn error_names_the_field() {
+        let err = decode::<TestStageRecord>(&json!({"serial": 1001, "probe_count": "a"})).unwrap_err();
+        assert!(err.starts_with("probe_count: invalid type"), "{}", err);
+
+        let err = decode::<IpBinRecord>(&json!({"bin": 1})).unwrap_err();
+        assert!(err.contains("missing field `serial`"), "{}", err);
+    }
+}
@@ -1,173 +1,186 @@
+///受信データの各ユニット情報を (LOT_NAME, SERIAL) をキーとしたupsertと、履歴の追加に変換する
+///実際の保存は保存先(SQLite・PostgreSQL)ごとに chip_store で行う
+///アラームはユニット内の全チップに登録し、チップごとの履歴は {TABLE}_alarms、チップに紐付かないものは machine_events に追加する
+///受信データに無い項目はNULLとして登録し、型が合わない項目はその項目名を含めたエラーにする
+///PLCの日時はISO 8601・エポックミリ秒・受信時刻との差に変換し、ユニットごとに受信時刻を記録する
+///更新履歴を有効にした場合は、チップの行へのupsertを {TABLE}_history にも受信順で追加する
+use chrono::{DateTime, FixedOffset};
 use serde_json::Value;
-use rusqlite::{Connection, Result,params};
-use tokio::net::tcp::ReuniteError;
-
-//LDトレイピックアップ情報をDBに挿入するためのsql文を生成
-pub fn regist_u1_tr_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);             //シリアル番号
-    let wano=hash_map.get("wano").and_then(|v| v.as_i64()).unwrap_or(0);                 //wa番号
-    let wax=hash_map.get("wax").and_then(|v| v.as_i64()).unwrap_or(0);                  //waX座標
-    let way=hash_map.get("way").and_then(|v| v.as_i64()).unwrap_or(0);                  //waY座標
-    let trayid=hash_map.get("trayid").and_then(|v| v.as_str()).unwrap_or("unknown");    //トレイID
-    let trayarm=hash_map.get("trayarm").and_then(|v| v.as_str()).unwrap_or("unknown");  //トレイアーム位置
-    let px=hash_map.get("px").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標X
-    let py=hash_map.get("py").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標Y
-    let pax=hash_map.get("pax").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量X
-    let pay=hash_map.get("pay").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量Y
-    let date=hash_map.get("date").and_then(|v| v.as_str()).unwrap_or("unknown");        //ピックアップ時刻
-
-        // SQL文字列をformat!で構築
-    let sql = format!(
-        "INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, 
-        WANO, WAX, WAY, LD_PICKUP_DATE, LD_TRAYID, LD_TRAY_ARM, 
-        LD_TRAY_POCKET_X, LD_TRAY_POCKET_Y, LD_TRAY_ALIGN_X, LD_TRAY_ALIGN_Y)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
-        ON CONFLICT(lot_name, serial)
-        DO UPDATE SET 
-        MACHINE_NAME = excluded.MACHINE_NAME, 
-        TYPE_NAME = excluded.TYPE_NAME, 
-        WANO = excluded.WANO, 
-        WAX = excluded.WAX, 
-        WAY = excluded.WAY,
-        LD_PICKUP_DATE = excluded.LD_PICKUP_DATE, 
-        LD_TRAYID = excluded.LD_TRAYID, 
-        LD_TRAY_ARM = excluded.LD_TRAY_ARM,
-        LD_TRAY_POCKET_X = excluded.LD_TRAY_POCKET_X,
-        LD_TRAY_POCKET_Y = excluded.LD_TRAY_POCKET_Y,
-        LD_TRAY_ALIGN_X = excluded.LD_TRAY_ALIGN_X,
-        LD_TRAY_ALIGN_Y = excluded.LD_TRAY_ALIGN_Y;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name,serial,wano,wax,way,date,trayid,trayarm,px,py,pax,pay])?;
-    Ok(()) 
+use crate::clock::{self, PlcTime};
+use crate::chip_store::{ChipUpsert, ChipWrite, ColumnValue, HistoryInsert};
+use crate::frame::{RecordKind, UnitRecord};
+use crate::output_sink::IngestFrame;
+use crate::records::{
+    decode, AlarmRecord, ArmColletRecord, IpBinRecord, IpStageRecord, PreheatRecord, TestStageRecord,
+    TrayPickupRecord, UldChipRecord, UldPocketRecord,
+};
+
+/// 設備イベントの種類(アラーム)
+pub const MACHINE_EVENT_ALARM: &str = "alarm";
+
+/// アラーム履歴のテーブル名
+pub fn alarm_table(table_name: &str) -> String {
+    format!("{}_alarms", table_name)
+}
 
+/// チップの更新履歴のテーブル名
+pub fn history_table(table_name: &str) -> String {
+    format!("{}_history", table_name)
 }
 
-pub fn regist_arm1_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let count=hash_map.get("count").and_then(|v| v.as_i64()).unwrap_or(0);
+/// チップの行へのupsertを更新履歴の1行にする
+/// 登録した値は DATA にJSONで入れる(回数の加算は履歴の行数で分かるので含めない)
+pub fn history_insert(table_name: &str, record: &UnitRecord, row: &ChipUpsert, received_at: &DateTime<FixedOffset>) -> HistoryInsert {
+    let mut data = serde_json::Map::new();
+    for (column, value) in &row.columns {
+        // 受信時刻は RECEIVED_AT に入れる
+        if column.ends_with("_RECEIVED_AT") {
+            continue;
+        }
+        let value = match value {
+            ColumnValue::Int(v) => Value::from(*v),
+            ColumnValue::Text(v) => Value::from(v.as_str()),
+            ColumnValue::Null => Value::Null,
+            ColumnValue::Increment => continue,
+        };
+        data.insert(column.clone(), value);
+    }
+    HistoryInsert::new(history_table(table_name))
+        .text("RECEIVED_AT", clock::to_iso(received_at).as_str())
+        .text("MACHINE_NAME", row.machine_name.as_str())
+        .text("TYPE_NAME", row.type_name.as_str())
+        .text("LOT_NAME", row.lot_name.as_str())
+        .int("SERIAL", row.serial)
+        .text("UNIT", unit_prefix(&record.unit).unwrap_or(record.unit.as_str()))
+        .text("RECORD_KIND", record.kind.as_str())
+        .text("RECORD_KEY", record.key.as_str())
+        .text("DATA", Value::Object(data).to_string().as_str())
+}
 
-    // カラム名を動的に生成
-    let unit=match unit_name{
-        "U1"=>"LD",
-        "U2"=>"DC1",
-        "U3"=>"AC1",
-        "U4"=>"AC2",
-        "U5"=>"DC2",
-        "U6"=>"IP",
-        "U7"=>"ULD",
-        _=>{return Err(rusqlite::Error::InvalidQuery);}
-    }; 
-    let column = format!("{}_ARM1_COLLET", unit);
+/// ユニット情報を種類に応じた登録内容に変換する
+pub fn build_writes(input: &IngestFrame, record: &UnitRecord) -> Result<Vec<ChipWrite>, String> {
+    let frame = &input.frame;
+    let machine_name = frame.machine_name.as_str();
+    let lot_name = frame.lot_name.as_str();
+    let type_name = frame.type_name.as_str();
+    let unit_name = record.unit.as_str();
+    let value = &record.value;
+    let received_at = &input.received_at;
+
+    let row = match record.kind {
+        RecordKind::TrayPickup => regist_u1_tr_info(machine_name,lot_name,type_name,received_at,value)?, //LD TRAYデータを登録
+        RecordKind::Arm1 => regist_arm1_info(machine_name,lot_name,type_name,unit_name,value)?, //上流アームコレットの使用回数データを登録
+        RecordKind::Arm2 => regist_arm2_info(machine_name,lot_name,type_name,unit_name,value)?, //下流アームコレットの使用回数データを登録
+        RecordKind::Preheat => regist_ph_info(machine_name,lot_name,type_name,unit_name,value)?, //DC1,ULD予熱テーブルのデータを登録
+        RecordKind::TestStage => regist_ts_info(machine_name,lot_name,type_name,unit_name,value)?, //DC1~DC2検査テーブルのデータを登録
+        RecordKind::IpStage => regist_ip_ts_info(machine_name,lot_name,type_name,value)?, //IP検査テーブルのデータを登録
+        RecordKind::IpSurfBin => regist_ip_surf_info(machine_name,lot_name,type_name,value)?, //IP表面検査のBINデータを登録
+        RecordKind::IpBackBin => regist_ip_back_info(machine_name,lot_name,type_name,value)?, //IP裏面検検のBINデータを登録
+        RecordKind::UldPocket => regist_uld_pocket_info(machine_name,lot_name,type_name,value)?, //ULDポケット認識時のデータを登録
+        RecordKind::UldChip => regist_uld_chip_info(machine_name,lot_name,type_name,received_at,value)?, //ULDポケット挿入時のデータを登録
+        RecordKind::Alarm => {
+            let writes = regist_alarm_info(machine_name,lot_name,type_name,unit_name,&input.table_name,received_at,value)?; //アラーム情報の登録
+            return Ok(stamp_received(writes,unit_name,received_at));
+        }
+    };
+    Ok(stamp_received(vec![ChipWrite::Upsert(row)],unit_name,received_at))
+}
+
+/// チップの行にユニットごとの受信時刻({UNIT}_RECEIVED_AT)を追加する
+fn stamp_received(mut writes: Vec<ChipWrite>, unit_name: &str, received_at: &DateTime<FixedOffset>) -> Vec<ChipWrite> {
+    let Ok(unit) = unit_prefix(unit_name) else {
+        return writes;
+    };
+    let column = format!("{}_RECEIVED_AT", unit);
+    let received_at = clock::to_iso(received_at);
+    for write in &mut writes {
+        if let ChipWrite::Upsert(row) = write {
+            row.columns.push((column.clone(), ColumnValue::Text(received_at.clone())));
+        }
+    }
+    writes
+}
 
-    // SQL文字列をformat!で構築
-    let sql = format!(
-        "INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, {column})
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         {column} = excluded.{column};");
+/// PLCの日時を変換した {PREFIX}_AT・{PREFIX}_EPOCH_MS と、受信時刻との差 {UNIT}_CLOCK_SKEW_MS を追加する
+/// 読めない日時はNULLとして登録する(元の文字列は *_DATE に残る)
+fn with_plc_time(row: ChipUpsert, prefix: &str, unit: &str, time: Option<PlcTime>) -> ChipUpsert {
+    row.text(format!("{}_AT", prefix), time.as_ref().map(|t| t.iso.as_str()))
+        .int(format!("{}_EPOCH_MS", prefix), time.as_ref().map(|t| t.epoch_ms))
+        .int(format!("{}_CLOCK_SKEW_MS", unit), time.as_ref().map(|t| t.skew_ms))
+}
 
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, count])?;
-    Ok(())
+/// ユニット名(U1~U7)をカラム名の接頭辞に変換する
+fn unit_prefix(unit_name: &str) -> Result<&'static str, String> {
+    match unit_name {
+        "U1"=>Ok("LD"),
+        "U2"=>Ok("DC1"),
+        "U3"=>Ok("AC1"),
+        "U4"=>Ok("AC2"),
+        "U5"=>Ok("DC2"),
+        "U6"=>Ok("IP"),
+        "U7"=>Ok("ULD"),
+        _=>Err(format!("Unknown unit: {}", unit_name)),
+    }
+}
 
+//LDトレイピックアップ情報をDBに登録するためのupsertを生成
+pub fn regist_u1_tr_info(machine_name:&str,lot_name:&str,type_name:&str,received_at:&DateTime<FixedOffset>,value:&Value)->Result<ChipUpsert,String>{
+    let record:TrayPickupRecord=decode(value)?;
+    let pickup_at=clock::plc_time(record.date.as_deref(),received_at);
+
+    let row=ChipUpsert::new(machine_name,type_name,lot_name,record.serial)
+        .int("WANO",record.wano)
+        .int("WAX",record.wax)
+        .int("WAY",record.way)
+        .text("LD_PICKUP_DATE",record.date.as_deref())
+        .text("LD_TRAYID",record.trayid.as_deref())
+        .text("LD_TRAY_ARM",record.trayarm.as_deref())
+        .int("LD_TRAY_POCKET_X",record.px)
+        .int("LD_TRAY_POCKET_Y",record.py)
+        .int("LD_TRAY_ALIGN_X",record.pax)
+        .int("LD_TRAY_ALIGN_Y",record.pay);
+    Ok(with_plc_time(row,"LD_PICKUP","LD",pickup_at))
 }
 
-pub fn regist_arm2_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let count=hash_map.get("count").and_then(|v| v.as_i64()).unwrap_or(0);
+pub fn regist_arm1_info(machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:ArmColletRecord=decode(value)?;
 
     // カラム名を動的に生成
-    let unit=match unit_name{
-        "U1"=>"LD",
-        "U2"=>"DC1",
-        "U3"=>"AC1",
-        "U4"=>"AC2",
-        "U5"=>"DC2",
-        "U6"=>"IP",
-        "U7"=>"ULD",
-        _=>{return Err(rusqlite::Error::InvalidQuery);}
-    }; 
-    let column = format!("{}_ARM2_COLLET", unit);
+    let unit=unit_prefix(unit_name)?;
+    let column = format!("{}_ARM1_COLLET", unit);
 
-    // SQL文字列をformat!で構築
-    let sql = format!(
-        "INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, {column})
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         {column} = excluded.{column};");
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial).int(column,record.count))
+}
 
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, count])?;
-    Ok(())
+pub fn regist_arm2_info(machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:ArmColletRecord=decode(value)?;
+
+    // カラム名を動的に生成
+    let unit=unit_prefix(unit_name)?;
+    let column = format!("{}_ARM2_COLLET", unit);
 
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial).int(column,record.count))
 }
 
 
-pub fn regist_ph_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let ax=hash_map.get("ax").and_then(|v| v.as_i64()).unwrap_or(0);    //補正量X
-    let ay=hash_map.get("ay").and_then(|v| v.as_i64()).unwrap_or(0);    //補正量Y
-    let at=hash_map.get("at").and_then(|v| v.as_i64()).unwrap_or(0);    //補正量Θ
+pub fn regist_ph_info(machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:PreheatRecord=decode(value)?;
 
     // カラム名を動的に生成
     let unit=match unit_name{
         "U2"=>"DC1",
         "U7"=>"ULD",
-        _ => {return Err(rusqlite::Error::InvalidQuery);}
-    }; 
-    let c1 = format!("{}_PRE_ALIGN_X", unit);
-    let c2 = format!("{}_PRE_ALIGN_Y", unit);
-    let c3 = format!("{}_PRE_ALIGN_T", unit);
-
-    // SQL文字列をformat!で構築
-    let sql = format!(
-        "INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, {c1}, {c2}, {c3})
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         {c1} = excluded.{c1}, 
-         {c2} = excluded.{c2}, 
-         {c3} = excluded.{c3};");
-
-    //DBに登録
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, ax,ay,at])?;
-
-    Ok(())
+        _ => {return Err(format!("Unit {} has no pre-align table", unit_name));}
+    };
 
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial)
+        .int(format!("{}_PRE_ALIGN_X", unit),record.ax)
+        .int(format!("{}_PRE_ALIGN_Y", unit),record.ay)
+        .int(format!("{}_PRE_ALIGN_T", unit),record.at))
 }
 
-pub fn regist_ts_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let stage_serial=hash_map.get("stage_serial").and_then(|v| v.as_str()).unwrap_or("unknown");
-    let stage_count=hash_map.get("stage_count").and_then(|v| v.as_i64()).unwrap_or(0);
-    let stage_z=hash_map.get("stage_z").and_then(|v| v.as_i64()).unwrap_or(0);
-    let pin_z=hash_map.get("pin_z").and_then(|v| v.as_i64()).unwrap_or(0);
-    let probe_serial=hash_map.get("probe_serial").and_then(|v| v.as_str()).unwrap_or("unknown");
-    let probe_count=hash_map.get("probe_count").and_then(|v| v.as_i64()).unwrap_or(0);
-    let prx1=hash_map.get("probe_x1").and_then(|v| v.as_i64()).unwrap_or(0);
-    let pry1=hash_map.get("probe_y1").and_then(|v| v.as_i64()).unwrap_or(0);
-    let prx2=hash_map.get("probe_x2").and_then(|v| v.as_i64()).unwrap_or(0);
-    let pry2=hash_map.get("probe_y2").and_then(|v| v.as_i64()).unwrap_or(0);
-    let ax=hash_map.get("ax").and_then(|v| v.as_i64()).unwrap_or(0);
-    let ay=hash_map.get("ay").and_then(|v| v.as_i64()).unwrap_or(0);
-    let at=hash_map.get("at").and_then(|v| v.as_i64()).unwrap_or(0);
-    let bin=hash_map.get("bin").and_then(|v| v.as_i64()).unwrap_or(-1);
+pub fn regist_ts_info(machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:TestStageRecord=decode(value)?;
 
     // カラム名を動的に生成
     let unit=match unit_name{
@@ -175,262 +188,115 @@ pub fn regist_ts_info(conn:&Connection,table_name:&str,machine_name:&str,lot_nam
         "U3"=>"AC1",
         "U4"=>"AC2",
         "U5"=>"DC2",
-        _ => {return Err(rusqlite::Error::InvalidQuery);}
+        _ => {return Err(format!("Unit {} has no test stage", unit_name));}
     };
 
-    // カラム名を動的に生成
-    let c1 = format!("{}_STAGE_SERIAL", unit);
-    let c2 = format!("{}_STAGE_COUNT", unit);
-    let c3 = format!("{}_PROBE_SERIAL", unit);
-    let c4 = format!("{}_PROBE_COUNT", unit);
-    let c5 = format!("{}_PROBE_X1", unit);
-    let c6 = format!("{}_PROBE_Y1", unit);
-    let c7 = format!("{}_PROBE_X2", unit);
-    let c8 = format!("{}_PROBE_Y2", unit);
-    let c9 = format!("{}_STAGE_Z", unit);
-    let c10 = format!("{}_PIN_Z", unit);
-    let c11 = format!("{}_CHIP_ALIGN_X", unit);
-    let c12 = format!("{}_CHIP_ALIGN_Y", unit);
-    let c13 = format!("{}_CHIP_ALIGN_T", unit);
-    let c14 = format!("{}_TEST_BIN", unit);
-
-    // SQL文字列をformat!で構築
-    let sql = format!(
-        "INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, 
-        {c1}, {c2}, {c3},{c4},{c5},{c6},{c7},{c8},{c9},{c10},{c11},{c12},{c13},{c14})
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME=excluded.MACHINE_NAME,
-         TYPE_NAME=excluded.TYPE_NAME,
-         {c1} = excluded.{c1}, 
-         {c2} = excluded.{c2}, 
-         {c3} = excluded.{c3},
-         {c4} = excluded.{c4},
-         {c5} = excluded.{c5},
-         {c6} = excluded.{c6},
-         {c7} = excluded.{c7},
-         {c8} = excluded.{c8},
-         {c9} = excluded.{c9},
-         {c10} = excluded.{c10},
-         {c11} = excluded.{c11},
-         {c12} = excluded.{c12},
-         {c13} = excluded.{c13},
-         {c14} = excluded.{c14};");
-
-    //DBに登録
-    conn.execute(
-        &sql, 
-        params![
-        machine_name,
-        type_name,
-        lot_name, 
-        serial, 
-        stage_serial,
-        stage_count,
-        probe_serial,
-        probe_count,
-        prx1,
-        pry1,
-        prx2,
-        pry2,
-        stage_z,
-        pin_z,
-        ax,
-        ay,
-        at,
-        bin
-        ]
-    )?;
-
-    Ok(()) 
-
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial)
+        .text(format!("{}_STAGE_SERIAL", unit),record.stage_serial.as_deref())
+        .int(format!("{}_STAGE_COUNT", unit),record.stage_count)
+        .text(format!("{}_PROBE_SERIAL", unit),record.probe_serial.as_deref())
+        .int(format!("{}_PROBE_COUNT", unit),record.probe_count)
+        .int(format!("{}_PROBE_X1", unit),record.probe_x1)
+        .int(format!("{}_PROBE_Y1", unit),record.probe_y1)
+        .int(format!("{}_PROBE_X2", unit),record.probe_x2)
+        .int(format!("{}_PROBE_Y2", unit),record.probe_y2)
+        .int(format!("{}_STAGE_Z", unit),record.stage_z)
+        .int(format!("{}_PIN_Z", unit),record.pin_z)
+        .int(format!("{}_CHIP_ALIGN_X", unit),record.ax)
+        .int(format!("{}_CHIP_ALIGN_Y", unit),record.ay)
+        .int(format!("{}_CHIP_ALIGN_T", unit),record.at)
+        .int(format!("{}_TEST_BIN", unit),record.bin))
 }
 
-pub fn regist_ip_ts_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let stage_count=hash_map.get("stage_count").and_then(|v| v.as_i64()).unwrap_or(0);
-
-    // SQL文字列をformat!で構築
-    let sql = 
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, IP_STAGE_COUNT)
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         IP_STAGE_COUNT = excluded.IP_STAGE_COUNT;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, stage_count])?;
-    Ok(())
+pub fn regist_ip_ts_info(machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:IpStageRecord=decode(value)?;
+
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial).int("IP_STAGE_COUNT",record.stage_count))
 }
 
-pub fn regist_ip_surf_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let bin=hash_map.get("bin").and_then(|v| v.as_i64()).unwrap_or(0);
-
-    // SQL文字列をformat!で構築
-    let sql = 
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, IP_SURF_BIN)
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         IP_SURF_BIN = excluded.IP_SURF_BIN;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, bin])?;
-    Ok(())
+pub fn regist_ip_surf_info(machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:IpBinRecord=decode(value)?;
+
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial).int("IP_SURF_BIN",record.bin))
 }
 
-pub fn regist_ip_back_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);
-    let bin=hash_map.get("bin").and_then(|v| v.as_i64()).unwrap_or(0);
-
-    // SQL文字列をformat!で構築
-    let sql = 
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, IP_BACK_BIN)
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET 
-         MACHINE_NAME = excluded.MACHINE_NAME, 
-         TYPE_NAME = excluded.TYPE_NAME, 
-         IP_BACk_BIN = excluded.IP_BACK_BIN;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, bin])?;
-    Ok(())
+pub fn regist_ip_back_info(machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:IpBinRecord=decode(value)?;
+
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial).int("IP_BACK_BIN",record.bin))
 }
 
 //ULDポケットアライメント情報をDBに登録
-pub fn regist_uld_pocket_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);             //シリアル番号
-    let trayid=hash_map.get("trayid").and_then(|v| v.as_str()).unwrap_or("unknown");    //トレイID
-    let px=hash_map.get("px").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標X
-    let py=hash_map.get("py").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標Y
-    let pax=hash_map.get("pax").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量X
-    let pay=hash_map.get("pay").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量Y
-
-        // SQL文字列をformat!で構築
-    let sql = 
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, 
-        ULD_TRAYID, ULD_POCKET_X, ULD_POCKET_Y, ULD_POCKET_ALIGN_X, ULD_POCKET_ALIGN_Y)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-        ON CONFLICT(lot_name, serial)
-        DO UPDATE SET 
-        MACHINE_NAME = excluded.MACHINE_NAME,
-        TYPE_NAME = excluded.TYPE_NAME,
-        ULD_TRAYID = excluded.ULD_TRAYID, 
-        ULD_POCKET_X = excluded.ULD_POCKET_X,
-        ULD_POCKET_Y = excluded.ULD_POCKET_Y,
-        ULD_POCKET_ALIGN_X = excluded.ULD_POCKET_ALIGN_X,
-        ULD_POCKET_ALIGN_Y = excluded.ULD_POCKET_ALIGN_Y;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name,serial,trayid,px,py,pax,pay])?;
-    Ok(()) 
-
+pub fn regist_uld_pocket_info(machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<ChipUpsert,String>{
+    let record:UldPocketRecord=decode(value)?;
+
+    Ok(ChipUpsert::new(machine_name,type_name,lot_name,record.serial)
+        .text("ULD_TRAYID",record.trayid.as_deref())
+        .int("ULD_POCKET_X",record.px)
+        .int("ULD_POCKET_Y",record.py)
+        .int("ULD_POCKET_ALIGN_X",record.pax)
+        .int("ULD_POCKET_ALIGN_Y",record.pay))
 }
 
 //ULD挿入後チップアライメント情報をDBに登録
-pub fn regist_uld_chip_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    //pf,serial,arm_pos,tpx,tpy,tpax,tpayを抜き出す
-    let hash_map=value.as_object().unwrap();
-    let serial=hash_map.get("serial").and_then(|v| v.as_i64()).unwrap_or(0);             //シリアル番号
-    let px=hash_map.get("px").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標X
-    let py=hash_map.get("py").and_then(|v| v.as_i64()).unwrap_or(0);                     //ポケット座標Y
-    let cax=hash_map.get("cax").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量X
-    let cay=hash_map.get("cay").and_then(|v| v.as_i64()).unwrap_or(0);                   //ポケット補正量Y
-    let date=hash_map.get("date").and_then(|v| v.as_str()).unwrap_or("unknown");        //挿入時刻
-
-    //lot_name,serialのULD_CHIP_ALIGN_NUMを取得する
-    //nullであれば、align_numを0にする、数値であれば+1する
-    let get_align_num_sql=
-        format!("SELECT ULD_CHIP_ALIGN_NUM FROM {} WHERE LOT_NAME='{}' AND SERIAL={}",table_name,lot_name,serial);
-
-    let align_num: i64 = conn.query_row(&get_align_num_sql, [], |row| {
-        row.get::<_, Option<i64>>(0)
-    })
-    .unwrap_or(None)
-    .map(|num| num + 1)
-    .unwrap_or(1);
-
-    // SQL文字列をformat!で構築
-    let sql = 
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, 
-        ULD_POCKET_X, ULD_POCKET_Y, ULD_CHIP_ALIGN_X, ULD_CHIP_ALIGN_Y,ULD_PUT_DATE,ULD_CHIP_ALIGN_NUM)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
-        ON CONFLICT(lot_name, serial)
-        DO UPDATE SET 
-        MACHINE_NAME = excluded.MACHINE_NAME,
-        TYPE_NAME = excluded.TYPE_NAME,
-        ULD_POCKET_X = excluded.ULD_POCKET_X,
-        ULD_POCKET_Y = excluded.ULD_POCKET_Y,
-        ULD_CHIP_ALIGN_X = excluded.ULD_CHIP_ALIGN_X,
-        ULD_CHIP_ALIGN_Y = excluded.ULD_CHIP_ALIGN_Y,
-        ULD_PUT_DATE = excluded.ULD_PUT_DATE,
-        ULD_CHIP_ALIGN_NUM = excluded.ULD_CHIP_ALIGN_NUM;");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name,serial,px,py,cax,cay,date,align_num])?;
-    Ok(()) 
-
+pub fn regist_uld_chip_info(machine_name:&str,lot_name:&str,type_name:&str,received_at:&DateTime<FixedOffset>,value:&Value)->Result<ChipUpsert,String>{
+    let record:UldChipRecord=decode(value)?;
+    let put_at=clock::plc_time(record.date.as_deref(),received_at);
+
+    //lot_name,serialのULD_CHIP_ALIGN_NUMは、未登録(null)であれば1、数値であれば+1する
+    let row=ChipUpsert::new(machine_name,type_name,lot_name,record.serial)
+        .int("ULD_POCKET_X",record.px)
+        .int("ULD_POCKET_Y",record.py)
+        .int("ULD_CHIP_ALIGN_X",record.cax)
+        .int("ULD_CHIP_ALIGN_Y",record.cay)
+        .text("ULD_PUT_DATE",record.date.as_deref())
+        .increment("ULD_CHIP_ALIGN_NUM");
+    Ok(with_plc_time(row,"ULD_PUT","ULD",put_at))
 }
 
-pub fn regist_alarm_info(conn:&Connection,table_name:&str,machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,value:&Value)->Result<(),rusqlite::Error>{
-    let hash_map=value.as_object().unwrap();
-    let alarm_num=hash_map.get("alarm_num").and_then(|v| v.as_i64()).unwrap_or(0);
+pub fn regist_alarm_info(machine_name:&str,lot_name:&str,type_name:&str,unit_name:&str,table_name:&str,received_at:&DateTime<FixedOffset>,value:&Value)->Result<Vec<ChipWrite>,String>{
+    let record:AlarmRecord=decode(value)?;
+    //1秒以内に繰り返したアラームも別の行にするため、重複の判定には受信時刻のエポックミリ秒を使う
+    let received_at_ms=received_at.timestamp_millis();
+    let received_at=clock::to_iso(received_at);
+    let received_at=received_at.as_str();
 
     // カラム名を動的に生成
-    let unit=match unit_name{
-        "U1"=>"LD",
-        "U2"=>"DC1",
-        "U3"=>"AC1",
-        "U4"=>"AC2",
-        "U5"=>"DC2",
-        "U6"=>"IP",
-        "U7"=>"ULD",
-        _=>{return Err(rusqlite::Error::InvalidQuery);}
-    }; 
+    let unit=unit_prefix(unit_name)?;
     let column = format!("{}_ALARM", unit);
 
-    //シリアルリストをVec<i32>として受け取る
-    let serial_list: Vec<i32> = hash_map
-        .get("serial")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| v.as_i64())
-                .map(|v| v as i32)
-                .collect()
-        })
-        .unwrap_or_default();
-
-    //最初の0でない要素を取得
-    let serial = match serial_list.iter().find(|&&s| s != 0) {
-        Some(&s) => s,
-        None => {
-            //全要素が0の場合は登録せずにリターン
-            return Ok(());
-        }
-    };
-
-    // SQL文字列をformat!で構築
-    let sql =
-        format!("INSERT INTO {table_name} (MACHINE_NAME, TYPE_NAME, LOT_NAME, SERIAL, {column})
-        VALUES (?1, ?2, ?3, ?4, ?5)
-        ON CONFLICT(lot_name, serial)
-         DO UPDATE SET
-         MACHINE_NAME = excluded.MACHINE_NAME,
-         TYPE_NAME = excluded.TYPE_NAME,
-         {column} = excluded.{column};");
-
-    conn.execute(&sql, params![machine_name,type_name,lot_name, serial, alarm_num])?;
-    Ok(())
+    //シリアルが無い(全要素が0の)アラームは設備のイベントとして登録する
+    let serials=record.serials();
+    if serials.is_empty(){
+        return Ok(vec![ChipWrite::Insert(
+            HistoryInsert::new("machine_events")
+                .text("RECEIVED_AT",received_at)
+                .int("RECEIVED_EPOCH_MS",received_at_ms)
+                .text("TABLE_NAME",table_name)
+                .text("MACHINE_NAME",machine_name)
+                .text("TYPE_NAME",type_name)
+                .text("LOT_NAME",lot_name)
+                .text("UNIT",unit)
+                .text("EVENT_TYPE",MACHINE_EVENT_ALARM)
+                .int("CODE",record.alarm_num),
+        )]);
+    }
+
+    //ユニット内の全チップに登録する(チップの行には最新のアラーム番号、全てのアラームは履歴テーブルに追加)
+    let mut writes=Vec::with_capacity(serials.len()*2);
+    for serial in serials{
+        writes.push(ChipWrite::Upsert(ChipUpsert::new(machine_name,type_name,lot_name,serial).int(column.as_str(),record.alarm_num)));
+        writes.push(ChipWrite::Insert(
+            HistoryInsert::new(alarm_table(table_name))
+                .text("RECEIVED_AT",received_at)
+                .int("RECEIVED_EPOCH_MS",received_at_ms)
+                .text("MACHINE_NAME",machine_name)
+                .text("TYPE_NAME",type_name)
+                .text("LOT_NAME",lot_name)
+                .int("SERIAL",serial)
+                .text("UNIT",unit)
+                .int("ALARM_NUM",record.alarm_num),
+        ));
+    }
+    Ok(writes)
 }
-
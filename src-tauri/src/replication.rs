@@ -0,0 +1,425 @@
+///ローカルのSQLiteに登録したチップ情報を中央サーバーへ複製する(ストア&フォワード)
+///各テーブルのトリガーで変更された行を replication_log に記録し、送信できた位置を replication_state に保存する
+///ネットワークが切れている間もSQLiteへの登録は続け、復旧後に溜まった分を古い順にまとめて送る
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::Local;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::{command, AppHandle, Emitter, Manager};
+
+use crate::chip_store::{ChipStore, ChipUpsert, StoreError};
+use crate::data_handler::get_db_path;
+use crate::postgres_store::PostgresStore;
+use crate::shutdown::ShutdownState;
+use crate::types::{PostgresConfig, ReplicationConfig};
+
+//変更ログと送信位置のテーブルを作成するためのsql文を読み込み
+static REPLICATION_SQL: &str = include_str!("sql/replication.sql");
+static REPLICATION_TRIGGER_SQL: &str = include_str!("sql/replication_trigger.sql");
+
+//書き込みスレッドと同時に書き込む場合にロック解除を待つ最大時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref REPLICATION_STATUS: Mutex<ReplicationStatus> = Mutex::new(ReplicationStatus::default());
+}
+
+/// 複製の状態(フロントエンド返却用)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReplicationStatus {
+    pub enabled: bool,
+    pub target: String,
+    /// 直近の送信に成功しているか
+    pub online: bool,
+    /// 未送信の変更件数
+    pub backlog: u64,
+    /// 送信済みの変更ログの位置
+    pub last_seq: i64,
+    pub sent_total: u64,
+    pub last_sent_at: Option<String>,
+    pub last_error: Option<String>,
+    /// 失敗後、次に送信を試みるまでの時間(ms)
+    pub retry_in_ms: u64,
+}
+
+/// 複製する1行(送信時点の行の内容をそのまま送る)
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicatedRow {
+    pub seq: i64,
+    pub table: String,
+    pub data: Map<String, Value>,
+}
+
+/// 複製用のテーブルを作成する
+/// 無効の場合は変更ログが溜まり続けないようにトリガーを削除する
+pub fn init_schema(conn: &Connection, enabled: bool) -> rusqlite::Result<()> {
+    if enabled {
+        return conn.execute_batch(REPLICATION_SQL);
+    }
+
+    let triggers: Vec<String> = conn
+        .prepare(
+            r"SELECT name FROM sqlite_master WHERE type = 'trigger'
+              AND (name LIKE '%\_replication\_insert' ESCAPE '\' OR name LIKE '%\_replication\_update' ESCAPE '\')",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for trigger in triggers {
+        conn.execute_batch(&format!("DROP TRIGGER IF EXISTS \"{}\"", trigger))?;
+        log::info!("Replication trigger {} dropped", trigger);
+    }
+    Ok(())
+}
+
+/// テーブルに変更ログのトリガーを作成する
+/// 新しく作成した場合は既存の行も未送信として記録する(複製を後から有効にした場合など)
+/// table_name は呼び出し側で確認済みであること
+pub fn ensure_triggers(conn: &Connection, table_name: &str) -> rusqlite::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = ?1)",
+        [format!("{}_replication_insert", table_name)],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Ok(());
+    }
+
+    conn.execute_batch(&REPLICATION_TRIGGER_SQL.replace("{TABLE_NAME}", table_name))?;
+    let queued = conn.execute(
+        &format!("INSERT INTO replication_log (\"TABLE_NAME\", \"ROW_ID\") SELECT ?1, \"ID\" FROM \"{}\"", table_name),
+        [table_name],
+    )?;
+    log::info!("Replication triggers created for {} ({} existing rows queued)", table_name, queued);
+    Ok(())
+}
+
+/// 複製の送信先
+trait ReplicationTarget: Send {
+    /// 全行を送れた場合のみOk(Errの場合は同じ行を後で送り直す)
+    fn send(&mut self, rows: &[ReplicatedRow]) -> Result<(), String>;
+}
+
+/// JSONをPOSTする送信先
+/// 送信内容: {"source": "...", "rows": [{"seq": 1, "table": "...", "data": {"ID": 1, "LOT_NAME": "...", ...}}]}
+/// 受信側は (source, table, LOT_NAME, SERIAL) をキーにupsertする想定で、2xx以外は失敗として再送する
+struct HttpTarget {
+    agent: ureq::Agent,
+    url: String,
+    token: Option<String>,
+    source: String,
+}
+
+impl HttpTarget {
+    fn new(config: &ReplicationConfig) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(config.request_timeout_secs.max(1))))
+            .build()
+            .into();
+        HttpTarget {
+            agent,
+            url: config.url.clone(),
+            token: config.token.clone(),
+            source: config.source.clone(),
+        }
+    }
+}
+
+impl ReplicationTarget for HttpTarget {
+    fn send(&mut self, rows: &[ReplicatedRow]) -> Result<(), String> {
+        let body = serde_json::json!({ "source": self.source, "rows": rows });
+        let mut request = self.agent.post(&self.url);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request
+            .send_json(&body)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send to {}: {}", self.url, e))
+    }
+}
+
+/// PostgreSQLに直接upsertする送信先(postgres セクションの接続先を使う)
+struct PostgresTarget {
+    store: PostgresStore,
+}
+
+impl ReplicationTarget for PostgresTarget {
+    fn send(&mut self, rows: &[ReplicatedRow]) -> Result<(), String> {
+        self.store.begin()?;
+        for row in rows {
+            self.store.ensure_table(&row.table)?;
+            let Some(upsert) = to_upsert(&row.data) else {
+                continue;
+            };
+            match self.store.upsert(&row.table, &upsert) {
+                Ok(()) => {}
+                // 値の異常はその行だけ読み飛ばす(送り直しても同じ結果になるため)
+                Err(StoreError::Record(e)) => {
+                    log::warn!("Skipped replicating {} row {}: {}", row.table, row.seq, e);
+                }
+                Err(StoreError::Backend(e)) => return Err(e),
+            }
+        }
+        self.store.commit()
+    }
+}
+
+/// SQLiteの1行をPostgreSQLへのupsertに変換する(SERIALが無い行は送らない)
+/// NULLのカラムは送らないので、中央側の既存の値は上書きしない
+fn to_upsert(data: &Map<String, Value>) -> Option<ChipUpsert> {
+    let text = |column: &str| data.get(column).and_then(|v| v.as_str()).unwrap_or_default();
+    let serial = data.get("SERIAL")?.as_i64()?;
+    let mut row = ChipUpsert::new(text("MACHINE_NAME"), text("TYPE_NAME"), text("LOT_NAME"), serial);
+    for (column, value) in data {
+        if matches!(column.as_str(), "ID" | "MACHINE_NAME" | "TYPE_NAME" | "LOT_NAME" | "SERIAL") {
+            continue;
+        }
+        row = match value {
+            Value::Number(n) => match n.as_i64() {
+                Some(v) => row.int(column.as_str(), v),
+                None => row.text(column.as_str(), n.to_string().as_str()),
+            },
+            Value::String(s) => row.text(column.as_str(), s.as_str()),
+            _ => row,
+        };
+    }
+    Some(row)
+}
+
+/// 変更ログを読み出して送信先に送る
+struct Forwarder {
+    config: ReplicationConfig,
+    conn: Option<Connection>,
+    target: Box<dyn ReplicationTarget>,
+}
+
+impl Forwarder {
+    /// 未送信の変更を1バッチ分送り、送った変更ログの件数を返す
+    fn forward_batch(&mut self) -> Result<usize, String> {
+        if self.conn.is_none() {
+            let conn = Connection::open(get_db_path()).map_err(|e| format!("Failed to open database: {}", e))?;
+            conn.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+            self.conn = Some(conn);
+        }
+        let conn = self.conn.as_ref().unwrap();
+        let target = self.config.target.as_str();
+
+        let last_seq = read_last_seq(conn, target).map_err(|e| format!("Failed to read replication state: {}", e))?;
+        let backlog = count_backlog(conn, last_seq).map_err(|e| format!("Failed to count replication backlog: {}", e))?;
+        {
+            let mut status = REPLICATION_STATUS.lock();
+            status.last_seq = last_seq;
+            status.backlog = backlog;
+        }
+        if backlog == 0 {
+            return Ok(0);
+        }
+
+        let (rows, entries, max_seq) = read_batch(conn, last_seq, self.config.batch_size.max(1))
+            .map_err(|e| format!("Failed to read replication log: {}", e))?;
+        if !rows.is_empty() {
+            self.target.send(&rows)?;
+        }
+
+        // 送信できた位置を保存して、送信済みの変更ログを削除する
+        let sent_at = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        mark_sent(conn, target, max_seq, &sent_at).map_err(|e| format!("Failed to save replication state: {}", e))?;
+
+        let mut status = REPLICATION_STATUS.lock();
+        status.last_seq = max_seq;
+        status.backlog = backlog.saturating_sub(entries as u64);
+        status.sent_total += rows.len() as u64;
+        status.last_sent_at = Some(sent_at);
+        Ok(entries)
+    }
+}
+
+fn read_last_seq(conn: &Connection, target: &str) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT \"LAST_SEQ\" FROM replication_state WHERE \"TARGET\" = ?1", [target], |row| row.get(0))
+        .optional()
+        .map(|seq| seq.unwrap_or(0))
+}
+
+fn count_backlog(conn: &Connection, last_seq: i64) -> rusqlite::Result<u64> {
+    conn.query_row("SELECT COUNT(*) FROM replication_log WHERE \"SEQ\" > ?1", [last_seq], |row| row.get(0))
+}
+
+/// 変更ログを古い順に読み出し、送信する行を返す
+/// 同じ行の変更が複数ある場合は最新の内容を1回だけ送る
+/// 戻り値: (送信する行, 読み出した変更ログの件数, 読み出した最後の位置)
+fn read_batch(conn: &Connection, last_seq: i64, limit: usize) -> rusqlite::Result<(Vec<ReplicatedRow>, usize, i64)> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT \"SEQ\", \"TABLE_NAME\", \"ROW_ID\" FROM replication_log WHERE \"SEQ\" > ?1 ORDER BY \"SEQ\" LIMIT ?2",
+    )?;
+    let entries: Vec<(i64, String, i64)> = stmt
+        .query_map(params![last_seq, limit as i64], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let max_seq = entries.last().map(|(seq, _, _)| *seq).unwrap_or(last_seq);
+
+    let mut latest: HashMap<(String, i64), i64> = HashMap::new();
+    for (seq, table, row_id) in &entries {
+        latest.insert((table.clone(), *row_id), *seq);
+    }
+    let mut changes: Vec<(i64, String, i64)> = latest.into_iter().map(|((table, row_id), seq)| (seq, table, row_id)).collect();
+    changes.sort_unstable_by_key(|(seq, _, _)| *seq);
+
+    let mut rows = Vec::with_capacity(changes.len());
+    for (seq, table, row_id) in changes {
+        // 削除済みの行は送らない
+        if let Some(data) = read_row(conn, &table, row_id)? {
+            rows.push(ReplicatedRow { seq, table, data });
+        }
+    }
+    Ok((rows, entries.len(), max_seq))
+}
+
+/// 1行分の全カラムをJSONにする
+fn read_row(conn: &Connection, table_name: &str, row_id: i64) -> rusqlite::Result<Option<Map<String, Value>>> {
+    let mut stmt = match conn.prepare_cached(&format!("SELECT * FROM \"{}\" WHERE \"ID\" = ?1", table_name)) {
+        Ok(stmt) => stmt,
+        // テーブルごと無くなっている場合
+        Err(e) if e.to_string().contains("no such table") => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    stmt.query_row([row_id], |row| {
+        let mut data = Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null | ValueRef::Blob(_) => Value::Null,
+                ValueRef::Integer(v) => Value::from(v),
+                ValueRef::Real(v) => Value::from(v),
+                ValueRef::Text(v) => Value::from(String::from_utf8_lossy(v).into_owned()),
+            };
+            data.insert(column.clone(), value);
+        }
+        Ok(data)
+    })
+    .optional()
+}
+
+fn mark_sent(conn: &Connection, target: &str, seq: i64, sent_at: &str) -> rusqlite::Result<()> {
+    conn.execute_batch("BEGIN IMMEDIATE")?;
+    let result = (|| {
+        conn.execute(
+            "INSERT INTO replication_state (\"TARGET\", \"LAST_SEQ\", \"LAST_SENT_AT\") VALUES (?1, ?2, ?3)
+             ON CONFLICT (\"TARGET\") DO UPDATE SET \"LAST_SEQ\" = excluded.\"LAST_SEQ\", \"LAST_SENT_AT\" = excluded.\"LAST_SENT_AT\"",
+            params![target, seq, sent_at],
+        )?;
+        conn.execute("DELETE FROM replication_log WHERE \"SEQ\" <= ?1", [seq])?;
+        conn.execute_batch("COMMIT")
+    })();
+    if result.is_err() && !conn.is_autocommit() {
+        conn.execute_batch("ROLLBACK").ok();
+    }
+    result
+}
+
+/// 複製を開始する(終了処理が始まったら停止する)
+/// 未送信の行が溜まっている間は待たずに続けて送り、失敗した場合は間隔を延ばしながら再送する
+pub async fn run(app: AppHandle, config: ReplicationConfig, postgres: PostgresConfig) {
+    {
+        let mut status = REPLICATION_STATUS.lock();
+        status.enabled = true;
+        status.target = config.target.clone();
+    }
+
+    let target: Box<dyn ReplicationTarget> = match config.target.as_str() {
+        "http" => Box::new(HttpTarget::new(&config)),
+        "postgres" => Box::new(PostgresTarget { store: PostgresStore::new(&postgres) }),
+        other => {
+            log::error!("Unknown replication target: {}", other);
+            REPLICATION_STATUS.lock().last_error = Some(format!("Unknown replication target: {}", other));
+            emit_status(&app);
+            return;
+        }
+    };
+    log::info!("Replication started (target={})", config.target);
+
+    let interval = Duration::from_millis(config.interval_ms.max(100));
+    let initial_backoff = Duration::from_millis(config.initial_backoff_ms.max(1));
+    let max_backoff = Duration::from_millis(config.max_backoff_ms).max(initial_backoff);
+    let batch_size = config.batch_size.max(1);
+    let mut shutdown_rx = app.state::<ShutdownState>().subscribe();
+    let mut backoff: Option<Duration> = None;
+    let mut forwarder = Forwarder { config, conn: None, target };
+
+    loop {
+        // SQLite・送信先へのアクセスはブロッキングなので専用スレッドで行う
+        let (returned, result) = match tokio::task::spawn_blocking(move || {
+            let result = forwarder.forward_batch();
+            (forwarder, result)
+        })
+        .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Replication task failed: {}", e);
+                break;
+            }
+        };
+        forwarder = returned;
+
+        let delay = match result {
+            Ok(sent) => {
+                let mut status = REPLICATION_STATUS.lock();
+                if !status.online && status.last_error.is_some() {
+                    log::info!("Replication resumed (backlog={})", status.backlog);
+                }
+                status.online = true;
+                status.last_error = None;
+                status.retry_in_ms = 0;
+                backoff = None;
+                if sent >= batch_size { Duration::ZERO } else { interval }
+            }
+            Err(e) => {
+                let next = backoff.map(|d| (d * 2).min(max_backoff)).unwrap_or(initial_backoff);
+                backoff = Some(next);
+                let mut status = REPLICATION_STATUS.lock();
+                if status.online || status.last_error.is_none() {
+                    log::warn!("Replication failed, retrying in {:?}: {}", next, e);
+                } else {
+                    log::debug!("Replication failed, retrying in {:?}: {}", next, e);
+                }
+                status.online = false;
+                status.last_error = Some(e);
+                status.retry_in_ms = next.as_millis() as u64;
+                next
+            }
+        };
+        emit_status(&app);
+
+        if *shutdown_rx.borrow() {
+            break;
+        }
+        if !delay.is_zero() {
+            tokio::select! {
+                _ = shutdown_rx.changed() => break,
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+    }
+    log::info!("Replication stopped (backlog={})", REPLICATION_STATUS.lock().backlog);
+}
+
+fn emit_status(app: &AppHandle) {
+    if let Err(e) = app.emit("replication-status", get_replication_snapshot()) {
+        log::error!("Failed to emit replication status: {}", e);
+    }
+}
+
+/// 現在の複製の状態を取得する
+pub fn get_replication_snapshot() -> ReplicationStatus {
+    REPLICATION_STATUS.lock().clone()
+}
+
+/// 複製の状態(未送信件数・最終送信時刻・エラー)をフロントエンドに返す
+#[command]
+pub fn get_replication_status() -> ReplicationStatus {
+    get_replication_snapshot()
+}
@@ -0,0 +1,713 @@
+///古いロットの保管・削除(データ保持ポリシー)
+///PLCごとのテーブルで最終受信から保持期間を過ぎた(実行中でない)ロットを、最終受信月ごとの保管用DBファイル(archive_YYYY-MM.db)に移して
+///元のDBから削除し、VACUUMで縮小する。ロットに紐付かない設備イベント・隔離データ・規格違反も受信月ごとに移す
+///保管用DBをATTACHすると、読み出し用の接続では元のテーブル名で保管分も合わせて読める
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use rusqlite::{params, Connection, ToSql, TransactionBehavior};
+use serde::Serialize;
+use tauri::command;
+
+use crate::clock;
+use crate::config::load_config;
+use crate::consumable_trace::existing_tables;
+use crate::data_handler::{get_db_path, is_valid_identifier};
+use crate::lot_tracker::STATUS_RUNNING;
+use crate::regist_data_to_db::{alarm_table, history_table};
+use chrono::{DateTime, FixedOffset};
+use crate::types::RetentionConfig;
+
+/// 保管用DBファイル名の接頭辞
+const ARCHIVE_PREFIX: &str = "archive_";
+
+/// 書き込みスレッドとDBのロックを取り合う場合の待ち時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 起動してから最初に定期実行するまでの時間
+const STARTUP_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// 1回の incremental_vacuum で解放するページ数(書き込みスレッドを長く待たせないよう小分けにする)
+const VACUUM_STEP_PAGES: i64 = 1024;
+
+/// incremental_vacuum の間に書き込みスレッドへ譲る時間
+const VACUUM_STEP_PAUSE: Duration = Duration::from_millis(50);
+
+/// 読み出し用の接続に同時にATTACHできる保管用DBの数(SQLiteの上限10から main を除く)
+const MAX_ATTACHED: usize = 9;
+
+lazy_static! {
+    /// 保管の実行中(定期実行と手動実行を重ねない)
+    static ref RUNNING: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+    /// 読み出し用の接続にATTACHする保管用DBファイル
+    static ref ATTACHED: parking_lot::RwLock<Vec<PathBuf>> = parking_lot::RwLock::new(Vec::new());
+}
+
+/// 保管対象のロット
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchiveLot {
+    pub table_name: String,
+    pub lot_name: String,
+    pub last_seen_at: String,
+    pub chip_count: i64,
+    /// 移動先の保管用DBファイル名
+    pub archive_file: String,
+}
+
+/// 保管対象の記録(ロットに紐付かないテーブルの、受信月ごとの行数)
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchiveRecords {
+    pub table_name: String,
+    pub month: String,
+    pub row_count: i64,
+    /// 移動先の保管用DBファイル名
+    pub archive_file: String,
+}
+
+/// 保管の結果(dry_run の場合は対象の一覧のみ)
+#[derive(Serialize, Debug, Clone)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub lots: Vec<ArchiveLot>,
+    pub records: Vec<ArchiveRecords>,
+    /// 移動した行数(チップ・アラーム履歴・更新履歴・ロット・規格違反・設備イベント・隔離データの合計)
+    pub moved_rows: i64,
+    pub vacuumed: bool,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// 保管用DBファイル
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchiveFile {
+    pub name: String,
+    pub size_bytes: u64,
+    /// 読み出し用の接続にATTACHしているか
+    pub attached: bool,
+}
+
+/// テーブルの保持期間(日)
+fn keep_days(config: &RetentionConfig, table_name: &str) -> u32 {
+    config
+        .policies
+        .iter()
+        .find(|policy| policy.table == table_name)
+        .map_or(config.keep_days, |policy| policy.keep_days)
+}
+
+/// 保持期間の開始時刻(これより前に最終受信したロット・記録が対象)
+fn cutoff(keep_days: u32) -> DateTime<FixedOffset> {
+    clock::now() - chrono::Duration::days(keep_days as i64)
+}
+
+/// 保管用DBファイル名(月は "YYYY-MM")
+fn archive_file(month: &str) -> String {
+    format!("{}{}.db", ARCHIVE_PREFIX, month)
+}
+
+/// 保管用の接続を開く(書き込みスレッドとは別の接続で、ATTACHとトランザクションを行う)
+fn open_archive_connection() -> Result<Connection, String> {
+    let conn = Connection::open(get_db_path()).map_err(|e| format!("Failed to open database: {}", e))?;
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+    Ok(conn)
+}
+
+/// テーブルのカラム名と型(テーブルが無い場合は空)
+fn table_info(conn: &Connection, schema: &str, table_name: &str) -> rusqlite::Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA {}.table_info(\"{}\")", schema, table_name))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// カラムのうちテーブルにあるものの最大値を求める式(無ければ空文字)
+fn latest_of(columns: &[(String, String)], names: &[&str]) -> String {
+    let present: Vec<String> = names
+        .iter()
+        .filter(|name| columns.iter().any(|(column, _)| column == *name))
+        .map(|name| format!("COALESCE(\"{}\", '')", name))
+        .collect();
+    match present.len() {
+        0 => "''".to_string(),
+        1 => present[0].clone(),
+        _ => format!("MAX({})", present.join(", ")),
+    }
+}
+
+/// ロット名 -> (最終受信時刻, チップ数)
+type LotLastReceived = HashMap<String, (Option<DateTime<FixedOffset>>, i64)>;
+
+/// ロットごとの最終受信時刻(PLCごとのテーブルのユニットの受信時刻とアラーム履歴の受信時刻から求める)
+/// 受信時刻を記録する前のバージョンで登録した行は、PLCの日時(LDピックアップ・ULD挿入)で代える
+fn lot_last_received(conn: &Connection, table_name: &str) -> Result<LotLastReceived, String> {
+    let columns = table_info(conn, "main", table_name).map_err(|e| e.to_string())?;
+    let received = latest_of(
+        &columns,
+        &["LD_RECEIVED_AT", "DC1_RECEIVED_AT", "AC1_RECEIVED_AT", "AC2_RECEIVED_AT", "DC2_RECEIVED_AT", "IP_RECEIVED_AT", "ULD_RECEIVED_AT"],
+    );
+    let plc_date = latest_of(&columns, &["LD_PICKUP_DATE", "ULD_PUT_DATE"]);
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT LOT_NAME, COUNT(*), MAX({received}), MAX({plc_date}) FROM \"{table_name}\"
+            WHERE LOT_NAME IS NOT NULL GROUP BY LOT_NAME"
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+        .map_err(|e| e.to_string())?;
+
+    let mut lots = HashMap::new();
+    for (lot_name, chip_count, received, plc_date) in rows {
+        let last = clock::parse_local(&received).or_else(|| clock::parse_plc_date(&plc_date));
+        lots.insert(lot_name, (last, chip_count));
+    }
+
+    // アラームだけが記録されたロットもある
+    let alarms = alarm_table(table_name);
+    if !table_info(conn, "main", &alarms).map_err(|e| e.to_string())?.is_empty() {
+        let mut stmt = conn
+            .prepare(&format!("SELECT LOT_NAME, MAX(RECEIVED_AT) FROM \"{alarms}\" WHERE LOT_NAME IS NOT NULL GROUP BY LOT_NAME"))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(|e| e.to_string())?;
+        for (lot_name, received) in rows {
+            let received = clock::parse_local(&received);
+            let entry = lots.entry(lot_name).or_insert((None, 0));
+            entry.0 = entry.0.max(received);
+        }
+    }
+    Ok(lots)
+}
+
+/// 保持期間を過ぎたロット(実行中のロットと、受信時刻の分からないロットは除く)
+fn find_expired_lots(conn: &Connection, config: &RetentionConfig) -> Result<Vec<ArchiveLot>, String> {
+    let mut lots = Vec::new();
+    for table_name in existing_tables(conn)? {
+        lots.extend(expired_lots_in(conn, config, &table_name)?);
+    }
+    Ok(lots)
+}
+
+/// PLCのテーブル1つの保持期間を過ぎたロット(最終受信の古い順)
+fn expired_lots_in(conn: &Connection, config: &RetentionConfig, table_name: &str) -> Result<Vec<ArchiveLot>, String> {
+    let cutoff = cutoff(keep_days(config, table_name));
+    let running: HashSet<String> = conn
+        .prepare("SELECT LOT_NAME FROM lots WHERE TABLE_NAME = ?1 AND STATUS = ?2")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![table_name, STATUS_RUNNING], |row| row.get(0))?
+                .collect::<rusqlite::Result<HashSet<String>>>()
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut unknown = 0;
+    let mut expired: Vec<ArchiveLot> = Vec::new();
+    for (lot_name, (last, chip_count)) in lot_last_received(conn, table_name)? {
+        let Some(last) = last else {
+            unknown += 1;
+            continue;
+        };
+        if last >= cutoff || running.contains(&lot_name) {
+            continue;
+        }
+        let last_seen_at = clock::to_iso(&last);
+        expired.push(ArchiveLot {
+            archive_file: archive_file(&last_seen_at[..7]),
+            table_name: table_name.to_string(),
+            lot_name,
+            last_seen_at,
+            chip_count,
+        });
+    }
+    if unknown > 0 {
+        log::warn!("{} lots in {} have no receive time and are kept", unknown, table_name);
+    }
+    expired.sort_by(|a, b| a.last_seen_at.cmp(&b.last_seen_at));
+    Ok(expired)
+}
+
+/// ロットに紐付かず、受信時刻で保管するテーブルと時刻のカラム
+/// (規格違反はロットと一緒にも移すが、ロット名の無いものや実行中のロットの古いものはここで移す)
+const RECORD_TABLES: [(&str, &str); 3] = [
+    ("machine_events", "RECEIVED_AT"),
+    ("quarantine", "RECEIVED_AT"),
+    ("limit_violations", "DETECTED_AT"),
+];
+
+/// 保持期間を過ぎた設備イベント・隔離データ・規格違反の受信月ごとの行数
+fn find_expired_records(conn: &Connection, config: &RetentionConfig) -> Result<Vec<ArchiveRecords>, String> {
+    let mut records = Vec::new();
+    for (table_name, column) in RECORD_TABLES {
+        if table_info(conn, "main", table_name).map_err(|e| e.to_string())?.is_empty() {
+            continue;
+        }
+        let cutoff = clock::to_iso(&cutoff(keep_days(config, table_name)));
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT substr(\"{column}\", 1, 7), COUNT(*) FROM \"{table_name}\" WHERE \"{column}\" < ?1
+                GROUP BY substr(\"{column}\", 1, 7) ORDER BY 1"
+            ))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([&cutoff], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(|e| e.to_string())?;
+        for (month, row_count) in rows {
+            records.push(ArchiveRecords {
+                table_name: table_name.to_string(),
+                archive_file: archive_file(&month),
+                month,
+                row_count,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// ロットと一緒に移すテーブルと条件(?1: ロット名, ?2: PLCのテーブル名)
+fn lot_tables(table_name: &str) -> [(String, &'static str); 5] {
+    [
+        (table_name.to_string(), "LOT_NAME = ?1"),
+        (alarm_table(table_name), "LOT_NAME = ?1"),
+        (history_table(table_name), "LOT_NAME = ?1"),
+        ("lots".to_string(), "TABLE_NAME = ?2 AND LOT_NAME = ?1"),
+        ("limit_violations".to_string(), "TABLE_NAME = ?2 AND LOT_NAME = ?1"),
+    ]
+}
+
+/// 条件に合う行を保管用DB(archive)に移す
+/// 保管用DBのテーブルは元のテーブルのカラムで(制約無しで)作成し、後から追加されたカラムは足す
+/// 保管用DBの行は消さない(元のDBで全件移した後に同じIDやロット名が使われても上書きしない)
+fn move_rows(conn: &Connection, table: &str, condition: &str, params: &[&dyn ToSql]) -> rusqlite::Result<i64> {
+    let columns = table_info(conn, "main", table)?;
+    // 記録を始める前に作成したテーブル(アラーム履歴・更新履歴)は無い
+    if columns.is_empty() {
+        return Ok(0);
+    }
+    let archived = table_info(conn, "archive", table)?;
+    if archived.is_empty() {
+        conn.execute_batch(&format!("CREATE TABLE archive.\"{table}\" AS SELECT * FROM main.\"{table}\" WHERE 0"))?;
+    } else {
+        for (name, sql_type) in &columns {
+            if !archived.iter().any(|(archived_name, _)| archived_name == name) {
+                conn.execute_batch(&format!("ALTER TABLE archive.\"{table}\" ADD COLUMN \"{name}\" {sql_type}"))?;
+            }
+        }
+    }
+
+    let names: Vec<String> = columns.iter().map(|(name, _)| format!("\"{}\"", name)).collect();
+    let names = names.join(", ");
+    let moved = conn.execute(
+        &format!("INSERT INTO archive.\"{table}\" ({names}) SELECT {names} FROM main.\"{table}\" WHERE {condition}"),
+        params,
+    )?;
+    conn.execute(&format!("DELETE FROM main.\"{table}\" WHERE {condition}"), params)?;
+    Ok(moved as i64)
+}
+
+/// ロットと記録を保管用DBファイルに移す(移した行数を返す)
+/// 保管用DBへの追加を先に行うので、途中で止まっても元のDBから消えるだけの状態にはならない
+fn archive_to(conn: &mut Connection, path: &Path, lots: &[&ArchiveLot], records: &[&ArchiveRecords], config: &RetentionConfig) -> rusqlite::Result<i64> {
+    conn.execute("ATTACH DATABASE ?1 AS archive", [path.to_string_lossy()])?;
+    let result = move_to_archive(conn, lots, records, config);
+    let detached = conn.execute_batch("DETACH DATABASE archive");
+    let moved = result?;
+    detached?;
+    Ok(moved)
+}
+
+/// ATTACH済みの保管用DBにロットと記録を1トランザクションで移す
+fn move_to_archive(conn: &mut Connection, lots: &[&ArchiveLot], records: &[&ArchiveRecords], config: &RetentionConfig) -> rusqlite::Result<i64> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut moved = 0;
+    for lot in lots {
+        for (table, condition) in lot_tables(&lot.table_name) {
+            let params: Vec<&dyn ToSql> = if condition.contains("?2") { vec![&lot.lot_name, &lot.table_name] } else { vec![&lot.lot_name] };
+            moved += move_rows(&tx, &table, condition, &params)?;
+        }
+    }
+    for record in records {
+        let Some((_, column)) = RECORD_TABLES.iter().find(|(table, _)| *table == record.table_name) else {
+            continue;
+        };
+        let cutoff = clock::to_iso(&cutoff(keep_days(config, &record.table_name)));
+        let condition = format!("\"{column}\" < ?1 AND substr(\"{column}\", 1, 7) = ?2");
+        moved += move_rows(&tx, &record.table_name, &condition, &[&cutoff, &record.month])?;
+    }
+    tx.commit()?;
+    Ok(moved)
+}
+
+/// 保管後の縮小に使えるよう auto_vacuum を INCREMENTAL にする(起動時、書き込みスレッドを開始する前に呼ぶ)
+/// 既存のDBは設定を反映するためにVACUUMが必要で、DB全体を排他ロックするので書き込みの始まる前に一度だけ行う
+pub fn prepare_database(config: &RetentionConfig) -> Result<(), String> {
+    if !config.vacuum {
+        return Ok(());
+    }
+    let conn = open_archive_connection()?;
+    let mode: i64 = conn
+        .query_row("PRAGMA auto_vacuum", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if mode == 2 {
+        return Ok(());
+    }
+    log::info!("Switching database to incremental auto_vacuum (one-time VACUUM)");
+    conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM")
+        .map_err(|e| format!("Failed to enable incremental vacuum: {}", e))
+}
+
+/// 削除した分の領域を解放してDBファイルを縮小する
+/// VACUUMはDB全体を排他ロックして書き込みスレッドを待たせるので、incremental_vacuum で少しずつ解放する
+/// WALはDB本体に書き戻すだけにして(書き込みを止めない)、切り詰めは終了時に行う
+fn vacuum_database(conn: &Connection) -> Result<bool, String> {
+    let mode: i64 = conn
+        .query_row("PRAGMA auto_vacuum", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if mode != 2 {
+        log::warn!("Database is not in incremental auto_vacuum mode, restart with retention.vacuum enabled to shrink it");
+        return Ok(false);
+    }
+    loop {
+        let free_pages: i64 = conn
+            .query_row("PRAGMA freelist_count", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if free_pages == 0 {
+            break;
+        }
+        // 1ページ解放するごとに1行返るので最後まで読む
+        conn.prepare(&format!("PRAGMA incremental_vacuum({})", VACUUM_STEP_PAGES))
+            .and_then(|mut stmt| {
+                let mut rows = stmt.query([])?;
+                while rows.next()?.is_some() {}
+                Ok(())
+            })
+            .map_err(|e| format!("Failed to vacuum database: {}", e))?;
+        std::thread::sleep(VACUUM_STEP_PAUSE);
+    }
+    conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+        .map_err(|e| format!("Failed to checkpoint WAL: {}", e))?;
+    Ok(true)
+}
+
+/// 保持期間を過ぎたロットと記録を保管用DBに移す(dry_run の場合は対象の一覧だけを返す)
+pub fn run_retention(config: &RetentionConfig, dry_run: bool, vacuum: bool) -> Result<RetentionReport, String> {
+    let _running = RUNNING.try_lock().ok_or("Archival is already running")?;
+    let started_at = clock::now_string();
+    let mut conn = open_archive_connection()?;
+    let lots = find_expired_lots(&conn, config)?;
+    let records = find_expired_records(&conn, config)?;
+
+    let mut moved_rows = 0;
+    let mut vacuumed = false;
+    if !dry_run && (!lots.is_empty() || !records.is_empty()) {
+        let archive_dir = Path::new(&config.archive_dir);
+        fs::create_dir_all(archive_dir).map_err(|e| format!("Failed to create {}: {}", config.archive_dir, e))?;
+
+        let mut files: Vec<&str> = lots
+            .iter()
+            .map(|lot| lot.archive_file.as_str())
+            .chain(records.iter().map(|record| record.archive_file.as_str()))
+            .collect();
+        files.sort();
+        files.dedup();
+        for file in files {
+            let lot_group: Vec<&ArchiveLot> = lots.iter().filter(|lot| lot.archive_file == file).collect();
+            let record_group: Vec<&ArchiveRecords> = records.iter().filter(|record| record.archive_file == file).collect();
+            moved_rows += archive_to(&mut conn, &archive_dir.join(file), &lot_group, &record_group, config)
+                .map_err(|e| format!("Failed to archive to {}: {}", file, e))?;
+            log::info!("Archived {} lots and {} record groups to {}", lot_group.len(), record_group.len(), file);
+        }
+        if vacuum && moved_rows > 0 {
+            vacuumed = vacuum_database(&conn)?;
+        }
+    }
+
+    Ok(RetentionReport {
+        dry_run,
+        lots,
+        records,
+        moved_rows,
+        vacuumed,
+        started_at,
+        finished_at: clock::now_string(),
+    })
+}
+
+/// 保持期間を過ぎたロットと記録を定期的に保管する
+pub async fn run(config: RetentionConfig) {
+    let period = Duration::from_secs(config.interval_hours.max(1) * 3600);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + STARTUP_DELAY, period);
+    loop {
+        interval.tick().await;
+        let task_config = config.clone();
+        let result = tauri::async_runtime::spawn_blocking(move || {
+            run_retention(&task_config, false, task_config.vacuum)
+        })
+        .await;
+        match result {
+            Ok(Ok(report)) => log::info!(
+                "Scheduled archival finished: {} lots, {} rows moved (vacuumed={})",
+                report.lots.len(),
+                report.moved_rows,
+                report.vacuumed
+            ),
+            Ok(Err(e)) => log::error!("Scheduled archival failed: {}", e),
+            Err(e) => log::error!("Scheduled archival task failed: {}", e),
+        }
+    }
+}
+
+/// 読み出し用の接続に保管用DBをATTACHし、保管分も合わせたビューを元のテーブル名で作る
+/// (TEMPのビューは同じ名前のテーブルより優先されるので、既存の読み出し処理はそのまま保管分も読める)
+pub fn attach_to(conn: &Connection) -> rusqlite::Result<()> {
+    let archives: Vec<PathBuf> = ATTACHED.read().iter().filter(|path| path.exists()).cloned().collect();
+    if archives.is_empty() {
+        return Ok(());
+    }
+
+    let mut schemas = Vec::new();
+    let mut tables: Vec<String> = Vec::new();
+    for (i, path) in archives.iter().enumerate() {
+        let schema = format!("archive_{}", i);
+        conn.execute(&format!("ATTACH DATABASE ?1 AS {}", schema), [path.to_string_lossy()])?;
+        let mut stmt = conn.prepare(&format!("SELECT name FROM {}.sqlite_master WHERE type = 'table'", schema))?;
+        for name in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let name = name?;
+            if is_valid_identifier(&name) && !tables.contains(&name) {
+                tables.push(name);
+            }
+        }
+        schemas.push(schema);
+    }
+
+    for table in tables {
+        let columns = table_info(conn, "main", &table)?;
+        if columns.is_empty() {
+            continue;
+        }
+        let names: Vec<String> = columns.iter().map(|(name, _)| format!("\"{}\"", name)).collect();
+        let mut selects = vec![format!("SELECT {} FROM main.\"{}\"", names.join(", "), table)];
+        for schema in &schemas {
+            let archived = table_info(conn, schema, &table)?;
+            if archived.is_empty() {
+                continue;
+            }
+            // 保管した時点に無かったカラムはNULLにする
+            let names: Vec<String> = columns
+                .iter()
+                .map(|(name, _)| {
+                    if archived.iter().any(|(archived_name, _)| archived_name == name) {
+                        format!("\"{}\"", name)
+                    } else {
+                        format!("NULL AS \"{}\"", name)
+                    }
+                })
+                .collect();
+            selects.push(format!("SELECT {} FROM {}.\"{}\"", names.join(", "), schema, table));
+        }
+        conn.execute_batch(&format!("CREATE TEMP VIEW \"{}\" AS {}", table, selects.join(" UNION ALL ")))?;
+    }
+    Ok(())
+}
+
+/// 保管用DBファイルの一覧
+fn archive_files(config: &RetentionConfig) -> Result<Vec<ArchiveFile>, String> {
+    let dir = Path::new(&config.archive_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let attached = ATTACHED.read();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", config.archive_dir, e))? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(ARCHIVE_PREFIX) || !name.ends_with(".db") {
+            continue;
+        }
+        files.push(ArchiveFile {
+            size_bytes: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            attached: attached.iter().any(|path| path == &entry.path()),
+            name,
+        });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// 保持期間を過ぎたロットと記録の一覧をフロントエンドに返す(何も変更しない)
+#[command]
+pub async fn preview_archival() -> Result<RetentionReport, String> {
+    let config = load_config()?.retention;
+    tauri::async_runtime::spawn_blocking(move || run_retention(&config, true, false))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// 保持期間を過ぎたロットと記録を保管用DBに移す(vacuum を省略した場合は設定に従う)
+#[command]
+pub async fn run_archival(vacuum: Option<bool>) -> Result<RetentionReport, String> {
+    let config = load_config()?.retention;
+    let vacuum = vacuum.unwrap_or(config.vacuum);
+    tauri::async_runtime::spawn_blocking(move || run_retention(&config, false, vacuum))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// 保管用DBファイルの一覧をフロントエンドに返す
+#[command]
+pub fn list_archives() -> Result<Vec<ArchiveFile>, String> {
+    archive_files(&load_config()?.retention)
+}
+
+/// 読み出しに含める保管用DBファイルを設定する(空にすると全て外す)
+/// 以降に開く読み出し用の接続(画面・HTTP APIの検索や集計)で保管分も合わせて読める
+#[command]
+pub fn attach_archives(names: Vec<String>) -> Result<Vec<ArchiveFile>, String> {
+    let config = load_config()?.retention;
+    if names.len() > MAX_ATTACHED {
+        return Err(format!("At most {} archives can be attached", MAX_ATTACHED));
+    }
+    let available = archive_files(&config)?;
+    let mut paths = Vec::new();
+    for name in &names {
+        if !available.iter().any(|file| &file.name == name) {
+            return Err(format!("Unknown archive: {}", name));
+        }
+        paths.push(Path::new(&config.archive_dir).join(name));
+    }
+    log::info!("Archives attached for queries: {:?}", names);
+    *ATTACHED.write() = paths;
+    archive_files(&config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 保持期間(180日)を過ぎた受信時刻
+    const OLD: &str = "2020-03-15T12:00:00.000+09:00";
+
+    fn archive_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("retention_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(archive_file("2020-03"))
+    }
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("sql/create_lots.sql")).unwrap();
+        conn.execute_batch(include_str!("sql/create_limit_violations.sql")).unwrap();
+        conn.execute_batch(include_str!("sql/create_machine_events.sql")).unwrap();
+        conn.execute_batch(include_str!("sql/create_quarantine.sql")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE plc1 (\"ID\" INTEGER PRIMARY KEY, \"LOT_NAME\" VARCHAR, \"SERIAL\" INTEGER, \"LD_RECEIVED_AT\" VARCHAR);
+            CREATE TABLE plc1_alarms (\"ID\" INTEGER PRIMARY KEY, \"RECEIVED_AT\" VARCHAR NOT NULL, \"LOT_NAME\" VARCHAR, \"ALARM_NUM\" INTEGER);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn insert_lot(conn: &Connection, lot_name: &str, received_at: &str, status: &str) {
+        conn.execute(
+            "INSERT INTO plc1 (LOT_NAME, SERIAL, LD_RECEIVED_AT) VALUES (?1, 1, ?2), (?1, 2, ?2)",
+            params![lot_name, received_at],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO plc1_alarms (RECEIVED_AT, LOT_NAME, ALARM_NUM) VALUES (?2, ?1, 5)",
+            params![lot_name, received_at],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO lots (PLC_ID, TABLE_NAME, LOT_NAME, STATUS, STARTED_AT, LAST_SEEN_AT, FRAME_COUNT) VALUES (1, 'plc1', ?1, ?3, ?2, ?2, 2)",
+            params![lot_name, received_at, status],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO limit_violations (DETECTED_AT, RECEIVED_AT, PLC_ID, TABLE_NAME, LOT_NAME, SERIAL, COLUMN_NAME, RULE)
+            VALUES (?2, ?2, 1, 'plc1', ?1, 1, 'DC1_BIN', 'max')",
+            params![lot_name, received_at],
+        )
+        .unwrap();
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn expired_lot_is_archived_and_deleted() {
+        let mut conn = database();
+        let now = clock::to_iso(&clock::now());
+        insert_lot(&conn, "L_OLD", OLD, "completed");
+        insert_lot(&conn, "L_NEW", &now, "completed");
+        // 実行中のロットは古くても移さない
+        insert_lot(&conn, "L_RUN", OLD, STATUS_RUNNING);
+
+        let config = RetentionConfig::default();
+        let lots = expired_lots_in(&conn, &config, "plc1").unwrap();
+        assert_eq!(lots.len(), 1);
+        assert_eq!(lots[0].lot_name, "L_OLD");
+        assert_eq!(lots[0].chip_count, 2);
+        assert_eq!(lots[0].archive_file, archive_file("2020-03"));
+
+        let path = archive_path("lot");
+        let moved = archive_to(&mut conn, &path, &[&lots[0]], &[], &config).unwrap();
+        // チップ2行・アラーム・ロット・規格違反
+        assert_eq!(moved, 5);
+        for table in ["plc1", "plc1_alarms", "lots", "limit_violations"] {
+            assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM {table} WHERE LOT_NAME = 'L_OLD'")), 0, "{}", table);
+            assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM {table} WHERE LOT_NAME <> 'L_OLD'")), if table == "plc1" { 4 } else { 2 }, "{}", table);
+        }
+
+        let archive = Connection::open(&path).unwrap();
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM plc1 WHERE LOT_NAME = 'L_OLD'"), 2);
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM plc1_alarms"), 1);
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM lots"), 1);
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM limit_violations"), 1);
+        // 更新履歴のテーブルが無いPLCでも移せる
+        assert!(table_info(&archive, "main", "plc1_history").unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_event_records_are_pruned() {
+        let mut conn = database();
+        let now = clock::to_iso(&clock::now());
+        for (received_at, epoch_ms) in [(OLD, 1), ("2020-03-20T08:00:00.000+09:00", 2), (now.as_str(), 3)] {
+            conn.execute(
+                "INSERT INTO machine_events (RECEIVED_AT, TABLE_NAME, EVENT_TYPE, RECEIVED_EPOCH_MS) VALUES (?1, 'plc1', 'connected', ?2)",
+                params![received_at, epoch_ms],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO quarantine (PLC_ID, TABLE_NAME, RECEIVED_AT, QUARANTINED_AT, ERRORS, MESSAGE) VALUES (1, 'plc1', ?1, ?1, '[]', '{}')",
+            params![OLD],
+        )
+        .unwrap();
+
+        let config = RetentionConfig::default();
+        let records = find_expired_records(&conn, &config).unwrap();
+        let found: Vec<(&str, &str, i64)> = records
+            .iter()
+            .map(|record| (record.table_name.as_str(), record.month.as_str(), record.row_count))
+            .collect();
+        assert_eq!(found, vec![("machine_events", "2020-03", 2), ("quarantine", "2020-03", 1)]);
+
+        let path = archive_path("records");
+        let group: Vec<&ArchiveRecords> = records.iter().collect();
+        assert_eq!(archive_to(&mut conn, &path, &[], &group, &config).unwrap(), 3);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM machine_events"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM quarantine"), 0);
+
+        let archive = Connection::open(&path).unwrap();
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM machine_events"), 2);
+        assert_eq!(count(&archive, "SELECT COUNT(*) FROM quarantine"), 1);
+    }
+}
@@ -0,0 +1,296 @@
+///アプリケーション終了処理の管理
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::watch;
+
+use crate::data_handler::{checkpoint_database, close_database, wait_writer_stopped};
+use crate::state::{ConnectionState, DbChannelState};
+
+//受信タスクの停止を待つ最大時間
+const RECEIVER_STOP_TIMEOUT: Duration = Duration::from_secs(3);
+//タイムアウト後、書き込み中のバッチが終わるのを待つ最大時間
+const WRITER_FINISH_TIMEOUT: Duration = Duration::from_secs(2);
+//タイムアウト後のWALチェックポイントを待つ最大時間
+const CHECKPOINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 終了処理の状態を管理する型
+pub type ShutdownState = Arc<ShutdownCoordinator>;
+
+/// 終了処理の調整役
+/// トレイの「終了」・ウィンドウマネージャからの終了要求・OSシグナルのどれから呼ばれても
+/// 受信停止 → ソケットクローズ → DBキューの書き出し → WALチェックポイント → 終了 の順に処理する
+pub struct ShutdownCoordinator {
+    started: AtomicBool,
+    finished: AtomicBool,
+    /// ウィンドウの表示・進捗の通知をしない(Windowsのセッション終了中はUIスレッドが終了処理の完了を待っているため)
+    quiet: AtomicBool,
+    signal: watch::Sender<bool>,
+    active_receivers: AtomicUsize,
+    drain_timeout: Duration,
+}
+
+/// 受信タスクが動いている間保持するガード
+/// drop時に受信タスク数を減らす
+pub struct ReceiverGuard {
+    coordinator: ShutdownState,
+}
+
+impl Drop for ReceiverGuard {
+    fn drop(&mut self) {
+        self.coordinator.active_receivers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new(drain_timeout_ms: u64) -> Self {
+        let (signal, _) = watch::channel(false);
+        ShutdownCoordinator {
+            started: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            quiet: AtomicBool::new(false),
+            signal,
+            active_receivers: AtomicUsize::new(0),
+            drain_timeout: Duration::from_millis(drain_timeout_ms),
+        }
+    }
+
+    /// 終了通知を受け取るレシーバーを返す
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.signal.subscribe()
+    }
+
+    /// 終了処理が開始されているか
+    pub fn is_shutting_down(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    /// 終了処理が完了しているか(プロセスを終了してよいか)
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// 以降の終了処理ではUIスレッドを使う処理(ウィンドウの表示・進捗の通知)をしない
+    /// UIスレッドで終了処理の完了を待つ前に呼ぶ
+    pub fn suppress_ui(&self) {
+        self.quiet.store(true, Ordering::SeqCst);
+    }
+
+    /// 終了処理が完了するまで待つ(最大で各段階の待ち時間の合計)
+    /// 完了した場合は true を返す
+    pub fn wait_finished(&self) -> bool {
+        let timeout = RECEIVER_STOP_TIMEOUT + self.drain_timeout + WRITER_FINISH_TIMEOUT + CHECKPOINT_TIMEOUT;
+        let start = Instant::now();
+        while !self.is_finished() {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        true
+    }
+
+    /// 受信タスクの開始を登録する
+    pub fn register_receiver(self: &Arc<Self>) -> ReceiverGuard {
+        self.active_receivers.fetch_add(1, Ordering::SeqCst);
+        ReceiverGuard {
+            coordinator: Arc::clone(self),
+        }
+    }
+}
+
+/// 終了処理を開始する(2回目以降の呼び出しは無視する)
+pub fn request_shutdown(app: &AppHandle, reason: &str) {
+    let coordinator = app.state::<ShutdownState>().inner().clone();
+    if coordinator.started.swap(true, Ordering::SeqCst) {
+        log::info!("Shutdown already in progress (requested by {})", reason);
+        return;
+    }
+
+    log::info!("Shutdown requested by {}", reason);
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        run_shutdown(&app, &coordinator).await;
+        coordinator.finished.store(true, Ordering::SeqCst);
+        app.exit(0);
+    });
+}
+
+/// 終了処理の進捗をフロントエンドに通知する
+fn emit_progress(app: &AppHandle, step: &str, message: &str, remaining: Option<usize>) {
+    log::info!("Shutdown: {}", message);
+    if app.state::<ShutdownState>().quiet.load(Ordering::SeqCst) {
+        return;
+    }
+    let payload = serde_json::json!({
+        "step": step,
+        "message": message,
+        "remaining": remaining,
+    });
+    if let Err(e) = app.emit("shutdown-progress", payload) {
+        eprintln!("Failed to emit shutdown progress: {}", e);
+    }
+}
+
+/// 終了処理本体
+async fn run_shutdown(app: &AppHandle, coordinator: &ShutdownCoordinator) {
+    // 進捗が見えるようにウィンドウを表示する
+    if !coordinator.quiet.load(Ordering::SeqCst) {
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+
+    // 1. PLCデータの受付を停止し、受信タスクにソケットを閉じさせる
+    emit_progress(app, "stopping", "PLCからの受信を停止しています", None);
+    coordinator.signal.send_replace(true);
+    {
+        let state = app.state::<ConnectionState>();
+        let mut connections = state.lock();
+        for conn in connections.values_mut() {
+            conn.is_connected = false;
+        }
+    }
+
+    let start = Instant::now();
+    while coordinator.active_receivers.load(Ordering::SeqCst) > 0 {
+        if start.elapsed() >= RECEIVER_STOP_TIMEOUT {
+            log::warn!(
+                "{} receive tasks did not stop in time",
+                coordinator.active_receivers.load(Ordering::SeqCst)
+            );
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // 2. DBキューを閉じ、書き込みスレッドに残りを書き出させる
+    let queue = app.state::<DbChannelState>().inner().clone();
+    queue.close();
+
+    let start = Instant::now();
+    let mut writer_stopped = false;
+    while start.elapsed() < coordinator.drain_timeout {
+        let remaining = queue.status().depth;
+        emit_progress(
+            app,
+            "draining",
+            &format!("DBへの書き込みを待っています(残り{}件)", remaining),
+            Some(remaining),
+        );
+        writer_stopped = tauri::async_runtime::spawn_blocking(|| wait_writer_stopped(Duration::from_millis(200)))
+            .await
+            .unwrap_or(false);
+        if writer_stopped {
+            break;
+        }
+    }
+
+    // 3. 書き込みが終わっていればWALをチェックポイントしてDBを閉じる
+    if writer_stopped {
+        emit_progress(app, "closing", "データベースを閉じています", Some(0));
+        tauri::async_runtime::spawn_blocking(close_database).await.ok();
+    } else {
+        // 書ききれなかった分は退避ファイルに残し、次回起動時に書き戻す
+        let spilled = queue.spill_remaining();
+        log::warn!("DB writer did not finish in time, {} requests spilled for next start", spilled);
+        emit_progress(
+            app,
+            "timeout",
+            &format!("書き込みが時間内に終わらなかったため{}件を退避しました", spilled),
+            Some(spilled),
+        );
+
+        // キューが空になったので書き込み中のバッチが終われば書き込みスレッドは止まる
+        // 止まらない場合も書き込み済みの分はWALからDBファイルに反映しておく
+        emit_progress(app, "closing", "データベースを閉じています", Some(0));
+        let writer_stopped = tauri::async_runtime::spawn_blocking(|| wait_writer_stopped(WRITER_FINISH_TIMEOUT))
+            .await
+            .unwrap_or(false);
+        let checkpoint = tauri::async_runtime::spawn_blocking(move || {
+            if writer_stopped {
+                close_database();
+            } else {
+                checkpoint_database();
+            }
+        });
+        if tokio::time::timeout(CHECKPOINT_TIMEOUT, checkpoint).await.is_err() {
+            log::error!("WAL checkpoint did not finish in time");
+        }
+    }
+
+    emit_progress(app, "done", "終了します", None);
+    log::info!("アプリを終了しました");
+}
+
+/// Windowsのログオフ・シャットダウン(WM_QUERYENDSESSION / WM_ENDSESSION)で終了処理を行う
+/// GUIサブシステムで動かしているのでコンソールの終了シグナルは届かず、ウィンドウへのメッセージで知る
+#[cfg(windows)]
+pub fn watch_session_end(app: &AppHandle) {
+    use windows_sys::Win32::UI::Shell::SetWindowSubclass;
+
+    let Some(window) = app.get_webview_window("main") else {
+        log::error!("Main window not found, session end will not be handled");
+        return;
+    };
+    let hwnd = match window.hwnd() {
+        Ok(hwnd) => hwnd.0 as windows_sys::Win32::Foundation::HWND,
+        Err(e) => {
+            log::error!("Failed to get main window handle: {}", e);
+            return;
+        }
+    };
+    // サブクラスプロシージャに渡すハンドル(アプリ終了まで使うので解放しない)
+    let app = Box::into_raw(Box::new(app.clone())) as usize;
+    if unsafe { SetWindowSubclass(hwnd, Some(session_end_proc), 1, app) } == 0 {
+        log::error!("Failed to watch for Windows session end");
+    }
+}
+
+#[cfg(not(windows))]
+pub fn watch_session_end(_app: &AppHandle) {}
+
+#[cfg(windows)]
+unsafe extern "system" fn session_end_proc(
+    hwnd: windows_sys::Win32::Foundation::HWND,
+    msg: u32,
+    wparam: windows_sys::Win32::Foundation::WPARAM,
+    lparam: windows_sys::Win32::Foundation::LPARAM,
+    _id: usize,
+    app: usize,
+) -> windows_sys::Win32::Foundation::LRESULT {
+    use windows_sys::Win32::UI::Shell::DefSubclassProc;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        ShutdownBlockReasonCreate, ShutdownBlockReasonDestroy, WM_ENDSESSION, WM_QUERYENDSESSION,
+    };
+
+    let app = &*(app as *const AppHandle);
+    match msg {
+        WM_QUERYENDSESSION => {
+            // 終了処理の間はシャットダウン画面に理由を表示してもらう
+            // WM_ENDSESSION ではこのスレッドで完了を待つので、終了処理からはUIスレッドを使わない
+            let reason: Vec<u16> = "PLCデータを保存しています".encode_utf16().chain(std::iter::once(0)).collect();
+            ShutdownBlockReasonCreate(hwnd, reason.as_ptr());
+            app.state::<ShutdownState>().suppress_ui();
+            request_shutdown(app, "Windows session end");
+            1
+        }
+        WM_ENDSESSION => {
+            // このメッセージから戻るとプロセスが終了させられるので、終了処理が終わるまで待つ
+            // (トレイなどから始めた終了処理の途中でも、待つ間はUIスレッドを使わせない)
+            if wparam != 0 {
+                let coordinator = app.state::<ShutdownState>();
+                coordinator.suppress_ui();
+                request_shutdown(app, "Windows session end");
+                if !coordinator.wait_finished() {
+                    log::error!("Shutdown did not finish before Windows session end");
+                }
+            }
+            ShutdownBlockReasonDestroy(hwnd);
+            0
+        }
+        _ => DefSubclassProc(hwnd, msg, wparam, lparam),
+    }
+}
@@ -0,0 +1,517 @@
+///登録済みの測定値(アライメント補正量・プローブ座標・ステージ高さ)の統計的工程管理(SPC)
+///PLC(設備)・ユニット(カラム)・ロットごとに X-bar/R 管理図と I-MR 管理図のデータを作成する
+///管理限界はベースライン区間(先頭の群・点)から推定し、全区間を Western Electric ルールで判定する
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::data_handler::{open_read_connection, table_columns};
+use crate::db_query::table_for_plc;
+
+//X-bar/R管理図の係数 (n, A2, D3, D4)
+const XBAR_R_CONSTANTS: [(usize, f64, f64, f64); 9] = [
+    (2, 1.880, 0.0, 3.267),
+    (3, 1.023, 0.0, 2.574),
+    (4, 0.729, 0.0, 2.282),
+    (5, 0.577, 0.0, 2.114),
+    (6, 0.483, 0.0, 2.004),
+    (7, 0.419, 0.076, 1.924),
+    (8, 0.373, 0.136, 1.864),
+    (9, 0.337, 0.184, 1.816),
+    (10, 0.308, 0.223, 1.777),
+];
+
+//I-MR管理図の係数(移動範囲の2点に対する E2 と D4)
+const IMR_E2: f64 = 2.660;
+const IMR_D4: f64 = 3.267;
+
+//省略時の設定
+const DEFAULT_SUBGROUP_SIZE: usize = 5;
+const DEFAULT_BASELINE_SUBGROUPS: usize = 25;
+const DEFAULT_BASELINE_POINTS: usize = 100;
+const DEFAULT_MAX_SAMPLES: usize = 2000;
+
+/// SPCの対象にするカラム名の一部
+const SPC_COLUMN_PATTERNS: [&str; 6] = ["_CHIP_ALIGN_", "_PRE_ALIGN_", "_PROBE_X", "_PROBE_Y", "_STAGE_Z", "_PIN_Z"];
+
+/// 管理図の種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChartKind {
+    /// X-bar/R 管理図(subgroup_size 個ずつの群)
+    XbarR,
+    /// 個別値・移動範囲管理図
+    Imr,
+}
+
+/// 管理線
+#[derive(Serialize, Debug, Clone)]
+pub struct ControlLimits {
+    pub center: f64,
+    pub ucl: f64,
+    pub lcl: f64,
+}
+
+/// 管理図の1点
+#[derive(Serialize, Debug, Clone)]
+pub struct SpcPoint {
+    pub index: usize,
+    /// 横軸の表示名(群の場合は先頭と末尾のシリアル)
+    pub label: String,
+    pub lot_name: String,
+    pub value: f64,
+    /// ベースライン区間の点か
+    pub baseline: bool,
+    /// 違反した Western Electric ルールの番号(1~4)
+    pub rules: Vec<u8>,
+}
+
+/// 管理図の系列(xbar, r, i, mr)
+#[derive(Serialize, Debug, Clone)]
+pub struct SpcSeries {
+    pub name: String,
+    pub limits: ControlLimits,
+    pub points: Vec<SpcPoint>,
+}
+
+/// ルール違反
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleViolation {
+    pub series: String,
+    pub index: usize,
+    pub rule: u8,
+    pub description: String,
+}
+
+/// 管理図のデータ
+#[derive(Serialize, Debug, Clone)]
+pub struct SpcChart {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub machine_name: Option<String>,
+    /// カラム名の接頭辞(DC1, AC1, ULD など)
+    pub unit: String,
+    pub column: String,
+    pub lot_name: Option<String>,
+    pub chart: ChartKind,
+    pub subgroup_size: usize,
+    /// 管理限界の推定に使った群数(I-MRは点数)
+    pub baseline_size: usize,
+    pub sample_count: usize,
+    pub series: Vec<SpcSeries>,
+    pub violations: Vec<RuleViolation>,
+}
+
+/// 管理図を作成するための条件
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpcQuery {
+    pub plc_id: u32,
+    pub column: String,
+    pub chart: ChartKind,
+    /// 指定した場合はそのロットの測定値のみ
+    #[serde(default)]
+    pub lot_name: Option<String>,
+    #[serde(default)]
+    pub subgroup_size: Option<usize>,
+    /// 管理限界の推定に使う先頭の群数(I-MRは点数)
+    #[serde(default)]
+    pub baseline: Option<usize>,
+    /// 直近から読み出す最大の測定値数
+    #[serde(default)]
+    pub max_samples: Option<usize>,
+}
+
+/// 測定値1件
+struct Sample {
+    lot_name: String,
+    serial: i64,
+    value: f64,
+}
+
+/// SPCの対象にできるカラムの一覧
+pub fn spc_columns() -> Vec<String> {
+    table_columns()
+        .into_iter()
+        .filter(|(name, sql_type)| {
+            // ULD_CHIP_ALIGN_NUM は補正量ではなく回数
+            sql_type.eq_ignore_ascii_case("INTEGER")
+                && !name.ends_with("_NUM")
+                && SPC_COLUMN_PATTERNS.iter().any(|p| name.contains(p))
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// 測定値を登録順(ID順)に読み出す
+fn load_samples(conn: &Connection, table_name: &str, column: &str, lot_name: Option<&str>, max_samples: usize) -> rusqlite::Result<Vec<Sample>> {
+    let sql = format!(
+        "SELECT LOT_NAME, SERIAL, \"{column}\" FROM (
+            SELECT ID, LOT_NAME, SERIAL, \"{column}\" FROM {table_name}
+            WHERE \"{column}\" IS NOT NULL AND (?1 IS NULL OR LOT_NAME = ?1)
+            ORDER BY ID DESC LIMIT ?2
+        ) ORDER BY ID"
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![lot_name, max_samples as i64], |row| {
+        Ok(Sample {
+            lot_name: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            serial: row.get::<_, Option<i64>>(1)?.unwrap_or_default(),
+            value: row.get::<_, f64>(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() { 0.0 } else { values.iter().sum::<f64>() / values.len() as f64 }
+}
+
+/// X-bar/R 管理図の系列を作成する
+fn xbar_r_series(samples: &[Sample], n: usize, baseline: usize) -> Result<(Vec<SpcSeries>, usize), String> {
+    let (_, a2, d3, d4) = XBAR_R_CONSTANTS
+        .iter()
+        .find(|(size, ..)| *size == n)
+        .copied()
+        .ok_or_else(|| format!("subgroup_size must be between 2 and 10: {}", n))?;
+
+    // 端数の測定値は群にしない
+    let groups: Vec<&[Sample]> = samples.chunks_exact(n).collect();
+    if groups.len() < 2 {
+        return Err(format!("Not enough samples for subgroups of {} ({} samples)", n, samples.len()));
+    }
+    let baseline = baseline.clamp(2, groups.len());
+
+    let means: Vec<f64> = groups.iter().map(|g| mean(&g.iter().map(|s| s.value).collect::<Vec<_>>())).collect();
+    let ranges: Vec<f64> = groups
+        .iter()
+        .map(|g| {
+            let max = g.iter().map(|s| s.value).fold(f64::MIN, f64::max);
+            let min = g.iter().map(|s| s.value).fold(f64::MAX, f64::min);
+            max - min
+        })
+        .collect();
+
+    let x_bar_bar = mean(&means[..baseline]);
+    let r_bar = mean(&ranges[..baseline]);
+    let label = |g: &[Sample]| format!("{}-{}", g[0].serial, g[g.len() - 1].serial);
+    let points = |values: &[f64]| -> Vec<SpcPoint> {
+        values
+            .iter()
+            .zip(&groups)
+            .enumerate()
+            .map(|(index, (value, g))| SpcPoint {
+                index,
+                label: label(g),
+                lot_name: g[0].lot_name.clone(),
+                value: *value,
+                baseline: index < baseline,
+                rules: Vec::new(),
+            })
+            .collect()
+    };
+
+    let series = vec![
+        SpcSeries {
+            name: "xbar".to_string(),
+            limits: ControlLimits { center: x_bar_bar, ucl: x_bar_bar + a2 * r_bar, lcl: x_bar_bar - a2 * r_bar },
+            points: points(&means),
+        },
+        SpcSeries {
+            name: "r".to_string(),
+            limits: ControlLimits { center: r_bar, ucl: d4 * r_bar, lcl: d3 * r_bar },
+            points: points(&ranges),
+        },
+    ];
+    Ok((series, baseline))
+}
+
+/// I-MR 管理図の系列を作成する
+fn imr_series(samples: &[Sample], baseline: usize) -> Result<(Vec<SpcSeries>, usize), String> {
+    if samples.len() < 3 {
+        return Err(format!("Not enough samples for an I-MR chart ({} samples)", samples.len()));
+    }
+    let baseline = baseline.clamp(3, samples.len());
+    let values: Vec<f64> = samples.iter().map(|s| s.value).collect();
+    let moving_ranges: Vec<f64> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+
+    let x_bar = mean(&values[..baseline]);
+    let mr_bar = mean(&moving_ranges[..baseline - 1]);
+    let point = |index: usize, sample: &Sample, value: f64| SpcPoint {
+        index,
+        label: sample.serial.to_string(),
+        lot_name: sample.lot_name.clone(),
+        value,
+        baseline: index < baseline,
+        rules: Vec::new(),
+    };
+
+    let series = vec![
+        SpcSeries {
+            name: "i".to_string(),
+            limits: ControlLimits { center: x_bar, ucl: x_bar + IMR_E2 * mr_bar, lcl: x_bar - IMR_E2 * mr_bar },
+            points: samples.iter().enumerate().map(|(i, s)| point(i, s, s.value)).collect(),
+        },
+        SpcSeries {
+            name: "mr".to_string(),
+            limits: ControlLimits { center: mr_bar, ucl: IMR_D4 * mr_bar, lcl: 0.0 },
+            // 移動範囲は2点目から
+            points: moving_ranges.iter().enumerate().map(|(i, mr)| point(i + 1, &samples[i + 1], *mr)).collect(),
+        },
+    ];
+    Ok((series, baseline))
+}
+
+/// Western Electric ルールで判定し、違反した点に印を付ける
+/// 位置の管理図(xbar, i)はルール1~4、ばらつきの管理図(r, mr)はルール1のみ
+fn apply_western_electric(series: &mut SpcSeries, all_rules: bool) -> Vec<RuleViolation> {
+    let limits = series.limits.clone();
+    let sigma = (limits.ucl - limits.center) / 3.0;
+    // 各点の中心線からの距離(σ単位、符号付き)
+    let z: Vec<f64> = series
+        .points
+        .iter()
+        .map(|p| if sigma > 0.0 { (p.value - limits.center) / sigma } else { 0.0 })
+        .collect();
+
+    let mut violations = Vec::new();
+    let mut flag = |points: &mut Vec<SpcPoint>, i: usize, rule: u8, description: &str| {
+        if !points[i].rules.contains(&rule) {
+            points[i].rules.push(rule);
+            violations.push(RuleViolation {
+                series: series.name.clone(),
+                index: points[i].index,
+                rule,
+                description: description.to_string(),
+            });
+        }
+    };
+
+    for i in 0..z.len() {
+        let value = series.points[i].value;
+        if value > limits.ucl || value < limits.lcl {
+            flag(&mut series.points, i, 1, "1 point beyond the 3 sigma control limit");
+        }
+        if !all_rules {
+            continue;
+        }
+        // ルール2: 連続3点中2点が同じ側の2σを超える
+        if i >= 2 {
+            for side in [1.0, -1.0] {
+                if z[i - 2..=i].iter().filter(|v| *v * side > 2.0).count() >= 2 && z[i] * side > 2.0 {
+                    flag(&mut series.points, i, 2, "2 of 3 consecutive points beyond 2 sigma on the same side");
+                }
+            }
+        }
+        // ルール3: 連続5点中4点が同じ側の1σを超える
+        if i >= 4 {
+            for side in [1.0, -1.0] {
+                if z[i - 4..=i].iter().filter(|v| *v * side > 1.0).count() >= 4 && z[i] * side > 1.0 {
+                    flag(&mut series.points, i, 3, "4 of 5 consecutive points beyond 1 sigma on the same side");
+                }
+            }
+        }
+        // ルール4: 連続8点が中心線の同じ側
+        if i >= 7 {
+            for side in [1.0, -1.0] {
+                if z[i - 7..=i].iter().all(|v| *v * side > 0.0) {
+                    flag(&mut series.points, i, 4, "8 consecutive points on the same side of the center line");
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// 管理図のデータを作成する
+pub fn build_chart(conn: &Connection, query: &SpcQuery) -> Result<SpcChart, String> {
+    let table_name = table_for_plc(query.plc_id)?;
+    if !spc_columns().contains(&query.column) {
+        return Err(format!("Column {} is not a measurement column", query.column));
+    }
+    let max_samples = query.max_samples.unwrap_or(DEFAULT_MAX_SAMPLES).max(1);
+    let samples = load_samples(conn, &table_name, &query.column, query.lot_name.as_deref(), max_samples)
+        .map_err(|e| format!("Failed to read {}: {}", query.column, e))?;
+    let machine_name: Option<String> = conn
+        .query_row(&format!("SELECT MAX(MACHINE_NAME) FROM {table_name}"), [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    let subgroup_size = match query.chart {
+        ChartKind::XbarR => query.subgroup_size.unwrap_or(DEFAULT_SUBGROUP_SIZE),
+        ChartKind::Imr => 1,
+    };
+    let (mut series, baseline_size) = match query.chart {
+        ChartKind::XbarR => xbar_r_series(&samples, subgroup_size, query.baseline.unwrap_or(DEFAULT_BASELINE_SUBGROUPS))?,
+        ChartKind::Imr => imr_series(&samples, query.baseline.unwrap_or(DEFAULT_BASELINE_POINTS))?,
+    };
+
+    let mut violations = Vec::new();
+    for (i, s) in series.iter_mut().enumerate() {
+        // 先頭の系列が位置(xbar, i)、2番目がばらつき(r, mr)
+        violations.extend(apply_western_electric(s, i == 0));
+    }
+
+    Ok(SpcChart {
+        plc_id: query.plc_id,
+        table_name,
+        machine_name,
+        unit: query.column.split('_').next().unwrap_or_default().to_string(),
+        column: query.column.clone(),
+        lot_name: query.lot_name.clone(),
+        chart: query.chart,
+        subgroup_size,
+        baseline_size,
+        sample_count: samples.len(),
+        series,
+        violations,
+    })
+}
+
+/// SPCの対象にできるカラムの一覧をフロントエンドに返す
+#[command]
+pub fn get_spc_columns() -> Vec<String> {
+    spc_columns()
+}
+
+/// 管理図(X-bar/R、I-MR)のデータをフロントエンドに返す
+#[command]
+pub async fn get_spc_chart(query: SpcQuery) -> Result<SpcChart, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        build_chart(&conn, &query)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(values: &[f64]) -> Vec<Sample> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Sample { lot_name: "LOT1".to_string(), serial: i as i64 + 1, value: *value })
+            .collect()
+    }
+
+    fn series(values: &[f64], center: f64, ucl: f64, lcl: f64) -> SpcSeries {
+        SpcSeries {
+            name: "i".to_string(),
+            limits: ControlLimits { center, ucl, lcl },
+            points: values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| SpcPoint {
+                    index,
+                    label: index.to_string(),
+                    lot_name: String::new(),
+                    value: *value,
+                    baseline: false,
+                    rules: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn xbar_r_limits_from_baseline() {
+        // 群の大きさ2: (1,3) (2,4) (10,10) の端数1点は使わない
+        let samples = samples(&[1.0, 3.0, 2.0, 4.0, 10.0, 10.0, 99.0]);
+        let (series, baseline) = xbar_r_series(&samples, 2, 2).unwrap();
+        assert_eq!(baseline, 2);
+        let (xbar, r) = (&series[0], &series[1]);
+        assert_eq!(xbar.points.iter().map(|p| p.value).collect::<Vec<_>>(), vec![2.0, 3.0, 10.0]);
+        assert_eq!(r.points.iter().map(|p| p.value).collect::<Vec<_>>(), vec![2.0, 2.0, 0.0]);
+        // 管理限界は先頭2群から: X̿=2.5, R̄=2
+        assert!(close(xbar.limits.center, 2.5));
+        assert!(close(xbar.limits.ucl, 2.5 + 1.880 * 2.0));
+        assert!(close(xbar.limits.lcl, 2.5 - 1.880 * 2.0));
+        assert!(close(r.limits.ucl, 3.267 * 2.0));
+        assert!(close(r.limits.lcl, 0.0));
+        assert_eq!(xbar.points[0].label, "1-2");
+        assert!(xbar.points[1].baseline && !xbar.points[2].baseline);
+    }
+
+    #[test]
+    fn xbar_r_rejects_bad_sizes() {
+        assert!(xbar_r_series(&samples(&[1.0; 20]), 1, 5).is_err());
+        assert!(xbar_r_series(&samples(&[1.0; 20]), 11, 5).is_err());
+        // 2群に満たない
+        assert!(xbar_r_series(&samples(&[1.0; 5]), 3, 5).is_err());
+        // ベースラインは群の数まで
+        let (_, baseline) = xbar_r_series(&samples(&[1.0; 9]), 3, 25).unwrap();
+        assert_eq!(baseline, 3);
+    }
+
+    #[test]
+    fn imr_limits_and_moving_ranges() {
+        let samples = samples(&[10.0, 12.0, 11.0, 13.0]);
+        let (series, baseline) = imr_series(&samples, 100).unwrap();
+        assert_eq!(baseline, 4);
+        let (i, mr) = (&series[0], &series[1]);
+        assert!(close(i.limits.center, 11.5));
+        // 移動範囲 2,1,2 の平均
+        let mr_bar = 5.0 / 3.0;
+        assert!(close(mr.limits.center, mr_bar));
+        assert!(close(i.limits.ucl, 11.5 + 2.660 * mr_bar));
+        assert!(close(mr.limits.ucl, 3.267 * mr_bar));
+        // 移動範囲は2点目から
+        assert_eq!(mr.points.len(), 3);
+        assert_eq!(mr.points[0].index, 1);
+        assert_eq!(mr.points[0].label, "2");
+        assert!(imr_series(&samples[..2], 100).is_err());
+    }
+
+    #[test]
+    fn western_electric_rule_1() {
+        let mut s = series(&[0.0, 3.5, -3.5, 2.9], 0.0, 3.0, -3.0);
+        let violations = apply_western_electric(&mut s, false);
+        assert_eq!(violations.iter().map(|v| (v.index, v.rule)).collect::<Vec<_>>(), vec![(1, 1), (2, 1)]);
+        assert_eq!(s.points[1].rules, vec![1]);
+        assert!(s.points[3].rules.is_empty());
+    }
+
+    #[test]
+    fn western_electric_rules_2_to_4() {
+        // σ=1: 2σ超えが3点中2点
+        let mut s = series(&[2.5, 0.0, 2.5], 0.0, 3.0, -3.0);
+        let violations = apply_western_electric(&mut s, true);
+        assert_eq!(violations.iter().map(|v| (v.index, v.rule)).collect::<Vec<_>>(), vec![(2, 2)]);
+
+        // 1σ超えが5点中4点(下側)
+        let mut s = series(&[-1.5, -1.5, 0.5, -1.5, -1.5], 0.0, 3.0, -3.0);
+        let violations = apply_western_electric(&mut s, true);
+        assert_eq!(violations.iter().map(|v| (v.index, v.rule)).collect::<Vec<_>>(), vec![(4, 3)]);
+
+        // 中心線の同じ側に8点
+        let mut s = series(&[0.5; 8], 0.0, 3.0, -3.0);
+        let violations = apply_western_electric(&mut s, true);
+        assert_eq!(violations.iter().map(|v| (v.index, v.rule)).collect::<Vec<_>>(), vec![(7, 4)]);
+
+        // ばらつきの管理図はルール1のみ
+        let mut s = series(&[0.5; 8], 0.0, 3.0, -3.0);
+        assert!(apply_western_electric(&mut s, false).is_empty());
+    }
+
+    #[test]
+    fn load_samples_in_id_order() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE t (ID INTEGER PRIMARY KEY, LOT_NAME TEXT, SERIAL INTEGER, DC1_STAGE_Z INTEGER);
+             INSERT INTO t (LOT_NAME, SERIAL, DC1_STAGE_Z) VALUES ('A', 1, 10), ('A', 2, NULL), ('B', 1, 30), ('B', 2, 40);",
+        )
+        .unwrap();
+        let values = |lot: Option<&str>, max: usize| -> Vec<f64> {
+            load_samples(&conn, "t", "DC1_STAGE_Z", lot, max).unwrap().iter().map(|s| s.value).collect()
+        };
+        assert_eq!(values(None, 10), vec![10.0, 30.0, 40.0]);
+        // 直近から max 件
+        assert_eq!(values(None, 2), vec![30.0, 40.0]);
+        assert_eq!(values(Some("B"), 10), vec![30.0, 40.0]);
+    }
+}
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS {TABLE_NAME}_alarms (
+	"ID"				INTEGER NOT NULL,
+	"RECEIVED_AT"		VARCHAR NOT NULL,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR,
+	"SERIAL"			INTEGER,
+	"UNIT"				VARCHAR NOT NULL,
+	"ALARM_NUM"			INTEGER,
+	"RECEIVED_EPOCH_MS"	INTEGER,
+	PRIMARY KEY("ID")
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "uix_{TABLE_NAME}_alarm_key" ON {TABLE_NAME}_alarms (COALESCE("LOT_NAME", ''), COALESCE("SERIAL", -1), "UNIT", COALESCE("ALARM_NUM", -1), "RECEIVED_EPOCH_MS");
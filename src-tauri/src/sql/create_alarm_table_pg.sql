@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS "{TABLE_NAME}_alarms" (
+	"ID"				BIGSERIAL,
+	"RECEIVED_AT"		TEXT NOT NULL,
+	"MACHINE_NAME"		TEXT,
+	"TYPE_NAME"			TEXT,
+	"LOT_NAME"			TEXT,
+	"SERIAL"			BIGINT,
+	"UNIT"				TEXT NOT NULL,
+	"ALARM_NUM"			BIGINT,
+	"RECEIVED_EPOCH_MS"	BIGINT,
+	PRIMARY KEY("ID")
+);
+DO $$
+BEGIN
+	IF NOT EXISTS (SELECT 1 FROM pg_class WHERE relname = 'uix_{TABLE_NAME}_alarm_key') THEN
+		ALTER TABLE "{TABLE_NAME}_alarms" ADD COLUMN IF NOT EXISTS "RECEIVED_EPOCH_MS" BIGINT;
+		ALTER TABLE "{TABLE_NAME}_alarms" DROP CONSTRAINT IF EXISTS "uix_{TABLE_NAME}_alarm";
+		ALTER TABLE "{TABLE_NAME}_alarms" DROP CONSTRAINT IF EXISTS "uix_{TABLE_NAME}_alarm_ms";
+		DELETE FROM "{TABLE_NAME}_alarms" WHERE "RECEIVED_EPOCH_MS" IS NOT NULL AND "ID" NOT IN (
+			SELECT MIN("ID") FROM "{TABLE_NAME}_alarms" WHERE "RECEIVED_EPOCH_MS" IS NOT NULL
+			GROUP BY COALESCE("LOT_NAME", ''), COALESCE("SERIAL", -1), "UNIT", COALESCE("ALARM_NUM", -1), "RECEIVED_EPOCH_MS"
+		);
+		CREATE UNIQUE INDEX "uix_{TABLE_NAME}_alarm_key" ON "{TABLE_NAME}_alarms" (COALESCE("LOT_NAME", ''), COALESCE("SERIAL", -1), "UNIT", COALESCE("ALARM_NUM", -1), "RECEIVED_EPOCH_MS");
+	END IF;
+END $$;
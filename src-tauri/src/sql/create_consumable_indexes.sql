@@ -0,0 +1,10 @@
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_dc1_probe" ON {TABLE_NAME} ("DC1_PROBE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_ac1_probe" ON {TABLE_NAME} ("AC1_PROBE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_ac2_probe" ON {TABLE_NAME} ("AC2_PROBE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_dc2_probe" ON {TABLE_NAME} ("DC2_PROBE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_dc1_stage" ON {TABLE_NAME} ("DC1_STAGE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_ac1_stage" ON {TABLE_NAME} ("AC1_STAGE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_ac2_stage" ON {TABLE_NAME} ("AC2_STAGE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_dc2_stage" ON {TABLE_NAME} ("DC2_STAGE_SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_ld_tray" ON {TABLE_NAME} ("LD_TRAYID");
+CREATE INDEX IF NOT EXISTS "idx_{TABLE_NAME}_uld_tray" ON {TABLE_NAME} ("ULD_TRAYID");
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS {TABLE_NAME}_history (
+	"ID"				INTEGER NOT NULL,
+	"RECEIVED_AT"		VARCHAR NOT NULL,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR NOT NULL,
+	"SERIAL"			INTEGER NOT NULL,
+	"UNIT"				VARCHAR NOT NULL,
+	"RECORD_KIND"		VARCHAR NOT NULL,
+	"RECORD_KEY"		VARCHAR NOT NULL,
+	"DATA"				VARCHAR NOT NULL,
+	PRIMARY KEY("ID"),
+	CONSTRAINT "uix_history" UNIQUE("LOT_NAME","SERIAL","RECORD_KEY","RECEIVED_AT")
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS "{TABLE_NAME}_history" (
+	"ID"				BIGSERIAL,
+	"RECEIVED_AT"		TEXT NOT NULL,
+	"MACHINE_NAME"		TEXT,
+	"TYPE_NAME"			TEXT,
+	"LOT_NAME"			TEXT NOT NULL,
+	"SERIAL"			BIGINT NOT NULL,
+	"UNIT"				TEXT NOT NULL,
+	"RECORD_KIND"		TEXT NOT NULL,
+	"RECORD_KEY"		TEXT NOT NULL,
+	"DATA"				TEXT NOT NULL,
+	PRIMARY KEY("ID"),
+	CONSTRAINT "uix_{TABLE_NAME}_history" UNIQUE("LOT_NAME","SERIAL","RECORD_KEY","RECEIVED_AT")
+);
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS limit_violations (
+	"ID"				INTEGER NOT NULL,
+	"DETECTED_AT"		VARCHAR NOT NULL,
+	"RECEIVED_AT"		VARCHAR NOT NULL,
+	"PLC_ID"			INTEGER NOT NULL,
+	"TABLE_NAME"		VARCHAR NOT NULL,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR,
+	"SERIAL"			INTEGER,
+	"COLUMN_NAME"		VARCHAR NOT NULL,
+	"VALUE"				INTEGER,
+	"RULE"				VARCHAR NOT NULL,
+	"LOWER_LIMIT"		REAL,
+	"UPPER_LIMIT"		REAL,
+	"DETAIL"			VARCHAR,
+	PRIMARY KEY("ID")
+);
+CREATE INDEX IF NOT EXISTS "idx_limit_violations_lot_serial" ON limit_violations ("LOT_NAME", "SERIAL");
+CREATE INDEX IF NOT EXISTS "idx_limit_violations_detected" ON limit_violations ("DETECTED_AT");
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS lots (
+	"ID"				INTEGER NOT NULL,
+	"PLC_ID"			INTEGER NOT NULL,
+	"TABLE_NAME"		VARCHAR NOT NULL,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR NOT NULL,
+	"STATUS"			VARCHAR NOT NULL,
+	"STARTED_AT"		VARCHAR NOT NULL,
+	"LAST_SEEN_AT"		VARCHAR NOT NULL,
+	"ENDED_AT"			VARCHAR,
+	"END_REASON"		VARCHAR,
+	"FRAME_COUNT"		INTEGER NOT NULL DEFAULT 0,
+	"CHIP_COUNT"		INTEGER,
+	PRIMARY KEY("ID"),
+	CONSTRAINT "uix_lot_run" UNIQUE("TABLE_NAME","LOT_NAME","STARTED_AT")
+);
+CREATE INDEX IF NOT EXISTS "idx_lots_status" ON lots ("STATUS", "TABLE_NAME");
+CREATE INDEX IF NOT EXISTS "idx_lots_started" ON lots ("STARTED_AT");
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS machine_events (
+	"ID"				INTEGER NOT NULL,
+	"RECEIVED_AT"		VARCHAR NOT NULL,
+	"TABLE_NAME"		VARCHAR NOT NULL,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR,
+	"UNIT"				VARCHAR,
+	"EVENT_TYPE"		VARCHAR NOT NULL,
+	"CODE"				INTEGER,
+	"RECEIVED_EPOCH_MS"	INTEGER,
+	PRIMARY KEY("ID")
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "uix_machine_event_key" ON machine_events ("TABLE_NAME", "RECEIVED_EPOCH_MS", COALESCE("UNIT", ''), "EVENT_TYPE", COALESCE("CODE", -1));
+CREATE INDEX IF NOT EXISTS "idx_machine_events_received" ON machine_events ("RECEIVED_AT");
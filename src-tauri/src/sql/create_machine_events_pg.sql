@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS machine_events (
+	"ID"				BIGSERIAL,
+	"RECEIVED_AT"		TEXT NOT NULL,
+	"TABLE_NAME"		TEXT NOT NULL,
+	"MACHINE_NAME"		TEXT,
+	"TYPE_NAME"			TEXT,
+	"LOT_NAME"			TEXT,
+	"UNIT"				TEXT,
+	"EVENT_TYPE"		TEXT NOT NULL,
+	"CODE"				BIGINT,
+	"RECEIVED_EPOCH_MS"	BIGINT,
+	PRIMARY KEY("ID")
+);
+DO $$
+BEGIN
+	IF NOT EXISTS (SELECT 1 FROM pg_class WHERE relname = 'uix_machine_event_key') THEN
+		ALTER TABLE machine_events ADD COLUMN IF NOT EXISTS "RECEIVED_EPOCH_MS" BIGINT;
+		ALTER TABLE machine_events DROP CONSTRAINT IF EXISTS "uix_machine_event";
+		ALTER TABLE machine_events DROP CONSTRAINT IF EXISTS "uix_machine_event_ms";
+		DELETE FROM machine_events WHERE "RECEIVED_EPOCH_MS" IS NOT NULL AND "ID" NOT IN (
+			SELECT MIN("ID") FROM machine_events WHERE "RECEIVED_EPOCH_MS" IS NOT NULL
+			GROUP BY "TABLE_NAME", "RECEIVED_EPOCH_MS", COALESCE("UNIT", ''), "EVENT_TYPE", COALESCE("CODE", -1)
+		);
+		CREATE UNIQUE INDEX "uix_machine_event_key" ON machine_events ("TABLE_NAME", "RECEIVED_EPOCH_MS", COALESCE("UNIT", ''), "EVENT_TYPE", COALESCE("CODE", -1));
+	END IF;
+END $$;
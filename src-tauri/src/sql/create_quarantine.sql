@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS quarantine (
+	"ID"				INTEGER NOT NULL,
+	"PLC_ID"			INTEGER NOT NULL,
+	"TABLE_NAME"		VARCHAR NOT NULL,
+	"RECEIVED_AT"		VARCHAR NOT NULL,
+	"QUARANTINED_AT"	VARCHAR NOT NULL,
+	"SCHEMA_NAME"		VARCHAR,
+	"MACHINE_NAME"		VARCHAR,
+	"TYPE_NAME"			VARCHAR,
+	"LOT_NAME"			VARCHAR,
+	"ERRORS"			VARCHAR NOT NULL,
+	"MESSAGE"			VARCHAR NOT NULL,
+	PRIMARY KEY("ID")
+);
+CREATE INDEX IF NOT EXISTS "idx_quarantine_plc" ON quarantine ("PLC_ID", "RECEIVED_AT");
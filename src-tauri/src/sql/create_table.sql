@@ -107,6 +107,19 @@ CREATE TABLE IF NOT EXISTS {TABLE_NAME} (
 	"ULD_CHIP_ALIGN_Y"		INTEGER,
 	"ULD_CHIP_ALIGN_NUM"	INTEGER,
 	"ULD_ALARM"				INTEGER,
+	"LD_PICKUP_AT"			VARCHAR,
+	"LD_PICKUP_EPOCH_MS"	INTEGER,
+	"LD_CLOCK_SKEW_MS"		INTEGER,
+	"ULD_PUT_AT"			VARCHAR,
+	"ULD_PUT_EPOCH_MS"		INTEGER,
+	"ULD_CLOCK_SKEW_MS"		INTEGER,
+	"LD_RECEIVED_AT"		VARCHAR,
+	"DC1_RECEIVED_AT"		VARCHAR,
+	"AC1_RECEIVED_AT"		VARCHAR,
+	"AC2_RECEIVED_AT"		VARCHAR,
+	"DC2_RECEIVED_AT"		VARCHAR,
+	"IP_RECEIVED_AT"		VARCHAR,
+	"ULD_RECEIVED_AT"		VARCHAR,
 	PRIMARY KEY("ID"),
 	CONSTRAINT "uix_lot_serial" UNIQUE("LOT_NAME","SERIAL")
 )
\ No newline at end of file
@@ -0,0 +1,125 @@
+CREATE TABLE IF NOT EXISTS "{TABLE_NAME}" (
+	"ID"					BIGSERIAL,
+	"MACHINE_NAME"			TEXT,
+	"TYPE_NAME"				TEXT,
+	"LOT_NAME"				TEXT,
+	"SERIAL"				BIGINT,
+	"WANO"					BIGINT,
+	"WAX"					BIGINT,
+	"WAY"					BIGINT,
+	"LD_PICKUP_DATE"		TEXT,
+	"LD_TRAYID"				TEXT,
+	"LD_TRAY_ARM"			TEXT,
+	"LD_TRAY_POCKET_X"		BIGINT,
+	"LD_TRAY_POCKET_Y"		BIGINT,
+	"LD_TRAY_ALIGN_X"		BIGINT,
+	"LD_TRAY_ALIGN_Y"		BIGINT,
+	"LD_ARM1_COLLET"		BIGINT,
+	"LD_ALARM"				BIGINT,
+	"DC1_PRE_ALIGN_X"		BIGINT,
+	"DC1_PRE_ALIGN_Y"		BIGINT,
+	"DC1_PRE_ALIGN_T"		BIGINT,
+	"DC1_ARM1_COLLET"		BIGINT,
+	"DC1_STAGE_SERIAL"		TEXT,
+	"DC1_STAGE_COUNT"		BIGINT,
+	"DC1_PROBE_SERIAL"		TEXT,
+	"DC1_PROBE_COUNT"		BIGINT,
+	"DC1_PROBE_X1"			BIGINT,
+	"DC1_PROBE_Y1"			BIGINT,
+	"DC1_PROBE_X2"			BIGINT,
+	"DC1_PROBE_Y2"			BIGINT,
+	"DC1_STAGE_Z"			BIGINT,
+	"DC1_PIN_Z"				BIGINT,
+	"DC1_CHIP_ALIGN_X"		BIGINT,
+	"DC1_CHIP_ALIGN_Y"		BIGINT,
+	"DC1_CHIP_ALIGN_T"		BIGINT,
+	"DC1_TEST_BIN"			BIGINT,
+	"DC1_ARM2_COLLET"		BIGINT,
+	"DC1_ALARM"				BIGINT,
+	"AC1_ARM1_COLLET"		BIGINT,
+	"AC1_STAGE_SERIAL"		TEXT,
+	"AC1_STAGE_COUNT"		BIGINT,
+	"AC1_PROBE_SERIAL"		TEXT,
+	"AC1_PROBE_COUNT"		BIGINT,
+	"AC1_PROBE_X1"			BIGINT,
+	"AC1_PROBE_Y1"			BIGINT,
+	"AC1_PROBE_X2"			BIGINT,
+	"AC1_PROBE_Y2"			BIGINT,
+	"AC1_STAGE_Z"			BIGINT,
+	"AC1_PIN_Z"				BIGINT,
+	"AC1_CHIP_ALIGN_X"		BIGINT,
+	"AC1_CHIP_ALIGN_Y"		BIGINT,
+	"AC1_CHIP_ALIGN_T"		BIGINT,
+	"AC1_TEST_BIN"			BIGINT,
+	"AC1_ARM2_COLLET"		BIGINT,
+	"AC1_ALARM"				BIGINT,
+	"AC2_ARM1_COLLET"		BIGINT,
+	"AC2_STAGE_SERIAL"		TEXT,
+	"AC2_STAGE_COUNT"		BIGINT,
+	"AC2_PROBE_SERIAL"		TEXT,
+	"AC2_PROBE_COUNT"		BIGINT,
+	"AC2_PROBE_X1"			BIGINT,
+	"AC2_PROBE_Y1"			BIGINT,
+	"AC2_PROBE_X2"			BIGINT,
+	"AC2_PROBE_Y2"			BIGINT,
+	"AC2_STAGE_Z"			BIGINT,
+	"AC2_PIN_Z"				BIGINT,
+	"AC2_CHIP_ALIGN_X"		BIGINT,
+	"AC2_CHIP_ALIGN_Y"		BIGINT,
+	"AC2_CHIP_ALIGN_T"		BIGINT,
+	"AC2_TEST_BIN"			BIGINT,
+	"AC2_ARM2_COLLET"		BIGINT,
+	"AC2_ALARM"				BIGINT,
+	"DC2_ARM1_COLLET"		BIGINT,
+	"DC2_STAGE_SERIAL"		TEXT,
+	"DC2_STAGE_COUNT"		BIGINT,
+	"DC2_PROBE_SERIAL"		TEXT,
+	"DC2_PROBE_COUNT"		BIGINT,
+	"DC2_PROBE_X1"			BIGINT,
+	"DC2_PROBE_Y1"			BIGINT,
+	"DC2_PROBE_X2"			BIGINT,
+	"DC2_PROBE_Y2"			BIGINT,
+	"DC2_STAGE_Z"			BIGINT,
+	"DC2_PIN_Z"				BIGINT,
+	"DC2_CHIP_ALIGN_X"		BIGINT,
+	"DC2_CHIP_ALIGN_Y"		BIGINT,
+	"DC2_CHIP_ALIGN_T"		BIGINT,
+	"DC2_TEST_BIN"			BIGINT,
+	"DC2_ARM2_COLLET"		BIGINT,
+	"DC2_ALARM"				BIGINT,
+	"IP_ARM1_COLLET"		BIGINT,
+	"IP_STAGE_COUNT"		BIGINT,
+	"IP_SURF_BIN"			BIGINT,
+	"IP_ARM2_COLLET"		BIGINT,
+	"IP_BACK_BIN"			BIGINT,
+	"IP_ALARM"				BIGINT,
+	"ULD_PRE_ALIGN_X"		BIGINT,
+	"ULD_PRE_ALIGN_Y"		BIGINT,
+	"ULD_PRE_ALIGN_T"		BIGINT,
+	"ULD_TRAYID"			TEXT,
+	"ULD_POCKET_X"			BIGINT,
+	"ULD_POCKET_Y"			BIGINT,
+	"ULD_POCKET_ALIGN_X"	BIGINT,
+	"ULD_POCKET_ALIGN_Y"	BIGINT,
+	"ULD_ARM1_COLLET"		BIGINT,
+	"ULD_PUT_DATE"			TEXT,
+	"ULD_CHIP_ALIGN_X"		BIGINT,
+	"ULD_CHIP_ALIGN_Y"		BIGINT,
+	"ULD_CHIP_ALIGN_NUM"	BIGINT,
+	"ULD_ALARM"				BIGINT,
+	"LD_PICKUP_AT"			TEXT,
+	"LD_PICKUP_EPOCH_MS"	BIGINT,
+	"LD_CLOCK_SKEW_MS"		BIGINT,
+	"ULD_PUT_AT"			TEXT,
+	"ULD_PUT_EPOCH_MS"		BIGINT,
+	"ULD_CLOCK_SKEW_MS"		BIGINT,
+	"LD_RECEIVED_AT"		TEXT,
+	"DC1_RECEIVED_AT"		TEXT,
+	"AC1_RECEIVED_AT"		TEXT,
+	"AC2_RECEIVED_AT"		TEXT,
+	"DC2_RECEIVED_AT"		TEXT,
+	"IP_RECEIVED_AT"		TEXT,
+	"ULD_RECEIVED_AT"		TEXT,
+	PRIMARY KEY("ID"),
+	CONSTRAINT "uix_{TABLE_NAME}_lot_serial" UNIQUE("LOT_NAME","SERIAL")
+)
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS replication_log (
+	"SEQ"			INTEGER PRIMARY KEY AUTOINCREMENT,
+	"TABLE_NAME"	VARCHAR NOT NULL,
+	"ROW_ID"		INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS replication_state (
+	"TARGET"		VARCHAR NOT NULL,
+	"LAST_SEQ"		INTEGER NOT NULL,
+	"LAST_SENT_AT"	VARCHAR,
+	PRIMARY KEY("TARGET")
+);
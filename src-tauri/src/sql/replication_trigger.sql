@@ -0,0 +1,8 @@
+CREATE TRIGGER IF NOT EXISTS "{TABLE_NAME}_replication_insert" AFTER INSERT ON "{TABLE_NAME}"
+BEGIN
+	INSERT INTO replication_log ("TABLE_NAME", "ROW_ID") VALUES ('{TABLE_NAME}', NEW."ID");
+END;
+CREATE TRIGGER IF NOT EXISTS "{TABLE_NAME}_replication_update" AFTER UPDATE ON "{TABLE_NAME}"
+BEGIN
+	INSERT INTO replication_log ("TABLE_NAME", "ROW_ID") VALUES ('{TABLE_NAME}', NEW."ID");
+END;
@@ -2,14 +2,22 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use parking_lot::Mutex;
 use crate::types::PlcConnection;
-use crate::data_handler::DbWriteRequest;
-use tokio::sync::mpsc;
+use crate::db_queue::DbQueue;
+use crate::live_feed::LiveEvent;
+use crate::mqtt_publisher::MqttPublisher;
+use tokio::sync::broadcast;
 
 /// グローバルな接続状態を管理する型
 pub type ConnectionState = Arc<Mutex<HashMap<u32, PlcConnection>>>;
 
-/// DB書き込みチャネルの送信側を管理する型
-pub type DbChannelState = mpsc::UnboundedSender<DbWriteRequest>;
+/// DB書き込みキューを管理する型
+pub type DbChannelState = Arc<DbQueue>;
+
+/// 受信データのライブ配信チャネルを管理する型
+pub type LiveFeedState = broadcast::Sender<LiveEvent>;
+
+/// MQTT送信を管理する型
+pub type MqttState = Arc<MqttPublisher>;
 
 /// 接続状態を初期化
 pub fn init_connection_state() -> ConnectionState {
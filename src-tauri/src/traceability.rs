@@ -0,0 +1,532 @@
+///チップのトレーサビリティ(返品解析用)
+///(LOT_NAME, SERIAL) の1行を工程順(LD → DC1 → AC1 → AC2 → DC2 → IP → ULD)の履歴に組み立て、
+///工程ごとのトレイポケット・コレット・ステージ・プローブカード・BIN・アラームと規格違反をまとめる
+///更新履歴を記録している場合は、再検査・再アライメントで上書きされる前の値も受信順で返す
+///印刷用のHTMLレポートも作成する(PDFはブラウザ・WebViewの印刷から出力する)
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::command;
+
+use crate::config::load_config;
+use crate::data_handler::{is_valid_identifier, open_read_connection, table_columns};
+use crate::db_query::{configured_plcs, find_chip, table_for_plc};
+use crate::regist_data_to_db::{alarm_table, history_table};
+
+/// 工程(カラム名の接頭辞, 表示名)
+const STAGES: [(&str, &str); 7] = [
+    ("LD", "LDトレイピックアップ"),
+    ("DC1", "DC1検査"),
+    ("AC1", "AC1検査"),
+    ("AC2", "AC2検査"),
+    ("DC2", "DC2検査"),
+    ("IP", "IP外観検査"),
+    ("ULD", "ULDトレイ収納"),
+];
+
+/// 工程の時刻を記録しているカラム
+const STAGE_DATE_COLUMNS: [(&str, &str); 2] = [("LD", "LD_PICKUP_DATE"), ("ULD", "ULD_PUT_DATE")];
+
+/// 使用したコレット
+#[derive(Serialize, Debug, Clone)]
+pub struct ColletUse {
+    /// ARM1(上流) / ARM2(下流)
+    pub arm: String,
+    /// 使用時点のコレット使用回数
+    pub count: Option<i64>,
+}
+
+/// 検査結果
+#[derive(Serialize, Debug, Clone)]
+pub struct BinResult {
+    pub column: String,
+    pub bin: i64,
+    pub passed: bool,
+}
+
+/// 工程の測定値・記録値
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceValue {
+    pub column: String,
+    pub value: Value,
+}
+
+/// 規格違反の記録
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceViolation {
+    pub received_at: String,
+    pub column: String,
+    pub value: Option<i64>,
+    pub rule: String,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub detail: Option<String>,
+}
+
+/// アラームの記録
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceAlarm {
+    pub stage: String,
+    pub alarm_num: Option<i64>,
+    /// アラーム履歴テーブルに無い(履歴の記録前の)アラームは None
+    pub received_at: Option<String>,
+}
+
+/// ユニット情報1件分の更新(受信順)
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceUpdate {
+    pub received_at: String,
+    pub stage: String,
+    pub kind: String,
+    pub key: String,
+    /// 登録した値(カラム名 -> 値)
+    pub values: Map<String, Value>,
+}
+
+/// 工程1つ分の履歴
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceStep {
+    pub stage: String,
+    pub title: String,
+    /// 工程の時刻(記録がある工程のみ)
+    pub timestamp: Option<String>,
+    /// 工程の記録が1つでもあるか(通過していない工程は false)
+    pub recorded: bool,
+    /// トレイID・ポケット座標(LD・ULD)
+    pub tray_id: Option<String>,
+    pub pocket_x: Option<i64>,
+    pub pocket_y: Option<i64>,
+    pub collets: Vec<ColletUse>,
+    pub stage_serial: Option<String>,
+    pub stage_count: Option<i64>,
+    pub probe_serial: Option<String>,
+    pub probe_count: Option<i64>,
+    pub bins: Vec<BinResult>,
+    pub alarm: Option<i64>,
+    /// 上記以外の記録値(補正量・プローブ座標・高さなど)
+    pub values: Vec<TraceValue>,
+    pub violations: Vec<TraceViolation>,
+}
+
+/// チップの履歴
+#[derive(Serialize, Debug, Clone)]
+pub struct ChipGenealogy {
+    pub table_name: String,
+    pub machine_name: Option<String>,
+    pub type_name: Option<String>,
+    pub lot_name: String,
+    pub serial: i64,
+    /// ウェハ上の位置
+    pub wafer_no: Option<i64>,
+    pub wafer_x: Option<i64>,
+    pub wafer_y: Option<i64>,
+    /// 検査した全工程で良品BINか(未検査の場合は None)
+    pub passed: Option<bool>,
+    pub steps: Vec<TraceStep>,
+    pub alarms: Vec<TraceAlarm>,
+    /// {TABLE}_history の記録(更新履歴が無効の場合は空)
+    pub updates: Vec<TraceUpdate>,
+    pub generated_at: String,
+}
+
+fn int(row: &Map<String, Value>, column: &str) -> Option<i64> {
+    row.get(column).and_then(Value::as_i64)
+}
+
+fn text(row: &Map<String, Value>, column: &str) -> Option<String> {
+    row.get(column).and_then(Value::as_str).map(str::to_string)
+}
+
+/// チップの行から工程順の履歴を組み立てる
+/// alarms はアラーム履歴テーブルの記録(受信順)。履歴が無い工程はチップの行のアラーム番号を使う
+pub fn build_genealogy(table_name: &str, row: &Map<String, Value>, violations: Vec<(String, TraceViolation)>, alarms: Vec<TraceAlarm>, pass_bins: &[i64]) -> ChipGenealogy {
+    let columns = table_columns();
+    let mut violations = violations;
+    let mut alarms = alarms;
+    let history_stages: Vec<String> = alarms.iter().map(|alarm| alarm.stage.clone()).collect();
+
+    let steps: Vec<TraceStep> = STAGES
+        .iter()
+        .map(|(stage, title)| {
+            let prefix = format!("{}_", stage);
+            let mut step = TraceStep {
+                stage: stage.to_string(),
+                title: title.to_string(),
+                timestamp: STAGE_DATE_COLUMNS
+                    .iter()
+                    .find(|(s, _)| s == stage)
+                    .and_then(|(_, column)| text(row, column)),
+                recorded: false,
+                tray_id: None,
+                pocket_x: None,
+                pocket_y: None,
+                collets: Vec::new(),
+                stage_serial: None,
+                stage_count: None,
+                probe_serial: None,
+                probe_count: None,
+                bins: Vec::new(),
+                alarm: None,
+                values: Vec::new(),
+                violations: Vec::new(),
+            };
+
+            for (column, _) in columns.iter().filter(|(c, _)| c.starts_with(&prefix)) {
+                let Some(value) = row.get(column).filter(|v| !v.is_null()) else {
+                    continue;
+                };
+                step.recorded = true;
+                let name = &column[prefix.len()..];
+                match name {
+                    "TRAYID" => step.tray_id = text(row, column),
+                    "TRAY_POCKET_X" | "POCKET_X" => step.pocket_x = int(row, column),
+                    "TRAY_POCKET_Y" | "POCKET_Y" => step.pocket_y = int(row, column),
+                    "STAGE_SERIAL" => step.stage_serial = text(row, column),
+                    "STAGE_COUNT" => step.stage_count = int(row, column),
+                    "PROBE_SERIAL" => step.probe_serial = text(row, column),
+                    "PROBE_COUNT" => step.probe_count = int(row, column),
+                    "ARM1_COLLET" | "ARM2_COLLET" => step.collets.push(ColletUse {
+                        arm: name.trim_end_matches("_COLLET").to_string(),
+                        count: value.as_i64(),
+                    }),
+                    "ALARM" => {
+                        step.alarm = value.as_i64();
+                        if step.alarm.is_some() && !history_stages.iter().any(|s| s == stage) {
+                            alarms.push(TraceAlarm {
+                                stage: stage.to_string(),
+                                alarm_num: step.alarm,
+                                received_at: None,
+                            });
+                        }
+                    }
+                    _ if name.ends_with("_BIN") => {
+                        if let Some(bin) = value.as_i64() {
+                            step.bins.push(BinResult {
+                                column: column.clone(),
+                                bin,
+                                passed: pass_bins.contains(&bin),
+                            });
+                        }
+                    }
+                    _ => step.values.push(TraceValue {
+                        column: column.clone(),
+                        value: value.clone(),
+                    }),
+                }
+            }
+            // 規格違反は測定したカラムの工程に付ける
+            let (own, rest): (Vec<_>, Vec<_>) = violations.drain(..).partition(|(column, _)| column.starts_with(&prefix));
+            violations = rest;
+            step.violations = own.into_iter().map(|(_, v)| v).collect();
+            step
+        })
+        .collect();
+
+    let bins: Vec<&BinResult> = steps.iter().flat_map(|s| &s.bins).collect();
+    ChipGenealogy {
+        table_name: table_name.to_string(),
+        machine_name: text(row, "MACHINE_NAME"),
+        type_name: text(row, "TYPE_NAME"),
+        lot_name: text(row, "LOT_NAME").unwrap_or_default(),
+        serial: int(row, "SERIAL").unwrap_or_default(),
+        wafer_no: int(row, "WANO"),
+        wafer_x: int(row, "WAX"),
+        wafer_y: int(row, "WAY"),
+        passed: if bins.is_empty() { None } else { Some(bins.iter().all(|b| b.passed)) },
+        steps,
+        alarms,
+        updates: Vec::new(),
+        generated_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    }
+}
+
+/// チップの規格違反を limit_violations テーブルから読み出す((カラム名, 違反) の受信順)
+fn load_violations(conn: &Connection, table_name: &str, lot_name: &str, serial: i64) -> rusqlite::Result<Vec<(String, TraceViolation)>> {
+    let mut stmt = match conn.prepare(
+        "SELECT RECEIVED_AT, COLUMN_NAME, VALUE, RULE, LOWER_LIMIT, UPPER_LIMIT, DETAIL FROM limit_violations
+         WHERE TABLE_NAME = ?1 AND LOT_NAME = ?2 AND SERIAL = ?3
+         ORDER BY ID",
+    ) {
+        Ok(stmt) => stmt,
+        // 規格チェックを使っていない古いDBにはテーブルが無い
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let rows = stmt.query_map(params![table_name, lot_name, serial], |row| {
+        let column: String = row.get(1)?;
+        Ok((
+            column.clone(),
+            TraceViolation {
+                received_at: row.get(0)?,
+                column,
+                value: row.get(2)?,
+                rule: row.get(3)?,
+                lower: row.get(4)?,
+                upper: row.get(5)?,
+                detail: row.get(6)?,
+            },
+        ))
+    })?;
+    rows.collect()
+}
+
+/// チップのアラームを {TABLE}_alarms から読み出す(受信順)
+fn load_alarms(conn: &Connection, table_name: &str, lot_name: &str, serial: i64) -> rusqlite::Result<Vec<TraceAlarm>> {
+    let sql = format!(
+        "SELECT UNIT, ALARM_NUM, RECEIVED_AT FROM {} WHERE LOT_NAME = ?1 AND SERIAL = ?2 ORDER BY ID",
+        alarm_table(table_name)
+    );
+    let mut stmt = match conn.prepare(&sql) {
+        Ok(stmt) => stmt,
+        // アラーム履歴の記録前に作成したテーブルには履歴が無い
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let rows = stmt.query_map(params![lot_name, serial], |row| {
+        Ok(TraceAlarm {
+            stage: row.get(0)?,
+            alarm_num: row.get(1)?,
+            received_at: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// チップの更新履歴を {TABLE}_history から読み出す(受信順)
+fn load_updates(conn: &Connection, table_name: &str, lot_name: &str, serial: i64) -> rusqlite::Result<Vec<TraceUpdate>> {
+    let sql = format!(
+        "SELECT RECEIVED_AT, UNIT, RECORD_KIND, RECORD_KEY, DATA FROM {} WHERE LOT_NAME = ?1 AND SERIAL = ?2 ORDER BY RECEIVED_AT, ID",
+        history_table(table_name)
+    );
+    let mut stmt = match conn.prepare(&sql) {
+        Ok(stmt) => stmt,
+        // 更新履歴の記録前に作成したテーブルには履歴が無い
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let rows = stmt.query_map(params![lot_name, serial], |row| {
+        let data: String = row.get(4)?;
+        Ok(TraceUpdate {
+            received_at: row.get(0)?,
+            stage: row.get(1)?,
+            kind: row.get(2)?,
+            key: row.get(3)?,
+            values: serde_json::from_str(&data).unwrap_or_default(),
+        })
+    })?;
+    rows.collect()
+}
+
+/// チップの履歴を取得する(tables から順に探す)
+pub fn chip_genealogy(conn: &Connection, tables: &[String], lot_name: &str, serial: i64, pass_bins: &[i64]) -> Result<Option<ChipGenealogy>, String> {
+    let Some((table_name, row)) = find_chip(conn, tables, lot_name, serial).map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    let violations = load_violations(conn, &table_name, lot_name, serial).map_err(|e| e.to_string())?;
+    let alarms = load_alarms(conn, &table_name, lot_name, serial).map_err(|e| e.to_string())?;
+    let updates = load_updates(conn, &table_name, lot_name, serial).map_err(|e| e.to_string())?;
+    let mut genealogy = build_genealogy(&table_name, &row, violations, alarms, pass_bins);
+    genealogy.updates = updates;
+    Ok(Some(genealogy))
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn opt<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| escape_html(&v.to_string())).unwrap_or_else(|| "-".to_string())
+}
+
+fn json_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => escape_html(s),
+        other => escape_html(&other.to_string()),
+    }
+}
+
+/// 印刷用のHTMLレポートを作成する
+pub fn render_html(g: &ChipGenealogy) -> String {
+    let mut html = String::new();
+    let title = format!("チップ履歴 {} / {}", escape_html(&g.lot_name), g.serial);
+    let result = match g.passed {
+        Some(true) => "<span class=\"pass\">良品</span>",
+        Some(false) => "<span class=\"fail\">不良</span>",
+        None => "未検査",
+    };
+
+    html.push_str(&format!(
+        "<!DOCTYPE html>\n<html lang=\"ja\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n\
+body {{ font-family: sans-serif; font-size: 12px; margin: 24px; color: #111; }}\n\
+h1 {{ font-size: 18px; margin: 0 0 8px; }}\n\
+h2 {{ font-size: 14px; margin: 16px 0 4px; border-bottom: 1px solid #888; }}\n\
+table {{ border-collapse: collapse; margin: 4px 0; }}\n\
+th, td {{ border: 1px solid #aaa; padding: 2px 6px; text-align: left; vertical-align: top; }}\n\
+th {{ background: #eee; }}\n\
+.pass {{ color: #060; font-weight: bold; }}\n\
+.fail {{ color: #b00; font-weight: bold; }}\n\
+.skipped {{ color: #888; }}\n\
+section {{ page-break-inside: avoid; }}\n\
+@media print {{ body {{ margin: 0; }} }}\n\
+</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    ));
+
+    html.push_str("<table>\n");
+    for (label, value) in [
+        ("設備", opt(&g.machine_name)),
+        ("品種", opt(&g.type_name)),
+        ("ロット", escape_html(&g.lot_name)),
+        ("シリアル", g.serial.to_string()),
+        ("テーブル", escape_html(&g.table_name)),
+        ("ウェハ", format!("No.{} (X {}, Y {})", opt(&g.wafer_no), opt(&g.wafer_x), opt(&g.wafer_y))),
+        ("判定", result.to_string()),
+        ("作成日時", escape_html(&g.generated_at)),
+    ] {
+        html.push_str(&format!("<tr><th>{label}</th><td>{value}</td></tr>\n"));
+    }
+    html.push_str("</table>\n");
+
+    for step in &g.steps {
+        html.push_str("<section>\n");
+        if !step.recorded {
+            html.push_str(&format!("<h2 class=\"skipped\">{} (記録なし)</h2>\n</section>\n", escape_html(&step.title)));
+            continue;
+        }
+        html.push_str(&format!("<h2>{}</h2>\n<table>\n", escape_html(&step.title)));
+        let mut rows: Vec<(String, String)> = Vec::new();
+        if step.timestamp.is_some() {
+            rows.push(("時刻".to_string(), opt(&step.timestamp)));
+        }
+        if step.tray_id.is_some() || step.pocket_x.is_some() || step.pocket_y.is_some() {
+            rows.push((
+                "トレイ / ポケット".to_string(),
+                format!("{} (X {}, Y {})", opt(&step.tray_id), opt(&step.pocket_x), opt(&step.pocket_y)),
+            ));
+        }
+        for collet in &step.collets {
+            rows.push((format!("{} コレット使用回数", collet.arm), opt(&collet.count)));
+        }
+        if step.stage_serial.is_some() || step.stage_count.is_some() {
+            rows.push(("ステージ".to_string(), format!("{} (使用回数 {})", opt(&step.stage_serial), opt(&step.stage_count))));
+        }
+        if step.probe_serial.is_some() || step.probe_count.is_some() {
+            rows.push(("プローブカード".to_string(), format!("{} (使用回数 {})", opt(&step.probe_serial), opt(&step.probe_count))));
+        }
+        for bin in &step.bins {
+            let class = if bin.passed { "pass" } else { "fail" };
+            rows.push((escape_html(&bin.column), format!("<span class=\"{class}\">BIN {}</span>", bin.bin)));
+        }
+        if let Some(alarm) = step.alarm {
+            rows.push(("アラーム".to_string(), format!("<span class=\"fail\">{}</span>", alarm)));
+        }
+        for value in &step.values {
+            rows.push((escape_html(&value.column), json_text(&value.value)));
+        }
+        for (label, value) in rows {
+            html.push_str(&format!("<tr><th>{label}</th><td>{value}</td></tr>\n"));
+        }
+        html.push_str("</table>\n");
+
+        if !step.violations.is_empty() {
+            html.push_str("<table>\n<tr><th>受信時刻</th><th>カラム</th><th>値</th><th>ルール</th><th>下限</th><th>上限</th><th>詳細</th></tr>\n");
+            for v in &step.violations {
+                html.push_str(&format!(
+                    "<tr class=\"fail\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&v.received_at),
+                    escape_html(&v.column),
+                    opt(&v.value),
+                    escape_html(&v.rule),
+                    opt(&v.lower),
+                    opt(&v.upper),
+                    opt(&v.detail),
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("</section>\n");
+    }
+
+    html.push_str("<section>\n<h2>アラーム</h2>\n");
+    if g.alarms.is_empty() {
+        html.push_str("<p>記録なし</p>\n");
+    } else {
+        html.push_str("<table>\n<tr><th>受信時刻</th><th>工程</th><th>アラーム番号</th></tr>\n");
+        for alarm in &g.alarms {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                opt(&alarm.received_at),
+                escape_html(&alarm.stage),
+                opt(&alarm.alarm_num)
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</section>\n");
+
+    // 更新履歴は記録している場合のみ載せる
+    if !g.updates.is_empty() {
+        html.push_str("<section>\n<h2>更新履歴</h2>\n");
+        html.push_str("<table>\n<tr><th>受信時刻</th><th>工程</th><th>種類</th><th>内容</th></tr>\n");
+        for update in &g.updates {
+            let values: Vec<String> = update
+                .values
+                .iter()
+                .map(|(column, value)| format!("{}={}", escape_html(column), json_text(value)))
+                .collect();
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(&update.received_at),
+                escape_html(&update.stage),
+                escape_html(&update.kind),
+                values.join(", ")
+            ));
+        }
+        html.push_str("</table>\n</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// 良品BINの設定を読み込み、読み出し用の接続でチップの履歴を取得する
+/// plc_id を省略した場合は全テーブルから探す
+async fn load_genealogy(plc_id: Option<u32>, lot_name: String, serial: i64) -> Result<ChipGenealogy, String> {
+    let pass_bins = load_config()?.quality.pass_bins;
+    let tables = match plc_id {
+        Some(plc_id) => vec![table_for_plc(plc_id)?],
+        None => configured_plcs()?
+            .into_iter()
+            .map(|plc| plc.table_name)
+            .filter(|table| is_valid_identifier(table))
+            .collect(),
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        chip_genealogy(&conn, &tables, &lot_name, serial, &pass_bins)?
+            .ok_or_else(|| format!("Chip {}/{} not found", lot_name, serial))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// チップの履歴をフロントエンドに返す
+#[command]
+pub async fn get_chip_genealogy(lot_name: String, serial: i64, plc_id: Option<u32>) -> Result<ChipGenealogy, String> {
+    load_genealogy(plc_id, lot_name, serial).await
+}
+
+/// チップの履歴の印刷用HTMLをフロントエンドに返す
+#[command]
+pub async fn get_chip_report(lot_name: String, serial: i64, plc_id: Option<u32>) -> Result<String, String> {
+    load_genealogy(plc_id, lot_name, serial).await.map(|g| render_html(&g))
+}
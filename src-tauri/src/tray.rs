@@ -3,6 +3,7 @@ use tauri::{
     menu::{Menu, MenuItem},
     tray::{TrayIconBuilder, TrayIconEvent, MouseButton, MouseButtonState},
 };
+use crate::shutdown::request_shutdown;
 
 /// トレイアイコンをセットアップ
 pub fn setup_tray_icon(app: &App) -> Result<()> {
@@ -33,7 +34,8 @@ fn on_menu_event(app: &AppHandle, event: tauri::menu::MenuEvent) {
             }
         }
         "quit" => {
-            std::process::exit(0);
+            // キューに残っているデータを書き出してから終了する
+            request_shutdown(app, "tray menu");
         }
         _ => {}
     }
@@ -0,0 +1,404 @@
+///LD・ULDトレイのポケットマップ(顧客からのトレイマップ要求への回答用)
+///トレイIDごとに、各ポケットに入っていたチップのシリアル・BIN・補正量を2次元の格子に並べる
+///フロントエンドのヒートマップ表示と、CSV(一覧形式・格子形式)の出力に使う
+use std::collections::BTreeMap;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::config::load_config;
+use crate::consumable_trace::{csv_field, csv_opt};
+use crate::data_handler::{is_valid_identifier, open_read_connection};
+use crate::db_query::{configured_plcs, table_for_plc, BIN_COLUMNS};
+
+/// トレイの種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraySide {
+    /// LDトレイ(ピックアップ元)
+    Ld,
+    /// ULDトレイ(収納先)
+    Uld,
+}
+
+impl TraySide {
+    /// (トレイID, ポケットX, ポケットY, 補正量X, 補正量Y, 時刻) のカラム
+    fn columns(&self) -> [&'static str; 6] {
+        match self {
+            TraySide::Ld => ["LD_TRAYID", "LD_TRAY_POCKET_X", "LD_TRAY_POCKET_Y", "LD_TRAY_ALIGN_X", "LD_TRAY_ALIGN_Y", "LD_PICKUP_DATE"],
+            TraySide::Uld => ["ULD_TRAYID", "ULD_POCKET_X", "ULD_POCKET_Y", "ULD_POCKET_ALIGN_X", "ULD_POCKET_ALIGN_Y", "ULD_PUT_DATE"],
+        }
+    }
+}
+
+/// CSVの形式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrayCsvLayout {
+    /// 1ポケット1行の一覧
+    List,
+    /// トレイの並びのままシリアルを並べた格子
+    Grid,
+}
+
+/// ポケット1つ分
+#[derive(Serialize, Debug, Clone)]
+pub struct PocketCell {
+    pub x: i64,
+    pub y: i64,
+    pub table_name: String,
+    pub lot_name: Option<String>,
+    pub serial: Option<i64>,
+    /// ユニットごとのBIN(記録があるユニットのみ)
+    pub bins: BTreeMap<String, i64>,
+    /// 記録されたBINが全て良品BINか(未検査の場合は None)
+    pub passed: Option<bool>,
+    /// ポケット補正量
+    pub align_x: Option<i64>,
+    pub align_y: Option<i64>,
+    /// ULDのチップ補正量
+    pub chip_align_x: Option<i64>,
+    pub chip_align_y: Option<i64>,
+    pub date: Option<String>,
+    /// 同じポケットに記録されたチップ数(トレイを使い回した場合は2以上、最新のチップを表示する)
+    pub occupants: usize,
+}
+
+/// トレイのポケットマップ
+#[derive(Serialize, Debug, Clone)]
+pub struct TrayMap {
+    pub tray_id: String,
+    pub side: TraySide,
+    pub lot_names: Vec<String>,
+    /// 格子の範囲(ポケット座標)
+    pub x_min: i64,
+    pub x_max: i64,
+    pub y_min: i64,
+    pub y_max: i64,
+    /// cells[y - y_min][x - x_min](空きポケットは None)
+    pub cells: Vec<Vec<Option<PocketCell>>>,
+    pub chip_count: usize,
+    /// 2つ以上のチップが記録されたポケット数
+    pub duplicate_pockets: usize,
+    /// ポケット座標が無いため格子に置けなかったチップ数
+    pub unplaced: usize,
+}
+
+/// トレイマップの条件
+#[derive(Deserialize, Debug, Clone)]
+pub struct TrayMapQuery {
+    pub tray_id: String,
+    pub side: TraySide,
+    /// 省略時は全テーブル
+    #[serde(default)]
+    pub plc_id: Option<u32>,
+    /// 省略時は全ロット(使い回しのトレイは指定を推奨)
+    #[serde(default)]
+    pub lot_name: Option<String>,
+}
+
+/// トレイマップを作成する
+pub fn build_tray_map(conn: &Connection, query: &TrayMapQuery, pass_bins: &[i64]) -> Result<TrayMap, String> {
+    let tables = match query.plc_id {
+        Some(plc_id) => vec![table_for_plc(plc_id)?],
+        None => configured_plcs()?
+            .into_iter()
+            .map(|plc| plc.table_name)
+            .filter(|table| is_valid_identifier(table))
+            .collect(),
+    };
+    tray_map_from_tables(conn, &tables, query, pass_bins)
+}
+
+/// 指定したテーブルからトレイマップを作成する
+fn tray_map_from_tables(conn: &Connection, tables: &[String], query: &TrayMapQuery, pass_bins: &[i64]) -> Result<TrayMap, String> {
+    let [tray_column, x_column, y_column, align_x_column, align_y_column, date_column] = query.side.columns();
+    let bin_columns: Vec<&str> = BIN_COLUMNS.iter().map(|(_, column)| *column).collect();
+
+    // (x, y) -> (ID, セル)
+    let mut pockets: BTreeMap<(i64, i64), (i64, PocketCell)> = BTreeMap::new();
+    let mut lot_names: Vec<String> = Vec::new();
+    let mut chip_count = 0;
+    let mut unplaced = 0;
+
+    for table in tables {
+        let sql = format!(
+            "SELECT ID, LOT_NAME, SERIAL, \"{x_column}\", \"{y_column}\", \"{align_x_column}\", \"{align_y_column}\",
+                ULD_CHIP_ALIGN_X, ULD_CHIP_ALIGN_Y, \"{date_column}\", {bins}
+            FROM {table}
+            WHERE \"{tray_column}\" = ?1 AND (?2 IS NULL OR LOT_NAME = ?2)",
+            bins = bin_columns.join(", "),
+        );
+        let mut stmt = match conn.prepare(&sql) {
+            Ok(stmt) => stmt,
+            // テーブルが未作成の場合は次のテーブルを探す
+            Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("no such table") => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut rows = stmt.query(params![query.tray_id, query.lot_name]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            chip_count += 1;
+            let id: i64 = row.get(0).map_err(|e| e.to_string())?;
+            let lot_name: Option<String> = row.get(1).map_err(|e| e.to_string())?;
+            if let Some(lot) = &lot_name {
+                if !lot_names.contains(lot) {
+                    lot_names.push(lot.clone());
+                }
+            }
+            let (Some(x), Some(y)) = (
+                row.get::<_, Option<i64>>(3).map_err(|e| e.to_string())?,
+                row.get::<_, Option<i64>>(4).map_err(|e| e.to_string())?,
+            ) else {
+                unplaced += 1;
+                continue;
+            };
+
+            let mut bins = BTreeMap::new();
+            for (i, (unit, _)) in BIN_COLUMNS.iter().enumerate() {
+                if let Some(bin) = row.get::<_, Option<i64>>(10 + i).map_err(|e| e.to_string())? {
+                    bins.insert(unit.to_string(), bin);
+                }
+            }
+            let cell = PocketCell {
+                x,
+                y,
+                table_name: table.clone(),
+                lot_name,
+                serial: row.get(2).map_err(|e| e.to_string())?,
+                passed: if bins.is_empty() { None } else { Some(bins.values().all(|bin| pass_bins.contains(bin))) },
+                bins,
+                align_x: row.get(5).map_err(|e| e.to_string())?,
+                align_y: row.get(6).map_err(|e| e.to_string())?,
+                chip_align_x: if query.side == TraySide::Uld { row.get(7).map_err(|e| e.to_string())? } else { None },
+                chip_align_y: if query.side == TraySide::Uld { row.get(8).map_err(|e| e.to_string())? } else { None },
+                date: row.get(9).map_err(|e| e.to_string())?,
+                occupants: 1,
+            };
+
+            // 同じポケットに複数のチップがある場合は最新(IDが大きい方)を表示する
+            match pockets.get_mut(&(x, y)) {
+                Some((current_id, current)) => {
+                    let occupants = current.occupants + 1;
+                    if id > *current_id {
+                        *current_id = id;
+                        *current = cell;
+                    }
+                    current.occupants = occupants;
+                }
+                None => {
+                    pockets.insert((x, y), (id, cell));
+                }
+            }
+        }
+    }
+
+    if pockets.is_empty() && chip_count == 0 {
+        return Err(format!("Tray {} not found", query.tray_id));
+    }
+
+    // ポケット座標は1始まりなので、格子は1から最大値まで(それより小さい値がある場合はそこから)
+    let x_min = pockets.keys().map(|(x, _)| *x).min().unwrap_or(1).min(1);
+    let y_min = pockets.keys().map(|(_, y)| *y).min().unwrap_or(1).min(1);
+    let x_max = pockets.keys().map(|(x, _)| *x).max().unwrap_or(0).max(x_min);
+    let y_max = pockets.keys().map(|(_, y)| *y).max().unwrap_or(0).max(y_min);
+
+    let duplicate_pockets = pockets.values().filter(|(_, cell)| cell.occupants > 1).count();
+    let mut cells: Vec<Vec<Option<PocketCell>>> = (y_min..=y_max).map(|_| vec![None; (x_max - x_min + 1) as usize]).collect();
+    for ((x, y), (_, cell)) in pockets {
+        cells[(y - y_min) as usize][(x - x_min) as usize] = Some(cell);
+    }
+
+    Ok(TrayMap {
+        tray_id: query.tray_id.clone(),
+        side: query.side,
+        lot_names,
+        x_min,
+        x_max,
+        y_min,
+        y_max,
+        cells,
+        chip_count,
+        duplicate_pockets,
+        unplaced,
+    })
+}
+
+/// トレイマップをCSVにする(Excelで開けるようBOM付き)
+pub fn to_csv(map: &TrayMap, layout: TrayCsvLayout) -> String {
+    let mut csv = String::from("\u{feff}");
+    match layout {
+        TrayCsvLayout::List => {
+            let units: Vec<&str> = BIN_COLUMNS.iter().map(|(unit, _)| *unit).collect();
+            csv.push_str(&format!(
+                "TRAY_ID,POCKET_X,POCKET_Y,TABLE_NAME,LOT_NAME,SERIAL,{},PASSED,ALIGN_X,ALIGN_Y,CHIP_ALIGN_X,CHIP_ALIGN_Y,DATE,OCCUPANTS\r\n",
+                units.iter().map(|unit| format!("{}_BIN", unit)).collect::<Vec<_>>().join(",")
+            ));
+            for cell in map.cells.iter().flatten().flatten() {
+                let mut fields = vec![
+                    csv_field(&map.tray_id),
+                    cell.x.to_string(),
+                    cell.y.to_string(),
+                    csv_field(&cell.table_name),
+                    csv_opt(&cell.lot_name),
+                    csv_opt(&cell.serial),
+                ];
+                fields.extend(units.iter().map(|unit| csv_opt(&cell.bins.get(*unit))));
+                fields.extend([
+                    csv_opt(&cell.passed.map(|p| if p { "OK" } else { "NG" })),
+                    csv_opt(&cell.align_x),
+                    csv_opt(&cell.align_y),
+                    csv_opt(&cell.chip_align_x),
+                    csv_opt(&cell.chip_align_y),
+                    csv_opt(&cell.date),
+                    cell.occupants.to_string(),
+                ]);
+                csv.push_str(&fields.join(","));
+                csv.push_str("\r\n");
+            }
+        }
+        TrayCsvLayout::Grid => {
+            // 1行目はX座標、1列目はY座標。NGのチップはシリアルの後に (NG) を付ける
+            let header: Vec<String> = (map.x_min..=map.x_max).map(|x| x.to_string()).collect();
+            csv.push_str(&format!("{},{}\r\n", csv_field(&map.tray_id), header.join(",")));
+            for (i, row) in map.cells.iter().enumerate() {
+                let mut fields = vec![(map.y_min + i as i64).to_string()];
+                fields.extend(row.iter().map(|cell| match cell {
+                    Some(cell) => {
+                        let serial = cell.serial.map(|s| s.to_string()).unwrap_or_default();
+                        if cell.passed == Some(false) { format!("{} (NG)", serial) } else { serial }
+                    }
+                    None => String::new(),
+                }));
+                csv.push_str(&fields.join(","));
+                csv.push_str("\r\n");
+            }
+        }
+    }
+    csv
+}
+
+/// 良品BINの設定を読み込み、読み出し用の接続でトレイマップを作成する
+async fn load_tray_map(query: TrayMapQuery) -> Result<TrayMap, String> {
+    let pass_bins = load_config()?.quality.pass_bins;
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        build_tray_map(&conn, &query, &pass_bins)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// トレイマップをフロントエンドに返す
+#[command]
+pub async fn get_tray_map(query: TrayMapQuery) -> Result<TrayMap, String> {
+    load_tray_map(query).await
+}
+
+/// トレイマップをCSVファイルに出力する
+#[command]
+pub async fn export_tray_map(query: TrayMapQuery, layout: TrayCsvLayout, path: String) -> Result<(), String> {
+    let map = load_tray_map(query).await?;
+    std::fs::write(&path, to_csv(&map, layout)).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    log::info!("Exported tray map {} to {}", map.tray_id, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASS_BINS: [i64; 1] = [1];
+
+    /// (トレイID, ポケットX, ポケットY, ロット, シリアル, DC1のBIN)
+    type Chip<'a> = (&'a str, Option<i64>, Option<i64>, &'a str, i64, Option<i64>);
+
+    fn table(conn: &Connection, name: &str, chips: &[Chip]) {
+        let bin_columns: Vec<String> = BIN_COLUMNS.iter().map(|(_, column)| format!("{} INTEGER", column)).collect();
+        conn.execute_batch(&format!(
+            "CREATE TABLE {name} (ID INTEGER PRIMARY KEY, LOT_NAME TEXT, SERIAL INTEGER,
+                LD_TRAYID TEXT, LD_TRAY_POCKET_X INTEGER, LD_TRAY_POCKET_Y INTEGER, LD_TRAY_ALIGN_X INTEGER, LD_TRAY_ALIGN_Y INTEGER, LD_PICKUP_DATE TEXT,
+                ULD_TRAYID TEXT, ULD_POCKET_X INTEGER, ULD_POCKET_Y INTEGER, ULD_POCKET_ALIGN_X INTEGER, ULD_POCKET_ALIGN_Y INTEGER, ULD_PUT_DATE TEXT,
+                ULD_CHIP_ALIGN_X INTEGER, ULD_CHIP_ALIGN_Y INTEGER, {})",
+            bin_columns.join(", ")
+        ))
+        .unwrap();
+        for (tray, x, y, lot, serial, bin) in chips {
+            conn.execute(
+                &format!(
+                    "INSERT INTO {name} (LOT_NAME, SERIAL, ULD_TRAYID, ULD_POCKET_X, ULD_POCKET_Y, ULD_CHIP_ALIGN_X, DC1_TEST_BIN, LD_TRAYID)
+                     VALUES (?1, ?2, ?3, ?4, ?5, 7, ?6, 'LD-' || ?3)"
+                ),
+                params![lot, serial, tray, x, y, bin],
+            )
+            .unwrap();
+        }
+    }
+
+    fn query(tray_id: &str, side: TraySide, lot_name: Option<&str>) -> TrayMapQuery {
+        TrayMapQuery { tray_id: tray_id.to_string(), side, plc_id: None, lot_name: lot_name.map(str::to_string) }
+    }
+
+    fn serials(map: &TrayMap) -> Vec<Vec<Option<i64>>> {
+        map.cells.iter().map(|row| row.iter().map(|cell| cell.as_ref().and_then(|c| c.serial)).collect()).collect()
+    }
+
+    #[test]
+    fn grid_starts_at_pocket_one() {
+        let conn = Connection::open_in_memory().unwrap();
+        table(&conn, "t", &[("T1", Some(2), Some(1), "LOT1", 1, Some(1)), ("T1", Some(3), Some(2), "LOT1", 2, Some(5))]);
+        let map = tray_map_from_tables(&conn, &["t".to_string()], &query("T1", TraySide::Uld, None), &PASS_BINS).unwrap();
+        // ポケット座標は1始まりなので、空きの1列目も格子に含める
+        assert_eq!((map.x_min, map.x_max, map.y_min, map.y_max), (1, 3, 1, 2));
+        assert_eq!(serials(&map), vec![vec![None, Some(1), None], vec![None, None, Some(2)]]);
+        let cell = map.cells[1][2].as_ref().unwrap();
+        assert_eq!((cell.x, cell.y), (3, 2));
+        assert_eq!(cell.passed, Some(false));
+        assert_eq!(cell.chip_align_x, Some(7));
+        assert_eq!(map.chip_count, 2);
+    }
+
+    #[test]
+    fn reused_pockets_show_the_latest_chip() {
+        let conn = Connection::open_in_memory().unwrap();
+        table(
+            &conn,
+            "a",
+            &[
+                ("T1", Some(1), Some(1), "LOT1", 1, Some(1)),
+                ("T1", Some(1), Some(1), "LOT2", 2, None),
+                ("T1", None, Some(1), "LOT2", 3, None),
+                ("T2", Some(1), Some(1), "LOT2", 4, None),
+            ],
+        );
+        let tables = vec!["a".to_string(), "missing".to_string()];
+        let map = tray_map_from_tables(&conn, &tables, &query("T1", TraySide::Uld, None), &PASS_BINS).unwrap();
+        assert_eq!(serials(&map), vec![vec![Some(2)]]);
+        let cell = map.cells[0][0].as_ref().unwrap();
+        assert_eq!(cell.occupants, 2);
+        assert_eq!(cell.passed, None);
+        assert_eq!(map.duplicate_pockets, 1);
+        assert_eq!(map.unplaced, 1);
+        assert_eq!(map.lot_names, vec!["LOT1".to_string(), "LOT2".to_string()]);
+
+        // ロットを指定すると使い回し前のチップだけになる
+        let map = tray_map_from_tables(&conn, &tables, &query("T1", TraySide::Uld, Some("LOT1")), &PASS_BINS).unwrap();
+        assert_eq!(serials(&map), vec![vec![Some(1)]]);
+        assert_eq!(map.duplicate_pockets, 0);
+
+        assert!(tray_map_from_tables(&conn, &tables, &query("T9", TraySide::Uld, None), &PASS_BINS).is_err());
+        // LD側はLDのトレイIDで探し、ポケット座標が無いので格子に置かない
+        let map = tray_map_from_tables(&conn, &tables, &query("LD-T1", TraySide::Ld, None), &PASS_BINS).unwrap();
+        assert_eq!((map.chip_count, map.unplaced), (3, 3));
+    }
+
+    #[test]
+    fn grid_csv_marks_failed_chips() {
+        let conn = Connection::open_in_memory().unwrap();
+        table(&conn, "t", &[("T1", Some(1), Some(1), "LOT1", 10, Some(1)), ("T1", Some(2), Some(2), "LOT1", 11, Some(5))]);
+        let map = tray_map_from_tables(&conn, &["t".to_string()], &query("T1", TraySide::Uld, None), &PASS_BINS).unwrap();
+        assert_eq!(to_csv(&map, TrayCsvLayout::Grid), "\u{feff}T1,1,2\r\n1,10,\r\n2,,11 (NG)\r\n");
+        let list = to_csv(&map, TrayCsvLayout::List);
+        assert_eq!(list.lines().count(), 3);
+        assert!(list.lines().nth(2).unwrap().starts_with("T1,2,2,t,LOT1,11,5,"));
+    }
+}
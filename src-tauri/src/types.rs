@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Local};
 
 /// ソケット通信用のデータ構造（旧バージョン用）
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,12 +19,701 @@ pub struct PlcConfig {
     pub plc_ip: String,
     pub plc_port: u16,
     pub pc_ip: String,
+    /// 受信データの出力先(sqlite, postgres, jsonl, csv, mqtt)
+    #[serde(default = "default_plc_sinks")]
+    pub sinks: Vec<String>,
+    /// 受信データの検証に使うスキーマ(省略時は validation.rules で MACHINE・TYPE から選ぶ)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+}
+
+pub fn default_plc_sinks() -> Vec<String> { vec!["sqlite".to_string()] }
+
+/// DB書き込みスレッドの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbWriterConfig {
+    /// 1トランザクションにまとめる最大リクエスト数
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// 1トランザクションを開いておく最大時間(ms)
+    #[serde(default = "default_max_latency_ms")]
+    pub max_latency_ms: u64,
+    /// prepare_cachedで保持するステートメント数
+    #[serde(default = "default_statement_cache_capacity")]
+    pub statement_cache_capacity: usize,
+}
+
+fn default_batch_size() -> usize { 500 }
+fn default_max_latency_ms() -> u64 { 200 }
+fn default_statement_cache_capacity() -> usize { 128 }
+
+impl Default for DbWriterConfig {
+    fn default() -> Self {
+        DbWriterConfig {
+            batch_size: default_batch_size(),
+            max_latency_ms: default_max_latency_ms(),
+            statement_cache_capacity: default_statement_cache_capacity(),
+        }
+    }
+}
+
+/// DBキューが満杯になった時の動作
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// 空きが出るまで受信側を待たせる(TCP受信が遅くなる)
+    #[default]
+    Block,
+    /// あふれた分をファイルに退避し、キューが空いたら書き戻す
+    Spill,
+    /// 最も古いリクエストを破棄して件数をカウントする
+    DropOldest,
+}
+
+/// DB書き込みキューの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbQueueConfig {
+    /// キューに保持する最大リクエスト数
+    #[serde(default = "default_queue_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+    /// Spill時の退避先ファイル
+    #[serde(default = "default_spill_path")]
+    pub spill_path: String,
+}
+
+fn default_queue_capacity() -> usize { 10000 }
+fn default_spill_path() -> String { "spill/db_spill.jsonl".to_string() }
+
+impl Default for DbQueueConfig {
+    fn default() -> Self {
+        DbQueueConfig {
+            capacity: default_queue_capacity(),
+            overflow_policy: OverflowPolicy::default(),
+            spill_path: default_spill_path(),
+        }
+    }
+}
+
+/// 終了処理の設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShutdownConfig {
+    /// DBキューの書き出しを待つ最大時間(ms)
+    #[serde(default = "default_drain_timeout_ms")]
+    pub drain_timeout_ms: u64,
+}
+
+fn default_drain_timeout_ms() -> u64 { 10000 }
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain_timeout_ms: default_drain_timeout_ms(),
+        }
+    }
+}
+
+/// Prometheus用 /metrics エンドポイントの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_metrics_bind_addr")]
+    pub bind_addr: String,
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
+}
+
+fn default_metrics_bind_addr() -> String { "127.0.0.1".to_string() }
+fn default_metrics_port() -> u16 { 9898 }
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            bind_addr: default_metrics_bind_addr(),
+            port: default_metrics_port(),
+        }
+    }
+}
+
+/// MES等向けの読み出し専用HTTP APIの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_api_bind_addr")]
+    pub bind_addr: String,
+    #[serde(default = "default_api_port")]
+    pub port: u16,
+    /// Authorization: Bearer で送られるトークン(空の場合はAPIを起動しない)
+    #[serde(default)]
+    pub token: String,
+    /// /ws/live の購読者ごとに溜められるイベント数(超えた分は古いものから読み飛ばす)
+    #[serde(default = "default_live_feed_buffer")]
+    pub live_feed_buffer: usize,
+}
+
+fn default_api_bind_addr() -> String { "127.0.0.1".to_string() }
+fn default_api_port() -> u16 { 8080 }
+fn default_live_feed_buffer() -> usize { 1024 }
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            enabled: false,
+            bind_addr: default_api_bind_addr(),
+            port: default_api_port(),
+            token: String::new(),
+            live_feed_buffer: default_live_feed_buffer(),
+        }
+    }
+}
+
+/// 良品判定の設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QualityConfig {
+    /// 良品とみなすBIN番号
+    #[serde(default = "default_pass_bins")]
+    pub pass_bins: Vec<i64>,
+}
+
+fn default_pass_bins() -> Vec<i64> { vec![1] }
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityConfig {
+            pass_bins: default_pass_bins(),
+        }
+    }
+}
+
+/// MQTT送信の設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MqttConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_mqtt_host")]
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// ユニット情報の送信先({machine} {unit} {kind} {plc_id} {lot} を置き換える)
+    #[serde(default = "default_mqtt_topic_template")]
+    pub topic_template: String,
+    /// 接続状態の送信先(retainで送信する、{plc_id} を置き換える)
+    #[serde(default = "default_mqtt_status_topic_template")]
+    pub status_topic_template: String,
+    /// 本アプリ自体の接続状態(online/offline、切断時はLast Willでofflineになる)
+    #[serde(default = "default_mqtt_client_status_topic")]
+    pub client_status_topic: String,
+    /// 0, 1, 2 のいずれか
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+    #[serde(default = "default_mqtt_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    /// ブローカーに繋がらない間に溜めておける送信数(超えた分は破棄する)
+    #[serde(default = "default_mqtt_offline_buffer")]
+    pub offline_buffer: usize,
+}
+
+fn default_mqtt_host() -> String { "127.0.0.1".to_string() }
+fn default_mqtt_port() -> u16 { 1883 }
+fn default_mqtt_client_id() -> String { "plc-data-collector".to_string() }
+fn default_mqtt_topic_template() -> String { "site/line/{machine}/{unit}/{kind}".to_string() }
+fn default_mqtt_status_topic_template() -> String { "site/line/plc/{plc_id}/status".to_string() }
+fn default_mqtt_client_status_topic() -> String { "site/line/collector/status".to_string() }
+fn default_mqtt_qos() -> u8 { 1 }
+fn default_mqtt_keep_alive_secs() -> u64 { 30 }
+fn default_mqtt_offline_buffer() -> usize { 10000 }
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            enabled: false,
+            host: default_mqtt_host(),
+            port: default_mqtt_port(),
+            client_id: default_mqtt_client_id(),
+            username: None,
+            password: None,
+            topic_template: default_mqtt_topic_template(),
+            status_topic_template: default_mqtt_status_topic_template(),
+            client_status_topic: default_mqtt_client_status_topic(),
+            qos: default_mqtt_qos(),
+            keep_alive_secs: default_mqtt_keep_alive_secs(),
+            offline_buffer: default_mqtt_offline_buffer(),
+        }
+    }
+}
+
+/// JSON Lines出力の設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonlSinkConfig {
+    /// 出力先ディレクトリ(テーブル・日付ごとにファイルを分ける)
+    #[serde(default = "default_jsonl_dir")]
+    pub dir: String,
+}
+
+fn default_jsonl_dir() -> String { "output/jsonl".to_string() }
+
+impl Default for JsonlSinkConfig {
+    fn default() -> Self {
+        JsonlSinkConfig {
+            dir: default_jsonl_dir(),
+        }
+    }
+}
+
+/// CSV出力の設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CsvSinkConfig {
+    /// 出力先ディレクトリ
+    #[serde(default = "default_csv_dir")]
+    pub dir: String,
+    /// 1ファイルの最大サイズ(超えたら新しいファイルに切り替える)
+    #[serde(default = "default_csv_max_file_bytes")]
+    pub max_file_bytes: u64,
+}
+
+fn default_csv_dir() -> String { "output/csv".to_string() }
+fn default_csv_max_file_bytes() -> u64 { 50 * 1024 * 1024 }
+
+impl Default for CsvSinkConfig {
+    fn default() -> Self {
+        CsvSinkConfig {
+            dir: default_csv_dir(),
+            max_file_bytes: default_csv_max_file_bytes(),
+        }
+    }
+}
+
+/// 出力先への書き込みに失敗した場合の再送設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SinkRetryConfig {
+    /// 1件あたりの最大試行回数(超えたら破棄する、SQLiteは破棄せず再送し続ける)
+    #[serde(default = "default_sink_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_sink_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_sink_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// 出力先ごとに再送待ちで保持する最大件数(超えたら古いものから破棄する、SQLiteは退避ファイルに書き出す)
+    #[serde(default = "default_sink_retry_buffer")]
+    pub buffer: usize,
+}
+
+fn default_sink_max_attempts() -> u32 { 5 }
+fn default_sink_initial_backoff_ms() -> u64 { 1000 }
+fn default_sink_max_backoff_ms() -> u64 { 60000 }
+fn default_sink_retry_buffer() -> usize { 10000 }
+
+impl Default for SinkRetryConfig {
+    fn default() -> Self {
+        SinkRetryConfig {
+            max_attempts: default_sink_max_attempts(),
+            initial_backoff_ms: default_sink_initial_backoff_ms(),
+            max_backoff_ms: default_sink_max_backoff_ms(),
+            buffer: default_sink_retry_buffer(),
+        }
+    }
+}
+
+/// PostgreSQL保存先の設定(PLCの sinks に "postgres" を指定したときに使う)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PostgresConfig {
+    /// 接続文字列(例: host=localhost port=5432 user=postgres password=postgres dbname=plc_data)
+    #[serde(default = "default_postgres_url")]
+    pub url: String,
+    #[serde(default = "default_postgres_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+}
+
+fn default_postgres_url() -> String { "host=localhost port=5432 user=postgres password=postgres dbname=plc_data".to_string() }
+fn default_postgres_connect_timeout_secs() -> u64 { 5 }
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        PostgresConfig {
+            url: default_postgres_url(),
+            connect_timeout_secs: default_postgres_connect_timeout_secs(),
+        }
+    }
+}
+
+/// 中央サーバーへの複製(ストア&フォワード)の設定
+/// ローカルのSQLiteへの書き込みはそのまま行い、未送信の行を変更ログから拾って送る
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 送信先の種類("http" または "postgres"。postgres は postgres セクションの接続先を使う)
+    #[serde(default = "default_replication_target")]
+    pub target: String,
+    /// target が "http" の場合の送信先URL(JSONをPOSTする)
+    #[serde(default = "default_replication_url")]
+    pub url: String,
+    /// 指定した場合は Authorization: Bearer <token> を付けて送る
+    #[serde(default)]
+    pub token: Option<String>,
+    /// 送信元を区別するための名前(複数ラインから同じサーバーに送る場合に使う)
+    #[serde(default = "default_replication_source")]
+    pub source: String,
+    /// 1回に送る最大行数
+    #[serde(default = "default_replication_batch_size")]
+    pub batch_size: usize,
+    /// 未送信の行が無い場合に次を確認するまでの間隔
+    #[serde(default = "default_replication_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_replication_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default = "default_replication_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_replication_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_replication_target() -> String { "http".to_string() }
+fn default_replication_url() -> String { "http://127.0.0.1:9000/replication".to_string() }
+fn default_replication_source() -> String { "plc-data-collector".to_string() }
+fn default_replication_batch_size() -> usize { 500 }
+fn default_replication_interval_ms() -> u64 { 2000 }
+fn default_replication_request_timeout_secs() -> u64 { 30 }
+fn default_replication_initial_backoff_ms() -> u64 { 1000 }
+fn default_replication_max_backoff_ms() -> u64 { 300000 }
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        ReplicationConfig {
+            enabled: false,
+            target: default_replication_target(),
+            url: default_replication_url(),
+            token: None,
+            source: default_replication_source(),
+            batch_size: default_replication_batch_size(),
+            interval_ms: default_replication_interval_ms(),
+            request_timeout_secs: default_replication_request_timeout_secs(),
+            initial_backoff_ms: default_replication_initial_backoff_ms(),
+            max_backoff_ms: default_replication_max_backoff_ms(),
+        }
+    }
+}
+
+/// スキーマの選択条件(省略した条件は全ての値に一致する)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SchemaRule {
+    /// schema_dir 内のスキーマファイル名
+    pub schema: String,
+    /// 受信データの MACHINE
+    #[serde(default)]
+    pub machine: Option<String>,
+    /// 受信データの TYPE
+    #[serde(default)]
+    pub type_name: Option<String>,
+}
+
+/// 受信データの検証設定
+/// 検証に失敗した受信データは登録せずに quarantine テーブルへ退避する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// JSON Schemaファイルを置くディレクトリ(相対パスは config.json のディレクトリから)
+    #[serde(default = "default_schema_dir")]
+    pub schema_dir: String,
+    /// 上から順に見て最初に一致したスキーマを使う(一致しない場合は型の検証のみ行う)
+    #[serde(default)]
+    pub rules: Vec<SchemaRule>,
+    /// 退避時に記録する最大エラー数
+    #[serde(default = "default_validation_max_issues")]
+    pub max_issues: usize,
+}
+
+fn default_schema_dir() -> String { "schemas".to_string() }
+fn default_validation_max_issues() -> usize { 20 }
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            enabled: false,
+            schema_dir: default_schema_dir(),
+            rules: Vec::new(),
+            max_issues: default_validation_max_issues(),
+        }
+    }
+}
+
+/// 測定値の規格ルール
+/// column は登録先のカラム名で、* を1つ含めると前方・後方一致になる(例: *_CHIP_ALIGN_X)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LimitRule {
+    pub column: String,
+    /// 受信データの MACHINE(省略時は全て)
+    #[serde(default)]
+    pub machine: Option<String>,
+    /// 受信データの TYPE(省略時は全て)
+    #[serde(default)]
+    pub type_name: Option<String>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// 連続して同じ方向に増加・減少したら違反とする点数(例: 7)
+    #[serde(default)]
+    pub trend_points: Option<usize>,
+    /// 連続して中心値の片側に並んだら違反とする点数(例: 9)
+    #[serde(default)]
+    pub run_points: Option<usize>,
+    /// run_points の中心値(省略時は min と max の中間)
+    #[serde(default)]
+    pub center: Option<f64>,
+}
+
+/// 測定値の規格チェックの設定
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LimitsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub rules: Vec<LimitRule>,
+}
+
+/// ロットの追跡設定
+/// 受信データの LOT・TYPE が切り替わったら前のロットを完了とし、新しいロットを開始する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LotTrackingConfig {
+    #[serde(default = "default_lot_tracking_enabled")]
+    pub enabled: bool,
+    /// ロット完了時に歩留まり集計(JSON)とチップ一覧(CSV)を出力する
+    #[serde(default)]
+    pub auto_export: bool,
+    /// auto_export の出力先ディレクトリ
+    #[serde(default = "default_lot_export_dir")]
+    pub export_dir: String,
+}
+
+fn default_lot_tracking_enabled() -> bool { true }
+fn default_lot_export_dir() -> String { "output/lots".to_string() }
+
+impl Default for LotTrackingConfig {
+    fn default() -> Self {
+        LotTrackingConfig {
+            enabled: default_lot_tracking_enabled(),
+            auto_export: false,
+            export_dir: default_lot_export_dir(),
+        }
+    }
+}
+
+/// チップの更新履歴の設定
+/// 有効にするとユニット情報ごとの登録内容を {TABLE}_history に受信順で追加する(チップの行はこれまで通り最新の値)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChipHistoryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// テーブルごとの保持期間
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionPolicy {
+    /// PLCごとのテーブル名、または machine_events・quarantine・limit_violations
+    pub table: String,
+    pub keep_days: u32,
+}
+
+/// 古いロットの保管・削除の設定
+/// 最終受信から保持期間を過ぎたロットと設備イベント・隔離データ・規格違反を月ごとの保管用DBファイルに移し、DBを縮小する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionConfig {
+    /// 定期実行するか(無効でも手動では実行できる)
+    #[serde(default)]
+    pub enabled: bool,
+    /// policies に無いテーブルの保持期間(日)
+    #[serde(default = "default_keep_days")]
+    pub keep_days: u32,
+    #[serde(default)]
+    pub policies: Vec<RetentionPolicy>,
+    /// 保管用DBファイルの出力先
+    #[serde(default = "default_archive_dir")]
+    pub archive_dir: String,
+    /// 定期実行の間隔(時間)
+    #[serde(default = "default_retention_interval_hours")]
+    pub interval_hours: u64,
+    /// 保管後に incremental_vacuum でDBファイルを縮小する(有効な場合、起動時にDBを auto_vacuum=INCREMENTAL にする)
+    #[serde(default = "default_retention_vacuum")]
+    pub vacuum: bool,
+}
+
+fn default_keep_days() -> u32 { 180 }
+fn default_archive_dir() -> String { "archive".to_string() }
+fn default_retention_interval_hours() -> u64 { 24 }
+fn default_retention_vacuum() -> bool { true }
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            enabled: false,
+            keep_days: default_keep_days(),
+            policies: Vec::new(),
+            archive_dir: default_archive_dir(),
+            interval_hours: default_retention_interval_hours(),
+            vacuum: default_retention_vacuum(),
+        }
+    }
+}
+
+/// 時刻の扱いの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeConfig {
+    /// 拠点のタイムゾーン(IANA名。受信時刻の記録とシフトの集計に使う)
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// PLCの時計のタイムゾーン(省略時は timezone と同じ)
+    #[serde(default)]
+    pub plc_timezone: Option<String>,
+    /// PLCの日時の書式(上から順に試す。オフセット付きのRFC 3339は常に受け付ける)
+    #[serde(default = "default_plc_date_formats")]
+    pub plc_date_formats: Vec<String>,
+    /// PLCの日時と受信時刻の差がこれを超えたら時計のずれとして通知する
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: f64,
+}
+
+fn default_timezone() -> String { "Asia/Tokyo".to_string() }
+pub fn default_plc_date_formats() -> Vec<String> {
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y/%m/%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y%m%d%H%M%S"]
+        .iter()
+        .map(|format| format.to_string())
+        .collect()
+}
+fn default_max_clock_skew_secs() -> f64 { 120.0 }
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            timezone: default_timezone(),
+            plc_timezone: None,
+            plc_date_formats: default_plc_date_formats(),
+            max_clock_skew_secs: default_max_clock_skew_secs(),
+        }
+    }
+}
+
+/// シフト(end が start 以前の場合は翌日の end までとする)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShiftConfig {
+    pub name: String,
+    /// 開始時刻(HH:MM)
+    pub start: String,
+    /// 終了時刻(HH:MM)
+    pub end: String,
+    /// 開始日の曜日(mon, tue, ... sun。省略時は毎日)
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+/// 理想サイクルタイム(上から順に見て最初に一致したものを使う。省略した条件は全てに一致する)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CycleTarget {
+    /// チップの MACHINE_NAME
+    #[serde(default)]
+    pub machine: Option<String>,
+    /// チップの TYPE_NAME
+    #[serde(default)]
+    pub type_name: Option<String>,
+    pub ideal_cycle_secs: f64,
+}
+
+/// 生産実績(UPH・サイクルタイム・稼働率)の集計設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductionConfig {
+    #[serde(default = "default_shifts")]
+    pub shifts: Vec<ShiftConfig>,
+    /// ULDへの払い出し間隔がこれを超えたら停止とみなす(サイクルタイムにも含めない)
+    #[serde(default = "default_stop_threshold_secs")]
+    pub stop_threshold_secs: f64,
+    /// サイクルタイム分布の刻み幅
+    #[serde(default = "default_cycle_bucket_secs")]
+    pub cycle_bucket_secs: f64,
+    /// 性能稼働率の計算に使う理想サイクルタイム(一致するものが無いチップがあれば性能稼働率は計算しない)
+    #[serde(default)]
+    pub targets: Vec<CycleTarget>,
+}
+
+fn default_shifts() -> Vec<ShiftConfig> {
+    vec![
+        ShiftConfig { name: "day".to_string(), start: "08:00".to_string(), end: "20:00".to_string(), days: Vec::new() },
+        ShiftConfig { name: "night".to_string(), start: "20:00".to_string(), end: "08:00".to_string(), days: Vec::new() },
+    ]
+}
+fn default_stop_threshold_secs() -> f64 { 300.0 }
+fn default_cycle_bucket_secs() -> f64 { 1.0 }
+
+impl Default for ProductionConfig {
+    fn default() -> Self {
+        ProductionConfig {
+            shifts: default_shifts(),
+            stop_threshold_secs: default_stop_threshold_secs(),
+            cycle_bucket_secs: default_cycle_bucket_secs(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+/// 出力先ごとの設定(どの出力先を使うかはPLCごとに sinks で指定する)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SinksConfig {
+    #[serde(default)]
+    pub jsonl: JsonlSinkConfig,
+    #[serde(default)]
+    pub csv: CsvSinkConfig,
+    #[serde(default)]
+    pub retry: SinkRetryConfig,
 }
 
 /// 設定ファイル全体の構造
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
     pub plcs: Vec<PlcConfig>,
+    #[serde(default)]
+    pub db_writer: DbWriterConfig,
+    #[serde(default)]
+    pub db_queue: DbQueueConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub quality: QualityConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub sinks: SinksConfig,
+    #[serde(default)]
+    pub postgres: PostgresConfig,
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub lots: LotTrackingConfig,
+    #[serde(default)]
+    pub production: ProductionConfig,
+    #[serde(default)]
+    pub time: TimeConfig,
+    #[serde(default)]
+    pub history: ChipHistoryConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 /// PLC接続情報を管理する構造体
@@ -35,5 +725,54 @@ pub struct PlcConnection {
     pub plc_port: u16,
     pub pc_ip: String,
     pub is_connected: bool,
+    pub stats: PlcStats,
+}
+
+/// PLCごとの通信統計(再接続しても引き継ぐ)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PlcStats {
+    pub bytes_received: u64,
+    pub frames_received: u64,
+    pub parse_failures: u64,
+    pub db_failures: u64,
+    /// 検証に失敗して退避したフレーム数
+    pub quarantined: u64,
+    /// 規格外の測定値の件数
+    pub limit_violations: u64,
+    /// 直近の日時付きユニット情報での PLCの日時 - 受信時刻(ms)
+    pub clock_skew_ms: Option<i64>,
+    pub reconnect_count: u32,
+    pub last_frame_at: Option<DateTime<Local>>,
+    pub connected_since: Option<DateTime<Local>>,
+    /// 現在の接続以降に受信したフレーム数(平均フレーム数の計算用)
+    pub frames_since_connect: u64,
+}
+
+impl PlcStats {
+    /// 現在の接続以降の1分あたりの平均フレーム数
+    pub fn frames_per_minute(&self) -> f64 {
+        match self.connected_since {
+            Some(since) => {
+                let minutes = (Local::now() - since).num_milliseconds() as f64 / 60000.0;
+                if minutes > 0.0 {
+                    self.frames_since_connect as f64 / minutes
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// PLCの接続状態と統計(フロントエンド返却用)
+#[derive(Serialize, Debug, Clone)]
+pub struct PlcStatus {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub is_connected: bool,
+    pub frames_per_minute: f64,
+    #[serde(flatten)]
+    pub stats: PlcStats,
 }
 
@@ -0,0 +1,316 @@
+///受信データの検証(DB書き込みスレッドで出力先に渡す前に行う)
+///PLCごとの設定、または受信データの MACHINE・TYPE で選んだJSON Schemaと、ユニット情報の型(records)で検証する
+///失敗した受信データは quarantine テーブルに退避し、plc-frame-quarantined イベントでフロントエンドに通知する
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use jsonschema::Validator;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::config::{get_config_path, load_config};
+use crate::output_sink::IngestFrame;
+use crate::types::{Config, SchemaRule, ValidationConfig};
+
+/// 検証エラー1件
+#[derive(Serialize, Debug, Clone)]
+pub struct ValidationIssue {
+    /// 受信データ内の位置(JSON Pointer。例: /U2_TS_1/bin)
+    pub path: String,
+    pub message: String,
+}
+
+/// 退避した受信データの報告
+#[derive(Serialize, Debug, Clone)]
+pub struct QuarantineReport {
+    pub plc_id: u32,
+    pub table_name: String,
+    pub timestamp: String,
+    /// 使用したスキーマ(型の検証のみの場合は None)
+    pub schema: Option<String>,
+    pub machine: String,
+    pub lot: String,
+    pub type_name: String,
+    pub issues: Vec<ValidationIssue>,
+    /// max_issues を超えて省略したエラー数
+    pub omitted: usize,
+}
+
+impl QuarantineReport {
+    /// ユニット情報に分解できなかった受信データ(JSONでない・MACHINE・LOT・TYPE が無いなど)の報告
+    pub fn parse_failure(plc_id: u32, table_name: &str, timestamp: &str, error: String) -> Self {
+        QuarantineReport {
+            plc_id,
+            table_name: table_name.to_string(),
+            timestamp: timestamp.to_string(),
+            schema: None,
+            machine: String::new(),
+            lot: String::new(),
+            type_name: String::new(),
+            issues: vec![ValidationIssue {
+                path: "/".to_string(),
+                message: error,
+            }],
+            omitted: 0,
+        }
+    }
+}
+
+/// 受信データの検証
+pub struct FrameValidator {
+    config: ValidationConfig,
+    /// 読み込んだスキーマ(読み込みに失敗したものは None にして再読み込みしない)
+    schemas: HashMap<String, Option<Validator>>,
+    /// PLCごとのスキーマ指定
+    plc_schemas: HashMap<u32, Option<String>>,
+    reports: UnboundedSender<QuarantineReport>,
+}
+
+impl FrameValidator {
+    pub fn from_config(config: &Config, reports: UnboundedSender<QuarantineReport>) -> Self {
+        if config.validation.enabled {
+            log::info!("Frame validation enabled ({} schema rules)", config.validation.rules.len());
+        }
+        FrameValidator {
+            config: config.validation.clone(),
+            schemas: HashMap::new(),
+            plc_schemas: config.plcs.iter().map(|plc| (plc.id, plc.schema.clone())).collect(),
+            reports,
+        }
+    }
+
+    /// 受信データを検証する(失敗した場合は退避用の報告を返す)
+    pub fn validate(&mut self, input: &IngestFrame, message: &str) -> Result<(), Box<QuarantineReport>> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let frame = &input.frame;
+        let schema_name = self.select_schema(input.plc_id, &frame.machine_name, &frame.type_name);
+
+        let mut issues = Vec::new();
+        if let Some(validator) = schema_name.as_deref().and_then(|name| self.schema(name)) {
+            match serde_json::from_str::<Value>(message) {
+                Ok(instance) => issues.extend(validator.iter_errors(&instance).map(|e| {
+                    let path = e.instance_path.to_string();
+                    ValidationIssue {
+                        path: if path.is_empty() { "/".to_string() } else { path },
+                        message: e.to_string(),
+                    }
+                })),
+                Err(e) => issues.push(ValidationIssue {
+                    path: "/".to_string(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        // ユニット情報の型の検証(登録時と同じ変換で確認する)
+        for (record, writes) in input.unit_writes() {
+            if let Err(e) = writes {
+                issues.push(ValidationIssue {
+                    path: format!("/{}", record.key),
+                    message: e.clone(),
+                });
+            }
+        }
+
+        if issues.is_empty() {
+            return Ok(());
+        }
+        let max_issues = self.config.max_issues.max(1);
+        let omitted = issues.len().saturating_sub(max_issues);
+        issues.truncate(max_issues);
+        Err(Box::new(QuarantineReport {
+            plc_id: input.plc_id,
+            table_name: input.table_name.clone(),
+            timestamp: input.timestamp.clone(),
+            schema: schema_name,
+            machine: frame.machine_name.clone(),
+            lot: frame.lot_name.clone(),
+            type_name: frame.type_name.clone(),
+            issues,
+            omitted,
+        }))
+    }
+
+    /// 退避したことをフロントエンドに通知する
+    pub fn notify(&self, report: QuarantineReport) {
+        // 受信側が無い場合(起動前・終了後)は通知しない
+        let _ = self.reports.send(report);
+    }
+
+    /// 使用するスキーマ名を選ぶ(PLCの指定を優先し、無ければ rules を上から順に見る)
+    fn select_schema(&mut self, plc_id: u32, machine: &str, type_name: &str) -> Option<String> {
+        // 起動後に追加されたPLCは設定ファイルを読み直す
+        let plc_schema = self.plc_schemas.entry(plc_id).or_insert_with(|| {
+            load_config()
+                .ok()
+                .and_then(|config| config.plcs.into_iter().find(|plc| plc.id == plc_id))
+                .and_then(|plc| plc.schema)
+        });
+        if let Some(schema) = plc_schema {
+            return Some(schema.clone());
+        }
+        self.config
+            .rules
+            .iter()
+            .find(|rule| rule_matches(rule, machine, type_name))
+            .map(|rule| rule.schema.clone())
+    }
+
+    fn schema(&mut self, name: &str) -> Option<&Validator> {
+        let schema_dir = &self.config.schema_dir;
+        self.schemas
+            .entry(name.to_string())
+            .or_insert_with(|| match load_schema(schema_dir, name) {
+                Ok(validator) => {
+                    log::info!("Schema {} loaded", name);
+                    Some(validator)
+                }
+                Err(e) => {
+                    log::error!("Failed to load schema {} (only record types are validated): {}", name, e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+fn rule_matches(rule: &SchemaRule, machine: &str, type_name: &str) -> bool {
+    rule.machine.as_deref().map_or(true, |m| m == machine) && rule.type_name.as_deref().map_or(true, |t| t == type_name)
+}
+
+/// スキーマファイルを読み込んでコンパイルする
+fn load_schema(schema_dir: &str, name: &str) -> Result<Validator, String> {
+    if name.contains(['/', '\\']) || name.contains("..") {
+        return Err(format!("Invalid schema name: {}", name));
+    }
+    let mut dir = PathBuf::from(schema_dir);
+    if dir.is_relative() {
+        if let Some(config_dir) = get_config_path().ok().as_deref().and_then(Path::parent) {
+            dir = config_dir.join(dir);
+        }
+    }
+    let path = dir.join(name);
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let schema: Value = serde_json::from_str(&content).map_err(|e| format!("Failed to parse {:?}: {}", path, e))?;
+    jsonschema::validator_for(&schema).map_err(|e| format!("Invalid schema {:?}: {}", path, e))
+}
+
+/// 退避の報告をフロントエンドに通知する(plc-frame-quarantined)
+pub async fn forward_reports(app: AppHandle, mut reports: UnboundedReceiver<QuarantineReport>) {
+    while let Some(report) = reports.recv().await {
+        if let Err(e) = app.emit("plc-frame-quarantined", &report) {
+            log::error!("Failed to emit quarantine report: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::frame::parse_frame;
+    use crate::regist_data_to_db::build_writes;
+
+    fn ingest(plc_id: u32, message: &str) -> IngestFrame {
+        let mut input = IngestFrame {
+            plc_id,
+            table_name: "plc1".to_string(),
+            timestamp: "2026-01-15 08:30:00".to_string(),
+            received_at: clock::now(),
+            frame: parse_frame(message).unwrap(),
+            message: message.to_string(),
+            sinks: None,
+            writes: Vec::new(),
+        };
+        input.writes = input.frame.records.iter().map(|record| build_writes(&input, record)).collect();
+        input
+    }
+
+    /// PLC 1 はスキーマ指定なし、PLC 2 は schema.json を使う
+    fn validator(schema_dir: &Path, rules: Vec<SchemaRule>, max_issues: usize) -> FrameValidator {
+        let (reports, _) = tokio::sync::mpsc::unbounded_channel();
+        FrameValidator {
+            config: ValidationConfig {
+                enabled: true,
+                schema_dir: schema_dir.to_string_lossy().to_string(),
+                rules,
+                max_issues,
+            },
+            schemas: HashMap::new(),
+            plc_schemas: HashMap::from([(1, None), (2, Some("schema.json".to_string()))]),
+            reports,
+        }
+    }
+
+    fn schema_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("validation_test_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("schema.json"),
+            r#"{"type": "object", "required": ["MACHINE", "U2_PH_1"], "properties": {"LOT": {"type": "string", "pattern": "^L"}}}"#,
+        )
+        .unwrap();
+        dir
+    }
+
+    const VALID: &str = r#"{"MACHINE": "M1", "LOT": "L001", "TYPE": "T1", "U2_PH_1": {"serial": 1, "ax": 10, "ay": -3}}"#;
+
+    #[test]
+    fn record_types_are_checked_without_schema() {
+        let dir = schema_dir("types");
+        let mut validator = validator(&dir, Vec::new(), 20);
+        assert!(validator.validate(&ingest(1, VALID), VALID).is_ok());
+
+        let message = r#"{"MACHINE": "M1", "LOT": "L001", "TYPE": "T1", "U2_PH_1": {"serial": "x"}, "U3_PH_1": {"serial": 1, "az": 1}}"#;
+        let report = validator.validate(&ingest(1, message), message).unwrap_err();
+        assert_eq!(report.schema, None);
+        assert_eq!((report.machine.as_str(), report.lot.as_str()), ("M1", "L001"));
+        let paths: Vec<&str> = report.issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(paths, vec!["/U2_PH_1", "/U3_PH_1"]);
+        // 未知のフィールドも型の誤りとして扱う
+        assert!(report.issues[1].message.contains("az"));
+    }
+
+    #[test]
+    fn plc_schema_and_rules() {
+        let dir = schema_dir("schema");
+        let rules = vec![SchemaRule { schema: "schema.json".to_string(), machine: Some("M9".to_string()), type_name: None }];
+        let mut validator = validator(&dir, rules, 20);
+
+        // PLC 2 はスキーマで検証する
+        assert!(validator.validate(&ingest(2, VALID), VALID).is_ok());
+        let message = r#"{"MACHINE": "M1", "LOT": "X001", "TYPE": "T1"}"#;
+        let report = validator.validate(&ingest(2, message), message).unwrap_err();
+        assert_eq!(report.schema.as_deref(), Some("schema.json"));
+        assert!(report.issues.iter().any(|issue| issue.path == "/"));
+        assert!(report.issues.iter().any(|issue| issue.path == "/LOT"));
+
+        // PLC 1 は MACHINE が M9 の場合だけ rules のスキーマを使う
+        assert!(validator.validate(&ingest(1, message), message).is_ok());
+        let message = r#"{"MACHINE": "M9", "LOT": "X001", "TYPE": "T1"}"#;
+        assert!(validator.validate(&ingest(1, message), message).is_err());
+    }
+
+    #[test]
+    fn issues_are_truncated() {
+        let dir = schema_dir("truncate");
+        let mut validator = validator(&dir, Vec::new(), 1);
+        let message = r#"{"MACHINE": "M1", "LOT": "L001", "TYPE": "T1", "U2_PH_1": {"serial": "x"}, "U3_PH_1": {"serial": "y"}, "U4_PH_1": {}}"#;
+        let report = validator.validate(&ingest(1, message), message).unwrap_err();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.omitted, 2);
+    }
+
+    #[test]
+    fn missing_or_invalid_schema_falls_back_to_types() {
+        let dir = schema_dir("missing");
+        std::fs::remove_file(dir.join("schema.json")).unwrap();
+        let mut validator = validator(&dir, Vec::new(), 20);
+        assert!(validator.validate(&ingest(2, VALID), VALID).is_ok());
+        assert!(load_schema(dir.to_str().unwrap(), "../schema.json").is_err());
+        assert!(load_schema(dir.to_str().unwrap(), "sub/schema.json").is_err());
+    }
+}
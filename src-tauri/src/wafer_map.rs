@@ -0,0 +1,564 @@
+///ウェハマップ(ハンドラーの検査結果とウェハ上の位置の対応付け用)
+///LDで記録したウェハ番号・座標(WANO, WAX, WAY)と検査BINから、ロット内のウェハごとのBINマップを作成する
+///出力形式は E142 形式のXML、SINF形式のテキスト、SVG・PNG画像
+///座標は左上が原点で、WAX は右、WAY は下に向かって増える
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::config::load_config;
+use crate::data_handler::open_read_connection;
+use crate::db_query::{table_for_plc, BIN_COLUMNS};
+
+/// PNGの1ダイの大きさ(ピクセル、うち1ピクセルは隙間)
+const PNG_DIE_PIXELS: u32 = 8;
+/// SVGの1ダイの大きさ
+const SVG_DIE_SIZE: u32 = 12;
+/// E142・SINFで未検査・ダイ無しを表すBINコード
+const NULL_BIN_CODE: &str = "FF";
+
+/// 出力形式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WaferMapFormat {
+    E142,
+    Sinf,
+    Svg,
+    Png,
+}
+
+impl WaferMapFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            WaferMapFormat::E142 => "xml",
+            WaferMapFormat::Sinf => "sinf",
+            WaferMapFormat::Svg => "svg",
+            WaferMapFormat::Png => "png",
+        }
+    }
+}
+
+/// ダイ1つ分
+#[derive(Serialize, Debug, Clone)]
+pub struct Die {
+    pub serial: Option<i64>,
+    /// マップに表示するBIN(未検査の場合は None)
+    pub bin: Option<i64>,
+    pub passed: Option<bool>,
+}
+
+/// ウェハ1枚分のBINマップ
+#[derive(Serialize, Debug, Clone)]
+pub struct WaferMap {
+    pub lot_name: String,
+    pub wafer_no: i64,
+    pub type_name: Option<String>,
+    pub x_min: i64,
+    pub x_max: i64,
+    pub y_min: i64,
+    pub y_max: i64,
+    /// rows[y - y_min][x - x_min](ダイの記録が無い位置は None)
+    pub rows: Vec<Vec<Option<Die>>>,
+    pub die_count: u64,
+    pub tested: u64,
+    pub passed: u64,
+    pub yield_percent: f64,
+    /// BINごとのダイ数
+    pub bin_counts: BTreeMap<i64, u64>,
+}
+
+/// ロットのウェハマップ
+#[derive(Serialize, Debug, Clone)]
+pub struct LotWaferMaps {
+    pub table_name: String,
+    pub lot_name: String,
+    /// BINを取ったユニット(省略時は全ユニットの総合判定)
+    pub unit: Option<String>,
+    pub pass_bins: Vec<i64>,
+    pub wafers: Vec<WaferMap>,
+    /// ウェハ番号・座標が無いためマップに置けなかったチップ数
+    pub unplaced: u64,
+    /// 同じ位置に複数のチップが記録された数(最新のチップを表示する)
+    pub duplicates: u64,
+}
+
+/// 集計中のウェハ1枚分
+#[derive(Default)]
+struct WaferDies {
+    /// (WAX, WAY) -> ダイ
+    dies: BTreeMap<(i64, i64), Die>,
+    type_name: Option<String>,
+}
+
+/// ダイのBINを決める
+/// unit を指定した場合はそのユニットのBIN、省略時は工程順で最初の不良BIN(全て良品の場合は最後のBIN)
+fn die_bin(bins: &[Option<i64>], unit_index: Option<usize>, pass_bins: &[i64]) -> Option<i64> {
+    if let Some(i) = unit_index {
+        return bins[i];
+    }
+    let recorded: Vec<i64> = bins.iter().flatten().copied().collect();
+    recorded.iter().copied().find(|bin| !pass_bins.contains(bin)).or(recorded.last().copied())
+}
+
+/// ロットのウェハマップを作成する
+pub fn build_wafer_maps(conn: &Connection, table_name: &str, lot_name: &str, unit: Option<&str>, pass_bins: &[i64]) -> Result<LotWaferMaps, String> {
+    let unit_index = match unit {
+        Some(unit) => Some(
+            BIN_COLUMNS
+                .iter()
+                .position(|(name, _)| name.eq_ignore_ascii_case(unit))
+                .ok_or_else(|| format!("Unknown unit: {}", unit))?,
+        ),
+        None => None,
+    };
+    let bin_columns: Vec<&str> = BIN_COLUMNS.iter().map(|(_, column)| *column).collect();
+    let sql = format!(
+        "SELECT WANO, WAX, WAY, SERIAL, TYPE_NAME, {} FROM {table_name} WHERE LOT_NAME = ?1 ORDER BY ID",
+        bin_columns.join(", ")
+    );
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let mut rows = stmt.query(params![lot_name]).map_err(|e| e.to_string())?;
+
+    // WANO -> ウェハ
+    let mut wafers: BTreeMap<i64, WaferDies> = BTreeMap::new();
+    let mut unplaced = 0;
+    let mut duplicates = 0;
+    let mut found = false;
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        found = true;
+        let position: (Option<i64>, Option<i64>, Option<i64>) = (
+            row.get(0).map_err(|e| e.to_string())?,
+            row.get(1).map_err(|e| e.to_string())?,
+            row.get(2).map_err(|e| e.to_string())?,
+        );
+        let (Some(wano), Some(x), Some(y)) = position else {
+            unplaced += 1;
+            continue;
+        };
+        let mut bins = Vec::with_capacity(BIN_COLUMNS.len());
+        for i in 0..BIN_COLUMNS.len() {
+            bins.push(row.get::<_, Option<i64>>(5 + i).map_err(|e| e.to_string())?);
+        }
+        let bin = die_bin(&bins, unit_index, pass_bins);
+        let die = Die {
+            serial: row.get(3).map_err(|e| e.to_string())?,
+            bin,
+            passed: bin.map(|b| pass_bins.contains(&b)),
+        };
+        let wafer = wafers.entry(wano).or_default();
+        if wafer.type_name.is_none() {
+            wafer.type_name = row.get(4).map_err(|e| e.to_string())?;
+        }
+        // ID順に読んでいるので後のチップで上書きする
+        if wafer.dies.insert((x, y), die).is_some() {
+            duplicates += 1;
+        }
+    }
+    if !found {
+        return Err(format!("Lot {} not found", lot_name));
+    }
+
+    let wafers = wafers
+        .into_iter()
+        .map(|(wafer_no, WaferDies { dies, type_name })| {
+            let x_min = dies.keys().map(|(x, _)| *x).min().unwrap_or(0);
+            let x_max = dies.keys().map(|(x, _)| *x).max().unwrap_or(0);
+            let y_min = dies.keys().map(|(_, y)| *y).min().unwrap_or(0);
+            let y_max = dies.keys().map(|(_, y)| *y).max().unwrap_or(0);
+
+            let mut bin_counts = BTreeMap::new();
+            let mut tested = 0;
+            let mut passed = 0;
+            for die in dies.values() {
+                if let Some(bin) = die.bin {
+                    tested += 1;
+                    *bin_counts.entry(bin).or_insert(0) += 1;
+                    if die.passed == Some(true) {
+                        passed += 1;
+                    }
+                }
+            }
+            let die_count = dies.len() as u64;
+            let mut rows: Vec<Vec<Option<Die>>> = (y_min..=y_max).map(|_| vec![None; (x_max - x_min + 1) as usize]).collect();
+            for ((x, y), die) in dies {
+                rows[(y - y_min) as usize][(x - x_min) as usize] = Some(die);
+            }
+            WaferMap {
+                lot_name: lot_name.to_string(),
+                wafer_no,
+                type_name,
+                x_min,
+                x_max,
+                y_min,
+                y_max,
+                rows,
+                die_count,
+                tested,
+                passed,
+                yield_percent: if tested > 0 { passed as f64 * 100.0 / tested as f64 } else { 0.0 },
+                bin_counts,
+            }
+        })
+        .collect();
+
+    Ok(LotWaferMaps {
+        table_name: table_name.to_string(),
+        lot_name: lot_name.to_string(),
+        unit: unit_index.map(|i| BIN_COLUMNS[i].0.to_string()),
+        pass_bins: pass_bins.to_vec(),
+        wafers,
+        unplaced,
+        duplicates,
+    })
+}
+
+/// BINの2桁の16進コード(255以上は FE に丸める)
+fn bin_code(die: &Option<Die>) -> String {
+    match die.as_ref().and_then(|d| d.bin) {
+        Some(bin) => format!("{:02X}", bin.clamp(0, 254)),
+        None => NULL_BIN_CODE.to_string(),
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// E142 形式(SEMI E142 SubstrateMap)のXML
+pub fn to_e142(map: &WaferMap, pass_bins: &[i64]) -> String {
+    let substrate_id = escape_xml(&format!("{}-{:02}", map.lot_name, map.wafer_no));
+    let columns = map.x_max - map.x_min + 1;
+    let rows = map.y_max - map.y_min + 1;
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<MapData xmlns=\"urn:semi-org:xsd.E142-1.V1005.SubstrateMap\">\n");
+    xml.push_str("  <Layouts>\n");
+    xml.push_str(&format!(
+        "    <Layout LayoutId=\"WaferLayout\" DefaultUnits=\"mm\" TopLevel=\"true\">\n      <Dimension X=\"{columns}\" Y=\"{rows}\"/>\n      <LowerLeft X=\"{}\" Y=\"{}\"/>\n    </Layout>\n",
+        map.x_min, map.y_max
+    ));
+    xml.push_str("  </Layouts>\n");
+    xml.push_str(&format!(
+        "  <Substrates>\n    <Substrate SubstrateType=\"Wafer\" SubstrateId=\"{substrate_id}\">\n      <LotId>{}</LotId>\n      <SlotNumber>{}</SlotNumber>\n    </Substrate>\n  </Substrates>\n",
+        escape_xml(&map.lot_name),
+        map.wafer_no
+    ));
+    xml.push_str(&format!(
+        "  <SubstrateMaps>\n    <SubstrateMap SubstrateType=\"Wafer\" SubstrateId=\"{substrate_id}\" LayoutSpecifier=\"WaferLayout\" OriginLocation=\"UpperLeft\" AxisDirection=\"DownRight\">\n"
+    ));
+    xml.push_str(&format!("      <BinCodeMap BinType=\"HexaDecimal\" NullBin=\"{NULL_BIN_CODE}\">\n        <BinDefinitions>\n"));
+    for (bin, count) in &map.bin_counts {
+        let quality = if pass_bins.contains(bin) { "Pass" } else { "Fail" };
+        xml.push_str(&format!(
+            "          <BinDefinition BinCode=\"{:02X}\" BinCount=\"{count}\" BinQuality=\"{quality}\" BinDescription=\"BIN {bin}\"/>\n",
+            (*bin).clamp(0, 254)
+        ));
+    }
+    xml.push_str("        </BinDefinitions>\n");
+    for row in &map.rows {
+        let codes: String = row.iter().map(bin_code).collect();
+        xml.push_str(&format!("        <BinCode>{codes}</BinCode>\n"));
+    }
+    xml.push_str("      </BinCodeMap>\n    </SubstrateMap>\n  </SubstrateMaps>\n</MapData>\n");
+    xml
+}
+
+/// SINF形式のテキストマップ
+pub fn to_sinf(map: &WaferMap, pass_bins: &[i64]) -> String {
+    let mut text = String::new();
+    text.push_str(&format!("DEVICE:{}\n", map.type_name.as_deref().unwrap_or("")));
+    text.push_str(&format!("LOT:{}\n", map.lot_name));
+    text.push_str(&format!("WAFER:{}\n", map.wafer_no));
+    text.push_str("FNLOC:0\n");
+    text.push_str(&format!("ROWCT:{}\n", map.y_max - map.y_min + 1));
+    text.push_str(&format!("COLCT:{}\n", map.x_max - map.x_min + 1));
+    let pass_codes: Vec<String> = pass_bins.iter().map(|bin| format!("{:02X}", (*bin).clamp(0, 254))).collect();
+    text.push_str(&format!("BCEQU:{}\n", pass_codes.join(" ")));
+    text.push_str(&format!("REFPX:{}\n", map.x_min));
+    text.push_str(&format!("REFPY:{}\n", map.y_min));
+    text.push_str("DUTMS:mm\n");
+    for row in &map.rows {
+        let codes: Vec<String> = row
+            .iter()
+            .map(|die| if die.is_some() { bin_code(die) } else { "__".to_string() })
+            .collect();
+        text.push_str(&format!("RowData:{}\n", codes.join(" ")));
+    }
+    text
+}
+
+/// ダイの色(良品は緑、未検査は灰、不良はBINごとの色)
+fn die_color(die: &Die) -> [u8; 3] {
+    const FAIL_COLORS: [[u8; 3]; 8] = [
+        [220, 50, 47],
+        [255, 140, 0],
+        [211, 54, 130],
+        [108, 113, 196],
+        [38, 139, 210],
+        [181, 137, 0],
+        [133, 60, 20],
+        [90, 90, 90],
+    ];
+    match (die.passed, die.bin) {
+        (Some(true), _) => [40, 167, 69],
+        (Some(false), Some(bin)) => FAIL_COLORS[bin.rem_euclid(FAIL_COLORS.len() as i64) as usize],
+        _ => [200, 200, 200],
+    }
+}
+
+/// SVG画像
+pub fn to_svg(map: &WaferMap, pass_bins: &[i64]) -> String {
+    let size = SVG_DIE_SIZE;
+    let columns = (map.x_max - map.x_min + 1) as u32;
+    let rows = (map.y_max - map.y_min + 1) as u32;
+    let legend_height = 20 + 16 * map.bin_counts.len() as u32;
+    let width = (columns * size).max(200);
+    let height = rows * size + legend_height;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\" font-family=\"sans-serif\" font-size=\"11\">\n"
+    );
+    svg.push_str(&format!(
+        "<title>{} W{} ({:.1}%)</title>\n<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n",
+        escape_xml(&map.lot_name),
+        map.wafer_no,
+        map.yield_percent
+    ));
+    for (r, row) in map.rows.iter().enumerate() {
+        for (c, die) in row.iter().enumerate() {
+            let Some(die) = die else {
+                continue;
+            };
+            let [red, green, blue] = die_color(die);
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({red},{green},{blue})\"><title>X{} Y{} S{} BIN {}</title></rect>\n",
+                c as u32 * size,
+                r as u32 * size,
+                size - 1,
+                size - 1,
+                map.x_min + c as i64,
+                map.y_min + r as i64,
+                die.serial.map(|s| s.to_string()).unwrap_or_default(),
+                die.bin.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string()),
+            ));
+        }
+    }
+    // 凡例
+    let top = rows * size + 16;
+    svg.push_str(&format!(
+        "<text x=\"0\" y=\"{top}\">{} W{}  {}/{} ({:.1}%)</text>\n",
+        escape_xml(&map.lot_name),
+        map.wafer_no,
+        map.passed,
+        map.tested,
+        map.yield_percent
+    ));
+    for (i, (bin, count)) in map.bin_counts.iter().enumerate() {
+        let y = top + 16 * (i as u32 + 1);
+        let [red, green, blue] = die_color(&Die { serial: None, bin: Some(*bin), passed: Some(pass_bins.contains(bin)) });
+        svg.push_str(&format!(
+            "<rect x=\"0\" y=\"{}\" width=\"10\" height=\"10\" fill=\"rgb({red},{green},{blue})\"/><text x=\"14\" y=\"{y}\">BIN {bin}: {count}</text>\n",
+            y - 9
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// PNG画像
+pub fn to_png(map: &WaferMap) -> Result<Vec<u8>, String> {
+    let size = PNG_DIE_PIXELS;
+    let width = (map.x_max - map.x_min + 1) as u32 * size;
+    let height = (map.y_max - map.y_min + 1) as u32 * size;
+    let mut pixels = vec![255u8; (width * height * 3) as usize];
+    for (r, row) in map.rows.iter().enumerate() {
+        for (c, die) in row.iter().enumerate() {
+            let Some(die) = die else {
+                continue;
+            };
+            let color = die_color(die);
+            // 右端・下端の1ピクセルは隙間として白のまま残す
+            for dy in 0..size - 1 {
+                for dx in 0..size - 1 {
+                    let px = c as u32 * size + dx;
+                    let py = r as u32 * size + dy;
+                    let offset = ((py * width + px) * 3) as usize;
+                    pixels[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(png_data)
+}
+
+/// 指定した形式で出力する
+pub fn render(map: &WaferMap, format: WaferMapFormat, pass_bins: &[i64]) -> Result<Vec<u8>, String> {
+    match format {
+        WaferMapFormat::E142 => Ok(to_e142(map, pass_bins).into_bytes()),
+        WaferMapFormat::Sinf => Ok(to_sinf(map, pass_bins).into_bytes()),
+        WaferMapFormat::Svg => Ok(to_svg(map, pass_bins).into_bytes()),
+        WaferMapFormat::Png => to_png(map),
+    }
+}
+
+/// 良品BINの設定を読み込み、読み出し用の接続でロットのウェハマップを作成する
+async fn load_wafer_maps(plc_id: u32, lot_name: String, unit: Option<String>) -> Result<LotWaferMaps, String> {
+    let table_name = table_for_plc(plc_id)?;
+    let pass_bins = load_config()?.quality.pass_bins;
+    tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_read_connection().map_err(|e| format!("Failed to open database: {}", e))?;
+        build_wafer_maps(&conn, &table_name, &lot_name, unit.as_deref(), &pass_bins)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// ロットのウェハマップをフロントエンドに返す
+#[command]
+pub async fn get_wafer_maps(plc_id: u32, lot_name: String, unit: Option<String>) -> Result<LotWaferMaps, String> {
+    load_wafer_maps(plc_id, lot_name, unit).await
+}
+
+/// ロットのウェハマップをウェハごとのファイルに出力する(出力したファイルのパスを返す)
+#[command]
+pub async fn export_wafer_maps(plc_id: u32, lot_name: String, unit: Option<String>, format: WaferMapFormat, dir: String) -> Result<Vec<String>, String> {
+    let maps = load_wafer_maps(plc_id, lot_name, unit).await?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+    // ロット名はファイル名に使えない文字を置き換える
+    let file_lot: String = maps
+        .lot_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    let mut paths = Vec::new();
+    for map in &maps.wafers {
+        let path = PathBuf::from(&dir).join(format!("{}_W{:02}.{}", file_lot, map.wafer_no, format.extension()));
+        let data = render(map, format, &maps.pass_bins)?;
+        std::fs::write(&path, data).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    log::info!("Exported {} wafer maps of lot {} to {}", paths.len(), maps.lot_name, dir);
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASS_BINS: [i64; 1] = [1];
+
+    /// (WANO, WAX, WAY, SERIAL, DC1のBIN, DC2のBIN)
+    type Chip = (Option<i64>, Option<i64>, Option<i64>, i64, Option<i64>, Option<i64>);
+
+    fn lot(chips: &[Chip]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        let bin_columns: Vec<String> = BIN_COLUMNS.iter().map(|(_, column)| format!("{} INTEGER", column)).collect();
+        conn.execute_batch(&format!(
+            "CREATE TABLE t (ID INTEGER PRIMARY KEY, LOT_NAME TEXT, TYPE_NAME TEXT, SERIAL INTEGER, WANO INTEGER, WAX INTEGER, WAY INTEGER, {})",
+            bin_columns.join(", ")
+        ))
+        .unwrap();
+        for (wano, x, y, serial, dc1, dc2) in chips {
+            conn.execute(
+                "INSERT INTO t (LOT_NAME, TYPE_NAME, SERIAL, WANO, WAX, WAY, DC1_TEST_BIN, DC2_TEST_BIN) VALUES ('LOT1', 'TYPE1', ?1, ?2, ?3, ?4, ?5, ?6)",
+                params![serial, wano, x, y, dc1, dc2],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn bins(map: &WaferMap) -> Vec<Vec<Option<i64>>> {
+        map.rows.iter().map(|row| row.iter().map(|die| die.as_ref().and_then(|d| d.bin)).collect()).collect()
+    }
+
+    #[test]
+    fn layout_is_row_per_y_from_upper_left() {
+        let conn = lot(&[
+            (Some(1), Some(-1), Some(2), 1, Some(1), Some(1)),
+            (Some(1), Some(1), Some(2), 2, Some(1), Some(5)),
+            (Some(1), Some(0), Some(3), 3, Some(7), Some(1)),
+        ]);
+        let maps = build_wafer_maps(&conn, "t", "LOT1", None, &PASS_BINS).unwrap();
+        let map = &maps.wafers[0];
+        assert_eq!((map.x_min, map.x_max, map.y_min, map.y_max), (-1, 1, 2, 3));
+        // rows[y - y_min][x - x_min]
+        assert_eq!(bins(map), vec![vec![Some(1), None, Some(5)], vec![None, Some(7), None]]);
+        assert_eq!(map.rows[1][1].as_ref().unwrap().serial, Some(3));
+        assert_eq!((map.die_count, map.tested, map.passed), (3, 3, 1));
+        assert_eq!(map.bin_counts, BTreeMap::from([(1, 1), (5, 1), (7, 1)]));
+        assert_eq!(map.type_name.as_deref(), Some("TYPE1"));
+    }
+
+    #[test]
+    fn wafers_duplicates_and_unplaced() {
+        let conn = lot(&[
+            (Some(2), Some(0), Some(0), 1, Some(1), None),
+            (Some(1), Some(0), Some(0), 2, Some(1), None),
+            // 同じ位置は後のチップを表示する
+            (Some(1), Some(0), Some(0), 3, Some(9), None),
+            (None, Some(0), Some(0), 4, Some(1), None),
+            (Some(1), None, Some(0), 5, Some(1), None),
+        ]);
+        let maps = build_wafer_maps(&conn, "t", "LOT1", None, &PASS_BINS).unwrap();
+        assert_eq!(maps.wafers.iter().map(|w| w.wafer_no).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(maps.duplicates, 1);
+        assert_eq!(maps.unplaced, 2);
+        assert_eq!(maps.wafers[0].rows[0][0].as_ref().unwrap().serial, Some(3));
+        assert!(build_wafer_maps(&conn, "t", "LOT2", None, &PASS_BINS).is_err());
+    }
+
+    #[test]
+    fn die_bin_by_unit_or_first_failure() {
+        assert_eq!(die_bin(&[Some(1), Some(5), Some(7)], None, &PASS_BINS), Some(5));
+        assert_eq!(die_bin(&[Some(1), None, Some(1)], None, &PASS_BINS), Some(1));
+        assert_eq!(die_bin(&[None, None], None, &PASS_BINS), None);
+        assert_eq!(die_bin(&[Some(1), Some(5)], Some(0), &PASS_BINS), Some(1));
+
+        let conn = lot(&[(Some(1), Some(0), Some(0), 1, Some(1), Some(5))]);
+        let maps = build_wafer_maps(&conn, "t", "LOT1", Some("dc2"), &PASS_BINS).unwrap();
+        assert_eq!(maps.unit.as_deref(), Some("DC2"));
+        assert_eq!(bins(&maps.wafers[0]), vec![vec![Some(5)]]);
+        assert!(build_wafer_maps(&conn, "t", "LOT1", Some("XX"), &PASS_BINS).is_err());
+    }
+
+    #[test]
+    fn text_formats_follow_the_layout() {
+        let conn = lot(&[
+            (Some(1), Some(0), Some(0), 1, Some(1), None),
+            (Some(1), Some(1), Some(1), 2, Some(300), None),
+        ]);
+        let maps = build_wafer_maps(&conn, "t", "LOT1", None, &PASS_BINS).unwrap();
+        let map = &maps.wafers[0];
+
+        let sinf = to_sinf(map, &PASS_BINS);
+        assert!(sinf.contains("ROWCT:2\nCOLCT:2\n"));
+        assert!(sinf.contains("BCEQU:01\n"));
+        assert!(sinf.ends_with("RowData:01 __\nRowData:__ FE\n"));
+
+        let e142 = to_e142(map, &PASS_BINS);
+        assert!(e142.contains("<Dimension X=\"2\" Y=\"2\"/>"));
+        assert!(e142.contains("<BinCode>01FF</BinCode>\n        <BinCode>FFFE</BinCode>"));
+        assert!(e142.contains("BinCode=\"01\" BinCount=\"1\" BinQuality=\"Pass\""));
+
+        let png = to_png(map).unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (2 * PNG_DIE_PIXELS, 2 * PNG_DIE_PIXELS));
+    }
+}
@@ -150,6 +150,43 @@ export default function PLCCard({ plc, config,onConnect, onDisconnect, onDelete,
               <p className="text-white">{plc.lastReceived}</p>
             </div>
 
+            {/* 通信統計 */}
+            {plc.stats && (
+              <div className="border-t border-gray-700 pt-4">
+                <p className="text-sm text-gray-400 mb-2">通信統計</p>
+                <div className="grid grid-cols-2 gap-2 text-sm">
+                  <p className="text-gray-400">受信フレーム数</p>
+                  <p className="text-white font-mono">{plc.stats.frames_received}</p>
+                  <p className="text-gray-400">受信バイト数</p>
+                  <p className="text-white font-mono">{plc.stats.bytes_received}</p>
+                  <p className="text-gray-400">平均フレーム数/分</p>
+                  <p className="text-white font-mono">{plc.stats.frames_per_minute.toFixed(1)}</p>
+                  <p className="text-gray-400">パース失敗</p>
+                  <p className={plc.stats.parse_failures > 0 ? "text-yellow-400 font-mono" : "text-white font-mono"}>
+                    {plc.stats.parse_failures}
+                  </p>
+                  <p className="text-gray-400">DB書き込み失敗</p>
+                  <p className={plc.stats.db_failures > 0 ? "text-yellow-400 font-mono" : "text-white font-mono"}>
+                    {plc.stats.db_failures}
+                  </p>
+                  <p className="text-gray-400">検証エラー(退避)</p>
+                  <p className={plc.stats.quarantined > 0 ? "text-yellow-400 font-mono" : "text-white font-mono"}>
+                    {plc.stats.quarantined}
+                  </p>
+                  <p className="text-gray-400">規格外の測定値</p>
+                  <p className={plc.stats.limit_violations > 0 ? "text-red-400 font-mono" : "text-white font-mono"}>
+                    {plc.stats.limit_violations}
+                  </p>
+                  <p className="text-gray-400">再接続回数</p>
+                  <p className="text-white font-mono">{plc.stats.reconnect_count}</p>
+                  <p className="text-gray-400">接続開始時刻</p>
+                  <p className="text-white">
+                    {plc.stats.connected_since ? new Date(plc.stats.connected_since).toLocaleString("ja-JP") : "-"}
+                  </p>
+                </div>
+              </div>
+            )}
+
             <div className="border-t border-gray-700 pt-4">
               <p className="text-sm text-gray-400 mb-2">受信データ</p>
               {plc.data ? (
@@ -12,6 +12,11 @@ export default function StackCard() {
   const [error, setError] = useState(null);
   const [plcConfigs, setPlcConfigs] = useState([]); // 元の設定データを保持
   const [isAddDialogOpen, setIsAddDialogOpen] = useState(false);
+  const [queueStatus, setQueueStatus] = useState(null); // DB書き込みキューの状態
+  const [shutdownProgress, setShutdownProgress] = useState(null); // 終了処理の進捗
+  const [replicationStatus, setReplicationStatus] = useState(null); // 中央サーバーへの複製の状態
+  const [quarantineReports, setQuarantineReports] = useState([]); // 検証エラーで退避した受信データ(新しい順)
+  const [limitViolations, setLimitViolations] = useState([]); // 規格外の測定値(新しい順)
 
   // アプリ起動時にPLC設定を読み込む
   useEffect(() => {
@@ -74,9 +79,47 @@ export default function StackCard() {
           );
         });
 
+        const unlistenQueue = await listen('db-queue-status', (event) => {
+          setQueueStatus(event.payload);
+        });
+
+        const unlistenStats = await listen('plc-stats', (event) => {
+          // 対象のplc_idの受信統計を更新
+          const statsById = new Map(event.payload.map((st) => [st.plc_id, st]));
+          setPlcList((prev) =>
+            prev.map((p) =>
+              statsById.has(p.id) ? { ...p, stats: statsById.get(p.id) } : p
+            )
+          );
+        });
+
+        const unlistenShutdown = await listen('shutdown-progress', (event) => {
+          setShutdownProgress(event.payload);
+        });
+
+        const unlistenReplication = await listen('replication-status', (event) => {
+          setReplicationStatus(event.payload);
+        });
+
+        const unlistenQuarantine = await listen('plc-frame-quarantined', (event) => {
+          // 直近10件だけ表示する
+          setQuarantineReports((prev) => [event.payload, ...prev].slice(0, 10));
+        });
+
+        const unlistenLimit = await listen('plc-limit-violation', (event) => {
+          // 直近10件だけ表示する
+          setLimitViolations((prev) => [event.payload, ...prev].slice(0, 10));
+        });
+
         return () => {
           unlistenMessage();
           unlistenDisconnect();
+          unlistenQueue();
+          unlistenStats();
+          unlistenShutdown();
+          unlistenReplication();
+          unlistenQuarantine();
+          unlistenLimit();
         };
       } catch (err) {
         console.error("Failed to setup listener:", err);
@@ -310,6 +353,36 @@ export default function StackCard() {
         <div className="flex items-center justify-between p-4">
           <h1 className="text-2xl font-bold">設備情報収集システム</h1>
           <div className="flex items-center gap-4">
+            {queueStatus && (
+              <span
+                className={`px-3 py-1 rounded-full text-sm font-medium ${
+                  queueStatus.dropped_total > 0 || queueStatus.spilled_pending > 0
+                    ? "bg-yellow-900/50 text-yellow-400"
+                    : "bg-gray-700 text-gray-300"
+                }`}
+                title={`破棄: ${queueStatus.dropped_total} / 退避中: ${queueStatus.spilled_pending} / 待機: ${queueStatus.blocked_total}`}
+              >
+                DBキュー {queueStatus.depth}/{queueStatus.capacity}
+              </span>
+            )}
+            {replicationStatus?.enabled && (
+              <span
+                className={`px-3 py-1 rounded-full text-sm font-medium ${
+                  !replicationStatus.online
+                    ? "bg-red-900/50 text-red-400"
+                    : replicationStatus.backlog > 0
+                      ? "bg-yellow-900/50 text-yellow-400"
+                      : "bg-gray-700 text-gray-300"
+                }`}
+                title={
+                  replicationStatus.last_error
+                    ? `送信失敗: ${replicationStatus.last_error}`
+                    : `最終送信: ${replicationStatus.last_sent_at ?? "-"}`
+                }
+              >
+                複製待ち {replicationStatus.backlog}
+              </span>
+            )}
             <span className="px-3 py-1 bg-blue-900/50 text-blue-400 rounded-full text-sm font-medium">
               {connectedCount}/{plcList.length} 接続中
             </span>
@@ -337,6 +410,64 @@ export default function StackCard() {
           </button>
         </div>
 
+        {/* 規格外の測定値 */}
+        {limitViolations.length > 0 && (
+          <div className="bg-red-900/30 border border-red-600 rounded-lg p-4 mb-4">
+            <div className="flex items-center justify-between mb-2">
+              <h3 className="font-semibold text-red-400">規格外の測定値</h3>
+              <button
+                onClick={() => setLimitViolations([])}
+                className="p-1 hover:bg-gray-700 rounded-full transition-colors"
+                aria-label="規格外の測定値の表示を閉じる"
+              >
+                <X size={16} />
+              </button>
+            </div>
+            <ul className="space-y-1 text-sm">
+              {limitViolations.map((v, index) => (
+                <li key={index} className="text-red-200">
+                  <span className="text-gray-400">{v.timestamp}</span>{" "}
+                  {plcList.find((p) => p.id === v.plc_id)?.name ?? `PLC ${v.plc_id}`}
+                  {" / "}LOT {v.lot} #{v.serial}
+                  {": "}
+                  {v.column} {v.detail}
+                </li>
+              ))}
+            </ul>
+          </div>
+        )}
+
+        {/* 検証エラーで退避した受信データ */}
+        {quarantineReports.length > 0 && (
+          <div className="bg-yellow-900/30 border border-yellow-600 rounded-lg p-4 mb-4">
+            <div className="flex items-center justify-between mb-2">
+              <h3 className="font-semibold text-yellow-400">検証エラーで退避した受信データ</h3>
+              <button
+                onClick={() => setQuarantineReports([])}
+                className="p-1 hover:bg-gray-700 rounded-full transition-colors"
+                aria-label="検証エラーの表示を閉じる"
+              >
+                <X size={16} />
+              </button>
+            </div>
+            <ul className="space-y-1 text-sm">
+              {quarantineReports.map((report, index) => (
+                <li key={index} className="text-yellow-200">
+                  <span className="text-gray-400">{report.timestamp}</span>{" "}
+                  {plcList.find((p) => p.id === report.plc_id)?.name ?? `PLC ${report.plc_id}`}
+                  {" / "}LOT {report.lot}
+                  {report.schema && <span className="text-gray-400"> ({report.schema})</span>}
+                  {": "}
+                  {report.issues[0]?.path} {report.issues[0]?.message}
+                  {report.issues.length + report.omitted > 1 && (
+                    <span className="text-gray-400"> ほか{report.issues.length + report.omitted - 1}件</span>
+                  )}
+                </li>
+              ))}
+            </ul>
+          </div>
+        )}
+
         <div className="space-y-2">
           {plcList.length > 0 ? (
             plcList.map((plc,index) => (
@@ -362,6 +493,17 @@ export default function StackCard() {
         onClose={() => setIsAddDialogOpen(false)}
         onAdd={handleAddPlc}
       />
+
+      {/* 終了処理中の表示 */}
+      {shutdownProgress && (
+        <div className="fixed inset-0 bg-black/70 flex items-center justify-center z-50">
+          <div className="bg-gray-800 rounded-lg p-6 shadow-xl text-center w-80">
+            <div className="animate-spin rounded-full h-12 w-12 border-b-2 border-blue-400 mx-auto mb-4"></div>
+            <h2 className="text-lg font-semibold mb-2">終了処理中</h2>
+            <p className="text-gray-300">{shutdownProgress.message}</p>
+          </div>
+        </div>
+      )}
     </div>
   );
 }